@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    if std::env::var_os("CARGO_FEATURE_REGEN_THRIFT").is_some() {
+        regen_thrift();
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+// Regenerate the bindings from the vendored IDL into OUT_DIR, instead of using the
+// checked-in copy. Needs a `thrift` compiler matching the thrift crate's version,
+// `THRIFT` picks a specific binary.
+fn regen_thrift() {
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").expect("cargo sets OUT_DIR"));
+    let thrift = std::env::var("THRIFT").unwrap_or_else(|_| "thrift".to_string());
+    println!("cargo:rerun-if-changed=osquery.thrift");
+    println!("cargo:rerun-if-env-changed=THRIFT");
+
+    let status = Command::new(&thrift)
+        .args(["-out"])
+        .arg(&out_dir)
+        .args(["--gen", "rs", "-r", "osquery.thrift"])
+        .status()
+        .unwrap_or_else(|e| {
+            panic!(
+                "regen-thrift needs a thrift compiler, couldn't run `{}`: {}",
+                thrift, e
+            )
+        });
+    if !status.success() {
+        panic!("`{}` failed to compile osquery.thrift: {}", thrift, status);
+    }
+
+    // the output is meant to be a file of its own, but it gets include!d into a module,
+    // where inner attributes and extern crate aren't allowed. that takes the compiler's
+    // stale cfg(feature = "cargo-clippy") with it, too
+    let generated = out_dir.join("osquery.rs");
+    let source = std::fs::read_to_string(&generated).expect("thrift wrote osquery.rs");
+    let source: String = source
+        .lines()
+        .filter(|line| !line.starts_with("#![") && !line.starts_with("extern crate"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    std::fs::write(&generated, source).expect("couldn't rewrite osquery.rs");
+}
@@ -101,4 +101,11 @@ service ExtensionManager extends Extension {
   ExtensionResponse getQueryColumns(
     1:string sql,
   ),
+  /// Allow an extension to stream events into an osquery events table.
+  ExtensionStatus streamEvents(
+    1:string name,
+    2:ExtensionPluginResponse events,
+  ),
+  /// Return the node key osquery enrolled with, if any.
+  string getNodeKey(),
 }
@@ -0,0 +1,18 @@
+//! The osquery extensions protocol, straight out of the thrift compiler, plus a few
+//! conveniences on the generated types. The `osquery` crate builds the plugin server on
+//! top of this; depend on this one directly if all you want is the wire types and the
+//! generated clients/processors.
+#![allow(dead_code, unused_imports, clippy::all, deprecated)]
+
+#[cfg(not(feature = "regen-thrift"))]
+mod osquery;
+// built from osquery.thrift by build.rs
+#[cfg(feature = "regen-thrift")]
+mod osquery {
+    include!(concat!(env!("OUT_DIR"), "/osquery.rs"));
+}
+
+mod status;
+
+pub use self::osquery::*;
+pub use thrift;
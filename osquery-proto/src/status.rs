@@ -0,0 +1,72 @@
+use std::convert::TryFrom;
+
+use crate::{ExtensionCode, ExtensionPluginResponse, ExtensionResponse, ExtensionStatus};
+
+impl ExtensionStatus {
+    /// `EXT_SUCCESS`, with no message
+    pub fn success() -> Self {
+        Self {
+            code: Some(ExtensionCode::ExtSuccess as i32),
+            message: None,
+            uuid: None,
+        }
+    }
+
+    /// `EXT_FAILED`, saying why
+    pub fn failure<S: Into<String>>(message: S) -> Self {
+        Self {
+            code: Some(ExtensionCode::ExtFailed as i32),
+            message: Some(message.into()),
+            uuid: None,
+        }
+    }
+
+    pub fn with_message<S: Into<String>>(mut self, message: S) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// The status code, or `None` if there isn't one or it's not one this crate knows.
+    pub fn code(&self) -> Option<ExtensionCode> {
+        self.code.and_then(|c| ExtensionCode::try_from(c).ok())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.code() == Some(ExtensionCode::ExtSuccess)
+    }
+
+    pub fn ok(self) -> Result<Option<String>, thrift::Error> {
+        if self.is_success() {
+            return Ok(self.message);
+        }
+        let e = thrift::ApplicationError::new(
+            thrift::ApplicationErrorKind::InternalError,
+            self.message
+                .unwrap_or_else(|| "Unknown error occurred!".to_string()),
+        );
+        Err(e.into())
+    }
+}
+
+impl ExtensionResponse {
+    pub fn success(response: ExtensionPluginResponse) -> Self {
+        Self {
+            status: Some(ExtensionStatus::success()),
+            response: Some(response),
+        }
+    }
+
+    /// A failed status and no rows
+    pub fn failure<S: Into<String>>(message: S) -> Self {
+        Self {
+            status: Some(ExtensionStatus::failure(message)),
+            response: Some(vec![]),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status
+            .as_ref()
+            .is_some_and(ExtensionStatus::is_success)
+    }
+}
@@ -0,0 +1,151 @@
+// Rows per second through each step between a table's `generate` and the bytes osquery
+// reads, then through all of them over the in-memory `Loopback` transport:
+//
+//     cargo bench --bench rows
+//     cargo bench --bench rows -- loopback
+//
+// `generate` builds the rows, `stringify` turns them into osquery's name -> string maps,
+// `encode` writes that response as thrift, `handle_call` is the dispatcher doing the first
+// two, and `loopback` is a client calling a served table, decoding included.
+use std::collections::BTreeMap;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use osquery::thrift::protocol::{TBinaryInputProtocol, TBinaryOutputProtocol};
+use osquery::transport::{Connector, Loopback, Stream};
+use osquery::{
+    Column, ColumnValue, ExtensionSyncClient, Handle, Plugin, PluginHandler, QueryContext,
+    Response, RowSet, TExtensionSyncClient, TablePlugin, TableRows,
+};
+
+const SIZES: &[usize] = &[10, 1_000, 100_000];
+
+// a mix of column types, roughly a processes-sized row
+#[derive(Debug)]
+struct Bench {
+    rows: usize,
+}
+
+impl Plugin for Bench {
+    type Error = std::convert::Infallible;
+    const NAME: &'static str = "bench";
+
+    fn new() -> Self {
+        Self { rows: 1 }
+    }
+}
+
+impl TablePlugin for Bench {
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(vec![
+            Column::big_int("pid"),
+            Column::text("name"),
+            Column::text("path"),
+            Column::integer("threads"),
+            Column::big_int("resident_size"),
+            Column::double("cpu"),
+        ])
+    }
+
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
+        Ok((0..self.rows)
+            .map(|i| {
+                let mut row = BTreeMap::new();
+                row.insert("pid".to_string(), ColumnValue::big_int(i as i64));
+                row.insert("name".to_string(), ColumnValue::text(format!("proc{}", i)));
+                row.insert(
+                    "path".to_string(),
+                    ColumnValue::text(format!("/usr/local/bin/proc{}", i)),
+                );
+                row.insert("threads".to_string(), ColumnValue::integer(i as i32 % 64));
+                row.insert(
+                    "resident_size".to_string(),
+                    ColumnValue::big_int(i as i64 * 4096),
+                );
+                row.insert("cpu".to_string(), ColumnValue::double(i as f64 / 7.0));
+                row
+            })
+            .collect())
+    }
+
+    fn shutdown(&self) {}
+}
+
+fn generate_request() -> osquery::PluginRequest {
+    let mut request = BTreeMap::new();
+    request.insert("action".to_string(), "generate".to_string());
+    request.insert("context".to_string(), "{}".to_string());
+    request
+}
+
+fn steps(c: &mut Criterion) {
+    let query = QueryContext::default();
+    let mut group = c.benchmark_group("rows");
+    for &rows in SIZES {
+        let table = Bench { rows };
+        let columns = table.columns().unwrap();
+        group.throughput(Throughput::Elements(rows as u64));
+        if rows >= 100_000 {
+            group.sample_size(10);
+        }
+
+        group.bench_function(BenchmarkId::new("generate", rows), |b| {
+            b.iter(|| table.generate(&query).unwrap())
+        });
+        group.bench_function(BenchmarkId::new("stringify", rows), |b| {
+            b.iter_batched(
+                || table.generate(&query).unwrap(),
+                |generated| RowSet::from_table_rows(&columns, generated).into_response(),
+                BatchSize::LargeInput,
+            )
+        });
+        let response = Response::success(table.generate_rows(&query).unwrap().into_response());
+        group.bench_function(BenchmarkId::new("encode", rows), |b| {
+            let mut bytes = Vec::new();
+            b.iter(|| {
+                bytes.clear();
+                let mut protocol = TBinaryOutputProtocol::new(&mut bytes, true);
+                response.write_to_out_protocol(&mut protocol).unwrap();
+            })
+        });
+        group.bench_function(BenchmarkId::new("handle_call", rows), |b| {
+            b.iter(|| {
+                table
+                    .handle_call("table".into(), Bench::NAME.into(), generate_request())
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn loopback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("loopback");
+    for &rows in SIZES {
+        let socket = format!("bench-{}", rows);
+        let _server = Handle::<_, Loopback>::on_transport(&socket, Bench { rows })
+            .start()
+            .unwrap();
+        // osquery's side of the call. `Client` talks to osquery, not to extensions
+        let stream = Loopback::connect(socket.as_ref()).unwrap();
+        let mut client = ExtensionSyncClient::new(
+            TBinaryInputProtocol::new(stream.try_clone().unwrap(), true),
+            TBinaryOutputProtocol::new(stream, true),
+        );
+        group.throughput(Throughput::Elements(rows as u64));
+        if rows >= 100_000 {
+            group.sample_size(10);
+        }
+        group.bench_function(BenchmarkId::new("generate", rows), |b| {
+            b.iter(|| {
+                let response = client
+                    .call("table".into(), Bench::NAME.into(), generate_request())
+                    .unwrap();
+                assert_eq!(response.response.map_or(0, |r| r.len()), rows);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, steps, loopback);
+criterion_main!(benches);
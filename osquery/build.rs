@@ -0,0 +1,61 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn main() {
+    // build info for the extension_info table. neither of these is worth failing the build over
+    let git_sha = own_repo()
+        .and_then(|git_dir| {
+            // so the sha follows commits and checkouts, not just edits to build.rs
+            for watched in &["HEAD", "refs", "packed-refs"] {
+                let path = git_dir.join(watched);
+                if path.exists() {
+                    println!("cargo:rerun-if-changed={}", path.display());
+                }
+            }
+            git(&["rev-parse", "--short", "HEAD"])
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=OSQUERY_RS_GIT_SHA={}", git_sha);
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=OSQUERY_RS_RUSTC_VERSION={}", rustc_version);
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Run git in this crate's directory, for its trimmed stdout.
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(env!("CARGO_MANIFEST_DIR"))
+        .args(args)
+        .output()
+        .ok()
+        .filter(|out| out.status.success())?;
+    String::from_utf8(out.stdout)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// The git dir of the osquery-rs checkout this crate is built from, if it is one. a
+/// vendored copy sitting inside somebody else's repo would otherwise pick up their sha.
+fn own_repo() -> Option<PathBuf> {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let toplevel = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?);
+    let ours = [Some(manifest_dir), manifest_dir.parent()]
+        .iter()
+        .flatten()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .any(|dir| toplevel.canonicalize().ok() == Some(dir));
+    if !ours {
+        return None;
+    }
+    git(&["rev-parse", "--absolute-git-dir"]).map(PathBuf::from)
+}
@@ -1,24 +1,35 @@
-use std::{thread::sleep, time::Duration};
+use std::time::Duration;
 
 use maplit::btreemap;
 use osquery::{
-    Client, Column, ColumnValue, QueryContext, TExtensionManagerSyncClient, TablePlugin, TableRows,
+    Client, Column, ColumnValue, Plugin, QueryContext, TExtensionManagerSyncClient, TablePlugin,
+    TableRows,
 };
 use tracing::{debug, error, info};
 
 #[derive(Debug, Default)]
 pub struct ExampleTable;
 
-impl TablePlugin for ExampleTable {
+impl Plugin for ExampleTable {
+    type Error = std::convert::Infallible;
     const NAME: &'static str = "example_table";
-    const COLUMNS: &'static [Column] = &[
-        Column::text("text"),
-        Column::integer("integer"),
-        Column::big_int("big_int"),
-        Column::double("double"),
-    ];
-
-    fn generate(&self, _query: &QueryContext) -> Result<TableRows, thrift::Error> {
+
+    fn new() -> Self {
+        Self
+    }
+}
+
+impl TablePlugin for ExampleTable {
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(vec![
+            Column::text("text"),
+            Column::integer("integer"),
+            Column::big_int("big_int"),
+            Column::double("double"),
+        ])
+    }
+
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
         Ok(vec![btreemap! {
             "text".to_string() => ColumnValue::text("hello_world"),
             "integer".to_string() => ColumnValue::integer(123),
@@ -26,11 +37,13 @@ impl TablePlugin for ExampleTable {
             "double".to_string() => ColumnValue::double(std::f64::consts::PI),
         }])
     }
+
+    fn shutdown(&self) {}
 }
 
-fn main() -> Result<(), Box<dyn (std::error::Error)>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt()
-        .with_env_filter("tester=trace,osquery=trace,info")
+        .with_max_level(tracing::Level::DEBUG)
         .pretty()
         .init();
     info!("Getting a client put together");
@@ -38,15 +51,12 @@ fn main() -> Result<(), Box<dyn (std::error::Error)>> {
     home.extend(&[".osquery", "shell.em"]);
     let mut client = Client::connect(&home, Duration::from_secs_f32(3.0))?;
     debug!("ostensibly connected");
-    let table = client.register_table::<ExampleTable>()?;
-    let handle = table.start();
-    let stfu = handle.join().unwrap();
-    if let Err(e) = stfu {
-        error!("shit broke: {}", e);
-    }
-
+    let table = client.register_table(ExampleTable::new())?;
     info!("ext. {:#?}", client.extensions()?);
-    //info!("opt. {:#?}", client.options()?);
-    sleep(Duration::from_secs(30));
+    let handle = table.start()?;
+    match handle.join().unwrap() {
+        Ok(reason) => info!("server stopped: {:?}", reason),
+        Err(e) => error!("server failed: {}", e),
+    }
     Ok(())
 }
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use osquery::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::context(data);
+});
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use osquery::fuzz::{self, FuzzTable};
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::call(&FuzzTable, data);
+});
@@ -0,0 +1,84 @@
+/*
+ * C API for the osquery crate, built with its `c-api` feature. See src/capi.rs.
+ *
+ * Functions returning int return 0 on success and -1 on failure, with the reason from
+ * osquery_rs_last_error(). Strings passed in are copied; none are kept.
+ */
+#ifndef OSQUERY_RS_H
+#define OSQUERY_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define OSQUERY_RS_TEXT 0
+#define OSQUERY_RS_INTEGER 1
+#define OSQUERY_RS_BIGINT 2
+#define OSQUERY_RS_DOUBLE 3
+
+typedef struct osquery_rs_extension osquery_rs_extension;
+typedef struct osquery_rs_rows osquery_rs_rows;
+
+typedef struct osquery_rs_column {
+    const char *name;
+    int type; /* OSQUERY_RS_TEXT etc */
+} osquery_rs_column;
+
+/*
+ * Fills rows for a query, given osquery's query context as JSON. Returns 0, or anything
+ * else to fail the query (with osquery_rs_rows_error for the message). Called from
+ * whichever thread osquery's call arrives on, possibly several at once.
+ */
+typedef int (*osquery_rs_generate)(void *state, const char *context, osquery_rs_rows *rows);
+
+/*
+ * Called once when osquery shuts the extension down, on a thread of its own and
+ * alongside the other tables' shutdowns.
+ */
+typedef void (*osquery_rs_shutdown)(void *state);
+
+/* Why the last failing call on this thread failed. Good until the next one fails. */
+const char *osquery_rs_last_error(void);
+
+/* A new extension that registers with osquery as name. NULL on failure. */
+osquery_rs_extension *osquery_rs_extension_new(const char *name);
+void osquery_rs_extension_free(osquery_rs_extension *extension);
+
+/* Add a table. shutdown may be NULL. generate and shutdown get state on every call. */
+int osquery_rs_register_table(osquery_rs_extension *extension,
+                              const char *name,
+                              const osquery_rs_column *columns,
+                              size_t column_count,
+                              osquery_rs_generate generate,
+                              osquery_rs_shutdown shutdown,
+                              void *state);
+
+/*
+ * Connect to osquery's extension socket (waiting up to timeout_ms for it), register the
+ * tables and serve them until osquery shuts the extension down.
+ */
+int osquery_rs_run(const osquery_rs_extension *extension, const char *socket, uint64_t timeout_ms);
+
+/* For use inside generate. Start a row, then set its columns; unset ones are empty. */
+int osquery_rs_row_begin(osquery_rs_rows *rows);
+int osquery_rs_row_text(osquery_rs_rows *rows, const char *column, const char *value);
+int osquery_rs_row_integer(osquery_rs_rows *rows, const char *column, int64_t value);
+int osquery_rs_row_double(osquery_rs_rows *rows, const char *column, double value);
+
+/* The message osquery gets if generate returns non-zero. */
+int osquery_rs_rows_error(osquery_rs_rows *rows, const char *message);
+
+/*
+ * The index'th distinct value the query compares column to with =, or NULL once there
+ * are no more. Lives until generate returns.
+ */
+const char *osquery_rs_equals(osquery_rs_rows *rows, const char *column, size_t index);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
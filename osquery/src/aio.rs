@@ -0,0 +1,643 @@
+//! An async client for the extension manager, on tokio.
+//!
+//! The thrift crate only does blocking IO, so this writes each call into a buffer with the
+//! generated types and the binary protocol, and reads the reply back the same way once
+//! all of it has arrived. Calls on one `Client` go one at a time, like the sync one, and
+//! speak the binary protocol as strictly as `Client::set_strictness` says.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use thrift::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TMessageType, TOutputProtocol, TStructIdentifier, TType,
+};
+use thrift::{ApplicationError, ApplicationErrorKind, TransportError, TransportErrorKind};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tracing::{debug, trace};
+
+use crate::protocol::{
+    LimitedInputProtocol, NegotiatedOutputProtocol, Negotiation, MAX_METHOD_NAME,
+};
+use crate::server::MessageLimits;
+use crate::{
+    ExtensionPluginRequest, ExtensionPluginResponse, ExtensionRegistry, ExtensionRouteUUID,
+    InternalExtensionInfo, InternalExtensionList, InternalOptionInfo, InternalOptionList, Response,
+    Status, Strictness,
+};
+
+const READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub struct Client {
+    socket_path: PathBuf,
+    stream: UnixStream,
+    // reply bytes read off the socket that haven't been parsed yet
+    buffer: Vec<u8>,
+    sequence_number: i32,
+    timeout: Duration,
+    negotiation: Negotiation,
+}
+
+impl Client {
+    /// Connect to osquery's extension socket. `timeout` applies to each call; a call that
+    /// times out leaves the connection in an unknown state, so make a new client after one.
+    pub async fn connect<P: AsRef<Path>>(path: P, timeout: Duration) -> thrift::Result<Self> {
+        let stream = tokio::time::timeout(timeout, UnixStream::connect(path.as_ref()))
+            .await
+            .map_err(|_| timed_out("connect"))??;
+        debug!(path = ?path.as_ref(), "connected to extension manager");
+        Ok(Self {
+            socket_path: path.as_ref().into(),
+            stream,
+            buffer: Vec::new(),
+            sequence_number: 0,
+            timeout,
+            negotiation: Negotiation::new(Strictness::default()),
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// How strictly to speak the binary protocol with osquery from now on, as with the
+    /// sync client's `set_strictness`.
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.negotiation.set(strictness);
+    }
+
+    /// `set_strictness`, builder style.
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.set_strictness(strictness);
+        self
+    }
+
+    pub async fn ping(&mut self) -> thrift::Result<Status> {
+        self.call_method("ping", |_| Ok(()), Status::read_from_in_protocol)
+            .await
+    }
+
+    pub async fn call(
+        &mut self,
+        registry: &str,
+        item: &str,
+        request: ExtensionPluginRequest,
+    ) -> thrift::Result<Response> {
+        self.call_method(
+            "call",
+            |o| {
+                write_string_field(o, "registry", 1, registry)?;
+                write_string_field(o, "item", 2, item)?;
+                o.write_field_begin(&TFieldIdentifier::new("request", TType::Map, 3))?;
+                write_string_map(o, &request)?;
+                o.write_field_end()
+            },
+            Response::read_from_in_protocol,
+        )
+        .await
+    }
+
+    pub async fn extensions(&mut self) -> thrift::Result<InternalExtensionList> {
+        self.call_method(
+            "extensions",
+            |_| Ok(()),
+            |i| {
+                let map = i.read_map_begin()?;
+                let mut extensions = BTreeMap::new();
+                for _ in 0..map.size {
+                    let uuid = i.read_i64()?;
+                    let info = InternalExtensionInfo::read_from_in_protocol(i)?;
+                    extensions.insert(uuid, info);
+                }
+                i.read_map_end()?;
+                Ok(extensions)
+            },
+        )
+        .await
+    }
+
+    pub async fn options(&mut self) -> thrift::Result<InternalOptionList> {
+        self.call_method(
+            "options",
+            |_| Ok(()),
+            |i| {
+                let map = i.read_map_begin()?;
+                let mut options = BTreeMap::new();
+                for _ in 0..map.size {
+                    let name = i.read_string()?;
+                    let info = InternalOptionInfo::read_from_in_protocol(i)?;
+                    options.insert(name, info);
+                }
+                i.read_map_end()?;
+                Ok(options)
+            },
+        )
+        .await
+    }
+
+    pub async fn register_extension(
+        &mut self,
+        info: &InternalExtensionInfo,
+        registry: &ExtensionRegistry,
+    ) -> thrift::Result<Status> {
+        self.call_method(
+            "registerExtension",
+            |o| {
+                o.write_field_begin(&TFieldIdentifier::new("info", TType::Struct, 1))?;
+                info.write_to_out_protocol(o)?;
+                o.write_field_end()?;
+                o.write_field_begin(&TFieldIdentifier::new("registry", TType::Map, 2))?;
+                o.write_map_begin(&TMapIdentifier::new(
+                    TType::String,
+                    TType::Map,
+                    registry.len() as i32,
+                ))?;
+                for (name, routes) in registry {
+                    o.write_string(name)?;
+                    o.write_map_begin(&TMapIdentifier::new(
+                        TType::String,
+                        TType::List,
+                        routes.len() as i32,
+                    ))?;
+                    for (item, response) in routes {
+                        o.write_string(item)?;
+                        write_response(o, response)?;
+                    }
+                    o.write_map_end()?;
+                }
+                o.write_map_end()?;
+                o.write_field_end()
+            },
+            Status::read_from_in_protocol,
+        )
+        .await
+    }
+
+    pub async fn deregister_extension(
+        &mut self,
+        uuid: ExtensionRouteUUID,
+    ) -> thrift::Result<Status> {
+        self.call_method(
+            "deregisterExtension",
+            |o| {
+                o.write_field_begin(&TFieldIdentifier::new("uuid", TType::I64, 1))?;
+                o.write_i64(uuid)?;
+                o.write_field_end()
+            },
+            Status::read_from_in_protocol,
+        )
+        .await
+    }
+
+    pub async fn query(&mut self, sql: &str) -> thrift::Result<Response> {
+        self.call_method(
+            "query",
+            |o| write_string_field(o, "sql", 1, sql),
+            Response::read_from_in_protocol,
+        )
+        .await
+    }
+
+    pub async fn get_query_columns(&mut self, sql: &str) -> thrift::Result<Response> {
+        self.call_method(
+            "getQueryColumns",
+            |o| write_string_field(o, "sql", 1, sql),
+            Response::read_from_in_protocol,
+        )
+        .await
+    }
+
+    /// Push rows into an events-backed table osquery already knows about
+    pub async fn send_events(
+        &mut self,
+        name: &str,
+        events: &ExtensionPluginResponse,
+    ) -> thrift::Result<Status> {
+        self.call_method(
+            "streamEvents",
+            |o| {
+                write_string_field(o, "name", 1, name)?;
+                o.write_field_begin(&TFieldIdentifier::new("events", TType::List, 2))?;
+                write_response(o, events)?;
+                o.write_field_end()
+            },
+            Status::read_from_in_protocol,
+        )
+        .await
+    }
+
+    /// The node key osquery enrolled with. Empty if it hasn't enrolled anywhere.
+    pub async fn node_key(&mut self) -> thrift::Result<String> {
+        self.call_method("getNodeKey", |_| Ok(()), |i| i.read_string())
+            .await
+    }
+
+    async fn call_method<W, R, T>(
+        &mut self,
+        name: &str,
+        write_args: W,
+        read_result: R,
+    ) -> thrift::Result<T>
+    where
+        W: FnOnce(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+        R: Fn(&mut dyn TInputProtocol) -> thrift::Result<T>,
+    {
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        let sequence_number = self.sequence_number;
+        let request = encode_call(name, sequence_number, &self.negotiation, write_args)?;
+        let timeout = self.timeout;
+        let exchange = async {
+            self.stream.write_all(&request).await?;
+            self.stream.flush().await?;
+            trace!(method = name, bytes = request.len(), "sent call");
+            self.read_reply(name, sequence_number, &read_result).await
+        };
+        tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| timed_out(name))?
+    }
+
+    // The binary protocol doesn't say how long a message is, so `Frame` follows the
+    // reply's structure as it arrives, and it's decoded once, when it's all there.
+    async fn read_reply<R, T>(
+        &mut self,
+        name: &str,
+        sequence_number: i32,
+        read_result: &R,
+    ) -> thrift::Result<T>
+    where
+        R: Fn(&mut dyn TInputProtocol) -> thrift::Result<T>,
+    {
+        let mut chunk = vec![0; READ_CHUNK];
+        let mut frame = Frame::new();
+        loop {
+            if let Some(len) = frame.scan(&self.buffer) {
+                let mut unread: &[u8] = &self.buffer[..len];
+                let result = decode_reply(
+                    &mut unread,
+                    &self.negotiation,
+                    name,
+                    sequence_number,
+                    read_result,
+                );
+                self.buffer.drain(..len);
+                return result;
+            }
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                self.buffer.clear();
+                return Err(TransportError::new(
+                    TransportErrorKind::EndOfFile,
+                    "osquery closed the connection mid-reply",
+                )
+                .into());
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+fn encode_call<W>(
+    name: &str,
+    sequence_number: i32,
+    negotiation: &Negotiation,
+    write_args: W,
+) -> thrift::Result<Vec<u8>>
+where
+    W: FnOnce(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+{
+    let mut request = Vec::new();
+    {
+        let mut o = NegotiatedOutputProtocol::new(&mut request, negotiation.clone());
+        o.write_message_begin(&TMessageIdentifier::new(
+            name,
+            TMessageType::Call,
+            sequence_number,
+        ))?;
+        o.write_struct_begin(&TStructIdentifier::new(format!("{}_args", name)))?;
+        write_args(&mut o)?;
+        o.write_field_stop()?;
+        o.write_struct_end()?;
+        o.write_message_end()?;
+    }
+    Ok(request)
+}
+
+fn decode_reply<R, T>(
+    unread: &mut &[u8],
+    negotiation: &Negotiation,
+    name: &str,
+    sequence_number: i32,
+    read_result: &R,
+) -> thrift::Result<T>
+where
+    R: Fn(&mut dyn TInputProtocol) -> thrift::Result<T>,
+{
+    let mut i = LimitedInputProtocol::new(unread, MessageLimits::UNLIMITED, negotiation.clone());
+    let message = i.read_message_begin()?;
+    if message.sequence_number != sequence_number {
+        return Err(ApplicationError::new(
+            ApplicationErrorKind::BadSequenceId,
+            format!(
+                "expected sequence number {}, got {}",
+                sequence_number, message.sequence_number
+            ),
+        )
+        .into());
+    }
+    if message.name != name {
+        return Err(ApplicationError::new(
+            ApplicationErrorKind::WrongMethodName,
+            format!("expected a reply to `{}`, got `{}`", name, message.name),
+        )
+        .into());
+    }
+    if message.message_type == TMessageType::Exception {
+        let error = thrift::Error::read_application_error_from_in_protocol(&mut i)?;
+        i.read_message_end()?;
+        return Err(error.into());
+    }
+    if message.message_type != TMessageType::Reply {
+        return Err(ApplicationError::new(
+            ApplicationErrorKind::InvalidMessageType,
+            format!("expected a reply, got {:?}", message.message_type),
+        )
+        .into());
+    }
+
+    let mut result = None;
+    i.read_struct_begin()?;
+    loop {
+        let field = i.read_field_begin()?;
+        if field.field_type == TType::Stop {
+            break;
+        }
+        match field.id {
+            Some(0) => result = Some(read_result(&mut i)?),
+            _ => i.skip(field.field_type)?,
+        }
+        i.read_field_end()?;
+    }
+    i.read_struct_end()?;
+    i.read_message_end()?;
+    result.ok_or_else(|| {
+        ApplicationError::new(
+            ApplicationErrorKind::MissingResult,
+            format!("no result received for {}", name),
+        )
+        .into()
+    })
+}
+
+// Where a binary protocol message ends, worked out from its field headers and lengths
+// without decoding anything, and picked up where it left off as more arrives. What it
+// can't make sense of ends the frame there, for the decoder to say what's wrong.
+struct Frame {
+    // what's still to come, innermost last
+    pending: Vec<Part>,
+    // the end of everything accounted for so far
+    end: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Part {
+    Header,
+    Fields,
+    Value(u8),
+    Elements { kind: u8, left: i32 },
+    Entries { key: u8, value: u8, left: i32 },
+}
+
+impl Frame {
+    fn new() -> Self {
+        Self {
+            pending: vec![Part::Header],
+            end: 0,
+        }
+    }
+
+    // the message's length, once `buffer` has all of it
+    fn scan(&mut self, buffer: &[u8]) -> Option<usize> {
+        while let Some(part) = self.pending.pop() {
+            match self.step(part, buffer) {
+                Step::Done => {}
+                Step::Short => {
+                    self.pending.push(part);
+                    return None;
+                }
+                Step::Invalid => {
+                    self.pending.clear();
+                    return Some(buffer.len());
+                }
+            }
+        }
+        Some(self.end)
+    }
+
+    fn step(&mut self, part: Part, buffer: &[u8]) -> Step {
+        let at = self.end;
+        let byte = |offset: usize| buffer.get(at + offset).copied();
+        let i32_at = |offset: usize| {
+            let bytes = buffer.get(at + offset..at + offset + 4)?;
+            Some(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        // only moves on if all of the part is there, then `then` is what follows it
+        let take = |frame: &mut Self, len: usize, then: &[Part]| {
+            if buffer.len() < at + len {
+                return Step::Short;
+            }
+            frame.end = at + len;
+            frame.pending.extend_from_slice(then);
+            Step::Done
+        };
+        match part {
+            // strict: version, name, sequence number. otherwise name, type, sequence number
+            Part::Header => {
+                let len = match (i32_at(0), i32_at(4)) {
+                    (Some(first), _) if first >= 0 => match first as usize {
+                        // not a method name osquery would send
+                        name if name > MAX_METHOD_NAME => return Step::Invalid,
+                        name => 9 + name,
+                    },
+                    (Some(_), Some(name)) if name >= 0 => 12 + name as usize,
+                    (Some(_), Some(_)) => return Step::Invalid,
+                    _ => return Step::Short,
+                };
+                take(self, len, &[Part::Fields])
+            }
+            Part::Fields => match byte(0) {
+                Some(0) => take(self, 1, &[]),
+                Some(kind) => take(self, 3, &[Part::Fields, Part::Value(kind)]),
+                None => Step::Short,
+            },
+            Part::Value(kind) => match kind {
+                2 | 3 => take(self, 1, &[]),
+                6 => take(self, 2, &[]),
+                8 => take(self, 4, &[]),
+                4 | 10 => take(self, 8, &[]),
+                11 => match i32_at(0) {
+                    Some(len) if len >= 0 => take(self, 4 + len as usize, &[]),
+                    Some(_) => Step::Invalid,
+                    None => Step::Short,
+                },
+                12 => take(self, 0, &[Part::Fields]),
+                13 => match (byte(0), byte(1), i32_at(2)) {
+                    (Some(key), Some(value), Some(left)) if left >= 0 => {
+                        take(self, 6, &[Part::Entries { key, value, left }])
+                    }
+                    (_, _, Some(_)) => Step::Invalid,
+                    _ => Step::Short,
+                },
+                14 | 15 => match (byte(0), i32_at(1)) {
+                    (Some(kind), Some(left)) if left >= 0 => {
+                        take(self, 5, &[Part::Elements { kind, left }])
+                    }
+                    (_, Some(_)) => Step::Invalid,
+                    _ => Step::Short,
+                },
+                _ => Step::Invalid,
+            },
+            Part::Elements { kind, left } if left > 0 => {
+                let rest = Part::Elements {
+                    kind,
+                    left: left - 1,
+                };
+                take(self, 0, &[rest, Part::Value(kind)])
+            }
+            Part::Entries { key, value, left } if left > 0 => {
+                let rest = Part::Entries {
+                    key,
+                    value,
+                    left: left - 1,
+                };
+                take(self, 0, &[rest, Part::Value(value), Part::Value(key)])
+            }
+            Part::Elements { .. } | Part::Entries { .. } => Step::Done,
+        }
+    }
+}
+
+enum Step {
+    Done,
+    Short,
+    Invalid,
+}
+
+fn timed_out(what: &str) -> thrift::Error {
+    TransportError::new(
+        TransportErrorKind::TimedOut,
+        format!("`{}` timed out talking to osquery", what),
+    )
+    .into()
+}
+
+fn write_string_field(
+    o: &mut dyn TOutputProtocol,
+    name: &str,
+    id: i16,
+    value: &str,
+) -> thrift::Result<()> {
+    o.write_field_begin(&TFieldIdentifier::new(name, TType::String, id))?;
+    o.write_string(value)?;
+    o.write_field_end()
+}
+
+fn write_string_map(
+    o: &mut dyn TOutputProtocol,
+    map: &BTreeMap<String, String>,
+) -> thrift::Result<()> {
+    o.write_map_begin(&TMapIdentifier::new(
+        TType::String,
+        TType::String,
+        map.len() as i32,
+    ))?;
+    for (k, v) in map {
+        o.write_string(k)?;
+        o.write_string(v)?;
+    }
+    o.write_map_end()
+}
+
+fn write_response(
+    o: &mut dyn TOutputProtocol,
+    response: &ExtensionPluginResponse,
+) -> thrift::Result<()> {
+    o.write_list_begin(&TListIdentifier::new(TType::Map, response.len() as i32))?;
+    for row in response {
+        write_string_map(o, row)?;
+    }
+    o.write_list_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(strictness: Strictness) -> Vec<u8> {
+        let negotiation = Negotiation::new(strictness);
+        let mut reply = Vec::new();
+        let mut o = NegotiatedOutputProtocol::new(&mut reply, negotiation);
+        o.write_message_begin(&TMessageIdentifier::new("query", TMessageType::Reply, 7))
+            .unwrap();
+        o.write_struct_begin(&TStructIdentifier::new("query_result"))
+            .unwrap();
+        o.write_field_begin(&TFieldIdentifier::new("success", TType::Struct, 0))
+            .unwrap();
+        let rows = vec![
+            std::iter::once(("name".to_string(), "a".to_string())).collect(),
+            BTreeMap::new(),
+        ];
+        let response = Response::new(Status::new(0, "OK".to_string(), None), rows);
+        response.write_to_out_protocol(&mut o).unwrap();
+        o.write_field_end().unwrap();
+        o.write_field_stop().unwrap();
+        o.write_struct_end().unwrap();
+        o.write_message_end().unwrap();
+        drop(o);
+        reply
+    }
+
+    #[test]
+    fn frames_a_reply_as_it_arrives() {
+        for &strictness in &[Strictness::Strict, Strictness::Lenient] {
+            let reply = reply(strictness);
+            let mut frame = Frame::new();
+            for end in 0..reply.len() {
+                assert_eq!(
+                    frame.scan(&reply[..end]),
+                    None,
+                    "{:?} at {}",
+                    strictness,
+                    end
+                );
+            }
+            // with the start of the next one behind it
+            let mut buffer = reply.clone();
+            buffer.extend_from_slice(&[0x80, 0x01]);
+            assert_eq!(frame.scan(&buffer), Some(reply.len()));
+
+            let mut unread = &reply[..];
+            let negotiation = Negotiation::new(strictness);
+            let response = decode_reply(&mut unread, &negotiation, "query", 7, &|i| {
+                Response::read_from_in_protocol(i)
+            })
+            .unwrap();
+            assert_eq!(response.response.unwrap().len(), 2);
+            assert!(unread.is_empty());
+        }
+    }
+
+    #[test]
+    fn leaves_garbage_to_the_decoder() {
+        let garbage = [0x7f, 0xff, 0xff, 0xff, 1, 2];
+        assert_eq!(Frame::new().scan(&garbage), Some(garbage.len()));
+        let mut unread = &garbage[..];
+        let negotiation = Negotiation::new(Strictness::Auto);
+        assert!(decode_reply(&mut unread, &negotiation, "ping", 1, &|i| {
+            Status::read_from_in_protocol(i)
+        })
+        .is_err());
+    }
+}
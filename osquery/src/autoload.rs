@@ -0,0 +1,180 @@
+// The setup osqueryd needs before it'll start an extension on its own: the binary under a
+// name ending in `.ext`, that path listed in `extensions.load`, and the flags pointing at
+// it. `osquery-autoload` is the command-line front end.
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "linux")]
+const LOAD_FILE: &str = "/etc/osquery/extensions.load";
+#[cfg(target_os = "macos")]
+const LOAD_FILE: &str = "/var/osquery/extensions.load";
+#[cfg(windows)]
+const LOAD_FILE: &str = r"C:\Program Files\osquery\extensions.load";
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const LOAD_FILE: &str = "/usr/local/etc/osquery/extensions.load";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AutoloadError {
+    #[error("{path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{0:?} has no file name")]
+    NoFileName(PathBuf),
+}
+
+fn at(path: &Path) -> impl FnOnce(io::Error) -> AutoloadError + '_ {
+    move |source| AutoloadError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Where an extension gets installed for autoloading, and how.
+#[derive(Debug, Clone)]
+pub struct Autoload {
+    extension: PathBuf,
+    dir: Option<PathBuf>,
+    load_file: PathBuf,
+    copy: bool,
+    timeout: u64,
+}
+
+impl Autoload {
+    /// Autoload the compiled extension at `extension`. By default the `.ext` is a symlink
+    /// next to it and the load file is osquery's usual one for the platform.
+    pub fn new<P: AsRef<Path>>(extension: P) -> Self {
+        Self {
+            extension: extension.as_ref().into(),
+            dir: None,
+            load_file: LOAD_FILE.into(),
+            copy: !cfg!(unix),
+            timeout: 3,
+        }
+    }
+
+    /// Put the `.ext` in `dir` instead. osquery refuses extensions in directories anyone
+    /// but root (or the owner running it) can write to.
+    pub fn install_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.as_ref().into());
+        self
+    }
+
+    pub fn load_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.load_file = path.as_ref().into();
+        self
+    }
+
+    /// Copy the binary rather than symlinking it, so later rebuilds don't change what
+    /// osquery runs. Always on where there are no symlinks to speak of.
+    pub fn copy(mut self, copy: bool) -> Self {
+        self.copy = copy || !cfg!(unix);
+        self
+    }
+
+    /// Seconds osqueryd waits for the extension's socket, for `--extensions_timeout`
+    pub fn timeout(mut self, seconds: u64) -> Self {
+        self.timeout = seconds;
+        self
+    }
+
+    /// The `.ext` path osquery will be told about.
+    pub fn target(&self) -> Result<PathBuf, AutoloadError> {
+        let stem = self
+            .extension
+            .file_stem()
+            .ok_or_else(|| AutoloadError::NoFileName(self.extension.clone()))?;
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => self
+                .extension
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        };
+        let mut name = stem.to_os_string();
+        name.push(".ext");
+        Ok(dir.join(name))
+    }
+
+    /// Write the `.ext`, replacing whatever was there. Returns its path.
+    pub fn write_ext(&self) -> Result<PathBuf, AutoloadError> {
+        let target = self.target()?;
+        // osquery resolves the load file's entries as written, so keep them absolute
+        let extension = fs::canonicalize(&self.extension).map_err(at(&self.extension))?;
+        let target = match target.parent().filter(|p| !p.as_os_str().is_empty()) {
+            Some(dir) => fs::canonicalize(dir)
+                .map_err(at(dir))?
+                .join(target.file_name().unwrap_or_default()),
+            None => std::env::current_dir().map_err(at(&target))?.join(&target),
+        };
+        if target == extension {
+            return Ok(target);
+        }
+        match fs::remove_file(&target) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(at(&target)(e)),
+            _ => {}
+        }
+        if self.copy {
+            fs::copy(&extension, &target).map_err(at(&target))?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&target, fs::Permissions::from_mode(0o755))
+                    .map_err(at(&target))?;
+            }
+        } else {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&extension, &target).map_err(at(&target))?;
+        }
+        Ok(target)
+    }
+
+    /// Add `ext` to the load file unless it's already listed, creating the file if need be.
+    /// Returns whether it was added.
+    pub fn append_load(&self, ext: &Path) -> Result<bool, AutoloadError> {
+        let existing = match fs::read_to_string(&self.load_file) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(at(&self.load_file)(e)),
+        };
+        let line = ext.to_string_lossy();
+        if existing.lines().any(|l| l.trim() == line) {
+            return Ok(false);
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.load_file)
+            .map_err(at(&self.load_file))?;
+        // don't glue our line onto the end of someone else's
+        let separator = if existing.is_empty() || existing.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        writeln!(file, "{}{}", separator, line).map_err(at(&self.load_file))?;
+        Ok(true)
+    }
+
+    /// `write_ext` then `append_load`. Returns the `.ext` path.
+    pub fn install(&self) -> Result<PathBuf, AutoloadError> {
+        let target = self.write_ext()?;
+        self.append_load(&target)?;
+        Ok(target)
+    }
+
+    /// The flags osqueryd needs to autoload from this load file, one per line, ready to
+    /// drop into a flagfile.
+    pub fn flagfile(&self) -> String {
+        // osqueryd won't be started from our working directory
+        let load_file = match std::env::current_dir() {
+            Ok(cwd) if self.load_file.is_relative() => cwd.join(&self.load_file),
+            _ => self.load_file.clone(),
+        };
+        format!(
+            "--disable_extensions=false\n--extensions_autoload={}\n--extensions_timeout={}\n",
+            load_file.display(),
+            self.timeout
+        )
+    }
+}
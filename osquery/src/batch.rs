@@ -0,0 +1,108 @@
+// Several queries in one round trip: every request goes out before any answer is read,
+// so a tick that polls five tables waits on the socket once instead of five times.
+use thrift::protocol::{
+    verify_expected_message_type, verify_expected_sequence_number, verify_expected_service_call,
+    TFieldIdentifier, TMessageIdentifier, TMessageType, TStructIdentifier, TType,
+};
+use thrift::TThriftClient;
+
+use crate::{Client, Code, Connector, ExtensionResponse, PluginResponse};
+
+/// One statement of a batch that didn't work. The rest of the batch isn't affected.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("query {index} failed ({code:?}): {message}")]
+pub struct BatchFailure {
+    /// Position of the statement in the batch
+    pub index: usize,
+    /// `None` for an exception, or a status without a code this crate knows
+    pub code: Option<Code>,
+    pub message: String,
+}
+
+impl<C: Connector> Client<C> {
+    /// Run each statement and return their results in the same order. A statement
+    /// osquery refuses fails on its own; only a broken connection fails the whole batch,
+    /// and leaves the client unusable like any other transport error.
+    pub fn query_batch<S: AsRef<str>>(
+        &mut self,
+        statements: &[S],
+    ) -> thrift::Result<Vec<Result<PluginResponse, BatchFailure>>> {
+        let client = &mut **self;
+        let first = client.sequence_number() + 1;
+        for sql in statements {
+            let sequence = client.increment_sequence_number();
+            let out = client.o_prot_mut();
+            out.write_message_begin(&TMessageIdentifier::new(
+                "query",
+                TMessageType::Call,
+                sequence,
+            ))?;
+            out.write_struct_begin(&TStructIdentifier::new("query_args"))?;
+            out.write_field_begin(&TFieldIdentifier::new("sql", TType::String, 1))?;
+            out.write_string(sql.as_ref())?;
+            out.write_field_end()?;
+            out.write_field_stop()?;
+            out.write_struct_end()?;
+            out.write_message_end()?;
+        }
+        client.o_prot_mut().flush()?;
+
+        let mut results = Vec::with_capacity(statements.len());
+        for (index, sequence) in (first..).take(statements.len()).enumerate() {
+            let input = client.i_prot_mut();
+            let message = input.read_message_begin()?;
+            verify_expected_sequence_number(sequence, message.sequence_number)?;
+            verify_expected_service_call("query", &message.name)?;
+            if message.message_type == TMessageType::Exception {
+                let error = thrift::Error::read_application_error_from_in_protocol(input)?;
+                input.read_message_end()?;
+                results.push(Err(BatchFailure {
+                    index,
+                    code: None,
+                    message: error.message,
+                }));
+                continue;
+            }
+            verify_expected_message_type(TMessageType::Reply, message.message_type)?;
+            let response = read_query_result(input)?;
+            input.read_message_end()?;
+            let status = response.status.unwrap_or_default();
+            results.push(if status.is_success() {
+                Ok(response.response.unwrap_or_default())
+            } else {
+                Err(BatchFailure {
+                    index,
+                    code: status.code(),
+                    message: status.message.unwrap_or_default(),
+                })
+            });
+        }
+        Ok(results)
+    }
+}
+
+// the reply struct of `query`, field 0 holding the response
+fn read_query_result(
+    input: &mut dyn thrift::protocol::TInputProtocol,
+) -> thrift::Result<ExtensionResponse> {
+    input.read_struct_begin()?;
+    let mut response = None;
+    loop {
+        let field = input.read_field_begin()?;
+        if field.field_type == TType::Stop {
+            break;
+        }
+        match field.id {
+            Some(0) => response = Some(ExtensionResponse::read_from_in_protocol(input)?),
+            _ => input.skip(field.field_type)?,
+        }
+        input.read_field_end()?;
+    }
+    input.read_struct_end()?;
+    response.ok_or_else(|| {
+        thrift::Error::Application(thrift::ApplicationError::new(
+            thrift::ApplicationErrorKind::MissingResult,
+            "no result received for query",
+        ))
+    })
+}
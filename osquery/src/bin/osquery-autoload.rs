@@ -0,0 +1,58 @@
+// Set a compiled extension up for osqueryd to autoload, and print the flags to go with it.
+//
+//   osquery-autoload target/release/my_ext
+//   osquery-autoload --copy --dir /usr/local/osquery/extensions target/release/my_ext
+//   osquery-autoload --flags-only --load-file /tmp/extensions.load my_ext >> osquery.flags
+use osquery::autoload::Autoload;
+
+const USAGE: &str = "usage: osquery-autoload [--dir DIR] [--load-file FILE] [--timeout SECS] \
+                     [--copy] [--flags-only] <extension>";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (mut dir, mut load_file, mut timeout) = (None, None, None);
+    let (mut copy, mut flags_only, mut extension) = (false, false, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = Some(args.next().unwrap_or_else(|| usage())),
+            "--load-file" => load_file = Some(args.next().unwrap_or_else(|| usage())),
+            "--timeout" => {
+                let secs = args.next().and_then(|s| s.parse().ok());
+                timeout = Some(secs.unwrap_or_else(|| usage()));
+            }
+            "--copy" => copy = true,
+            "--flags-only" => flags_only = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') || extension.is_some() => usage(),
+            _ => extension = Some(arg),
+        }
+    }
+    let mut autoload = Autoload::new(extension.unwrap_or_else(|| usage())).copy(copy);
+    if let Some(dir) = dir {
+        autoload = autoload.install_dir(dir);
+    }
+    if let Some(load_file) = load_file {
+        autoload = autoload.load_file(load_file);
+    }
+    if let Some(timeout) = timeout {
+        autoload = autoload.timeout(timeout);
+    }
+    if !flags_only {
+        match autoload.install() {
+            Ok(target) => eprintln!("installed {}", target.display()),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    print!("{}", autoload.flagfile());
+}
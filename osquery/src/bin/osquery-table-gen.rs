@@ -0,0 +1,32 @@
+// Print a Rust table skeleton for an osquery `.table` spec or `CREATE TABLE` statement.
+//
+//   osquery-table-gen specs/processes.table > src/processes.rs
+//   echo 'CREATE TABLE foo(bar TEXT)' | osquery-table-gen -
+use std::io::Read;
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: osquery-table-gen <spec file, or - for stdin>");
+            std::process::exit(2);
+        }
+    };
+    let mut source = String::new();
+    let read = if path == "-" {
+        std::io::stdin().read_to_string(&mut source).map(|_| ())
+    } else {
+        std::fs::read_to_string(&path).map(|s| source = s)
+    };
+    if let Err(e) = read {
+        eprintln!("couldn't read {}: {}", path, e);
+        std::process::exit(1);
+    }
+    match osquery::codegen::parse(&source) {
+        Ok(spec) => print!("{}", spec.to_rust()),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// A little free-list of byte buffers so that every new connection (and every
+// big response on an existing one) doesn't have to grow fresh transport buffers
+// from nothing. Buffers go back to the pool when the transport using them drops.
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    capacity: usize,
+    max_pooled: usize,
+}
+
+impl BufferPool {
+    pub(crate) fn new(capacity: usize, max_pooled: usize) -> Arc<Self> {
+        Arc::new(Self {
+            buffers: Mutex::new(Vec::new()),
+            capacity: capacity.max(1),
+            max_pooled,
+        })
+    }
+
+    fn take(&self) -> Vec<u8> {
+        self.buffers
+            .lock()
+            .ok()
+            .and_then(|mut b| b.pop())
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity))
+    }
+
+    fn give(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.max_pooled {
+                buffers.push(buf);
+            }
+        }
+    }
+}
+
+pub(crate) struct PooledReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    pool: Arc<BufferPool>,
+}
+
+impl<R: Read> PooledReader<R> {
+    pub(crate) fn new(inner: R, pool: Arc<BufferPool>) -> Self {
+        let mut buf = pool.take();
+        buf.resize(pool.capacity, 0);
+        Self {
+            inner,
+            buf,
+            pos: 0,
+            filled: 0,
+            pool,
+        }
+    }
+}
+
+impl<R> PooledReader<R> {
+    // read off the socket but not handed out yet
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn buffered(&self) -> usize {
+        self.filled - self.pos
+    }
+}
+
+impl<R: Read> Read for PooledReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.filled {
+            // nothing buffered and the caller wants more than we'd hold anyway, skip the copy
+            if out.len() >= self.buf.len() {
+                return self.inner.read(out).map_err(timed_out);
+            }
+            self.filled = self.inner.read(&mut self.buf).map_err(timed_out)?;
+            self.pos = 0;
+        }
+        let available = &self.buf[self.pos..self.filled];
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<R> Drop for PooledReader<R> {
+    fn drop(&mut self) {
+        self.pool.give(std::mem::take(&mut self.buf));
+    }
+}
+
+pub(crate) struct PooledWriter<W> {
+    inner: W,
+    buf: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl<W: Write> PooledWriter<W> {
+    pub(crate) fn new(inner: W, pool: Arc<BufferPool>) -> Self {
+        Self {
+            inner,
+            buf: pool.take(),
+            pool,
+        }
+    }
+
+    fn flush_buf(&mut self) -> std::io::Result<()> {
+        self.inner.write_all(&self.buf).map_err(timed_out)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for PooledWriter<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.buf.len() + data.len() > self.pool.capacity {
+            self.flush_buf()?;
+        }
+        if data.len() >= self.pool.capacity {
+            return self.inner.write(data).map_err(timed_out);
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_buf()?;
+        self.inner.flush().map_err(timed_out)
+    }
+}
+
+impl<W> Drop for PooledWriter<W> {
+    fn drop(&mut self) {
+        self.pool.give(std::mem::take(&mut self.buf));
+    }
+}
+
+// adds up the bytes that go through, for the connection's stats when it closes
+pub(crate) struct Counted<S> {
+    inner: S,
+    count: Arc<AtomicU64>,
+}
+
+impl<S> Counted<S> {
+    pub(crate) fn new(inner: S) -> (Self, Arc<AtomicU64>) {
+        let count = Arc::new(AtomicU64::new(0));
+        (
+            Self {
+                inner,
+                count: count.clone(),
+            },
+            count,
+        )
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(out)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(data)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// a socket timeout comes back as WouldBlock (EAGAIN), which thrift lumps in with every
+// other unknown error. Call it what it is so the connection loop can tell.
+fn timed_out(e: io::Error) -> io::Error {
+    if e.kind() == ErrorKind::WouldBlock {
+        io::Error::new(ErrorKind::TimedOut, e)
+    } else {
+        e
+    }
+}
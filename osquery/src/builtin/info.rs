@@ -0,0 +1,161 @@
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use maplit::btreemap;
+
+use crate::{
+    Column, ColumnValue, ExtensionRouteUUID, Plugin, QueryContext, TablePlugin, TableRows,
+};
+
+static REGISTRATIONS: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone)]
+struct Registration {
+    name: String,
+    uuid: ExtensionRouteUUID,
+    socket_path: PathBuf,
+    columns: Vec<Column>,
+}
+
+// called from Plugin::install once osquery has handed back a uuid
+pub(crate) fn record_registration(
+    name: &str,
+    uuid: ExtensionRouteUUID,
+    socket_path: &Path,
+    columns: Vec<Column>,
+) {
+    let mut registrations = match REGISTRATIONS.lock() {
+        Ok(r) => r,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    registrations.push(Registration {
+        name: name.to_string(),
+        uuid,
+        socket_path: socket_path.into(),
+        columns,
+    });
+}
+
+// called once a registration is withdrawn, e.g. to re-register a reloaded table
+pub(crate) fn forget_registration(uuid: ExtensionRouteUUID) {
+    let mut registrations = match REGISTRATIONS.lock() {
+        Ok(r) => r,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    registrations.retain(|r| r.uuid != uuid);
+}
+
+// how many plugins osquery has accepted so far
+pub(crate) fn registrations() -> usize {
+    match REGISTRATIONS.lock() {
+        Ok(r) => r.len(),
+        Err(poisoned) => poisoned.into_inner().len(),
+    }
+}
+
+// (table, uuid, socket) for each registration, for the state dump
+pub(crate) fn registration_list() -> Vec<(String, ExtensionRouteUUID, PathBuf)> {
+    let registrations = match REGISTRATIONS.lock() {
+        Ok(r) => r.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+    registrations
+        .into_iter()
+        .map(|r| (r.name, r.uuid, r.socket_path))
+        .collect()
+}
+
+// each registered table's columns, for `extension_schema`
+pub(crate) fn registered_columns() -> Vec<(String, Vec<Column>)> {
+    let registrations = match REGISTRATIONS.lock() {
+        Ok(r) => r.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+    registrations
+        .into_iter()
+        .map(|r| (r.name, r.columns))
+        .collect()
+}
+
+/// `extension_info`: one row per table this process has registered with osquery, along
+/// with the uuid and socket it's served on and what the extension was built with.
+#[derive(Debug)]
+pub struct InfoTable {
+    extension: String,
+    version: String,
+    git_sha: String,
+}
+
+impl InfoTable {
+    /// Report the extension's own name and version rather than the osquery crate's.
+    pub fn with_extension<S: Into<String>, V: Into<String>>(mut self, name: S, version: V) -> Self {
+        self.extension = name.into();
+        self.version = version.into();
+        self
+    }
+
+    /// Override the git sha, e.g. with one baked into the extension binary's own build.
+    pub fn with_git_sha<S: Into<String>>(mut self, sha: S) -> Self {
+        self.git_sha = sha.into();
+        self
+    }
+}
+
+impl Default for InfoTable {
+    fn default() -> Self {
+        Self {
+            extension: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: env!("OSQUERY_RS_GIT_SHA").to_string(),
+        }
+    }
+}
+
+impl Plugin for InfoTable {
+    type Error = Infallible;
+    const NAME: &'static str = "extension_info";
+
+    fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TablePlugin for InfoTable {
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let registrations = match REGISTRATIONS.lock() {
+            Ok(r) => r.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        Ok(registrations
+            .into_iter()
+            .map(|r| {
+                btreemap! {
+                    "name".to_string() => ColumnValue::text(self.extension.as_str()),
+                    "version".to_string() => ColumnValue::text(self.version.as_str()),
+                    "sdk_version".to_string() => ColumnValue::text(env!("CARGO_PKG_VERSION")),
+                    "table_name".to_string() => ColumnValue::text(r.name),
+                    "uuid".to_string() => ColumnValue::big_int(r.uuid),
+                    "socket_path".to_string() => ColumnValue::text(r.socket_path.to_string_lossy()),
+                    "git_sha".to_string() => ColumnValue::text(self.git_sha.as_str()),
+                    "rustc_version".to_string() => ColumnValue::text(env!("OSQUERY_RS_RUSTC_VERSION")),
+                }
+            })
+            .collect())
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(vec![
+            Column::text("name"),
+            Column::text("version"),
+            Column::text("sdk_version"),
+            Column::text("table_name"),
+            Column::big_int("uuid"),
+            Column::text("socket_path"),
+            Column::text("git_sha"),
+            Column::text("rustc_version"),
+        ])
+    }
+
+    fn shutdown(&self) {}
+}
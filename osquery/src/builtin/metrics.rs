@@ -0,0 +1,64 @@
+use std::convert::Infallible;
+
+use maplit::btreemap;
+
+use crate::{metrics, Column, ColumnValue, Plugin, QueryContext, TablePlugin, TableRows};
+
+/// `extension_metrics`: one row per table this process has served, with call counts,
+/// error counts and latency percentiles, plus process-wide connection count and uptime.
+#[derive(Debug, Default)]
+pub struct MetricsTable;
+
+impl Plugin for MetricsTable {
+    type Error = Infallible;
+    const NAME: &'static str = "extension_metrics";
+
+    fn new() -> Self {
+        MetricsTable
+    }
+}
+
+impl TablePlugin for MetricsTable {
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let snapshot = metrics::global().snapshot();
+        let uptime = snapshot.uptime.as_secs() as i64;
+        let active = snapshot.active_connections as i64;
+        if snapshot.tables.is_empty() {
+            return Ok(vec![btreemap! {
+                "active_connections".to_string() => ColumnValue::big_int(active),
+                "uptime".to_string() => ColumnValue::big_int(uptime),
+            }]);
+        }
+        Ok(snapshot
+            .tables
+            .into_iter()
+            .map(|t| {
+                btreemap! {
+                    "table_name".to_string() => ColumnValue::text(t.name),
+                    "calls".to_string() => ColumnValue::big_int(t.calls as i64),
+                    "errors".to_string() => ColumnValue::big_int(t.errors as i64),
+                    "p50_ms".to_string() => ColumnValue::double(t.p50.as_secs_f64() * 1000.0),
+                    "p90_ms".to_string() => ColumnValue::double(t.p90.as_secs_f64() * 1000.0),
+                    "p99_ms".to_string() => ColumnValue::double(t.p99.as_secs_f64() * 1000.0),
+                    "active_connections".to_string() => ColumnValue::big_int(active),
+                    "uptime".to_string() => ColumnValue::big_int(uptime),
+                }
+            })
+            .collect())
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(vec![
+            Column::text("table_name"),
+            Column::big_int("calls"),
+            Column::big_int("errors"),
+            Column::double("p50_ms"),
+            Column::double("p90_ms"),
+            Column::double("p99_ms"),
+            Column::big_int("active_connections"),
+            Column::big_int("uptime"),
+        ])
+    }
+
+    fn shutdown(&self) {}
+}
@@ -0,0 +1,15 @@
+// Tables the crate can register on its own, alongside whatever the extension provides.
+// None of these are registered unless you ask for them.
+mod info;
+mod metrics;
+mod recent;
+mod schema;
+
+pub use info::InfoTable;
+pub(crate) use info::{
+    forget_registration, record_registration, registered_columns, registration_list, registrations,
+};
+pub use metrics::MetricsTable;
+pub(crate) use recent::record_generate;
+pub use recent::RecentQueriesTable;
+pub use schema::SchemaTable;
@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use maplit::btreemap;
+
+use crate::{Column, ColumnValue, Plugin, QueryContext, Response, TablePlugin, TableRows};
+
+// nothing is kept until a RecentQueriesTable asks for it
+static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+static RECENT: Mutex<VecDeque<RecentQuery>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone)]
+struct RecentQuery {
+    table: String,
+    constraints: String,
+    time: SystemTime,
+    duration: Duration,
+    rows: usize,
+    status: String,
+}
+
+// called by the table dispatcher after every generate
+pub(crate) fn record_generate(
+    table: &str,
+    query: &QueryContext,
+    duration: Duration,
+    result: &thrift::Result<Response>,
+) {
+    let capacity = CAPACITY.load(Ordering::Relaxed);
+    if capacity == 0 {
+        return;
+    }
+    let (rows, status) = match result {
+        Ok(response) if response.is_success() => (
+            response.response.as_ref().map_or(0, Vec::len),
+            "ok".to_string(),
+        ),
+        Ok(response) => (
+            0,
+            response
+                .status
+                .as_ref()
+                .and_then(|s| s.message.clone())
+                .unwrap_or_else(|| "failed".to_string()),
+        ),
+        Err(e) => (0, e.to_string()),
+    };
+    let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    while recent.len() >= capacity {
+        recent.pop_front();
+    }
+    recent.push_back(RecentQuery {
+        table: table.to_string(),
+        constraints: query.constraint_summary(),
+        time: SystemTime::now(),
+        duration,
+        rows,
+        status,
+    });
+}
+
+/// `extension_recent_queries`: the last `generate` calls this process served, oldest
+/// first, with the table, its constraints, how long it took, how many rows went back and
+/// whether it worked. Calls are only kept once this table has been created.
+#[derive(Debug)]
+pub struct RecentQueriesTable;
+
+impl RecentQueriesTable {
+    /// Keep the last `capacity` calls, 100 by default.
+    pub fn with_capacity(capacity: usize) -> Self {
+        CAPACITY.store(capacity.max(1), Ordering::Relaxed);
+        RecentQueriesTable
+    }
+}
+
+impl Plugin for RecentQueriesTable {
+    type Error = Infallible;
+    const NAME: &'static str = "extension_recent_queries";
+
+    fn new() -> Self {
+        Self::with_capacity(100)
+    }
+}
+
+impl TablePlugin for RecentQueriesTable {
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let recent = RECENT.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        Ok(recent
+            .into_iter()
+            .map(|q| {
+                let time = q.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                btreemap! {
+                    "table_name".to_string() => ColumnValue::text(q.table),
+                    "constraints".to_string() => ColumnValue::text(q.constraints),
+                    "time".to_string() => ColumnValue::big_int(time.as_secs() as i64),
+                    "duration_ms".to_string() => ColumnValue::double(q.duration.as_secs_f64() * 1000.0),
+                    "rows".to_string() => ColumnValue::big_int(q.rows as i64),
+                    "status".to_string() => ColumnValue::text(q.status),
+                }
+            })
+            .collect())
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(vec![
+            Column::text("table_name"),
+            Column::text("constraints"),
+            Column::big_int("time"),
+            Column::double("duration_ms"),
+            Column::big_int("rows"),
+            Column::text("status"),
+        ])
+    }
+
+    fn shutdown(&self) {}
+}
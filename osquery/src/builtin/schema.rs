@@ -0,0 +1,63 @@
+use std::convert::Infallible;
+
+use maplit::btreemap;
+
+use crate::{Column, ColumnOptions, ColumnValue, Plugin, QueryContext, TablePlugin, TableRows};
+
+/// `extension_schema`: one row per column of every table this process has registered,
+/// with its type, description and platforms, so the tables can be looked up from
+/// osquery the way `.schema` does for built-in ones.
+#[derive(Debug, Default)]
+pub struct SchemaTable;
+
+impl Plugin for SchemaTable {
+    type Error = Infallible;
+    const NAME: &'static str = "extension_schema";
+
+    fn new() -> Self {
+        SchemaTable
+    }
+}
+
+impl TablePlugin for SchemaTable {
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let flag = |c: &Column, option| ColumnValue::integer(c.options.contains(option) as i32);
+        Ok(super::registered_columns()
+            .into_iter()
+            .flat_map(|(table, columns)| {
+                columns.into_iter().map(move |c| {
+                    let mut row = btreemap! {
+                        "table_name".to_string() => ColumnValue::text(table.as_str()),
+                        "name".to_string() => ColumnValue::text(c.name.as_str()),
+                        "type".to_string() => ColumnValue::text(c.kind.to_string()),
+                        "required".to_string() => flag(&c, ColumnOptions::REQUIRED),
+                        "index".to_string() => flag(&c, ColumnOptions::INDEX),
+                        "hidden".to_string() => flag(&c, ColumnOptions::HIDDEN),
+                    };
+                    if let Some(description) = c.description {
+                        row.insert("description".to_string(), ColumnValue::text(description));
+                    }
+                    if let Some(platform) = c.platform {
+                        row.insert("platform".to_string(), ColumnValue::text(platform));
+                    }
+                    row
+                })
+            })
+            .collect())
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(vec![
+            Column::text("table_name").describe("Table the column belongs to"),
+            Column::text("name").describe("Column name"),
+            Column::text("type").describe("TEXT, INTEGER, BIGINT or DOUBLE"),
+            Column::text("description").describe("What the column holds, if documented"),
+            Column::text("platform").describe("Platforms the column is filled in on, if not all"),
+            Column::integer("required").describe("1 if queries need an `=` constraint on it"),
+            Column::integer("index").describe("1 if the column is indexed"),
+            Column::integer("hidden").describe("1 if left out of `SELECT *`"),
+        ])
+    }
+
+    fn shutdown(&self) {}
+}
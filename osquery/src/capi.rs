@@ -0,0 +1,495 @@
+// A C API over the runtime, for tables written in C, C++, Zig or anything else that can
+// call C. This crate does the registering, the socket and thrift; the other side only
+// provides columns and a `generate` callback that fills in rows. `include/osquery_rs.h`
+// declares all of it.
+//
+// To get a library to link against, build a `staticlib` (or `cdylib`) crate that depends
+// on this one with the `c-api` feature and has `pub use osquery::capi::*;`.
+//
+// Functions returning `int` return 0 on success and -1 on failure, with the reason from
+// `osquery_rs_last_error`. Strings passed in are copied; none are kept.
+#![allow(non_camel_case_types)]
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::gen::table::ColumnType;
+use crate::multi_table::{generate_query, multi_table_plugin, MultiTable, ServedTable};
+use crate::{
+    Client, Column, ColumnValue, ExtensionPluginRequest, ExtensionPluginResponse, Plugin,
+    QueryContext, Response, RowSet,
+};
+
+pub const OSQUERY_RS_TEXT: c_int = 0;
+pub const OSQUERY_RS_INTEGER: c_int = 1;
+pub const OSQUERY_RS_BIGINT: c_int = 2;
+pub const OSQUERY_RS_DOUBLE: c_int = 3;
+
+/// A column as C describes it, `type` being one of the `OSQUERY_RS_*` constants.
+#[repr(C)]
+pub struct osquery_rs_column {
+    pub name: *const c_char,
+    pub r#type: c_int,
+}
+
+/// Fills `rows` for a query, given osquery's query context as JSON. Returns 0, or
+/// anything else to fail the query (with `osquery_rs_rows_error` for the message).
+/// Called from whichever thread osquery's call arrives on, possibly several at once.
+pub type osquery_rs_generate = unsafe extern "C" fn(
+    state: *mut c_void,
+    context: *const c_char,
+    rows: *mut osquery_rs_rows,
+) -> c_int;
+
+/// Called once when osquery shuts the extension down, on a thread of its own and
+/// alongside the other tables' shutdowns.
+pub type osquery_rs_shutdown = unsafe extern "C" fn(state: *mut c_void);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn fail<E: ToString>(error: E) -> c_int {
+    let message = CString::new(error.to_string().replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    -1
+}
+
+// safe as long as the caller kept to the header: NULL or a nul-terminated string
+unsafe fn text<'a>(s: *const c_char, what: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", what));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| format!("{} isn't UTF-8: {}", what, e))
+}
+
+pub(crate) struct CTable {
+    columns: Vec<Column>,
+    routes: ExtensionPluginResponse,
+    generate: osquery_rs_generate,
+    shutdown: Option<osquery_rs_shutdown>,
+    state: *mut c_void,
+}
+
+// safe: the header requires generate to be callable from any thread
+unsafe impl Send for CTable {}
+unsafe impl Sync for CTable {}
+
+/// The tables one extension offers. Made by `osquery_rs_extension_new`.
+#[derive(Clone)]
+pub struct osquery_rs_extension {
+    name: String,
+    tables: BTreeMap<String, Arc<CTable>>,
+}
+
+impl fmt::Debug for osquery_rs_extension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("osquery_rs_extension")
+            .field("name", &self.name)
+            .field("tables", &self.tables.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Rows being built by a `generate` callback. Only valid until it returns.
+pub struct osquery_rs_rows {
+    set: RowSet,
+    index: BTreeMap<String, usize>,
+    row: Option<Vec<Option<ColumnValue>>>,
+    query: QueryContext,
+    // what `osquery_rs_equals` has handed out, kept alive until generate returns
+    equals: BTreeMap<String, Vec<CString>>,
+    error: Option<String>,
+}
+
+impl osquery_rs_rows {
+    fn new(columns: &[Column], query: QueryContext) -> Self {
+        Self {
+            set: RowSet::new(columns),
+            index: columns
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (c.name.clone(), i))
+                .collect(),
+            row: None,
+            query,
+            equals: BTreeMap::new(),
+            error: None,
+        }
+    }
+
+    fn finish_row(&mut self) {
+        if let Some(row) = self.row.take() {
+            // always the schema's width, so this can't fail
+            let _ = self.set.push_partial(row);
+        }
+    }
+
+    fn set(&mut self, column: *const c_char, value: ColumnValue) -> c_int {
+        // safe: see `text`
+        let column = match unsafe { text(column, "column") } {
+            Ok(column) => column,
+            Err(e) => return fail(e),
+        };
+        let i = match self.index.get(column) {
+            Some(&i) => i,
+            None => return fail(format!("no column `{}`", column)),
+        };
+        match self.row.as_mut() {
+            Some(row) => row[i] = Some(value),
+            None => return fail("no row started, call osquery_rs_row_begin first"),
+        }
+        0
+    }
+}
+
+impl ServedTable for CTable {
+    fn routes(&self) -> &ExtensionPluginResponse {
+        &self.routes
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn shutdown(&self) -> Result<(), String> {
+        if let Some(shutdown) = self.shutdown {
+            // safe: as for generate
+            unsafe { shutdown(self.state) };
+        }
+        Ok(())
+    }
+}
+
+impl osquery_rs_extension {
+    fn named(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            tables: BTreeMap::new(),
+        }
+    }
+}
+
+impl MultiTable for osquery_rs_extension {
+    type Table = CTable;
+
+    fn extension_name(&self) -> &str {
+        &self.name
+    }
+
+    fn table(&self, name: &str) -> Option<Arc<CTable>> {
+        self.tables.get(name).cloned()
+    }
+
+    fn tables_by_name(&self) -> Vec<(String, Arc<CTable>)> {
+        self.tables
+            .iter()
+            .map(|(name, table)| (name.clone(), table.clone()))
+            .collect()
+    }
+
+    fn call(&self, item: &str, table: &CTable, request: ExtensionPluginRequest) -> Response {
+        // C gets the context as osquery sent it
+        let context = request.get("context").cloned().unwrap_or_default();
+        let query = match generate_query(item, &table.columns, request) {
+            Ok(query) => query,
+            Err(response) => return response,
+        };
+        let context = CString::new(context).unwrap_or_default();
+        let mut rows = osquery_rs_rows::new(&table.columns, query);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            // safe: the callback and its state came from `osquery_rs_register_table`
+            unsafe { (table.generate)(table.state, context.as_ptr(), &mut rows) }
+        }));
+        match result {
+            Ok(0) => {
+                rows.finish_row();
+                Response::success(rows.set.into_response())
+            }
+            Ok(code) => Response::failure(
+                rows.error
+                    .unwrap_or_else(|| format!("`{}` failed with {}", item, code)),
+            ),
+            Err(_) => Response::failure(format!("`{}` panicked", item)),
+        }
+    }
+}
+
+multi_table_plugin!(
+    osquery_rs_extension,
+    "c_extension",
+    osquery_rs_extension::named
+);
+
+/// Why the last call on this thread that returned -1 (or NULL) failed. Owned by the
+/// library, and only good until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn osquery_rs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// A new extension that registers with osquery as `name`. NULL on failure.
+///
+/// # Safety
+/// `name` must be NULL or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_extension_new(
+    name: *const c_char,
+) -> *mut osquery_rs_extension {
+    match text(name, "name") {
+        Ok(name) => Box::into_raw(Box::new(osquery_rs_extension::named(name))),
+        Err(e) => {
+            fail(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `extension` must be NULL or from `osquery_rs_extension_new`, and not freed already.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_extension_free(extension: *mut osquery_rs_extension) {
+    if !extension.is_null() {
+        drop(Box::from_raw(extension));
+    }
+}
+
+/// Add a table called `name` with `column_count` columns to `extension`. `generate` gets
+/// `state` on every call, and so does `shutdown` (which may be NULL).
+///
+/// # Safety
+/// `extension` must be from `osquery_rs_extension_new`, `columns` must point to
+/// `column_count` columns, and `generate` and `shutdown` must be safe to call from any
+/// thread with `state` for as long as the extension runs.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_register_table(
+    extension: *mut osquery_rs_extension,
+    name: *const c_char,
+    columns: *const osquery_rs_column,
+    column_count: usize,
+    generate: Option<osquery_rs_generate>,
+    shutdown: Option<osquery_rs_shutdown>,
+    state: *mut c_void,
+) -> c_int {
+    let extension = match extension.as_mut() {
+        Some(extension) => extension,
+        None => return fail("extension is NULL"),
+    };
+    let name = match text(name, "name") {
+        Ok(name) => name,
+        Err(e) => return fail(e),
+    };
+    let generate = match generate {
+        Some(generate) => generate,
+        None => return fail("generate is NULL"),
+    };
+    if columns.is_null() || column_count == 0 {
+        return fail(format!("table `{}` has no columns", name));
+    }
+    let mut schema = Vec::with_capacity(column_count);
+    for column in std::slice::from_raw_parts(columns, column_count) {
+        let column_name = match text(column.name, "column name") {
+            Ok(column_name) => column_name,
+            Err(e) => return fail(e),
+        };
+        let kind = match column.r#type {
+            OSQUERY_RS_TEXT => ColumnType::Text,
+            OSQUERY_RS_INTEGER => ColumnType::Integer,
+            OSQUERY_RS_BIGINT => ColumnType::BigInt,
+            OSQUERY_RS_DOUBLE => ColumnType::Double,
+            other => {
+                return fail(format!(
+                    "column `{}` has unknown type {}",
+                    column_name, other
+                ))
+            }
+        };
+        schema.push(Column::new(column_name, kind));
+    }
+    if extension.tables.contains_key(name) {
+        return fail(format!("table `{}` is already registered", name));
+    }
+    let routes = match crate::table_routes(name, &schema, vec![]) {
+        Ok(routes) => routes,
+        Err(e) => return fail(e),
+    };
+    extension.tables.insert(
+        name.to_string(),
+        Arc::new(CTable {
+            columns: schema,
+            routes,
+            generate,
+            shutdown,
+            state,
+        }),
+    );
+    0
+}
+
+/// Connect to osquery's extension socket at `socket` (waiting up to `timeout_ms` for it),
+/// register the extension's tables, and serve them until osquery shuts the extension
+/// down. The extension can be freed once this returns.
+///
+/// # Safety
+/// `extension` must be from `osquery_rs_extension_new`, `socket` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_run(
+    extension: *const osquery_rs_extension,
+    socket: *const c_char,
+    timeout_ms: u64,
+) -> c_int {
+    let extension = match extension.as_ref() {
+        Some(extension) => extension.clone(),
+        None => return fail("extension is NULL"),
+    };
+    let socket = match text(socket, "socket") {
+        Ok(socket) => socket,
+        Err(e) => return fail(e),
+    };
+    let served = std::panic::catch_unwind(AssertUnwindSafe(|| -> Result<(), anyhow::Error> {
+        let mut client = Client::connect(socket, Duration::from_millis(timeout_ms))?;
+        let server = extension.install(&mut client)?.start()?;
+        match server.join() {
+            Ok(result) => result.map(drop).map_err(Into::into),
+            Err(_) => Err(anyhow::anyhow!("the server thread panicked")),
+        }
+    }));
+    match served {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => fail(e),
+        Err(_) => fail("panicked while serving"),
+    }
+}
+
+/// Start a new row. Columns left unset are empty.
+///
+/// # Safety
+/// `rows` must be the one passed to the running `generate` callback.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_row_begin(rows: *mut osquery_rs_rows) -> c_int {
+    let rows = match rows.as_mut() {
+        Some(rows) => rows,
+        None => return fail("rows is NULL"),
+    };
+    rows.finish_row();
+    rows.row = Some(vec![None; rows.set.width()]);
+    0
+}
+
+/// # Safety
+/// As for `osquery_rs_row_begin`, with `column` and `value` nul-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_row_text(
+    rows: *mut osquery_rs_rows,
+    column: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let rows = match rows.as_mut() {
+        Some(rows) => rows,
+        None => return fail("rows is NULL"),
+    };
+    match text(value, "value") {
+        Ok(value) => rows.set(column, ColumnValue::text(value)),
+        Err(e) => fail(e),
+    }
+}
+
+/// # Safety
+/// As for `osquery_rs_row_begin`, with `column` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_row_integer(
+    rows: *mut osquery_rs_rows,
+    column: *const c_char,
+    value: i64,
+) -> c_int {
+    match rows.as_mut() {
+        Some(rows) => rows.set(column, ColumnValue::big_int(value)),
+        None => fail("rows is NULL"),
+    }
+}
+
+/// # Safety
+/// As for `osquery_rs_row_begin`, with `column` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_row_double(
+    rows: *mut osquery_rs_rows,
+    column: *const c_char,
+    value: f64,
+) -> c_int {
+    match rows.as_mut() {
+        Some(rows) => rows.set(column, ColumnValue::double(value)),
+        None => fail("rows is NULL"),
+    }
+}
+
+/// The message osquery gets if `generate` returns non-zero.
+///
+/// # Safety
+/// As for `osquery_rs_row_begin`, with `message` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_rows_error(
+    rows: *mut osquery_rs_rows,
+    message: *const c_char,
+) -> c_int {
+    let rows = match rows.as_mut() {
+        Some(rows) => rows,
+        None => return fail("rows is NULL"),
+    };
+    match text(message, "message") {
+        Ok(message) => {
+            rows.error = Some(message.to_string());
+            0
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// The `index`th distinct value the query compares `column` to with `=`, or NULL once
+/// there are no more. Saves parsing the context JSON for the usual `WHERE x = ...`. The
+/// string lives until `generate` returns.
+///
+/// # Safety
+/// As for `osquery_rs_row_begin`, with `column` a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn osquery_rs_equals(
+    rows: *mut osquery_rs_rows,
+    column: *const c_char,
+    index: usize,
+) -> *const c_char {
+    let rows = match rows.as_mut() {
+        Some(rows) => rows,
+        None => {
+            fail("rows is NULL");
+            return std::ptr::null();
+        }
+    };
+    let column = match text(column, "column") {
+        Ok(column) => column,
+        Err(e) => {
+            fail(e);
+            return std::ptr::null();
+        }
+    };
+    let query = &rows.query;
+    let values = rows.equals.entry(column.to_string()).or_insert_with(|| {
+        let mut values = query.equals_set(column).into_iter().collect::<Vec<_>>();
+        values.sort();
+        values
+            .into_iter()
+            .filter_map(|v| CString::new(v).ok())
+            .collect()
+    });
+    values.get(index).map_or(std::ptr::null(), |v| v.as_ptr())
+}
@@ -0,0 +1,677 @@
+// Turns osquery's own table specs (the Python-ish `.table` files, or a plain
+// `CREATE TABLE`) into a Rust table skeleton, so extensions can track specs kept
+// elsewhere. `osquery-table-gen` is the command-line front end.
+use std::fmt::Write as _;
+
+use crate::gen::table::{ColumnOptions, ColumnType};
+
+#[derive(Debug, Clone)]
+pub struct TableSpec {
+    pub name: String,
+    pub description: Option<String>,
+    pub columns: Vec<SpecColumn>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpecColumn {
+    pub name: String,
+    pub kind: ColumnType,
+    pub description: Option<String>,
+    /// The spec's column options (`required`, `index`, `hidden`, ...) that were set
+    pub options: Vec<String>,
+    /// Set for columns that only exist on some platforms, from `extended_schema`
+    pub platform: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SpecError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("spec has no table_name")]
+    MissingTableName,
+    #[error("spec has no columns")]
+    NoColumns,
+}
+
+fn syntax(line: usize, message: impl Into<String>) -> SpecError {
+    SpecError::Syntax {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Map a spec or SQL type name onto the four types extensions can declare.
+/// Dates and blobs are text as far as osquery is concerned.
+pub fn column_type(name: &str) -> ColumnType {
+    let name = name.trim().to_uppercase().replace('_', " ");
+    match name.as_str() {
+        "INTEGER" | "INT" => ColumnType::Integer,
+        "BIGINT" | "UNSIGNED BIGINT" => ColumnType::BigInt,
+        "DOUBLE" | "REAL" | "FLOAT" => ColumnType::Double,
+        _ => ColumnType::Text,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    // only ever show up as values nobody here cares about
+    Number,
+    Punct(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, SpecError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '"' | '\'' => {
+                chars.next();
+                let start = line;
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(other) => value.push(other),
+                            None => return Err(syntax(start, "unterminated string")),
+                        },
+                        Some(other) => {
+                            if other == '\n' {
+                                line += 1;
+                            }
+                            value.push(other)
+                        }
+                        None => return Err(syntax(start, "unterminated string")),
+                    }
+                }
+                // adjacent literals are one string, python style
+                match tokens.last_mut() {
+                    Some((_, Token::Str(previous))) => previous.push_str(&value),
+                    _ => tokens.push((start, Token::Str(value))),
+                }
+            }
+            '(' | ')' | '[' | ']' | ',' | '=' => {
+                chars.next();
+                tokens.push((line, Token::Punct(c)));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                while chars
+                    .peek()
+                    .is_some_and(|&c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+                {
+                    chars.next();
+                }
+                tokens.push((line, Token::Number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut value = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+                tokens.push((line, Token::Ident(value)));
+            }
+            other => return Err(syntax(line, format!("unexpected `{}`", other))),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Str(String),
+    Ident(String),
+    Number,
+    List(Vec<Expr>),
+    Call(Call),
+}
+
+#[derive(Debug, Clone)]
+struct Call {
+    line: usize,
+    name: String,
+    args: Vec<Expr>,
+    kwargs: Vec<(String, Expr)>,
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|(line, _)| *line)
+            .unwrap_or(1)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(_, t)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), SpecError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(syntax(self.line(), format!("expected `{}`", c)))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, SpecError> {
+        let line = self.line();
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Expr::Str(s)),
+            Some(Token::Number) => Ok(Expr::Number),
+            Some(Token::Punct('[')) => {
+                let mut items = vec![];
+                while !self.eat(']') {
+                    items.push(self.expr()?);
+                    if !self.eat(',') {
+                        self.expect(']')?;
+                        break;
+                    }
+                }
+                Ok(Expr::List(items))
+            }
+            Some(Token::Ident(name)) => {
+                if self.eat('(') {
+                    self.call_args(line, name).map(Expr::Call)
+                } else {
+                    Ok(Expr::Ident(name))
+                }
+            }
+            Some(other) => Err(syntax(line, format!("unexpected {:?}", other))),
+            None => Err(syntax(line, "unexpected end of spec")),
+        }
+    }
+
+    // called just after the opening paren
+    fn call_args(&mut self, line: usize, name: String) -> Result<Call, SpecError> {
+        let mut call = Call {
+            line,
+            name,
+            args: vec![],
+            kwargs: vec![],
+        };
+        while !self.eat(')') {
+            let is_kwarg = matches!(
+                (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)),
+                (Some((_, Token::Ident(_))), Some((_, Token::Punct('='))))
+            );
+            if is_kwarg {
+                if let Some(Token::Ident(key)) = self.next() {
+                    self.pos += 1;
+                    call.kwargs.push((key, self.expr()?));
+                }
+            } else {
+                call.args.push(self.expr()?);
+            }
+            if !self.eat(',') {
+                self.expect(')')?;
+                break;
+            }
+        }
+        Ok(call)
+    }
+}
+
+fn string_arg(call: &Call, i: usize) -> Result<String, SpecError> {
+    match call.args.get(i) {
+        Some(Expr::Str(s)) => Ok(s.clone()),
+        _ => Err(syntax(
+            call.line,
+            format!("`{}` expects a string argument", call.name),
+        )),
+    }
+}
+
+fn spec_column(call: &Call, platform: Option<&str>) -> Result<SpecColumn, SpecError> {
+    if call.name != "Column" {
+        return Err(syntax(
+            call.line,
+            format!("expected Column, got `{}`", call.name),
+        ));
+    }
+    let kind = match call.args.get(1) {
+        Some(Expr::Ident(kind)) | Some(Expr::Str(kind)) => column_type(kind),
+        _ => return Err(syntax(call.line, "Column needs a type")),
+    };
+    let description = match call.args.get(2) {
+        Some(Expr::Str(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let options = call
+        .kwargs
+        .iter()
+        .filter(|(_, v)| matches!(v, Expr::Ident(v) if v == "True"))
+        .map(|(k, _)| k.clone())
+        .collect();
+    Ok(SpecColumn {
+        name: string_arg(call, 0)?,
+        kind,
+        description,
+        options,
+        platform: platform.map(str::to_string),
+    })
+}
+
+fn spec_columns(
+    call: &Call,
+    list: Option<&Expr>,
+    platform: Option<&str>,
+) -> Result<Vec<SpecColumn>, SpecError> {
+    match list {
+        Some(Expr::List(items)) => items
+            .iter()
+            .map(|item| match item {
+                Expr::Call(column) => spec_column(column, platform),
+                _ => Err(syntax(call.line, "schema entries must be Column(...)")),
+            })
+            .collect(),
+        _ => Err(syntax(call.line, format!("`{}` expects a list", call.name))),
+    }
+}
+
+/// Parse an osquery `.table` spec. Only `table_name`, `description`, `schema` and
+/// `extended_schema` matter here, everything else is skipped.
+pub fn parse_table_spec(source: &str) -> Result<TableSpec, SpecError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let mut name = None;
+    let mut description = None;
+    let mut columns = vec![];
+    while parser.peek().is_some() {
+        let call = match parser.expr()? {
+            Expr::Call(call) => call,
+            _ => return Err(syntax(parser.line(), "expected a call")),
+        };
+        match call.name.as_str() {
+            "table_name" => name = Some(string_arg(&call, 0)?),
+            "description" => description = Some(string_arg(&call, 0)?),
+            "schema" => columns.extend(spec_columns(&call, call.args.first(), None)?),
+            "extended_schema" => {
+                let platform = match call.args.first() {
+                    Some(Expr::Ident(p)) | Some(Expr::Str(p)) => p.clone(),
+                    _ => return Err(syntax(call.line, "extended_schema needs a platform")),
+                };
+                columns.extend(spec_columns(&call, call.args.get(1), Some(&platform))?);
+            }
+            _ => {}
+        }
+    }
+    let name = name.ok_or(SpecError::MissingTableName)?;
+    if columns.is_empty() {
+        return Err(SpecError::NoColumns);
+    }
+    Ok(TableSpec {
+        name,
+        description,
+        columns,
+    })
+}
+
+fn unquote(identifier: &str) -> String {
+    let trimmed = identifier.trim();
+    let quoted = ['"', '`', '['].iter().any(|q| trimmed.starts_with(*q)) && trimmed.len() >= 2;
+    if quoted {
+        trimmed[1..trimmed.len() - 1].to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Split on commas that aren't inside parens.
+fn split_top_level(body: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in body.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&body[start..]);
+    parts
+}
+
+/// Parse a `CREATE TABLE` statement, as printed by `.schema` in osqueryi.
+pub fn parse_create_table(sql: &str) -> Result<TableSpec, SpecError> {
+    let open = sql
+        .find('(')
+        .ok_or_else(|| syntax(1, "expected a column list"))?;
+    let close = sql
+        .rfind(')')
+        .filter(|&close| close > open)
+        .ok_or_else(|| syntax(1, "unterminated column list"))?;
+    let head: Vec<&str> = sql[..open].split_whitespace().collect();
+    let is_create = head.len() >= 3
+        && head[0].eq_ignore_ascii_case("create")
+        && head[1].eq_ignore_ascii_case("table");
+    if !is_create {
+        return Err(syntax(1, "expected CREATE TABLE"));
+    }
+    let name = head
+        .last()
+        .map(|n| unquote(n))
+        .ok_or(SpecError::MissingTableName)?;
+
+    let mut columns = vec![];
+    for definition in split_top_level(&sql[open + 1..close]) {
+        let words: Vec<&str> = definition.split_whitespace().collect();
+        let first = match words.first() {
+            Some(first) => first.to_uppercase(),
+            None => continue,
+        };
+        if ["PRIMARY", "UNIQUE", "CHECK", "FOREIGN", "CONSTRAINT"].contains(&first.as_str()) {
+            continue;
+        }
+        let rest: Vec<String> = words[1..].iter().map(|w| w.to_uppercase()).collect();
+        let type_words: Vec<&str> = rest
+            .iter()
+            .map(String::as_str)
+            .take_while(|w| !["HIDDEN", "NOT", "NULL", "PRIMARY", "DEFAULT", "UNIQUE"].contains(w))
+            .collect();
+        columns.push(SpecColumn {
+            name: unquote(words[0]),
+            kind: column_type(&type_words.join(" ")),
+            description: None,
+            options: rest
+                .iter()
+                .filter(|w| *w == "HIDDEN")
+                .map(|w| w.to_lowercase())
+                .collect(),
+            platform: None,
+        });
+    }
+    if columns.is_empty() {
+        return Err(SpecError::NoColumns);
+    }
+    Ok(TableSpec {
+        name,
+        description: None,
+        columns,
+    })
+}
+
+/// Parse either kind of spec, going by whether it starts with `CREATE`.
+pub fn parse(source: &str) -> Result<TableSpec, SpecError> {
+    let trimmed = source.trim_start();
+    if trimmed.len() >= 6 && trimmed[..6].eq_ignore_ascii_case("create") {
+        parse_create_table(source)
+    } else {
+        parse_table_spec(source)
+    }
+}
+
+impl SpecColumn {
+    /// The options osquery knows about, anything else (`collate`, ...) is dropped.
+    pub fn column_options(&self) -> ColumnOptions {
+        self.options
+            .iter()
+            .filter_map(|o| option_const(o))
+            .fold(ColumnOptions::DEFAULT, |acc, (_, o)| acc | o)
+    }
+}
+
+fn option_const(name: &str) -> Option<(&'static str, ColumnOptions)> {
+    match name.to_lowercase().as_str() {
+        "index" => Some(("INDEX", ColumnOptions::INDEX)),
+        "required" => Some(("REQUIRED", ColumnOptions::REQUIRED)),
+        "additional" => Some(("ADDITIONAL", ColumnOptions::ADDITIONAL)),
+        "optimized" => Some(("OPTIMIZED", ColumnOptions::OPTIMIZED)),
+        "hidden" => Some(("HIDDEN", ColumnOptions::HIDDEN)),
+        _ => None,
+    }
+}
+
+fn type_name(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+impl TableSpec {
+    /// A `COLUMNS` array plus a `TablePlugin` skeleton that serves it, ready to fill in.
+    pub fn to_rust(&self) -> String {
+        let ty = type_name(&self.name);
+        let mut out = String::new();
+        let _ = writeln!(out, "// generated from the `{}` table spec", self.name);
+        out.push_str("use osquery::gen::table::ColumnType;\n");
+        out.push_str(
+            "use osquery::{Column, ColumnOptions, Plugin, QueryContext, TablePlugin, TableRows};\n\n",
+        );
+        out.push_str("pub const COLUMNS: &[(&str, ColumnType, ColumnOptions)] = &[\n");
+        for column in &self.columns {
+            let mut notes: Vec<String> = column.description.iter().cloned().collect();
+            if !column.options.is_empty() {
+                notes.push(format!("({})", column.options.join(", ")));
+            }
+            if let Some(platform) = &column.platform {
+                notes.push(format!("[{}]", platform));
+            }
+            if !notes.is_empty() {
+                let _ = writeln!(out, "    // {}", notes.join(" "));
+            }
+            let options = column
+                .options
+                .iter()
+                .filter_map(|o| option_const(o))
+                .map(|(name, _)| format!("ColumnOptions::{}", name))
+                .reduce(|acc, o| format!("{}.union({})", acc, o))
+                .unwrap_or_else(|| "ColumnOptions::DEFAULT".to_string());
+            let _ = writeln!(
+                out,
+                "    ({:?}, ColumnType::{:?}, {}),",
+                column.name, column.kind, options
+            );
+        }
+        out.push_str("];\n");
+        out.push_str("osquery::assert_columns!(COLUMNS);\n\n");
+        if let Some(description) = &self.description {
+            let _ = writeln!(out, "/// {}", description);
+        }
+        let _ = writeln!(out, "#[derive(Debug, Default)]\npub struct {};\n", ty);
+        let _ = writeln!(
+            out,
+            "impl Plugin for {ty} {{
+    type Error = std::convert::Infallible;
+    const NAME: &'static str = {name:?};
+
+    fn new() -> Self {{
+        {ty}
+    }}
+}}
+
+impl TablePlugin for {ty} {{
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {{
+        todo!()
+    }}
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {{
+        Ok(COLUMNS
+            .iter()
+            .map(|&(name, kind, options)| Column::new(name, kind).with_options(options))
+            .collect())
+    }}
+
+    fn shutdown(&self) {{}}
+}}",
+            ty = ty,
+            name = self.name
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the same spec `tests/codegen.rs` builds the generated skeleton from
+    const PROCESSES: &str = include_str!("../tests/codegen/processes.table");
+    const PROCESSES_RS: &str = include_str!("../tests/codegen/processes.rs");
+
+    fn column<'a>(spec: &'a TableSpec, name: &str) -> &'a SpecColumn {
+        spec.columns.iter().find(|c| c.name == name).unwrap()
+    }
+
+    #[test]
+    fn osquery_table_spec() {
+        let spec = parse(PROCESSES).unwrap();
+        assert_eq!(spec.name, "processes");
+        assert_eq!(
+            spec.description.as_deref(),
+            Some("All running processes on the host system.")
+        );
+        let names: Vec<_> = spec.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "pid",
+                "name",
+                "path",
+                "cmdline",
+                "on_disk",
+                "resident_size",
+                "user_time",
+                "parent",
+                "elevated_token",
+                "secure_process",
+                "upid",
+            ]
+        );
+
+        let pid = column(&spec, "pid");
+        assert_eq!(pid.kind.to_string(), "BIGINT");
+        assert_eq!(pid.options, ["index", "optimized"]);
+        assert_eq!(
+            pid.column_options().bits(),
+            (ColumnOptions::INDEX | ColumnOptions::OPTIMIZED).bits()
+        );
+        // `hidden=False` isn't an option that's set
+        assert_eq!(column(&spec, "parent").options, ["additional"]);
+        // adjacent literals run together
+        assert_eq!(
+            column(&spec, "on_disk").description.as_deref(),
+            Some("The process path exists yes=1, no=0, unknown=-1")
+        );
+        assert_eq!(column(&spec, "name").platform, None);
+        let secure = column(&spec, "secure_process");
+        assert_eq!(secure.platform.as_deref(), Some("WINDOWS"));
+        assert_eq!(secure.column_options().bits(), ColumnOptions::HIDDEN.bits());
+        assert_eq!(column(&spec, "upid").platform.as_deref(), Some("DARWIN"));
+    }
+
+    #[test]
+    fn create_table() {
+        let spec = parse(
+            "CREATE TABLE `processes`(`pid` BIGINT, `name` TEXT, `on_disk` INTEGER, \
+             `cpu` DOUBLE HIDDEN, `started` UNSIGNED_BIGINT, `blob` BLOB, \
+             PRIMARY KEY (`pid`)) WITHOUT ROWID",
+        )
+        .unwrap();
+        assert_eq!(spec.name, "processes");
+        let columns: Vec<_> = spec
+            .columns
+            .iter()
+            .map(|c| format!("{} {} {:?}", c.name, c.kind.to_string(), c.options))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                "pid BIGINT []",
+                "name TEXT []",
+                "on_disk INTEGER []",
+                "cpu DOUBLE [\"hidden\"]",
+                "started BIGINT []",
+                "blob TEXT []",
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let error = parse("table_name(\"processes\")\ndescription(\"never ends)\n").unwrap_err();
+        match error {
+            SpecError::Syntax { line, message } => {
+                assert_eq!(line, 2);
+                assert_eq!(message, "unterminated string");
+            }
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_table_name() {
+        let error = parse("schema([Column(\"pid\", BIGINT)])").unwrap_err();
+        assert!(matches!(error, SpecError::MissingTableName), "{:?}", error);
+    }
+
+    #[test]
+    fn no_columns() {
+        let error = parse("table_name(\"empty\")\nschema([])").unwrap_err();
+        assert!(matches!(error, SpecError::NoColumns), "{:?}", error);
+        let error = parse("CREATE TABLE empty(PRIMARY KEY (id))").unwrap_err();
+        assert!(matches!(error, SpecError::NoColumns), "{:?}", error);
+    }
+
+    #[test]
+    fn skeleton_matches_snapshot() {
+        // regenerate with `osquery-table-gen tests/codegen/processes.table` when the
+        // skeleton's meant to change
+        assert_eq!(parse(PROCESSES).unwrap().to_rust(), PROCESSES_RS);
+    }
+}
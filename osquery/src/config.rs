@@ -0,0 +1,174 @@
+// Config plugins hand osquery its configuration when it asks via `genConfig`.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{debug, info, instrument, trace, warn};
+
+use crate::{
+    metrics, ExtensionPluginRequest, ExtensionStatus, Plugin, PluginHandler, PluginResponse,
+    Response, Routes,
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/osquery/osquery.conf";
+
+type Configs = Arc<Mutex<BTreeMap<String, String>>>;
+
+/// Serves one or more JSON config files, keyed by path. Files are re-read on every
+/// `genConfig` (or `refresh`), unless `watch` is on, in which case they're only re-read
+/// when they change on disk. Files that fail to read or parse keep their last good version.
+#[derive(Debug)]
+pub struct FileConfigPlugin {
+    paths: Vec<PathBuf>,
+    configs: Configs,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl FileConfigPlugin {
+    pub fn from_paths<I, P>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let plugin = Self {
+            // absolute, so they line up with the paths the watcher reports
+            paths: paths
+                .into_iter()
+                .map(|p| match std::env::current_dir() {
+                    Ok(cwd) => cwd.join(p),
+                    Err(_) => p.as_ref().into(),
+                })
+                .collect(),
+            configs: Default::default(),
+            watcher: Mutex::new(None),
+        };
+        reload(&plugin.paths, &plugin.configs);
+        plugin
+    }
+
+    /// Keep the configs up to date with a filesystem watcher instead of reading them on
+    /// every request. Watches the containing directories, so editors that save by
+    /// renaming over the file are picked up too.
+    pub fn watch(self) -> Result<Self, notify::Error> {
+        let paths = self.paths.clone();
+        let configs = self.configs.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) if event.paths.iter().any(|p| paths.contains(p)) => {
+                    debug!(?event, "config file changed");
+                    reload(&paths, &configs);
+                }
+                Ok(_) => {}
+                Err(error) => warn!(%error, "config watcher error"),
+            })?;
+        for path in &self.paths {
+            let dir = path.parent().unwrap_or_else(|| Path::new("."));
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        *self.watcher.lock().unwrap_or_else(|e| e.into_inner()) = Some(watcher);
+        Ok(self)
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.watcher.lock().map(|w| w.is_some()).unwrap_or_default()
+    }
+
+    /// Re-read every file right now.
+    pub fn refresh(&self) {
+        reload(&self.paths, &self.configs);
+    }
+
+    /// The current config sources, keyed by path.
+    pub fn configs(&self) -> BTreeMap<String, String> {
+        self.configs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+fn read_config(path: &Path) -> Result<String, anyhow::Error> {
+    let content = std::fs::read_to_string(path)?;
+    // osquery would choke on it anyway, better to keep serving the last one that worked
+    serde_json::from_str::<serde_json::Value>(&content)?;
+    Ok(content)
+}
+
+fn reload(paths: &[PathBuf], configs: &Mutex<BTreeMap<String, String>>) {
+    for path in paths {
+        match read_config(path) {
+            Ok(content) => {
+                trace!(?path, "loaded config");
+                configs
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(path.to_string_lossy().into_owned(), content);
+            }
+            Err(error) => warn!(?path, %error, "couldn't load config, keeping the previous one"),
+        }
+    }
+}
+
+impl Plugin for FileConfigPlugin {
+    type Error = std::io::Error;
+    const NAME: &'static str = "file_config";
+    const REGISTRY: &'static str = "config";
+
+    fn new() -> Self {
+        Self::from_paths([DEFAULT_CONFIG_PATH])
+    }
+}
+
+impl Routes for FileConfigPlugin {
+    fn routes(&self) -> PluginResponse {
+        vec![]
+    }
+}
+
+impl PluginHandler for FileConfigPlugin {
+    #[instrument(target = "osquery::ping", level = "trace")]
+    fn handle_ping(&self) -> thrift::Result<ExtensionStatus> {
+        trace!(target = "osquery::ping", "pong");
+        Ok(ExtensionStatus::success().with_message("OK"))
+    }
+
+    #[instrument(level = "trace")]
+    fn handle_call(
+        &self,
+        _registry: String,
+        _item: String,
+        mut request: ExtensionPluginRequest,
+    ) -> thrift::Result<Response> {
+        let started = Instant::now();
+        let action = request.remove("action").unwrap_or_default();
+        let success = Response::success;
+        let result = match action.as_str() {
+            "genConfig" => {
+                if !self.is_watching() {
+                    self.refresh();
+                }
+                Ok(success(vec![self.configs()]))
+            }
+            "refresh" => {
+                info!("refreshing config files");
+                self.refresh();
+                Ok(success(vec![]))
+            }
+            other => self.handle_action(other, request),
+        };
+        metrics::global().record_response(Self::NAME, started.elapsed(), &result);
+        result
+    }
+
+    #[instrument(level = "trace")]
+    fn handle_shutdown(&self) -> thrift::Result<()> {
+        // dropping the watcher stops it
+        self.watcher
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Polars interop: query results as a `DataFrame` to aggregate or join locally, and a
+//! frame's rows back out to serve as a derived table.
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use polars::prelude::{AnyValue, DataFrame, DataType, NamedFrom, PolarsError, Series};
+
+use crate::gen::table::ColumnType;
+use crate::scheduler::{query_column_types, run_query};
+use crate::tables::parse_value;
+use crate::{Client, Column, ColumnValue, Connector, TableRows};
+
+impl<C: Connector> Client<C> {
+    /// Run `sql` and collect the results into a frame, typed the way osquery reports the
+    /// query's columns: TEXT as strings, INTEGER and BIGINT as i32/i64, DOUBLE as f64.
+    /// Values that don't parse as their column's type (usually empty, osquery's NULL)
+    /// are null.
+    pub fn query_frame(&mut self, sql: &str) -> Result<DataFrame, crate::Error> {
+        let columns = query_column_types(self, sql)?;
+        let rows = run_query(self, sql)?;
+        let series = columns.into_iter().map(|(name, kind)| {
+            let values = rows
+                .iter()
+                .map(|row| row.get(&name).and_then(|raw| parse_value(kind, raw)));
+            let series = match kind {
+                ColumnType::Integer => Series::new(
+                    name.as_str().into(),
+                    values
+                        .map(|v| match v {
+                            Some(ColumnValue::Integer(v)) => Some(v),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+                ColumnType::BigInt => Series::new(
+                    name.as_str().into(),
+                    values
+                        .map(|v| match v {
+                            Some(ColumnValue::BigInt(v)) => Some(v),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+                ColumnType::Double => Series::new(
+                    name.as_str().into(),
+                    values
+                        .map(|v| match v {
+                            Some(ColumnValue::Double(v)) => Some(v),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+                ColumnType::Text | ColumnType::Unknown => Series::new(
+                    name.as_str().into(),
+                    rows.iter()
+                        .map(|row| row.get(&name).cloned())
+                        .collect::<Vec<_>>(),
+                ),
+            };
+            series.into()
+        });
+        Ok(DataFrame::new(series.collect())?)
+    }
+}
+
+/// A table schema for the frame's columns. Integers up to 32 bits are INTEGER, wider ones
+/// BIGINT, floats DOUBLE, and anything else TEXT.
+pub fn columns(frame: &DataFrame) -> Vec<Column> {
+    frame
+        .get_columns()
+        .iter()
+        .map(|c| {
+            let kind = match c.dtype() {
+                DataType::Boolean
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::UInt8
+                | DataType::UInt16 => ColumnType::Integer,
+                DataType::Int64 | DataType::UInt32 | DataType::UInt64 => ColumnType::BigInt,
+                DataType::Float32 | DataType::Float64 => ColumnType::Double,
+                _ => ColumnType::Text,
+            };
+            Column::new(c.name(), kind)
+        })
+        .collect()
+}
+
+/// The frame's rows, ready to return from `generate`. Nulls are left out of their row.
+pub fn to_rows(frame: &DataFrame) -> Result<TableRows, PolarsError> {
+    let mut rows = vec![BTreeMap::new(); frame.height()];
+    for column in frame.get_columns() {
+        for (i, row) in rows.iter_mut().enumerate() {
+            if let Some(value) = column_value(column.get(i)?) {
+                row.insert(column.name().to_string(), value);
+            }
+        }
+    }
+    Ok(rows)
+}
+
+fn column_value(value: AnyValue) -> Option<ColumnValue> {
+    if let Some(s) = value.get_str() {
+        return Some(ColumnValue::Text(s.to_string()));
+    }
+    Some(match value {
+        AnyValue::Null => return None,
+        AnyValue::Boolean(v) => ColumnValue::Integer(v.into()),
+        AnyValue::Int8(v) => ColumnValue::Integer(v.into()),
+        AnyValue::Int16(v) => ColumnValue::Integer(v.into()),
+        AnyValue::Int32(v) => ColumnValue::Integer(v),
+        AnyValue::UInt8(v) => ColumnValue::Integer(v.into()),
+        AnyValue::UInt16(v) => ColumnValue::Integer(v.into()),
+        AnyValue::Int64(v) => ColumnValue::BigInt(v),
+        AnyValue::UInt32(v) => ColumnValue::BigInt(v.into()),
+        AnyValue::UInt64(v) => match i64::try_from(v) {
+            Ok(v) => ColumnValue::BigInt(v),
+            Err(_) => ColumnValue::Text(v.to_string()),
+        },
+        AnyValue::Float32(v) => ColumnValue::Double(v.into()),
+        AnyValue::Float64(v) => ColumnValue::Double(v),
+        other => ColumnValue::Text(other.to_string()),
+    })
+}
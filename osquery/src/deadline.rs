@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a `generate` call has left. Generation isn't interrupted from the outside, so
+/// tables doing a lot of work should check `is_expired` (or `check`) as they go and bail
+/// out early; anything returned after the deadline gets thrown away anyway.
+#[derive(Debug, Clone, Default)]
+pub struct Deadline {
+    at: Option<Instant>,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[derive(thiserror::Error, Debug, Clone, Copy)]
+#[error("deadline exceeded")]
+pub struct DeadlineExceeded;
+
+impl Deadline {
+    /// A deadline that never expires on its own, but can still be cancelled.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn after(timeout: Duration) -> Self {
+        Self::at(Instant::now() + timeout)
+    }
+
+    pub fn at(at: Instant) -> Self {
+        Self {
+            at: Some(at),
+            cancelled: Default::default(),
+        }
+    }
+
+    pub fn instant(&self) -> Option<Instant> {
+        self.at
+    }
+
+    /// Time left, or `None` if there's no deadline at all.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Mark the call as abandoned. Every clone of this deadline sees it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.is_cancelled() || matches!(self.at, Some(at) if Instant::now() >= at)
+    }
+
+    /// `?`-friendly version of `is_expired`.
+    pub fn check(&self) -> Result<(), DeadlineExceeded> {
+        if self.is_expired() {
+            return Err(DeadlineExceeded);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn none_never_expires() {
+        let deadline = Deadline::none();
+        assert_eq!(deadline.remaining(), None);
+        assert!(!deadline.is_expired());
+        assert!(deadline.check().is_ok());
+    }
+
+    #[test]
+    fn expires_once_passed() {
+        assert!(Deadline::after(Duration::from_secs(60)).check().is_ok());
+        let deadline = Deadline::at(Instant::now());
+        assert!(deadline.is_expired());
+        assert_eq!(deadline.remaining(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn cancelling_reaches_every_clone() {
+        let deadline = Deadline::after(Duration::from_secs(60));
+        let clone = deadline.clone();
+        assert!(!clone.is_expired());
+        deadline.cancel();
+        assert!(clone.is_cancelled());
+        assert!(clone.check().is_err());
+    }
+}
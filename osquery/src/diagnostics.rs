@@ -0,0 +1,149 @@
+//! Dumping the server's state to the log, to debug a hang in production without
+//! restarting it: connections, calls in flight and how long they've been at it, what's
+//! registered with osquery, and each table's metrics. Call `dump` from whatever trigger
+//! suits, or `dump_on_sigusr1` for `kill -USR1 <pid>`.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::info;
+
+use crate::request_id::RequestId;
+use crate::{builtin, metrics};
+
+static IN_FLIGHT: Mutex<BTreeMap<RequestId, Call>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug)]
+struct Call {
+    registry: String,
+    item: String,
+    started: Instant,
+}
+
+/// Lists a call as in flight until dropped.
+#[derive(Debug)]
+pub(crate) struct CallGuard {
+    id: RequestId,
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+pub(crate) fn call_started(id: RequestId, registry: &str, item: &str) -> CallGuard {
+    IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner()).insert(
+        id,
+        Call {
+            registry: registry.to_string(),
+            item: item.to_string(),
+            started: Instant::now(),
+        },
+    );
+    CallGuard { id }
+}
+
+/// Log everything there is to know about the server right now, at info level.
+pub fn dump() {
+    let snapshot = metrics::global().snapshot();
+    info!(
+        uptime = ?snapshot.uptime,
+        servers = snapshot.servers,
+        active_connections = snapshot.active_connections,
+        "state dump"
+    );
+    for (table, uuid, socket) in builtin::registration_list() {
+        info!(%table, uuid, ?socket, "registered");
+    }
+    {
+        let calls = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+        if calls.is_empty() {
+            info!("no calls in flight");
+        }
+        for (id, call) in calls.iter() {
+            info!(
+                %id,
+                registry = %call.registry,
+                item = %call.item,
+                running = ?call.started.elapsed(),
+                "call in flight"
+            );
+        }
+    }
+    for t in snapshot.tables {
+        info!(
+            table = %t.name,
+            calls = t.calls,
+            errors = t.errors,
+            p50 = ?t.p50,
+            p99 = ?t.p99,
+            "table metrics"
+        );
+    }
+}
+
+/// `dump` whenever the process gets SIGUSR1. The handler only wakes a thread that does
+/// the logging, since hardly anything is safe to do inside a signal handler. Calling
+/// this more than once is harmless.
+#[cfg(unix)]
+pub fn dump_on_sigusr1() -> std::io::Result<()> {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        let fd = WAKE.load(Ordering::Relaxed);
+        if fd >= 0 {
+            // write is async-signal-safe, and the pipe is non-blocking so a flood of
+            // signals can't wedge the handler
+            unsafe { libc::write(fd, [0u8].as_ptr() as *const libc::c_void, 1) };
+        }
+    }
+
+    let mut fds = [0; 2];
+    // safe: fds has room for both ends
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // safe: both fds were just opened and nothing else owns them
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    if unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if WAKE
+        .compare_exchange(-1, fds[1], Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        // already set up, the new pipe closes on return
+        return Ok(());
+    }
+    // the handler writes to it for the rest of the process
+    std::mem::forget(writer);
+
+    std::thread::spawn(move || {
+        let mut reader = reader;
+        let mut buf = [0u8; 64];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            dump();
+        }
+    });
+
+    // safe: the handler only touches an atomic and write(2)
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    if unsafe { libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! Differential results: what changed in a query's rows from one run to the next, the
+//! way osqueryd reports its scheduled queries. Used by `Scheduler`, and usable by
+//! anything else that polls, like loggers deduplicating results or event tables.
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A result row as osquery returns it, every value a string.
+pub type Row = BTreeMap<String, String>;
+
+/// What changed between two runs of a query. Rows are compared whole, and duplicates
+/// count, so two identical rows going down to one shows up as one removal. Added rows
+/// are in the order they appear in the new results, removed ones in their old order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diff<R = Row> {
+    pub added: Vec<R>,
+    pub removed: Vec<R>,
+}
+
+impl<R> Default for Diff<R> {
+    fn default() -> Self {
+        Self {
+            added: vec![],
+            removed: vec![],
+        }
+    }
+}
+
+impl<R> Diff<R> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn map<T, F: FnMut(R) -> T>(self, mut f: F) -> Diff<T> {
+        Diff {
+            added: self.added.into_iter().map(&mut f).collect(),
+            removed: self.removed.into_iter().map(f).collect(),
+        }
+    }
+}
+
+impl<R: Hash + Eq + Clone> Diff<R> {
+    pub fn between(before: &[R], after: &[R]) -> Self {
+        let mut unmatched: HashMap<&R, usize> = HashMap::new();
+        for row in before {
+            *unmatched.entry(row).or_default() += 1;
+        }
+        let mut diff = Diff::default();
+        for row in after {
+            match unmatched.get_mut(row) {
+                Some(n) if *n > 0 => *n -= 1,
+                _ => diff.added.push(row.clone()),
+            }
+        }
+        // whatever's left over from before is gone
+        for row in before {
+            if let Some(n) = unmatched.get_mut(row).filter(|n| **n > 0) {
+                *n -= 1;
+                diff.removed.push(row.clone());
+            }
+        }
+        diff
+    }
+}
+
+/// One run's worth of differential results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Results<R = Row> {
+    pub diff: Diff<R>,
+    /// The epoch the results belong to
+    pub epoch: u64,
+    /// Runs since the epoch began. 0 means these are the first results, so every row is
+    /// in `added` and none of them are news.
+    pub counter: u64,
+}
+
+/// Keeps a query's last results to diff the next run against, with osqueryd's epoch and
+/// counter: the counter goes up each run, and moving to a new epoch forgets what came
+/// before, starting the counter over at 0 with everything added again.
+#[derive(Debug, Clone)]
+pub struct Differential<R = Row> {
+    last: Option<Vec<R>>,
+    epoch: u64,
+    counter: u64,
+}
+
+impl<R> Default for Differential<R> {
+    fn default() -> Self {
+        Self::with_epoch(0)
+    }
+}
+
+impl<R> Differential<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_epoch(epoch: u64) -> Self {
+        Self {
+            last: None,
+            epoch,
+            counter: 0,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Move to `epoch`, dropping the saved results if it's a different one.
+    pub fn set_epoch(&mut self, epoch: u64) {
+        if epoch != self.epoch {
+            self.epoch = epoch;
+            self.reset();
+        }
+    }
+
+    /// Forget the saved results, so the next update reports everything as added.
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.counter = 0;
+    }
+
+    /// The rows from the last update, if there's been one this epoch
+    pub fn last(&self) -> Option<&[R]> {
+        self.last.as_deref()
+    }
+}
+
+impl<R: Hash + Eq + Clone> Differential<R> {
+    /// Diff a new run's rows against the last, and keep them for next time.
+    pub fn update(&mut self, rows: Vec<R>) -> Results<R> {
+        let (diff, counter) = match &self.last {
+            Some(last) => (Diff::between(last, &rows), self.counter + 1),
+            None => (Diff::between(&[], &rows), 0),
+        };
+        self.last = Some(rows);
+        self.counter = counter;
+        Results {
+            diff,
+            epoch: self.epoch,
+            counter,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, size: &str) -> Row {
+        [("name", name), ("size", size)]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reordered_rows_are_unchanged() {
+        let before = [row("a", "1"), row("b", "2"), row("c", "3")];
+        let after = [row("c", "3"), row("a", "1"), row("b", "2")];
+        assert!(Diff::between(&before, &after).is_empty());
+    }
+
+    #[test]
+    fn duplicates_count() {
+        let before = [row("a", "1"), row("a", "1"), row("b", "2")];
+        let after = [row("a", "1"), row("b", "2"), row("b", "2"), row("b", "2")];
+        let diff = Diff::between(&before, &after);
+        assert_eq!(diff.removed, [row("a", "1")]);
+        assert_eq!(diff.added, [row("b", "2"), row("b", "2")]);
+    }
+
+    #[test]
+    fn changed_values_are_a_removal_and_an_addition() {
+        let before = [row("a", "1"), row("b", "2")];
+        let after = [row("b", "20"), row("a", "1")];
+        let diff = Diff::between(&before, &after);
+        assert_eq!(diff.removed, [row("b", "2")]);
+        assert_eq!(diff.added, [row("b", "20")]);
+    }
+
+    #[test]
+    fn differential_counts_runs_and_resets_on_a_new_epoch() {
+        let mut differential = Differential::new();
+        let first = differential.update(vec![row("a", "1")]);
+        assert_eq!((first.counter, first.diff.added.len()), (0, 1));
+        let second = differential.update(vec![row("a", "1"), row("a", "1")]);
+        assert_eq!(second.counter, 1);
+        assert_eq!(second.diff.added, [row("a", "1")]);
+        differential.set_epoch(7);
+        let third = differential.update(vec![row("a", "1")]);
+        assert_eq!((third.epoch, third.counter), (7, 0));
+        assert_eq!(third.diff.added, [row("a", "1")]);
+    }
+}
@@ -0,0 +1,263 @@
+// Distributed plugins hand osquery ad-hoc queries to run and take the results back. Run
+// osquery with `--disable_distributed=false --distributed_plugin=<name>`, implement
+// `DistributedPlugin` (and `Plugin`, with `REGISTRY = "distributed"`), then
+// `distributed_plugin!(YourType)` wires up the routes and the thrift handler.
+//
+// File carving rides on the same loop: a query against osquery's `carves` table with
+// `carve = 1` starts one (`CarveRequest` writes it), and later results from that same
+// table report how it went (`Results::carves`). The carved data itself is uploaded
+// to osquery's `--carver_*_endpoint`s, not through the plugin.
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use maplit::btreemap;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{metrics, ExtensionPluginRequest, ExtensionStatus, Plugin, PluginResponse, Response};
+
+/// What `getQueries` answers with: queries to run, keyed by an id that comes back with
+/// their results.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Queries {
+    pub queries: BTreeMap<String, String>,
+    /// Queries that have to return rows for `queries` to run at all
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub discovery: BTreeMap<String, String>,
+    /// Seconds to poll faster for, after something interesting comes up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accelerate: Option<String>,
+}
+
+impl Queries {
+    pub fn query<I: Into<String>, S: Into<String>>(mut self, id: I, sql: S) -> Self {
+        self.queries.insert(id.into(), sql.into());
+        self
+    }
+
+    /// Ask osquery to carve files. The result rows for `id` say the carve was scheduled,
+    /// watch for its progress with more `carves` queries (see `CarveRequest::status_sql`).
+    pub fn carve<I: Into<String>>(self, id: I, carve: &CarveRequest) -> Self {
+        self.query(id, carve.to_sql())
+    }
+}
+
+/// Files to carve, by path (SQL `LIKE` patterns work, `%` included).
+#[derive(Debug, Clone, Default)]
+pub struct CarveRequest {
+    pub paths: Vec<String>,
+    /// Tags the carve so its status can be picked out later
+    pub request_id: Option<String>,
+}
+
+impl CarveRequest {
+    pub fn new<I, S>(paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            paths: paths.into_iter().map(Into::into).collect(),
+            request_id: None,
+        }
+    }
+
+    pub fn with_request_id<S: Into<String>>(mut self, request_id: S) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    /// The query that starts the carve.
+    pub fn to_sql(&self) -> String {
+        format!("SELECT * FROM carves WHERE carve = 1{}", self.filter())
+    }
+
+    /// A query for how the carve is going, without starting another one.
+    pub fn status_sql(&self) -> String {
+        format!("SELECT * FROM carves WHERE 1 = 1{}", self.filter())
+    }
+
+    fn filter(&self) -> String {
+        let mut filter = String::new();
+        if !self.paths.is_empty() {
+            let paths = self
+                .paths
+                .iter()
+                .map(|p| format!("path LIKE {}", quote(p)))
+                .collect::<Vec<_>>();
+            filter.push_str(&format!(" AND ({})", paths.join(" OR ")));
+        }
+        if let Some(id) = &self.request_id {
+            filter.push_str(&format!(" AND request_id = {}", quote(id)));
+        }
+        filter
+    }
+}
+
+// a SQL string literal
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// What osquery sends to `writeResults`: rows, status and error message per query id.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Results {
+    #[serde(default)]
+    pub queries: BTreeMap<String, Vec<BTreeMap<String, String>>>,
+    /// 0 when the query ran, osquery's error code otherwise
+    #[serde(default)]
+    pub statuses: BTreeMap<String, i32>,
+    #[serde(default)]
+    pub messages: BTreeMap<String, String>,
+}
+
+impl Results {
+    pub fn rows(&self, id: &str) -> &[BTreeMap<String, String>] {
+        self.queries.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether query `id` ran, `None` if its results aren't in this batch.
+    pub fn succeeded(&self, id: &str) -> Option<bool> {
+        self.statuses.get(id).map(|status| *status == 0)
+    }
+
+    /// The carves reported by query `id`, if it was a query against `carves`.
+    pub fn carves(&self, id: &str) -> Vec<CarveStatus> {
+        self.rows(id).iter().map(CarveStatus::from_row).collect()
+    }
+}
+
+/// A row of osquery's `carves` table.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CarveStatus {
+    pub path: String,
+    /// e.g. `SCHEDULED`, `PENDING`, `SUCCESS`, or `FAILED` with a reason
+    pub status: String,
+    pub carve_guid: String,
+    pub request_id: String,
+    pub sha256: String,
+    pub size: Option<u64>,
+    pub time: Option<u64>,
+}
+
+impl CarveStatus {
+    pub fn from_row(row: &BTreeMap<String, String>) -> Self {
+        let text = |key: &str| row.get(key).cloned().unwrap_or_default();
+        let number = |key: &str| row.get(key).and_then(|v| v.trim().parse().ok());
+        Self {
+            path: text("path"),
+            status: text("status"),
+            carve_guid: text("carve_guid"),
+            request_id: text("request_id"),
+            sha256: text("sha256"),
+            size: number("size"),
+            time: number("time"),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.status == "SUCCESS" || self.is_failed()
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.status.starts_with("FAILED")
+    }
+}
+
+pub trait DistributedPlugin: Plugin {
+    /// Queries for osquery to run now. Called every `--distributed_interval`.
+    fn get_queries(&self) -> Result<Queries, Self::Error>;
+    fn write_results(&self, results: Results) -> Result<(), Self::Error>;
+    fn shutdown(&self) {}
+}
+
+#[doc(hidden)]
+pub fn pong() -> ExtensionStatus {
+    ExtensionStatus::success().with_message("OK")
+}
+
+#[doc(hidden)]
+pub fn routes() -> PluginResponse {
+    vec![]
+}
+
+// what `distributed_plugin!` hooks up as the thrift call handler
+#[doc(hidden)]
+pub fn handle_call<D: DistributedPlugin>(
+    plugin: &D,
+    mut request: ExtensionPluginRequest,
+) -> thrift::Result<Response> {
+    let started = Instant::now();
+    let internal = |message: String| {
+        thrift::Error::Application(thrift::ApplicationError::new(
+            thrift::ApplicationErrorKind::InternalError,
+            message,
+        ))
+    };
+    let action = request.remove("action");
+    debug!(plugin = D::NAME, ?action, "handling distributed call");
+    let result = match action.as_deref() {
+        Some("getQueries") => plugin
+            .get_queries()
+            .map_err(|e| internal(e.to_string()))
+            .and_then(|queries| {
+                serde_json::to_string(&queries).map_err(|e| internal(e.to_string()))
+            })
+            .map(|queries| Response::success(vec![btreemap! {"results".to_string() => queries}])),
+        Some("writeResults") => {
+            let raw = request.get("results").map_or("{}", String::as_str);
+            serde_json::from_str::<Results>(raw)
+                .map_err(|e| {
+                    thrift::Error::Application(thrift::ApplicationError::new(
+                        thrift::ApplicationErrorKind::ProtocolError,
+                        format!("got error deserializing distributed results: {}", e),
+                    ))
+                })
+                .and_then(|results| {
+                    plugin
+                        .write_results(results)
+                        .map_err(|e| internal(e.to_string()))
+                })
+                .map(|_| Response::success(vec![]))
+        }
+        Some(other) => plugin.handle_action(other, request),
+        None => Err(thrift::Error::Protocol(thrift::ProtocolError::new(
+            thrift::ProtocolErrorKind::NotImplemented,
+            format!("unsupported call to distributed plugin `{}`", D::NAME),
+        ))),
+    };
+    metrics::global().record_response(D::NAME, started.elapsed(), &result);
+    result
+}
+
+/// Implements `Routes` and the thrift handler for a `DistributedPlugin`.
+#[macro_export]
+macro_rules! distributed_plugin {
+    ($plugin:ty) => {
+        impl $crate::Routes for $plugin {
+            fn routes(&self) -> $crate::PluginResponse {
+                $crate::distributed::routes()
+            }
+        }
+
+        impl $crate::PluginHandler for $plugin {
+            fn handle_ping(&self) -> $crate::thrift::Result<$crate::ExtensionStatus> {
+                Ok($crate::distributed::pong())
+            }
+
+            fn handle_call(
+                &self,
+                _registry: String,
+                _item: String,
+                request: $crate::ExtensionPluginRequest,
+            ) -> $crate::thrift::Result<$crate::Response> {
+                $crate::distributed::handle_call(self, request)
+            }
+
+            fn handle_shutdown(&self) -> $crate::thrift::Result<()> {
+                $crate::distributed::DistributedPlugin::shutdown(self);
+                Ok(())
+            }
+        }
+    };
+}
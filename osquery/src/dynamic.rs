@@ -0,0 +1,612 @@
+// Tables in shared objects, loaded at runtime. One blessed extension binary runs a `Host`,
+// and teams ship their tables as cdylibs built with `export_tables!` to drop in next to it.
+//
+// Rust has no stable ABI, so everything crossing between the two is C: a module struct
+// with a version, and per table its name, its routes and columns as JSON, and a `call`
+// taking osquery's request as JSON and handing back the response as JSON. The host and
+// the plugins can be built by different compilers and different versions of this crate.
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::panic::AssertUnwindSafe;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{PluginHandler, PluginRequest, Response, Status, TablePlugin};
+
+/// Bumped whenever `PluginModule` or `TableDescriptor` change shape
+pub const ABI_VERSION: u32 = 1;
+/// The symbol a plugin library exports, an `EntryPoint`
+pub const ENTRY_POINT: &str = "osquery_rs_plugin_module";
+
+/// `extern "C" fn() -> *const PluginModule`. The module has to live as long as the library
+/// stays loaded.
+pub type EntryPoint = unsafe extern "C" fn() -> *const PluginModule;
+
+/// What a plugin library has to offer.
+#[repr(C)]
+pub struct PluginModule {
+    pub abi_version: u32,
+    pub tables: *const TableDescriptor,
+    pub table_count: usize,
+}
+
+/// One table. Strings are nul-terminated UTF-8 owned by the library. `call` and `shutdown`
+/// may be called from several threads at once.
+#[repr(C)]
+pub struct TableDescriptor {
+    pub name: *const c_char,
+    /// What osquery is told at registration, a JSON array of string maps
+    pub routes: *const c_char,
+    /// The columns for `extension_schema`, a JSON array of `Column`s
+    pub columns: *const c_char,
+    /// Answer one call, a JSON object of osquery's request. Returns a JSON `WireResponse`
+    /// that the caller hands back to `free`.
+    pub call: unsafe extern "C" fn(state: *mut c_void, request: *const c_char) -> *mut c_char,
+    pub free: unsafe extern "C" fn(response: *mut c_char),
+    pub shutdown: unsafe extern "C" fn(state: *mut c_void),
+    pub state: *mut c_void,
+}
+
+/// A response as it crosses the boundary.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WireResponse {
+    pub code: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub rows: Vec<BTreeMap<String, String>>,
+}
+
+impl WireResponse {
+    fn failure(message: String) -> Self {
+        Self {
+            code: 1,
+            message: Some(message),
+            rows: vec![],
+        }
+    }
+}
+
+impl From<Response> for WireResponse {
+    fn from(response: Response) -> Self {
+        let status = response.status.unwrap_or_default();
+        Self {
+            code: status.code.unwrap_or_default(),
+            message: status.message,
+            rows: response.response.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<WireResponse> for Response {
+    fn from(wire: WireResponse) -> Self {
+        let status = Status::new(Some(wire.code), wire.message, None);
+        Response::new(status, wire.rows)
+    }
+}
+
+fn c_string(s: String) -> CString {
+    // nothing osquery sends or a table returns should have one, but don't fall over on it
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+/// The exporting side: a table, kept alive for as long as the library is loaded.
+#[doc(hidden)]
+pub struct Exported {
+    descriptors: Vec<TableDescriptor>,
+    module: PluginModule,
+    // what the descriptors point into
+    _strings: Vec<CString>,
+}
+
+// safe: the pointers are to the strings and tables this owns, which never move or change,
+// and the tables themselves are Send + Sync
+unsafe impl Send for Exported {}
+unsafe impl Sync for Exported {}
+
+#[doc(hidden)]
+pub struct ExportedTable {
+    descriptor: TableDescriptor,
+    strings: Vec<CString>,
+}
+
+impl Exported {
+    pub fn new(tables: Vec<ExportedTable>) -> Box<Self> {
+        let mut strings = vec![];
+        let descriptors = tables
+            .into_iter()
+            .map(|table| {
+                strings.extend(table.strings);
+                table.descriptor
+            })
+            .collect::<Vec<_>>();
+        let mut exported = Box::new(Self {
+            module: PluginModule {
+                abi_version: ABI_VERSION,
+                tables: std::ptr::null(),
+                table_count: descriptors.len(),
+            },
+            descriptors,
+            _strings: strings,
+        });
+        exported.module.tables = exported.descriptors.as_ptr();
+        exported
+    }
+
+    pub fn module(&self) -> *const PluginModule {
+        &self.module
+    }
+}
+
+/// Describe `T` for export, see `export_tables!`.
+#[doc(hidden)]
+pub fn export<T>() -> ExportedTable
+where
+    T: TablePlugin + PluginHandler + Send + Sync + 'static,
+{
+    let table = T::new();
+    let routes = table.try_routes().unwrap_or_else(|error| {
+        tracing::error!(table = T::NAME, %error, "can't export the table's routes");
+        vec![]
+    });
+    let strings = vec![
+        c_string(T::NAME.to_string()),
+        c_string(serde_json::to_string(&routes).unwrap_or_default()),
+        c_string(serde_json::to_string(&table.schema()).unwrap_or_default()),
+    ];
+    ExportedTable {
+        descriptor: TableDescriptor {
+            name: strings[0].as_ptr(),
+            routes: strings[1].as_ptr(),
+            columns: strings[2].as_ptr(),
+            call: call::<T>,
+            free,
+            shutdown: shutdown::<T>,
+            state: Box::into_raw(Box::new(table)) as *mut c_void,
+        },
+        strings,
+    }
+}
+
+unsafe extern "C" fn call<T: TablePlugin + PluginHandler>(
+    state: *mut c_void,
+    request: *const c_char,
+) -> *mut c_char {
+    // no unwinding into the host, whatever the table does
+    let response = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        // safe: state is the table `export` leaked, request is the host's C string
+        let table = &*(state as *const T);
+        let request = CStr::from_ptr(request).to_string_lossy();
+        let request = match serde_json::from_str::<PluginRequest>(&request) {
+            Ok(request) => request,
+            Err(e) => return WireResponse::failure(format!("malformed request: {}", e)),
+        };
+        match table.handle_call(T::REGISTRY.into(), T::NAME.into(), request) {
+            Ok(response) => response.into(),
+            Err(e) => WireResponse::failure(e.to_string()),
+        }
+    }))
+    .unwrap_or_else(|_| WireResponse::failure(format!("`{}` panicked", T::NAME)));
+    c_string(serde_json::to_string(&response).unwrap_or_default()).into_raw()
+}
+
+unsafe extern "C" fn free(response: *mut c_char) {
+    if !response.is_null() {
+        // safe: it came from `call`'s into_raw
+        drop(CString::from_raw(response));
+    }
+}
+
+unsafe extern "C" fn shutdown<T: TablePlugin>(state: *mut c_void) {
+    let _ = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        // safe: state is the table `export` leaked
+        (*(state as *const T)).shutdown()
+    }));
+}
+
+/// Export tables from a cdylib for a `Host` to load:
+/// `osquery::export_tables!(ProcessTree, Sockets);`
+#[macro_export]
+macro_rules! export_tables {
+    ($($table:ty),+ $(,)?) => {
+        #[no_mangle]
+        pub extern "C" fn osquery_rs_plugin_module() -> *const $crate::dynamic::PluginModule {
+            static MODULE: ::std::sync::OnceLock<::std::boxed::Box<$crate::dynamic::Exported>> =
+                ::std::sync::OnceLock::new();
+            MODULE
+                .get_or_init(|| {
+                    $crate::dynamic::Exported::new(vec![$($crate::dynamic::export::<$table>()),+])
+                })
+                .module()
+        }
+    };
+}
+
+#[cfg(feature = "dynamic-plugins")]
+pub use self::host::{Host, LoadError, Reloaded};
+
+#[cfg(feature = "dynamic-plugins")]
+mod host {
+    use std::collections::BTreeMap;
+    use std::ffi::{CStr, CString};
+    use std::fmt;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
+
+    use libloading::Library;
+    use tracing::{info, warn};
+
+    use super::*;
+    use crate::multi_table::{multi_table_plugin, MultiTable, ServedTable};
+    use crate::reload::drain_then_shutdown;
+    use crate::{
+        Client, Column, Connector, ExtensionPluginRequest, ExtensionPluginResponse,
+        ExtensionRouteUUID, Handle, Plugin,
+    };
+
+    #[derive(thiserror::Error, Debug)]
+    pub enum LoadError {
+        #[error("{path:?}: {source}")]
+        Library {
+            path: PathBuf,
+            source: libloading::Error,
+        },
+        #[error("{path:?} speaks plugin ABI {found}, this host speaks {}", ABI_VERSION)]
+        Abi { path: PathBuf, found: u32 },
+        #[error("{path:?}: {message}")]
+        Malformed { path: PathBuf, message: String },
+        #[error("{path:?} has a `{table}` table, and so does an earlier library")]
+        Duplicate { path: PathBuf, table: String },
+        #[error("{path:?} is already loaded, reload a new build from a new path")]
+        AlreadyLoaded { path: PathBuf },
+        #[error("{path:?}: {source}")]
+        Io {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+
+    pub(crate) struct Loaded {
+        routes: ExtensionPluginResponse,
+        columns: Vec<Column>,
+        call: unsafe extern "C" fn(*mut c_void, *const c_char) -> *mut c_char,
+        free: unsafe extern "C" fn(*mut c_char),
+        shutdown: unsafe extern "C" fn(*mut c_void),
+        state: *mut c_void,
+        // which loaded module it came from, to spot the loader handing one back twice
+        module: *const PluginModule,
+        // last, so the library's only unloaded once nothing can call into it
+        _library: Arc<Library>,
+    }
+
+    // safe: the ABI requires `call` and `shutdown` to be callable from any thread
+    unsafe impl Send for Loaded {}
+    unsafe impl Sync for Loaded {}
+
+    type Tables = BTreeMap<String, Arc<Loaded>>;
+
+    /// Serves every table from the plugin libraries it's loaded, as one extension. Clones
+    /// share the tables, so one can `reload` while another is being served.
+    #[derive(Clone)]
+    pub struct Host {
+        name: String,
+        tables: Arc<RwLock<Tables>>,
+    }
+
+    impl fmt::Debug for Host {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Host")
+                .field("name", &self.name)
+                .field("tables", &self.tables())
+                .finish()
+        }
+    }
+
+    /// What a `reload` did.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Reloaded {
+        /// Tables that took the place of ones already loaded
+        pub replaced: Vec<String>,
+        /// Tables the host didn't have before
+        pub added: Vec<String>,
+        /// osquery needs a fresh registration to see the change, see `reregister`
+        pub routes_changed: bool,
+        /// Every call on the replaced tables finished in time and they've been shut down
+        pub drained: bool,
+    }
+
+    fn text(path: &Path, s: *const c_char, what: &str) -> Result<String, LoadError> {
+        if s.is_null() {
+            return Err(LoadError::Malformed {
+                path: path.into(),
+                message: format!("no {}", what),
+            });
+        }
+        // safe: the ABI says it's a nul-terminated string that lives with the library
+        let s = unsafe { CStr::from_ptr(s) };
+        s.to_str()
+            .map(str::to_owned)
+            .map_err(|e| LoadError::Malformed {
+                path: path.into(),
+                message: format!("{} isn't UTF-8: {}", what, e),
+            })
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(
+        path: &Path,
+        s: *const c_char,
+        what: &str,
+    ) -> Result<T, LoadError> {
+        serde_json::from_str(&text(path, s, what)?).map_err(|e| LoadError::Malformed {
+            path: path.into(),
+            message: format!("bad {}: {}", what, e),
+        })
+    }
+
+    // the tables in the library at `path`
+    unsafe fn open(path: &Path) -> Result<Tables, LoadError> {
+        let library = Library::new(path).map_err(|source| LoadError::Library {
+            path: path.into(),
+            source,
+        })?;
+        let symbol = CString::new(ENTRY_POINT).unwrap_or_default();
+        let entry = library
+            .get::<EntryPoint>(symbol.as_bytes_with_nul())
+            .map_err(|source| LoadError::Library {
+                path: path.into(),
+                source,
+            })?;
+        let raw = entry();
+        if raw.is_null() {
+            return Err(LoadError::Malformed {
+                path: path.into(),
+                message: "no plugin module".into(),
+            });
+        }
+        let module = &*raw;
+        if module.abi_version != ABI_VERSION {
+            return Err(LoadError::Abi {
+                path: path.into(),
+                found: module.abi_version,
+            });
+        }
+        let descriptors = if module.table_count == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(module.tables, module.table_count)
+        };
+        let library = Arc::new(library);
+        let mut tables = BTreeMap::new();
+        for d in descriptors {
+            let name = text(path, d.name, "table name")?;
+            let table = Loaded {
+                routes: json(path, d.routes, "routes")?,
+                columns: json(path, d.columns, "columns")?,
+                call: d.call,
+                free: d.free,
+                shutdown: d.shutdown,
+                state: d.state,
+                module: raw,
+                _library: library.clone(),
+            };
+            if tables.insert(name.clone(), Arc::new(table)).is_some() {
+                return Err(LoadError::Duplicate {
+                    path: path.into(),
+                    table: name,
+                });
+            }
+        }
+        Ok(tables)
+    }
+
+    impl Host {
+        /// A host registering with osquery as `name`
+        pub fn named<S: Into<String>>(name: S) -> Self {
+            Self {
+                name: name.into(),
+                tables: Arc::default(),
+            }
+        }
+
+        fn read(&self) -> std::sync::RwLockReadGuard<'_, Tables> {
+            self.tables.read().unwrap_or_else(|e| e.into_inner())
+        }
+
+        fn write(&self) -> std::sync::RwLockWriteGuard<'_, Tables> {
+            self.tables.write().unwrap_or_else(|e| e.into_inner())
+        }
+
+        pub fn tables(&self) -> Vec<String> {
+            self.read().keys().cloned().collect()
+        }
+
+        /// Load the tables from the plugin library at `path`, returning their names.
+        ///
+        /// # Safety
+        /// This runs the library's initializers and trusts it to follow the ABI, so it's
+        /// exactly as safe as the library is. Only load what you'd link in yourself.
+        pub unsafe fn load<P: AsRef<Path>>(&self, path: P) -> Result<Vec<String>, LoadError> {
+            let path = path.as_ref();
+            let loaded = open(path)?;
+            let mut tables = self.write();
+            if let Some(name) = loaded.keys().find(|name| tables.contains_key(*name)) {
+                return Err(LoadError::Duplicate {
+                    path: path.into(),
+                    table: name.clone(),
+                });
+            }
+            let names = loaded.keys().cloned().collect::<Vec<_>>();
+            info!(?path, tables = ?names, "loaded plugin library");
+            tables.extend(loaded);
+            Ok(names)
+        }
+
+        /// `load` every shared library (by the platform's extension) in `dir`. One that
+        /// fails to load is logged and skipped, rather than taking the rest down with it.
+        ///
+        /// # Safety
+        /// See `load`, for every library in the directory.
+        pub unsafe fn load_dir<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<String>, LoadError> {
+            let dir = dir.as_ref();
+            let io = |source| LoadError::Io {
+                path: dir.into(),
+                source,
+            };
+            let mut paths = std::fs::read_dir(dir)
+                .map_err(io)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(io)?;
+            paths.retain(|p| {
+                p.extension().and_then(|e| e.to_str()) == Some(std::env::consts::DLL_EXTENSION)
+            });
+            // same order every time, so a duplicate table is always blamed on the same library
+            paths.sort();
+            let mut names = vec![];
+            for path in paths {
+                match self.load(&path) {
+                    Ok(loaded) => names.extend(loaded),
+                    Err(error) => warn!(%error, "skipping plugin library"),
+                }
+            }
+            Ok(names)
+        }
+
+        /// Load the library at `path` and swap its tables in, replacing any already loaded
+        /// under the same names. Calls running on a replaced table finish on it; it's shut
+        /// down once they have, or left to go when the last one does if that takes longer
+        /// than `drain`. The old library is unloaded once none of its tables are left.
+        ///
+        /// The dynamic loader hands back the library it already has for a path it's seen,
+        /// so put each new build at a new path (a versioned file name, say).
+        ///
+        /// # Safety
+        /// See `load`.
+        pub unsafe fn reload<P: AsRef<Path>>(
+            &self,
+            path: P,
+            drain: Duration,
+        ) -> Result<Reloaded, LoadError> {
+            let path = path.as_ref();
+            let loaded = open(path)?;
+            let mut replaced = vec![];
+            let mut added = vec![];
+            let mut routes_changed = false;
+            {
+                let mut tables = self.write();
+                for (name, table) in &loaded {
+                    if let Some(old) = tables.get(name) {
+                        if old.module == table.module {
+                            return Err(LoadError::AlreadyLoaded { path: path.into() });
+                        }
+                    }
+                }
+                for (name, table) in loaded {
+                    routes_changed |=
+                        tables.get(&name).map(|old| &old.routes) != Some(&table.routes);
+                    match tables.insert(name.clone(), table) {
+                        Some(old) => replaced.push((name, old)),
+                        None => added.push(name),
+                    }
+                }
+            }
+            let mut drained = true;
+            let deadline = Instant::now() + drain;
+            let replaced = replaced
+                .into_iter()
+                .map(|(name, old)| {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    // safe: as for `call`
+                    drained &=
+                        drain_then_shutdown(&name, old, left, |t| unsafe { (t.shutdown)(t.state) });
+                    name
+                })
+                .collect::<Vec<_>>();
+            info!(
+                ?path,
+                ?replaced,
+                ?added,
+                routes_changed,
+                drained,
+                "reloaded plugin library"
+            );
+            Ok(Reloaded {
+                replaced,
+                added,
+                routes_changed,
+                drained,
+            })
+        }
+
+        /// Withdraw the registration osquery gave `uuid` and register the host's tables as
+        /// they are now, for after a `reload` that changed them. The returned handle serves
+        /// them on the new socket osquery hands out; the old server stops getting calls.
+        pub fn reregister<C: Connector>(
+            &self,
+            client: &mut Client<C>,
+            uuid: ExtensionRouteUUID,
+        ) -> Result<Handle<Self, C>, anyhow::Error> {
+            crate::deregister(client, uuid)?;
+            self.clone().install(client)
+        }
+    }
+
+    impl ServedTable for Loaded {
+        fn routes(&self) -> &ExtensionPluginResponse {
+            &self.routes
+        }
+
+        fn columns(&self) -> &[Column] {
+            &self.columns
+        }
+
+        fn shutdown(&self) -> Result<(), String> {
+            // safe: as for `call`
+            unsafe { (self.shutdown)(self.state) };
+            Ok(())
+        }
+    }
+
+    impl MultiTable for Host {
+        type Table = Loaded;
+
+        fn extension_name(&self) -> &str {
+            &self.name
+        }
+
+        // the caller holds on to this table (not the lock) for the call, so a reload can
+        // swap it
+        fn table(&self, name: &str) -> Option<Arc<Loaded>> {
+            self.read().get(name).cloned()
+        }
+
+        fn tables_by_name(&self) -> Vec<(String, Arc<Loaded>)> {
+            self.read()
+                .iter()
+                .map(|(name, table)| (name.clone(), table.clone()))
+                .collect()
+        }
+
+        fn call(&self, item: &str, table: &Loaded, request: ExtensionPluginRequest) -> Response {
+            let request = c_string(serde_json::to_string(&request).unwrap_or_default());
+            // safe: the library is loaded, and the ABI says how these get called
+            let wire = unsafe {
+                let raw = (table.call)(table.state, request.as_ptr());
+                if raw.is_null() {
+                    return Response::failure(format!("`{}` returned nothing", item));
+                }
+                let wire = serde_json::from_slice::<WireResponse>(CStr::from_ptr(raw).to_bytes());
+                (table.free)(raw);
+                wire
+            };
+            match wire {
+                Ok(wire) => wire.into(),
+                Err(e) => Response::failure(format!("`{}` returned a bad response: {}", item, e)),
+            }
+        }
+    }
+
+    multi_table_plugin!(Host, "plugin_host", Host::named);
+}
@@ -0,0 +1,185 @@
+// Serving on the calling thread alone, for appliances and containers that count threads.
+// `Handle::start` runs an accept loop on a thread of its own and a thread per connection;
+// `Handle::run_single_threaded` polls the listener and every connection from one loop
+// instead. A connection with a message waiting gets it read and answered in full before
+// anything else happens, so one slow `generate` (or a peer that sends half a message)
+// holds up everyone, as does a heartbeat ping osquery is slow to answer. The manager
+// handshake (`Client::register_table` and friends) already happens on the caller's
+// thread, so with this, nothing else is ever started.
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info, info_span, warn};
+
+use crate::buffer::BufferPool;
+use crate::heartbeat::Watch;
+use crate::transport::{Listener, Unix};
+use crate::{
+    metrics, Connection, ExtensionSyncProcessor, Handle, Plugin, PluginHandler, Served,
+    ShutdownReason, NEXT_CONNECTION,
+};
+
+impl<T> Handle<T, Unix>
+where
+    T: Plugin + PluginHandler + Debug,
+{
+    /// Serve the plugin on the calling thread, without starting any others, until osquery
+    /// asks the extension to shut down. Calls are answered one at a time, in the order
+    /// they arrive. `ServerOptions::accept_queue`'s high-water mark still caps how many
+    /// connections are open at once (past it they wait in the listen backlog), and a
+    /// sandbox applies to the calling thread.
+    pub fn run_single_threaded(self) -> Result<ShutdownReason, thrift::Error> {
+        let options = self.options;
+        let shutdown_requested = Arc::new(AtomicBool::new(false));
+        let processor = ExtensionSyncProcessor::new(Served {
+            plugin: Arc::new(self.server),
+            // on this thread, like everything else here
+            shutdown: |plugin| plugin.handle_shutdown(),
+            shutdown_requested: shutdown_requested.clone(),
+            // this loop checks after every call, there's nothing to wake
+            wake: Box::new(|| {}),
+        });
+        let listener = <UnixListener as Listener>::bind(&self.socket_path, &options)?;
+        info!("Listening at {:?} on one thread", self.socket_path);
+        // connected while we can still reach osquery's socket
+        let mut heartbeat = self
+            .heartbeat
+            .map(|(manager, heartbeat)| Watch::<Unix>::new(manager, heartbeat));
+        if let Some(run_as) = &options.run_as {
+            run_as.apply()?;
+            info!(uid = run_as.uid, gid = run_as.gid, "dropped privileges");
+        }
+        let read_pool = BufferPool::new(options.read_buffer_size, options.pooled_buffers);
+        let write_pool = BufferPool::new(options.write_buffer_size, options.pooled_buffers);
+
+        let _span = info_span!("listening").entered();
+        let _serving = metrics::global().server_started();
+        let mut connections: Vec<(Connection<UnixStream>, RawFd, Instant)> = Vec::new();
+        let mut failures = 0;
+        loop {
+            let accepting = match options.accept_queue.high_water {
+                Some(high_water) => connections.len() < high_water,
+                None => true,
+            };
+            let mut fds = Vec::with_capacity(connections.len() + 1);
+            fds.push(pollfd(listener.as_raw_fd(), accepting));
+            for (_, fd, _) in &connections {
+                fds.push(pollfd(*fd, true));
+            }
+            let timeout = idle_timeout(&connections, options.read_timeout);
+            let timeout = match &heartbeat {
+                Some(watch) => {
+                    Some(timeout.map_or(watch.until_next(), |t| t.min(watch.until_next())))
+                }
+                None => timeout,
+            };
+            poll(&mut fds, timeout)?;
+            if let Some(watch) = &mut heartbeat {
+                if !watch.beat() {
+                    info!("osquery's gone, no longer serving");
+                    return Ok(ShutdownReason::ManagerGone);
+                }
+            }
+
+            // answer before accepting, so a storm of new connections can't starve them
+            let mut i = 0;
+            connections.retain_mut(|(connection, _, last_active)| {
+                i += 1;
+                if fds[i].revents == 0 {
+                    return match options.read_timeout {
+                        Some(timeout) if last_active.elapsed() >= timeout => {
+                            debug!("connection timed out, closing it");
+                            false
+                        }
+                        _ => true,
+                    };
+                }
+                *last_active = Instant::now();
+                // everything the peer's sent, including what's already been read ahead
+                loop {
+                    if !connection.process(&processor) {
+                        return false;
+                    }
+                    if shutdown_requested.load(Ordering::SeqCst) || !connection.buffered() {
+                        return true;
+                    }
+                }
+            });
+            if shutdown_requested.load(Ordering::SeqCst) {
+                info!("osquery asked for a shutdown, no longer serving");
+                return Ok(ShutdownReason::Requested);
+            }
+
+            if fds[0].revents == 0 {
+                continue;
+            }
+            match Listener::accept(&listener) {
+                Ok(stream) => {
+                    failures = 0;
+                    let id = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+                    let fd = stream.as_raw_fd();
+                    match Connection::open(id, stream, &read_pool, &write_pool, &options) {
+                        Ok(Some(connection)) => connections.push((connection, fd, Instant::now())),
+                        Ok(None) => {}
+                        Err(error) => warn!(%error, "couldn't set up the connection"),
+                    }
+                }
+                Err(e) => match options.accept_errors.backoff(failures) {
+                    Some(backoff) => {
+                        warn!(error = %e, ?backoff, "couldn't accept a connection, retrying");
+                        failures = failures.saturating_add(1);
+                        std::thread::sleep(backoff);
+                    }
+                    None => {
+                        error!("incoming connection had a problem! {}", e);
+                        return Err(e.into());
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn pollfd(fd: RawFd, readable: bool) -> libc::pollfd {
+    libc::pollfd {
+        fd: if readable { fd } else { -1 },
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+// until the first idle connection would time out, or forever
+fn idle_timeout<S>(
+    connections: &[(S, RawFd, Instant)],
+    timeout: Option<Duration>,
+) -> Option<Duration> {
+    let timeout = timeout?;
+    connections
+        .iter()
+        .map(|(_, _, last_active)| timeout.saturating_sub(last_active.elapsed()))
+        .min()
+}
+
+fn poll(fds: &mut [libc::pollfd], timeout: Option<Duration>) -> io::Result<()> {
+    let timeout = timeout.map_or(-1, |t| {
+        // rounded up, so a timeout a hair away doesn't spin
+        i32::try_from(t.as_millis() + 1).unwrap_or(i32::MAX)
+    });
+    loop {
+        // safe: fds is a live slice of pollfds and its length goes with it
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ret >= 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
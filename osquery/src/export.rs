@@ -0,0 +1,91 @@
+// Writing rows out as CSV or newline-delimited JSON, for tools that dump osquery data to
+// files. `Export` covers both what tables generate and what `Client::query` returns.
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufWriter, Write};
+
+use serde_json::{Number, Value};
+
+use crate::ColumnValue;
+
+pub trait Export {
+    /// CSV with a header row. The columns are every key any row has, sorted, and a row
+    /// without one of them leaves it empty.
+    fn to_csv<W: Write>(&self, writer: W) -> io::Result<()>;
+    /// One JSON object per line
+    fn to_ndjson<W: Write>(&self, writer: W) -> io::Result<()>;
+}
+
+/// `TableRows`. Numbers come out as JSON numbers.
+impl Export for [BTreeMap<String, ColumnValue>] {
+    fn to_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_csv(self, writer, ColumnValue::to_string)
+    }
+
+    fn to_ndjson<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_ndjson(self, writer, |value| match value {
+            ColumnValue::Text(v) => Value::from(v.as_str()),
+            ColumnValue::Integer(v) => Value::from(*v),
+            ColumnValue::BigInt(v) => Value::from(*v),
+            // NaN and infinities have no JSON spelling
+            ColumnValue::Double(v) => Number::from_f64(*v).map_or(Value::Null, Value::Number),
+        })
+    }
+}
+
+/// Query results, every value a string the way osquery sends them.
+impl Export for [BTreeMap<String, String>] {
+    fn to_csv<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_csv(self, writer, String::clone)
+    }
+
+    fn to_ndjson<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_ndjson(self, writer, |value| Value::from(value.as_str()))
+    }
+}
+
+fn write_csv<V, W, F>(rows: &[BTreeMap<String, V>], writer: W, text: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(&V) -> String,
+{
+    let mut writer = BufWriter::new(writer);
+    let columns: BTreeSet<&String> = rows.iter().flat_map(|row| row.keys()).collect();
+    write_record(&mut writer, columns.iter().map(|c| c.to_string()))?;
+    for row in rows {
+        let fields = columns
+            .iter()
+            .map(|c| row.get(*c).map(&text).unwrap_or_default());
+        write_record(&mut writer, fields)?;
+    }
+    writer.flush()
+}
+
+// RFC 4180: quote a field with a comma, quote, or line break in it, doubling its quotes
+fn write_record<W: Write>(writer: &mut W, fields: impl Iterator<Item = String>) -> io::Result<()> {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        if field.contains(&[',', '"', '\r', '\n'][..]) {
+            write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            writer.write_all(field.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\r\n")
+}
+
+fn write_ndjson<V, W, F>(rows: &[BTreeMap<String, V>], writer: W, json: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(&V) -> Value,
+{
+    let mut writer = BufWriter::new(writer);
+    for row in rows {
+        let object: serde_json::Map<String, Value> =
+            row.iter().map(|(k, v)| (k.clone(), json(v))).collect();
+        serde_json::to_writer(&mut writer, &object)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
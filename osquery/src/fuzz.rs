@@ -0,0 +1,211 @@
+// Fuzzing the code that takes osqueryd's calls apart. `call` turns fuzzer input into a
+// plugin request (mostly osquery-shaped, so it gets past the first checks, with arbitrary
+// actions, fields and context JSON thrown in) and hands it to `handle_call`; `context`
+// feeds raw bytes to the QueryContext parser and everything that reads it. Either panics
+// if the code under test does, or if a call comes back without a status for osquery.
+//
+// The cargo-fuzz targets in `fuzz/` run these against `FuzzTable`. Plugin crates can run
+// `call` against their own tables the same way.
+use std::collections::BTreeMap;
+
+use arbitrary::{Arbitrary, Unstructured};
+use serde_json::{json, Value};
+
+use crate::{
+    Column, ColumnValue, ExtensionPluginRequest, Plugin, PluginHandler, QueryContext, Response,
+    TablePlugin, TableRows,
+};
+
+#[derive(Debug, Arbitrary)]
+enum Action {
+    Generate,
+    Columns,
+    Insert,
+    Update,
+    Delete,
+    Other(String),
+    Missing,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Constraint {
+    // which of the table's columns, or past the end for one it doesn't have
+    column: u8,
+    op: u8,
+    expr: String,
+    // older osquery quoted everything
+    quoted: bool,
+}
+
+#[derive(Debug, Arbitrary)]
+enum Context {
+    Structured {
+        constraints: Vec<Constraint>,
+        cols_used: Vec<u8>,
+        affinity: Option<String>,
+    },
+    Json(String),
+    Missing,
+}
+
+/// Fuzzer input as a plugin request.
+#[derive(Debug, Arbitrary)]
+pub struct Request {
+    action: Action,
+    context: Context,
+    extra: Vec<(String, String)>,
+}
+
+impl Request {
+    /// The request as osquery would send it to a table with `columns`.
+    pub fn into_plugin_request(self, columns: &[Column]) -> ExtensionPluginRequest {
+        let column = |i: u8| match columns.get(i as usize) {
+            Some(column) => column.name.clone(),
+            None => format!("column_{}", i),
+        };
+        let mut request = self.extra.into_iter().collect::<BTreeMap<_, _>>();
+        let action = match self.action {
+            Action::Generate => Some("generate".to_string()),
+            Action::Columns => Some("columns".to_string()),
+            Action::Insert => Some("insert".to_string()),
+            Action::Update => Some("update".to_string()),
+            Action::Delete => Some("delete".to_string()),
+            Action::Other(action) => Some(action),
+            Action::Missing => None,
+        };
+        if let Some(action) = action {
+            request.insert("action".to_string(), action);
+        }
+        let context = match self.context {
+            Context::Structured {
+                constraints,
+                cols_used,
+                affinity,
+            } => {
+                let constraints = constraints
+                    .into_iter()
+                    .map(|c| {
+                        let op = if c.quoted {
+                            Value::from(c.op.to_string())
+                        } else {
+                            Value::from(c.op)
+                        };
+                        json!({
+                            "name": column(c.column),
+                            "affinity": affinity.clone().unwrap_or_else(|| "TEXT".into()),
+                            "list": [{ "op": op, "expr": c.expr }],
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let cols_used = cols_used.into_iter().map(column).collect::<Vec<_>>();
+                Some(json!({ "colsUsed": cols_used, "constraints": constraints }).to_string())
+            }
+            Context::Json(json) => Some(json),
+            Context::Missing => None,
+        };
+        if let Some(context) = context {
+            request.insert("context".to_string(), context);
+        }
+        request
+    }
+}
+
+/// What a call has to come back with: a response with a status, or an error the server
+/// can report to osquery as an exception.
+pub fn check(result: &thrift::Result<Response>) {
+    match result {
+        Ok(response) => assert!(
+            response.status.is_some(),
+            "response without a status: {:?}",
+            response
+        ),
+        Err(thrift::Error::Application(_)) | Err(thrift::Error::Protocol(_)) => {}
+        Err(e) => panic!(
+            "call failed with {:?}, which osquery can't be told about",
+            e
+        ),
+    }
+}
+
+/// Make a request out of `data`, send it to `table` and `check` what comes back.
+pub fn call<T: TablePlugin + PluginHandler>(table: &T, data: &[u8]) {
+    let request = match Request::arbitrary(&mut Unstructured::new(data)) {
+        Ok(request) => request,
+        Err(_) => return,
+    };
+    let columns = table.columns().unwrap_or_default();
+    let request = request.into_plugin_request(&columns);
+    check(&table.handle_call(T::REGISTRY.into(), T::NAME.into(), request));
+}
+
+/// Parse `data` as a query context and read it every way a table might.
+pub fn context(data: &[u8]) {
+    let query = match serde_json::from_slice::<QueryContext>(data) {
+        Ok(query) => query,
+        Err(_) => return,
+    };
+    let _ = query.constraint_summary();
+    let _ = query.columns_used();
+    for list in &query.constraints {
+        let name = list.name.as_str();
+        let _ = query.is_column_used(name);
+        let _ = query.equals_set(name);
+        let _ = query.int_equals_set(name);
+        let _ = query.int_range(name);
+        let _ = query.double_range(name);
+        for constraint in &list.list {
+            let _ = constraint.matches(name);
+        }
+    }
+}
+
+/// A table leaning on as much of the dispatcher as it can: every column type, a required
+/// column, row validation, and a `generate` that reads its constraints every which way.
+#[derive(Debug, Default)]
+pub struct FuzzTable;
+
+impl Plugin for FuzzTable {
+    type Error = std::convert::Infallible;
+    const NAME: &'static str = "fuzz_table";
+
+    fn new() -> Self {
+        Self
+    }
+}
+
+impl TablePlugin for FuzzTable {
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(vec![
+            Column::text("key").required(),
+            Column::text("path").index(),
+            Column::integer("number"),
+            Column::big_int("big"),
+            Column::double("ratio").hidden(),
+        ])
+    }
+
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let numbers = query.int_range("number").ok();
+        let rows = query
+            .equals_set("key")
+            .into_iter()
+            .filter(|key| query.constraints_on("path").all(|c| c.matches(key)))
+            .map(|key| {
+                let mut row = BTreeMap::new();
+                row.insert("key".to_string(), ColumnValue::text(key.as_str()));
+                query.insert_if_used(&mut row, "path", || key.clone());
+                if let Some(numbers) = &numbers {
+                    row.insert("big".to_string(), ColumnValue::big_int(*numbers.start()));
+                }
+                row
+            })
+            .collect();
+        Ok(rows)
+    }
+
+    fn validate_rows(&self) -> bool {
+        true
+    }
+
+    fn shutdown(&self) {}
+}
@@ -3,8 +3,7 @@
     clippy::unused_unit,
     clippy::redundant_field_names,
     clippy::match_single_binding,
-    deprecated,
-    renamed_and_removed_lints
+    deprecated
 )]
-pub mod osquery;
+pub use osquery_proto as osquery;
 pub mod table;
\ No newline at end of file
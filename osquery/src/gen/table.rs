@@ -1,4 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::num::{ParseFloatError, ParseIntError};
+use std::ops::{Bound, RangeInclusive};
+
+use crate::{pattern, ColumnValue, Deadline, PluginRequest};
 
 // GENERATED! DO NOT MANGLE. source: table.go
 use serde::{Deserialize, Serialize};
@@ -23,12 +27,155 @@ pub type Row = HashMap<String, String>;
 pub struct Column {
     pub name: String,
     pub kind: ColumnType,
+    #[serde(default)]
+    pub options: ColumnOptions,
+    /// What the column holds, shown in `extension_schema`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Which platforms the column is filled in on, if not all of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
 }
 
 impl Column {
+    pub fn new(name: &str, kind: ColumnType) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            options: ColumnOptions::DEFAULT,
+            description: None,
+            platform: None,
+        }
+    }
+
+    pub fn describe<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// e.g. `"linux"` or `"darwin,linux"`, as in osquery's own specs
+    pub fn platform<S: Into<String>>(mut self, platform: S) -> Self {
+        self.platform = Some(platform.into());
+        self
+    }
+
     pub fn to_pair(&self) -> (String, ColumnType) {
         (self.name.to_string(), self.kind)
     }
+
+    pub fn with_options(mut self, options: ColumnOptions) -> Self {
+        self.options = self.options | options;
+        self
+    }
+
+    /// osquery can look rows up by this column efficiently
+    pub fn index(self) -> Self {
+        self.with_options(ColumnOptions::INDEX)
+    }
+
+    /// Queries have to constrain this column with `=`, see `ColumnOptions::REQUIRED`
+    pub fn required(self) -> Self {
+        self.with_options(ColumnOptions::REQUIRED)
+    }
+
+    pub fn additional(self) -> Self {
+        self.with_options(ColumnOptions::ADDITIONAL)
+    }
+
+    pub fn optimized(self) -> Self {
+        self.with_options(ColumnOptions::OPTIMIZED)
+    }
+
+    /// Left out of `SELECT *`
+    pub fn hidden(self) -> Self {
+        self.with_options(ColumnOptions::HIDDEN)
+    }
+}
+
+/// Fails to compile (by panicking in a const) if a `COLUMNS`-style schema is empty or
+/// names a column twice, mistakes osquery only reports at query time and only in its
+/// own log. Use it through `assert_columns!`.
+pub const fn check_columns(columns: &[(&str, ColumnType, ColumnOptions)]) {
+    if columns.is_empty() {
+        panic!("table schema has no columns");
+    }
+    let mut i = 0;
+    while i < columns.len() {
+        if columns[i].0.is_empty() {
+            panic!("table schema has a column with no name");
+        }
+        let mut j = i + 1;
+        while j < columns.len() {
+            if same_name(columns[i].0, columns[j].0) {
+                panic!("table schema has two columns with the same name");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+// str equality that works in a const fn
+const fn same_name(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Check a const column list at compile time, see `check_columns`:
+/// `osquery::assert_columns!(COLUMNS);`
+#[macro_export]
+macro_rules! assert_columns {
+    ($columns:expr) => {
+        const _: () = $crate::gen::table::check_columns($columns);
+    };
+}
+
+// ColumnOptions mirrors osquery's column option flags, sent as the column's `op`
+// in the plugin's routes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(transparent)]
+pub struct ColumnOptions(u8);
+
+impl ColumnOptions {
+    pub const DEFAULT: Self = Self(0);
+    pub const INDEX: Self = Self(1);
+    /// The table can't be generated without an `=` constraint on the column. The
+    /// dispatcher turns away `generate` calls that don't have one.
+    pub const REQUIRED: Self = Self(2);
+    pub const ADDITIONAL: Self = Self(4);
+    pub const OPTIMIZED: Self = Self(8);
+    pub const HIDDEN: Self = Self(16);
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// `|`, but usable in consts
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOr for ColumnOptions {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
 }
 
 // TextColumn is a helper for defining columns containing strings.
@@ -44,8 +191,10 @@ impl Column {
 pub enum ColumnType {
     Text,
     Integer,
+    #[serde(alias = "UNSIGNED_BIGINT")]
     BigInt,
     Double,
+    #[serde(other)]
     Unknown,
 }
 
@@ -54,22 +203,325 @@ pub enum ColumnType {
 // that can optionally be used to optimize the table generation. Note that the
 // osquery SQLite engine will perform the filtering with these constraints, so
 // it is not mandatory that they be used in table generation.
-#[derive(Serialize, Deserialize, Clone)]
+//
+// Every field is read leniently (see `lenient` below), so a context from any osquery
+// version parses; anything that can't be understood is left out rather than failing.
+#[derive(Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct QueryContext {
+    #[serde(default, deserialize_with = "lenient::strings")]
     pub cols_used: Vec<String>,
+    #[serde(default, deserialize_with = "lenient::number")]
     pub cols_used_bitset: usize,
+    #[serde(default, deserialize_with = "lenient::constraint_lists")]
     pub constraints: Vec<ConstraintList>,
+    // not part of what osquery sends, filled in by the dispatcher
+    #[serde(skip)]
+    pub(crate) deadline: Deadline,
+    // likewise, the rest of the request the context came in
+    #[serde(skip)]
+    pub(crate) request: PluginRequest,
+}
+
+impl QueryContext {
+    /// How long this call has left before the server gives up on it.
+    pub fn deadline(&self) -> &Deadline {
+        &self.deadline
+    }
+
+    /// Everything else osquery sent with this call besides `action` and `context`, e.g. an
+    /// `id`, for fields this crate doesn't model (yet).
+    pub fn request(&self) -> &PluginRequest {
+        &self.request
+    }
+
+    pub fn request_field(&self, key: &str) -> Option<&str> {
+        self.request.get(key).map(String::as_str)
+    }
+
+    /// The columns the query actually references. Empty if osquery didn't tell us,
+    /// in which case every column should be treated as used.
+    pub fn columns_used(&self) -> BTreeSet<&str> {
+        self.cols_used.iter().map(String::as_str).collect()
+    }
+
+    pub fn is_column_used(&self, name: &str) -> bool {
+        self.cols_used.is_empty() || self.cols_used.iter().any(|c| c == name)
+    }
+
+    /// Only run `f` if the query selects (or filters on) `name`. Handy for columns that
+    /// are expensive to compute, like hashes or remote lookups.
+    pub fn if_used<V, F: FnOnce() -> V>(&self, name: &str, f: F) -> Option<V> {
+        self.is_column_used(name).then(f)
+    }
+
+    /// Whether the query has an `=` constraint on `name`.
+    pub fn has_equals(&self, name: &str) -> bool {
+        self.constraints_on(name).any(|c| c.op == Operator::Equals)
+    }
+
+    /// Every constraint on `name`, whatever the operator.
+    pub fn constraints_on<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Constraint> {
+        self.constraints
+            .iter()
+            .filter(move |c| c.name == name)
+            .flat_map(|c| c.list.iter())
+    }
+
+    /// The values `name` has to be one of, from `name = ...` or `name IN (...)` (which
+    /// osquery hands over as one `=` per value). Empty if the query doesn't pin it down.
+    pub fn equals_set(&self, name: &str) -> HashSet<String> {
+        self.constraints_on(name)
+            .filter(|c| c.op == Operator::Equals)
+            .map(|c| c.expr.clone())
+            .collect()
+    }
+
+    /// `equals_set` for integer columns
+    pub fn int_equals_set(&self, name: &str) -> Result<HashSet<i64>, ParseIntError> {
+        self.constraints_on(name)
+            .filter(|c| c.op == Operator::Equals)
+            .map(|c| c.expr.trim().parse())
+            .collect()
+    }
+
+    /// For tables keyed on `name`: when the query pins it down with `=` or `IN`, hand every
+    /// key to `get_many` at once, e.g. for one batched API call instead of one per key.
+    /// `None` when it doesn't, and the table has to fall back to listing everything.
+    pub fn get_many<T, F>(&self, name: &str, get_many: F) -> Option<T>
+    where
+        F: FnOnce(&HashSet<String>) -> T,
+    {
+        let keys = self.equals_set(name);
+        if keys.is_empty() {
+            return None;
+        }
+        Some(get_many(&keys))
+    }
+
+    /// The integers `name` can be, going by its `=`, `<`, `<=`, `>` and `>=` constraints,
+    /// e.g. `1024..=65535` for `port > 1023`. Unconstrained ends are `i64::MIN`/`MAX`,
+    /// and a query no value could satisfy gives an empty range. Several `=` (how osquery
+    /// passes `IN (...)`) widen it to span all of them.
+    pub fn int_range(&self, name: &str) -> Result<RangeInclusive<i64>, ParseIntError> {
+        let (lower, upper) = self.numeric_range(name, str::parse::<i64>)?;
+        let start = match lower {
+            Bound::Included(v) => Some(v),
+            Bound::Excluded(v) => v.checked_add(1),
+            Bound::Unbounded => Some(i64::MIN),
+        };
+        let end = match upper {
+            Bound::Included(v) => Some(v),
+            Bound::Excluded(v) => v.checked_sub(1),
+            Bound::Unbounded => Some(i64::MAX),
+        };
+        match (start, end) {
+            (Some(start), Some(end)) => Ok(start..=end),
+            // `> i64::MAX` or `< i64::MIN`, nothing fits
+            #[allow(clippy::reversed_empty_ranges)]
+            _ => Ok(1..=0),
+        }
+    }
+
+    /// `int_range` for doubles. The bounds work with `RangeBounds::contains`.
+    pub fn double_range(&self, name: &str) -> Result<(Bound<f64>, Bound<f64>), ParseFloatError> {
+        self.numeric_range(name, str::parse::<f64>)
+    }
+
+    fn numeric_range<T, E, F>(&self, name: &str, parse: F) -> Result<(Bound<T>, Bound<T>), E>
+    where
+        T: PartialOrd + Copy,
+        F: Fn(&str) -> Result<T, E>,
+    {
+        let (mut lower, mut upper) = (Bound::Unbounded, Bound::Unbounded);
+        let mut equals: Option<(T, T)> = None;
+        for constraint in self.constraints_on(name) {
+            let op = &constraint.op;
+            if !matches!(
+                op,
+                Operator::Equals
+                    | Operator::GreaterThan
+                    | Operator::GreaterThanOrEquals
+                    | Operator::LessThan
+                    | Operator::LessThanOrEquals
+            ) {
+                continue;
+            }
+            let v = parse(constraint.expr.trim())?;
+            match op {
+                Operator::GreaterThan => lower = tighter(lower, Bound::Excluded(v), true),
+                Operator::GreaterThanOrEquals => lower = tighter(lower, Bound::Included(v), true),
+                Operator::LessThan => upper = tighter(upper, Bound::Excluded(v), false),
+                Operator::LessThanOrEquals => upper = tighter(upper, Bound::Included(v), false),
+                _ => {
+                    equals = Some(match equals {
+                        Some((min, max)) => {
+                            (if v < min { v } else { min }, if v > max { v } else { max })
+                        }
+                        None => (v, v),
+                    })
+                }
+            }
+        }
+        if let Some((min, max)) = equals {
+            lower = tighter(lower, Bound::Included(min), true);
+            upper = tighter(upper, Bound::Included(max), false);
+        }
+        Ok((lower, upper))
+    }
+
+    /// Short human-readable rundown of the constraints, e.g. `path = /etc/hosts, size > 10`.
+    pub fn constraint_summary(&self) -> String {
+        self.constraints
+            .iter()
+            .flat_map(|c| {
+                c.list
+                    .iter()
+                    .map(move |con| format!("{} {} {}", c.name, con.op.as_sql(), con.expr))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Like `if_used`, but drops the result straight into a row.
+    pub fn insert_if_used<V, F>(&self, row: &mut BTreeMap<String, ColumnValue>, name: &str, f: F)
+    where
+        V: Into<ColumnValue>,
+        F: FnOnce() -> V,
+    {
+        if let Some(value) = self.if_used(name, f) {
+            row.insert(name.to_string(), value.into());
+        }
+    }
+}
+
+impl QueryContext {
+    /// Build a context by hand, for tests. See also `qc!`.
+    pub fn builder() -> QueryContextBuilder {
+        QueryContextBuilder::default()
+    }
+}
+
+/// A `QueryContext` put together a constraint at a time, the way osquery would send it
+/// for the equivalent `WHERE` clause. `x IN (a, b)` is one `equals` per value.
+#[derive(Default, Clone)]
+pub struct QueryContextBuilder {
+    context: QueryContext,
+}
+
+impl QueryContextBuilder {
+    /// Add `name <op> value`
+    pub fn constraint<V: ToString>(mut self, name: &str, op: Operator, value: V) -> Self {
+        let constraint = Constraint {
+            op,
+            expr: value.to_string(),
+        };
+        match self.context.constraints.iter_mut().find(|c| c.name == name) {
+            Some(list) => list.list.push(constraint),
+            None => self.context.constraints.push(ConstraintList {
+                name: name.to_string(),
+                affinity: ColumnType::Unknown,
+                list: vec![constraint],
+            }),
+        }
+        self
+    }
+
+    pub fn equals<V: ToString>(self, name: &str, value: V) -> Self {
+        self.constraint(name, Operator::Equals, value)
+    }
+
+    pub fn greater_than<V: ToString>(self, name: &str, value: V) -> Self {
+        self.constraint(name, Operator::GreaterThan, value)
+    }
+
+    pub fn greater_than_or_equals<V: ToString>(self, name: &str, value: V) -> Self {
+        self.constraint(name, Operator::GreaterThanOrEquals, value)
+    }
+
+    pub fn less_than<V: ToString>(self, name: &str, value: V) -> Self {
+        self.constraint(name, Operator::LessThan, value)
+    }
+
+    pub fn less_than_or_equals<V: ToString>(self, name: &str, value: V) -> Self {
+        self.constraint(name, Operator::LessThanOrEquals, value)
+    }
+
+    pub fn like<V: ToString>(self, name: &str, pattern: V) -> Self {
+        self.constraint(name, Operator::Like, pattern)
+    }
+
+    pub fn glob<V: ToString>(self, name: &str, pattern: V) -> Self {
+        self.constraint(name, Operator::Glob, pattern)
+    }
+
+    pub fn regexp<V: ToString>(self, name: &str, pattern: V) -> Self {
+        self.constraint(name, Operator::Regexp, pattern)
+    }
+
+    /// The affinity osquery reports for `name`'s constraints, `Unknown` by default
+    pub fn affinity(mut self, name: &str, affinity: ColumnType) -> Self {
+        match self.context.constraints.iter_mut().find(|c| c.name == name) {
+            Some(list) => list.affinity = affinity,
+            None => self.context.constraints.push(ConstraintList {
+                name: name.to_string(),
+                affinity,
+                list: vec![],
+            }),
+        }
+        self
+    }
+
+    /// The columns the query references, for `is_column_used`. All of them by default.
+    pub fn columns_used<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.context.cols_used = columns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn build(self) -> QueryContext {
+        self.context
+    }
+}
+
+// the narrower of two lower (or upper) bounds
+fn tighter<T: PartialOrd>(a: Bound<T>, b: Bound<T>, lower: bool) -> Bound<T> {
+    fn value<T>(bound: &Bound<T>) -> Option<&T> {
+        match bound {
+            Bound::Included(v) | Bound::Excluded(v) => Some(v),
+            Bound::Unbounded => None,
+        }
+    }
+    let b_wins = match (value(&a), value(&b)) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(x), Some(y)) if x == y => matches!(b, Bound::Excluded(_)),
+        (Some(x), Some(y)) => (y > x) == lower,
+    };
+    if b_wins {
+        b
+    } else {
+        a
+    }
 }
 
 // ConstraintList contains the details of the constraints for the given column.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ConstraintList {
     pub name: String,
+    #[serde(default = "unknown_affinity")]
     pub affinity: ColumnType,
+    #[serde(default, deserialize_with = "lenient::constraints")]
     pub list: Vec<Constraint>,
 }
 
+fn unknown_affinity() -> ColumnType {
+    ColumnType::Unknown
+}
+
 // Constraint contains both an operator and an expression that are applied as
 // constraints in the query.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -78,6 +530,20 @@ pub struct Constraint {
     pub expr: String,
 }
 
+impl Constraint {
+    /// Whether `value` passes this constraint the way SQLite would judge it, for `=`,
+    /// `LIKE`, and `GLOB`. Every other operator depends on the column's affinity, so this
+    /// says `true` and leaves them to osquery, which filters the rows again regardless.
+    pub fn matches(&self, value: &str) -> bool {
+        match self.op {
+            Operator::Equals => value == self.expr,
+            Operator::Like => pattern::like(&self.expr, value),
+            Operator::Glob => pattern::glob(&self.expr, value),
+            _ => true,
+        }
+    }
+}
+
 // Operator is an enum of the osquery operators.
 #[derive(serde_repr::Serialize_repr, serde_repr::Deserialize_repr, Debug, PartialEq, Clone)]
 #[repr(u8)]
@@ -93,3 +559,307 @@ pub enum Operator {
     Regexp = 67,
     Unique = 1,
 }
+
+impl Operator {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            Operator::Equals => "=",
+            Operator::GreaterThan => ">",
+            Operator::LessThanOrEquals => "<=",
+            Operator::LessThan => "<",
+            Operator::GreaterThanOrEquals => ">=",
+            Operator::Match => "MATCH",
+            Operator::Like => "LIKE",
+            Operator::Glob => "GLOB",
+            Operator::Regexp => "REGEXP",
+            Operator::Unique => "UNIQUE",
+        }
+    }
+}
+
+// osquery's context JSON has changed shape over the years: older versions wrote every
+// value as a string (`"op": "2"`) and empty lists as `""`, and newer SQLite brings
+// operators this crate doesn't know. These take any of it, dropping what they can't use.
+mod lenient {
+    use serde::{Deserialize, Deserializer};
+    use serde_json::Value;
+    use tracing::debug;
+
+    use super::{Constraint, ConstraintList, Operator};
+
+    fn as_u64(value: &Value) -> Option<u64> {
+        match value {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn as_string(value: Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    // an array, or nothing for `""`, `null`, or anything else that isn't one
+    fn items<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Value>, D::Error> {
+        match Value::deserialize(d)? {
+            Value::Array(items) => Ok(items),
+            _ => Ok(vec![]),
+        }
+    }
+
+    pub(super) fn number<'de, D: Deserializer<'de>>(d: D) -> Result<usize, D::Error> {
+        Ok(as_u64(&Value::deserialize(d)?).unwrap_or_default() as usize)
+    }
+
+    pub(super) fn strings<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<String>, D::Error> {
+        Ok(items(d)?.into_iter().filter_map(as_string).collect())
+    }
+
+    pub(super) fn constraint_lists<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Vec<ConstraintList>, D::Error> {
+        Ok(items(d)?
+            .into_iter()
+            .filter_map(|item| match ConstraintList::deserialize(item) {
+                Ok(list) => Some(list),
+                Err(error) => {
+                    debug!(%error, "skipping constraint list that doesn't parse");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    pub(super) fn constraints<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<Vec<Constraint>, D::Error> {
+        Ok(items(d)?
+            .into_iter()
+            .filter_map(|item| {
+                let op = item.get("op").and_then(as_u64);
+                let op = op.and_then(|op| serde_json::from_value::<Operator>(op.into()).ok());
+                let expr = item.get("expr").cloned().and_then(as_string);
+                match (op, expr) {
+                    (Some(op), Some(expr)) => Some(Constraint { op, expr }),
+                    _ => {
+                        // osquery filters the rows itself, so a constraint we can't read only
+                        // costs us the chance to narrow what we generate
+                        debug!(constraint = %item, "skipping constraint that doesn't parse");
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::RangeBounds;
+
+    fn parse(json: &str) -> QueryContext {
+        serde_json::from_str(json).expect("context should parse")
+    }
+
+    fn ops(list: &ConstraintList) -> Vec<(Operator, &str)> {
+        list.list
+            .iter()
+            .map(|c| (c.op.clone(), c.expr.as_str()))
+            .collect()
+    }
+
+    // 3.x serialized through boost's property trees: every value's a string, empty
+    // arrays come out as "", and there's no bitset
+    #[test]
+    fn osquery_3_context() {
+        let query = parse(
+            r#"{"colsUsed":["path","size"],"constraints":[
+                {"name":"path","list":[{"op":"2","expr":"/etc/hosts"}],"affinity":"TEXT"},
+                {"name":"size","list":"","affinity":"BIGINT"}]}"#,
+        );
+        assert_eq!(query.cols_used, ["path", "size"]);
+        assert_eq!(query.cols_used_bitset, 0);
+        assert_eq!(query.constraints.len(), 2);
+        assert_eq!(
+            ops(&query.constraints[0]),
+            [(Operator::Equals, "/etc/hosts")]
+        );
+        assert!(query.constraints[1].list.is_empty());
+        assert!(matches!(query.constraints[1].affinity, ColumnType::BigInt));
+
+        let query = parse(r#"{"constraints":""}"#);
+        assert!(query.constraints.is_empty());
+        assert!(query.cols_used.is_empty());
+    }
+
+    // 4.x moved to rapidjson, so numbers are numbers
+    #[test]
+    fn osquery_4_context() {
+        let query = parse(
+            r#"{"colsUsed":["pid","name"],"colsUsedBitset":3,"constraints":[
+                {"name":"pid","list":[{"op":32,"expr":"100"},{"op":16,"expr":"200"}],
+                 "affinity":"INTEGER"}]}"#,
+        );
+        assert_eq!(query.cols_used_bitset, 3);
+        assert_eq!(
+            ops(&query.constraints[0]),
+            [
+                (Operator::GreaterThanOrEquals, "100"),
+                (Operator::LessThan, "200")
+            ]
+        );
+        assert_eq!(query.int_range("pid").unwrap(), 100..=199);
+    }
+
+    // 5.x: the same shape, plus affinities and operators from newer SQLite
+    #[test]
+    fn osquery_5_context() {
+        let query = parse(
+            r#"{"colsUsed":["inode","path"],"colsUsedBitset":5,"constraints":[
+                {"name":"inode","list":[{"op":2,"expr":"42"}],"affinity":"UNSIGNED_BIGINT"},
+                {"name":"path","list":[{"op":65,"expr":"/tmp/%"},{"op":70,"expr":"x"}],
+                 "affinity":"TEXT"}]}"#,
+        );
+        assert!(matches!(query.constraints[0].affinity, ColumnType::BigInt));
+        // 70 is SQLite's IS NOT, which this crate doesn't know: dropped, the LIKE kept
+        assert_eq!(ops(&query.constraints[1]), [(Operator::Like, "/tmp/%")]);
+        assert!(query.is_column_used("path"));
+    }
+
+    #[test]
+    fn unknown_fields_are_ignored() {
+        let query = parse(
+            r#"{"colsUsed":["a"],"somethingNew":{"x":1},"constraints":[
+                {"name":"a","list":[{"op":2,"expr":"1","collation":"BINARY"}],
+                 "affinity":"BLOB","usable":true}]}"#,
+        );
+        assert!(matches!(query.constraints[0].affinity, ColumnType::Unknown));
+        assert_eq!(ops(&query.constraints[0]), [(Operator::Equals, "1")]);
+    }
+
+    #[test]
+    fn missing_fields_default() {
+        let query = parse("{}");
+        assert!(query.cols_used.is_empty());
+        assert_eq!(query.cols_used_bitset, 0);
+        assert!(query.constraints.is_empty());
+
+        // no affinity, no list, and a constraint missing its expr
+        let query = parse(
+            r#"{"constraints":[{"name":"a"},{"name":"b","list":[{"op":2},{"op":2,"expr":7}]}]}"#,
+        );
+        assert!(matches!(query.constraints[0].affinity, ColumnType::Unknown));
+        assert!(query.constraints[0].list.is_empty());
+        assert_eq!(ops(&query.constraints[1]), [(Operator::Equals, "7")]);
+    }
+
+    #[test]
+    fn unreadable_pieces_are_dropped() {
+        // a list without a name, and values of the wrong type altogether
+        let query = parse(
+            r#"{"colsUsed":"","colsUsedBitset":"lots","constraints":[
+                {"list":[{"op":2,"expr":"x"}]},{"name":"b","list":[{"op":2,"expr":"y"}]}]}"#,
+        );
+        assert!(query.cols_used.is_empty());
+        assert_eq!(query.cols_used_bitset, 0);
+        assert_eq!(query.constraints.len(), 1);
+        assert_eq!(query.constraints[0].name, "b");
+    }
+
+    fn range(query: QueryContextBuilder) -> RangeInclusive<i64> {
+        query.build().int_range("n").expect("bounds should parse")
+    }
+
+    #[test]
+    fn int_range_exclusive_bounds() {
+        let query = QueryContext::builder()
+            .greater_than("n", 1023)
+            .less_than("n", 65536);
+        assert_eq!(range(query), 1024..=65535);
+        let query = QueryContext::builder()
+            .greater_than_or_equals("n", 10)
+            .less_than_or_equals("n", 20);
+        assert_eq!(range(query), 10..=20);
+        assert_eq!(range(QueryContext::builder()), i64::MIN..=i64::MAX);
+    }
+
+    #[test]
+    fn int_range_past_the_ends_is_empty() {
+        let above = range(QueryContext::builder().greater_than("n", i64::MAX));
+        assert_eq!((*above.start(), *above.end()), (1, 0));
+        assert!(above.is_empty());
+        assert!(range(QueryContext::builder().less_than("n", i64::MIN)).is_empty());
+        let query = QueryContext::builder().greater_than("n", i64::MAX - 1);
+        assert_eq!(range(query), i64::MAX..=i64::MAX);
+    }
+
+    #[test]
+    fn int_range_equals_with_bounds() {
+        let query = QueryContext::builder().equals("n", 5).greater_than("n", 3);
+        assert_eq!(range(query), 5..=5);
+        // IN (1, 5, 9) with n < 7
+        let query = QueryContext::builder()
+            .equals("n", 1)
+            .equals("n", 5)
+            .equals("n", 9)
+            .less_than("n", 7);
+        assert_eq!(range(query), 1..=6);
+        let query = QueryContext::builder().equals("n", 5).greater_than("n", 5);
+        assert!(range(query).is_empty());
+    }
+
+    #[test]
+    fn int_range_contradictions_are_empty() {
+        let query = QueryContext::builder()
+            .greater_than("n", 10)
+            .less_than("n", 5);
+        assert!(range(query).is_empty());
+        let query = QueryContext::builder()
+            .greater_than("n", 4)
+            .less_than("n", 5);
+        assert!(range(query).is_empty());
+        // the tightest of several bounds on the same side wins
+        let query = QueryContext::builder()
+            .greater_than("n", 1)
+            .greater_than_or_equals("n", 3)
+            .less_than("n", 100)
+            .less_than_or_equals("n", 50);
+        assert_eq!(range(query), 3..=50);
+    }
+
+    #[test]
+    fn numeric_ranges_need_numbers() {
+        let query = QueryContext::builder().greater_than("n", "ten").build();
+        assert!(query.int_range("n").is_err());
+        assert!(query.double_range("n").is_err());
+        // only the operators a range cares about are parsed
+        let query = QueryContext::builder().like("n", "1%").build();
+        assert_eq!(query.int_range("n").unwrap(), i64::MIN..=i64::MAX);
+    }
+
+    #[test]
+    fn double_range_contains() {
+        let query = QueryContext::builder()
+            .greater_than("n", 0.5)
+            .less_than_or_equals("n", "2.5")
+            .build();
+        let bounds = query.double_range("n").unwrap();
+        assert_eq!(bounds, (Bound::Excluded(0.5), Bound::Included(2.5)));
+        assert!(!bounds.contains(&0.5));
+        assert!(bounds.contains(&0.5000001));
+        assert!(bounds.contains(&2.5));
+        assert!(!bounds.contains(&2.6));
+
+        let query = QueryContext::builder().equals("n", 1.5).build();
+        let bounds = query.double_range("n").unwrap();
+        assert!(bounds.contains(&1.5) && !bounds.contains(&1.6));
+        let unbounded = QueryContext::default().double_range("n").unwrap();
+        assert!(unbounded.contains(&f64::MAX) && unbounded.contains(&f64::MIN));
+    }
+}
@@ -0,0 +1,103 @@
+//! A small HTTP endpoint for orchestrators to health-check a long-running extension
+//! directly, rather than through osquery.
+//!
+//! - `GET /healthz`: 200 once a plugin is registered with osquery and a server is
+//!   accepting its calls, 503 until then (or after every server has stopped)
+//! - `GET /status`: the details as JSON, with per-table call and error counts and when
+//!   each table was last called
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde_json::json;
+use tracing::{debug, info};
+
+use crate::{builtin, metrics};
+
+/// Start answering health checks on `addr`, on a thread of its own. Returns the address
+/// actually bound, for when `addr`'s port is 0.
+pub fn serve(addr: SocketAddr) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let bound = listener.local_addr()?;
+    info!(addr = %bound, "serving health checks");
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(respond);
+            if let Err(error) = result {
+                debug!(%error, "health check connection failed");
+            }
+        }
+    });
+    Ok(bound)
+}
+
+/// Whether the extension is registered with osquery and serving it.
+pub fn is_ready() -> bool {
+    builtin::registrations() > 0 && metrics::global().snapshot().servers > 0
+}
+
+fn status() -> serde_json::Value {
+    let snapshot = metrics::global().snapshot();
+    let registered = builtin::registrations();
+    let tables: Vec<_> = snapshot
+        .tables
+        .iter()
+        .map(|t| {
+            json!({
+                "name": t.name,
+                "calls": t.calls,
+                "errors": t.errors,
+                "last_call": t.last_call
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+            })
+        })
+        .collect();
+    json!({
+        "ready": registered > 0 && snapshot.servers > 0,
+        "registered": registered,
+        "servers": snapshot.servers,
+        "active_connections": snapshot.active_connections,
+        "uptime": snapshot.uptime.as_secs(),
+        "tables": tables,
+    })
+}
+
+fn respond(stream: TcpStream) -> io::Result<()> {
+    // a checker that connects and says nothing shouldn't hold up the next one
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the headers don't matter, but read past them so the client sees a clean close
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/healthz")) if is_ready() => ("200 OK", "text/plain", "ok\n".into()),
+        (Some("GET"), Some("/healthz")) => (
+            "503 Service Unavailable",
+            "text/plain",
+            "not ready\n".into(),
+        ),
+        (Some("GET"), Some("/status")) => ("200 OK", "application/json", status().to_string()),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,164 @@
+// Noticing osquery has gone away. An extension's server only hears from osquery when it
+// has something to ask, so on its own it would keep serving a socket nobody will connect
+// to again after osqueryd exits or restarts. The heartbeat pings the manager every
+// `--extensions_interval`, like osquery's own extension watchdog, and gives up on it
+// once nothing's answered for `--extensions_timeout`. `Client::register_table` (and the
+// other installers) set one up with osquery's own settings; `Handle::start` runs it on a
+// thread of its own and stops with `ShutdownReason::ManagerGone` when it gives up.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::transport::Stream;
+use crate::version::Capabilities;
+use crate::{Client, Connector, TExtensionSyncClient};
+
+/// osquery's own default for `--extensions_interval`
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(3);
+/// osquery's own default for `--extensions_timeout`
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often to check the manager is still there, and how long it can go without
+/// answering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    /// Also how long each ping waits for its answer
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Heartbeat {
+    /// What osquery's flags say, with its defaults for anything it wouldn't tell.
+    pub fn from_capabilities(capabilities: &Capabilities) -> Self {
+        let interval = capabilities.extensions_interval.unwrap_or(DEFAULT_INTERVAL);
+        let timeout = capabilities.extensions_timeout.unwrap_or(DEFAULT_TIMEOUT);
+        // a flag set to 0 would ping in a tight loop, or give up at the first miss
+        Self {
+            interval: interval.max(Duration::from_secs(1)),
+            timeout: timeout.max(Duration::from_secs(1)),
+        }
+    }
+}
+
+impl<C: Connector> Client<C> {
+    /// The heartbeat osquery's `--extensions_interval` and `--extensions_timeout` call
+    /// for, or osquery's defaults if it won't say.
+    pub fn heartbeat(&mut self) -> Heartbeat {
+        match self.capabilities() {
+            Ok(capabilities) => Heartbeat::from_capabilities(capabilities),
+            Err(error) => {
+                debug!(%error, "couldn't ask osquery about its heartbeat, using the defaults");
+                Heartbeat::default()
+            }
+        }
+    }
+}
+
+// the pinging itself, for a loop that's waiting on other things too
+pub(crate) struct Watch<C: Connector> {
+    manager: PathBuf,
+    heartbeat: Heartbeat,
+    client: Option<Client<C>>,
+    answered: Instant,
+    next: Instant,
+}
+
+impl<C: Connector> Watch<C> {
+    // connects straight away, so make one before `RunAs::apply`: the user it drops to
+    // may not be allowed on osquery's socket
+    pub(crate) fn new(manager: PathBuf, heartbeat: Heartbeat) -> Self {
+        let client = Self::connect(&manager, heartbeat)
+            .map_err(|error| debug!(%error, ?manager, "couldn't connect the heartbeat yet"))
+            .ok();
+        let now = Instant::now();
+        Self {
+            manager,
+            heartbeat,
+            client,
+            answered: now,
+            next: now + heartbeat.interval,
+        }
+    }
+
+    // with every ping, reply included, bounded by the heartbeat's timeout
+    fn connect(manager: &Path, heartbeat: Heartbeat) -> thrift::Result<Client<C>> {
+        let stream = C::connect_timeout(manager, heartbeat.timeout)?;
+        stream.set_timeouts(Some(heartbeat.timeout))?;
+        Client::from_stream(manager, stream)
+    }
+
+    // until the next ping is due
+    pub(crate) fn until_next(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+
+    // ping if it's time. false once the manager's gone
+    pub(crate) fn beat(&mut self) -> bool {
+        if Instant::now() < self.next {
+            return true;
+        }
+        let result = match self.client.take() {
+            Some(client) => Ok(client),
+            None => Self::connect(&self.manager, self.heartbeat),
+        }
+        .and_then(|mut client| TExtensionSyncClient::ping(&mut *client).map(|_| client));
+        match result {
+            Ok(client) => {
+                self.client = Some(client);
+                self.answered = Instant::now();
+            }
+            // a new connection next time
+            Err(error) => debug!(%error, manager = ?self.manager, "manager didn't answer a ping"),
+        }
+        self.next = Instant::now() + self.heartbeat.interval;
+        let silent = self.answered.elapsed();
+        if silent >= self.heartbeat.timeout && self.client.is_none() {
+            warn!(?silent, manager = ?self.manager, "osquery stopped answering, giving up on it");
+            return false;
+        }
+        true
+    }
+
+    // ping on a thread of its own until the guard's dropped, calling `lost` if the
+    // manager goes first
+    pub(crate) fn spawn<F>(mut self, lost: F) -> Beating
+    where
+        C: 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopping = stopped.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(self.until_next());
+            if stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            if !self.beat() {
+                lost();
+                return;
+            }
+        });
+        Beating(stopped)
+    }
+}
+
+// the thread notices within an interval
+pub(crate) struct Beating(Arc<AtomicBool>);
+
+impl Drop for Beating {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
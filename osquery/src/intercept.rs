@@ -0,0 +1,221 @@
+//! Hooks around every call a `Client` makes to the extension manager, for logging,
+//! metrics or an audit trail without wrapping each method. They sit in the client's
+//! protocol, so calls made through `Deref` to the generated client are seen too.
+//! An `on_error` hook can't retry the call itself, but it's where to note that one
+//! should be.
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thrift::protocol::{
+    TFieldIdentifier, TInputProtocol, TListIdentifier, TMapIdentifier, TMessageIdentifier,
+    TMessageType, TOutputProtocol, TSetIdentifier, TStructIdentifier,
+};
+use thrift::{ApplicationError, ApplicationErrorKind};
+
+/// Callbacks for manager RPCs. `method` is the thrift method name (`query`,
+/// `registerExtension`, ...).
+pub trait Interceptor: Send + Sync {
+    fn on_request(&self, _method: &str) {}
+    /// The call got an answer. It may still be a failure status, that's up to the caller.
+    fn on_response(&self, _method: &str, _elapsed: Duration) {}
+    /// The call failed on the wire, or osquery answered with an exception.
+    fn on_error(&self, _method: &str, _elapsed: Duration, _error: &thrift::Error) {}
+}
+
+// shared by a client's input and output protocols
+#[derive(Default)]
+pub(crate) struct Hooks {
+    interceptors: Mutex<Vec<Arc<dyn Interceptor>>>,
+    // calls sent and not yet answered, oldest first (more than one when pipelining)
+    pending: Mutex<VecDeque<(String, Instant)>>,
+}
+
+impl Hooks {
+    pub(crate) fn add(&self, interceptor: Arc<dyn Interceptor>) {
+        self.interceptors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(interceptor);
+    }
+
+    fn each(&self, f: impl Fn(&dyn Interceptor)) {
+        // clone the list so a hook can't deadlock by adding another
+        let interceptors = self
+            .interceptors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for interceptor in interceptors {
+            f(interceptor.as_ref());
+        }
+    }
+
+    fn start(&self, method: &str) {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push_back((method.to_string(), Instant::now()));
+        self.each(|i| i.on_request(method));
+    }
+
+    // the oldest call got its answer
+    fn finish(&self, error: Option<&thrift::Error>) {
+        let call = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop_front();
+        if let Some((method, started)) = call {
+            let elapsed = started.elapsed();
+            match error {
+                Some(error) => self.each(|i| i.on_error(&method, elapsed, error)),
+                None => self.each(|i| i.on_response(&method, elapsed)),
+            }
+        }
+    }
+
+    // the connection broke, nothing pending is getting an answer now
+    fn check<T>(&self, result: thrift::Result<T>) -> thrift::Result<T> {
+        if let Err(error) = &result {
+            let pending =
+                std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+            for (method, started) in pending {
+                self.each(|i| i.on_error(&method, started.elapsed(), error));
+            }
+        }
+        result
+    }
+}
+
+/// The protocols a `Client` talks through, calling its interceptors.
+pub struct InterceptedInput<P> {
+    inner: P,
+    hooks: Arc<Hooks>,
+    exception: bool,
+}
+
+pub struct InterceptedOutput<P> {
+    inner: P,
+    hooks: Arc<Hooks>,
+}
+
+impl<P> InterceptedInput<P> {
+    pub(crate) fn new(inner: P, hooks: Arc<Hooks>) -> Self {
+        Self {
+            inner,
+            hooks,
+            exception: false,
+        }
+    }
+}
+
+impl<P> InterceptedOutput<P> {
+    pub(crate) fn new(inner: P, hooks: Arc<Hooks>) -> Self {
+        Self { inner, hooks }
+    }
+}
+
+macro_rules! read {
+    ($($name:ident -> $ty:ty),+ $(,)?) => {
+        $(
+        fn $name(&mut self) -> thrift::Result<$ty> {
+            let r = self.inner.$name();
+            self.hooks.check(r)
+        }
+        )+
+    };
+}
+
+macro_rules! write {
+    ($($name:ident($($arg:ident: $ty:ty),*)),+ $(,)?) => {
+        $(
+        fn $name(&mut self, $($arg: $ty),*) -> thrift::Result<()> {
+            let r = self.inner.$name($($arg),*);
+            self.hooks.check(r)
+        }
+        )+
+    };
+}
+
+impl<P: TInputProtocol> TInputProtocol for InterceptedInput<P> {
+    fn read_message_begin(&mut self) -> thrift::Result<TMessageIdentifier> {
+        let r = self.inner.read_message_begin();
+        let identifier = self.hooks.check(r)?;
+        self.exception = identifier.message_type == TMessageType::Exception;
+        Ok(identifier)
+    }
+
+    fn read_message_end(&mut self) -> thrift::Result<()> {
+        let r = self.inner.read_message_end();
+        let r = self.hooks.check(r);
+        if r.is_ok() {
+            if self.exception {
+                // the client decodes the exception itself, this is just so hooks hear of it
+                let error = thrift::Error::Application(ApplicationError::new(
+                    ApplicationErrorKind::Unknown,
+                    "osquery answered with an exception",
+                ));
+                self.hooks.finish(Some(&error));
+            } else {
+                self.hooks.finish(None);
+            }
+        }
+        r
+    }
+
+    read!(
+        read_struct_begin -> Option<TStructIdentifier>,
+        read_struct_end -> (),
+        read_field_begin -> TFieldIdentifier,
+        read_field_end -> (),
+        read_bool -> bool,
+        read_bytes -> Vec<u8>,
+        read_i8 -> i8,
+        read_i16 -> i16,
+        read_i32 -> i32,
+        read_i64 -> i64,
+        read_double -> f64,
+        read_string -> String,
+        read_list_begin -> TListIdentifier,
+        read_list_end -> (),
+        read_set_begin -> TSetIdentifier,
+        read_set_end -> (),
+        read_map_begin -> TMapIdentifier,
+        read_map_end -> (),
+        read_byte -> u8,
+    );
+}
+
+impl<P: TOutputProtocol> TOutputProtocol for InterceptedOutput<P> {
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> thrift::Result<()> {
+        self.hooks.start(&identifier.name);
+        let r = self.inner.write_message_begin(identifier);
+        self.hooks.check(r)
+    }
+
+    write!(
+        write_message_end(),
+        write_struct_begin(identifier: &TStructIdentifier),
+        write_struct_end(),
+        write_field_begin(identifier: &TFieldIdentifier),
+        write_field_end(),
+        write_field_stop(),
+        write_bool(b: bool),
+        write_bytes(b: &[u8]),
+        write_i8(i: i8),
+        write_i16(i: i16),
+        write_i32(i: i32),
+        write_i64(i: i64),
+        write_double(d: f64),
+        write_string(s: &str),
+        write_list_begin(identifier: &TListIdentifier),
+        write_list_end(),
+        write_set_begin(identifier: &TSetIdentifier),
+        write_set_end(),
+        write_map_begin(identifier: &TMapIdentifier),
+        write_map_end(),
+        flush(),
+        write_byte(b: u8),
+    );
+}
@@ -1,39 +1,129 @@
 use maplit::btreemap;
-use serde_json::json;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::io::ErrorKind;
-use std::os::unix::net::{UnixListener, UnixStream};
+use std::marker::PhantomData;
+use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use std::time::Duration;
-use thrift::protocol::TBinaryInputProtocol;
-use thrift::protocol::TBinaryOutputProtocol;
+use std::time::{Duration, Instant};
 use thrift::server::TProcessor;
-use thrift::transport::{TBufferedReadTransport, TBufferedWriteTransport};
 use thrift::{ApplicationError, ProtocolError, TransportError, TransportErrorKind};
-use tracing::{debug, error, info, info_span, instrument, trace, warn};
+use tracing::{debug, error, field, info, info_span, instrument, trace, warn};
 
 pub use anyhow::{anyhow, Error};
 pub use thrift;
 pub mod gen;
+pub use batch::BatchFailure;
+pub use deadline::Deadline;
+pub use diff::{Diff, Differential};
+pub use export::Export;
 pub use gen::osquery::ExtensionPluginRequest as PluginRequest;
 pub use gen::osquery::ExtensionPluginResponse as PluginResponse;
 pub use gen::osquery::*;
-pub use gen::table::{Column, QueryContext};
+pub use gen::table::{Column, ColumnOptions, QueryContext, QueryContextBuilder};
+pub use heartbeat::Heartbeat;
+pub use intercept::Interceptor;
+pub use limit::{Limiter, ResponseBudget};
+pub use pool::ClientPool;
+pub use protocol::Strictness;
+pub use reload::Reloadable;
+pub use rows::RowSet;
+pub use runner::Runner;
+pub use scheduler::{Scheduler, SchedulerHandle};
+pub use server::{
+    AcceptErrorPolicy, AcceptQueue, MessageLimits, PeerAuth, RunAs, ServerOptions, ShutdownReason,
+    WhenFull,
+};
+pub use transport::{Connector, DefaultTransport};
+pub use version::{Capabilities, IncompatibleManager};
 pub use ExtensionCode as Code;
 pub use ExtensionResponse as Response;
 pub use ExtensionStatus as Status;
 
+use self::buffer::{BufferPool, Counted, PooledReader, PooledWriter};
 use self::gen::table::ColumnType;
+use self::heartbeat::Watch;
+use self::intercept::{Hooks, InterceptedInput, InterceptedOutput};
+use self::limit::OverBudget;
+use self::protocol::{LimitedInputProtocol, NegotiatedOutputProtocol, Negotiation};
+use self::request_id::RequestId;
+use self::transport::{Listener, Stream};
+use self::workers::Workers;
 
+#[cfg(all(unix, feature = "aio"))]
+pub mod aio;
+pub mod autoload;
+mod batch;
+mod buffer;
+pub mod builtin;
+#[cfg(feature = "c-api")]
+pub mod capi;
+pub mod codegen;
+#[cfg(feature = "file-config")]
+pub mod config;
+#[cfg(feature = "polars")]
+pub mod dataframe;
+pub mod deadline;
+pub mod diagnostics;
+pub mod diff;
+pub mod distributed;
+pub mod dynamic;
+#[cfg(unix)]
+mod embedded;
+mod export;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod health;
+pub mod heartbeat;
+pub mod intercept;
+pub mod limit;
+#[cfg(feature = "log-bridge")]
+pub mod log_bridge;
+pub mod logger;
+pub mod metrics;
+#[cfg(any(feature = "c-api", feature = "dynamic-plugins", feature = "python"))]
+mod multi_table;
+#[cfg(feature = "otel")]
+pub mod otel;
+mod pattern;
+pub mod pool;
+pub mod preflight;
+mod protocol;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "arrow")]
+mod record_batch;
+pub mod reload;
+mod request_id;
+pub mod rows;
+pub mod runner;
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+pub mod sandbox;
+pub mod scheduler;
+pub mod server;
+pub mod shutdown;
+#[cfg(all(unix, feature = "systemd"))]
+pub mod systemd;
+pub mod tables;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transport;
+#[cfg(feature = "typed-tables")]
+pub mod typed;
+#[cfg(unix)]
 mod util;
+mod values;
+pub mod version;
+mod workers;
+pub mod writable;
 
 macro_rules! column_types {
     ($($variant:ident : $kind:ty,)+) => { column_types!($( $variant : $kind ),+ ); };
     ($($variant:ident : $kind:ty),+) => {
-        #[derive(PartialEq, PartialOrd, Debug)]
+        #[derive(PartialEq, PartialOrd, Debug, Clone)]
         pub enum ColumnValue {
             $($variant($kind),)+
         }
@@ -80,10 +170,7 @@ macro_rules! column_types {
             $(
             ::paste::paste! {
                 pub fn [< $variant:snake >](name: &str) -> Column {
-                    Column {
-                        name: name.to_string(),
-                        kind: ColumnType::[< $variant:camel >]
-                    }
+                    Column::new(name, ColumnType::[< $variant:camel >])
                 }
             }
             )+
@@ -93,17 +180,65 @@ macro_rules! column_types {
 
 column_types!(Text: String, Integer: i32, BigInt: i64, Double: f64,);
 
+pub const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_secs(2);
+
 pub type TableColumns = Vec<Column>;
 pub type TableRows = Vec<BTreeMap<String, ColumnValue>>;
 
 pub trait TablePlugin: Plugin {
     fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error>;
     fn columns(&self) -> Result<Vec<Column>, Self::Error>;
+    /// What the dispatcher actually calls for `generate`. Big tables can override this to
+    /// build a RowSet directly and skip the per-row maps that `generate` hands back.
+    fn generate_rows(&self, query: &QueryContext) -> Result<RowSet, Self::Error> {
+        let rows = self.generate(query)?;
+        Ok(RowSet::from_table_rows(&self.columns()?, rows))
+    }
+    /// `generate` calls that take longer than this get logged as a warning with the
+    /// constraints and row count, to help track down what's upsetting the watchdog.
+    fn slow_query_threshold(&self) -> Option<Duration> {
+        Some(DEFAULT_SLOW_QUERY_THRESHOLD)
+    }
+    /// Limits how many `generate` calls can run at once, and how often, see `Limiter`.
+    fn generate_limiter(&self) -> Option<&Limiter> {
+        None
+    }
+    /// Caps the size of a `generate` response, see `ResponseBudget`.
+    fn response_budget(&self) -> Option<ResponseBudget> {
+        None
+    }
+    /// Other names osquery should also answer to for this table, e.g. a short name
+    /// alongside a namespaced one.
+    fn aliases(&self) -> Vec<String> {
+        vec![]
+    }
+    /// Check every row `generate` returns against `columns`, failing the call with the
+    /// offending table and column if a key isn't a column or a used column is missing.
+    /// Validation works on `generate`'s output, so an overridden `generate_rows` isn't
+    /// called while it's on. Meant for development, e.g. `cfg!(debug_assertions)`.
+    fn validate_rows(&self) -> bool {
+        false
+    }
+    /// How long `generate` gets. It's handed to `generate` as `QueryContext::deadline`, so
+    /// a table with a lot to do should check it as it goes and stop early; nothing stops
+    /// it from the outside. Rows that come back after it's passed are thrown away and
+    /// osquery's told the call failed.
+    fn generate_deadline(&self) -> Option<Duration> {
+        None
+    }
     fn shutdown(&self);
 }
 
 pub trait Routes {
     fn routes(&self) -> ExtensionPluginResponse;
+    /// `routes`, or why the plugin can't be registered the way it is.
+    fn try_routes(&self) -> Result<ExtensionPluginResponse, anyhow::Error> {
+        Ok(self.routes())
+    }
+    /// The columns behind `routes`, for `extension_schema`. Empty unless it's a table.
+    fn schema(&self) -> Vec<Column> {
+        vec![]
+    }
 }
 
 impl<T> Routes for T
@@ -111,50 +246,171 @@ where
     T: TablePlugin,
 {
     fn routes(&self) -> ExtensionPluginResponse {
-        let columns = match self.columns() {
-            Ok(col) => col,
-            Err(error) => {
-                error!(table=std::any::type_name::<Self>(), %error, "problem getting columns for routes");
-                return vec![];
-            }
-        };
+        self.try_routes().unwrap_or_else(|error| {
+            error!(table = T::NAME, %error, "problem getting columns for routes");
+            vec![]
+        })
+    }
 
-        columns
-            .iter()
-            .map(|col| {
-                serde_json::from_value(serde_json::json!({
-                    "id": "column",
-                    "name": col.name,
-                    "type": col.kind,
-                    "op": "0",
-                }))
-                .unwrap()
-            })
-            .collect()
+    fn try_routes(&self) -> Result<ExtensionPluginResponse, anyhow::Error> {
+        let columns = self
+            .columns()
+            .map_err(|e| anyhow!("`{}`: {}", T::NAME, e))?;
+        table_routes(T::NAME, &columns, self.aliases())
+    }
+
+    fn schema(&self) -> Vec<Column> {
+        self.columns().unwrap_or_default()
     }
 }
 
-impl ExtensionStatus {
-    pub fn ok(self) -> Result<Option<String>, thrift::Error> {
-        if self.code == Some(Code::ExtSuccess as i32) {
-            return Ok(self.message);
+// osquery rejects a table with no columns or a repeated one, but only says so in its
+// own log, so catch those here
+pub(crate) fn table_routes(
+    table: &str,
+    columns: &[Column],
+    aliases: Vec<String>,
+) -> Result<ExtensionPluginResponse, anyhow::Error> {
+    if columns.is_empty() {
+        return Err(anyhow!("table `{}` has no columns", table));
+    }
+    let mut seen = std::collections::HashSet::new();
+    for col in columns {
+        if col.name.is_empty() {
+            return Err(anyhow!("table `{}` has a column with no name", table));
+        }
+        if !seen.insert(col.name.as_str()) {
+            return Err(anyhow!(
+                "table `{}` has more than one `{}` column",
+                table,
+                col.name
+            ));
         }
-        let e = thrift::ApplicationError::new(
-            thrift::ApplicationErrorKind::InternalError,
-            self.message
-                .unwrap_or_else(|| "Unknown error occurred!".to_string()),
-        );
-        Err(e.into())
     }
+
+    let columns = columns.iter().map(|col| {
+        btreemap! {
+            "id".to_string() => "column".to_string(),
+            "name".to_string() => col.name.clone(),
+            "type".to_string() => col.kind.to_string(),
+            "op".to_string() => col.options.bits().to_string(),
+        }
+    });
+    let aliases = aliases.into_iter().map(|alias| {
+        btreemap! {
+            "id".to_string() => "alias".to_string(),
+            "alias".to_string() => alias,
+        }
+    });
+    Ok(columns.chain(aliases).collect())
+}
+
+// routes are worked out once per plugin, then reused by every registration
+static ROUTES: Mutex<BTreeMap<(&'static str, &'static str), ExtensionPluginResponse>> =
+    Mutex::new(BTreeMap::new());
+
+fn cached_routes<P: Plugin>(plugin: &P) -> Result<ExtensionPluginResponse, anyhow::Error> {
+    let key = (P::REGISTRY, P::NAME);
+    if let Some(routes) = ROUTES.lock().unwrap_or_else(|e| e.into_inner()).get(&key) {
+        return Ok(routes.clone());
+    }
+    let routes = plugin.try_routes()?;
+    ROUTES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key, routes.clone());
+    Ok(routes)
+}
+
+// for a plugin whose routes have changed since they were cached
+pub(crate) fn forget_routes(registry: &str, name: &str) {
+    ROUTES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|&(r, n), _| (r, n) != (registry, name));
 }
 
-type BinaryIn = TBinaryInputProtocol<UnixStream>;
-type BinaryOut = TBinaryOutputProtocol<UnixStream>;
+// numbers the server's connections, for telling them apart in the logs
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+type BinaryIn<C> = InterceptedInput<LimitedInputProtocol<<C as Connector>::Stream>>;
+type BinaryOut<C> = InterceptedOutput<NegotiatedOutputProtocol<<C as Connector>::Stream>>;
 
 #[derive(Debug)]
-pub struct Handle<T> {
+pub struct Handle<T, C = DefaultTransport> {
     socket_path: PathBuf,
     server: T,
+    options: ServerOptions,
+    // the manager's socket, and how to check on it
+    heartbeat: Option<(PathBuf, Heartbeat)>,
+    transport: PhantomData<fn() -> C>,
+}
+
+/// What a plugin does with the calls osquery makes on its socket. Tables get this for
+/// free, other plugin types implement it (see `logger_plugin!`).
+pub trait PluginHandler {
+    fn handle_ping(&self) -> thrift::Result<ExtensionStatus>;
+    fn handle_call(
+        &self,
+        registry: String,
+        item: String,
+        request: ExtensionPluginRequest,
+    ) -> thrift::Result<Response>;
+    fn handle_shutdown(&self) -> thrift::Result<()>;
+}
+
+// the generated processor wants its own handler trait, which lives in osquery-proto.
+// this is also where a shutdown from osquery gets noticed, so the accept loop can stop
+struct Served<T> {
+    plugin: Arc<T>,
+    // how the plugin's own shutdown gets run
+    shutdown: fn(&Arc<T>) -> thrift::Result<()>,
+    shutdown_requested: Arc<AtomicBool>,
+    // the accept loop only checks for shutdown between connections, so give it one
+    wake: Box<dyn Fn() + Send + Sync>,
+}
+
+// with `shutdown::broadcast`'s timeout and reporting, like the tables of a plugin serving
+// several, which broadcast to their tables themselves
+fn broadcast_shutdown<T>(plugin: &Arc<T>) -> thrift::Result<()>
+where
+    T: Plugin + PluginHandler + Send + Sync + 'static,
+{
+    if plugin.served_tables().is_some() {
+        return plugin.handle_shutdown();
+    }
+    let plugin = plugin.clone();
+    let shutdown = move || plugin.handle_shutdown();
+    shutdown::broadcast(
+        std::iter::once((T::NAME.to_string(), shutdown)),
+        shutdown::DEFAULT_TIMEOUT,
+    )?;
+    Ok(())
+}
+
+impl<T: PluginHandler> ExtensionSyncHandler for Served<T> {
+    fn handle_ping(&self) -> thrift::Result<ExtensionStatus> {
+        self.plugin.handle_ping()
+    }
+
+    fn handle_call(
+        &self,
+        registry: String,
+        item: String,
+        request: ExtensionPluginRequest,
+    ) -> thrift::Result<Response> {
+        let id = RequestId::next();
+        let _span = info_span!("request", %id, %registry, %item).entered();
+        let _in_flight = diagnostics::call_started(id, &registry, &item);
+        id.tag(self.plugin.handle_call(registry, item, request))
+    }
+
+    fn handle_shutdown(&self) -> thrift::Result<()> {
+        let result = (self.shutdown)(&self.plugin);
+        self.shutdown_requested.store(true, Ordering::SeqCst);
+        (self.wake)();
+        result
+    }
 }
 
 pub trait PluginError: std::error::Error {}
@@ -163,39 +419,116 @@ impl<T> PluginError for T where T: std::error::Error + Send + Sync + Into<anyhow
 pub trait Plugin: Routes + Sized {
     type Error: PluginError;
     const NAME: &'static str;
+    /// Which osquery registry the plugin goes in: `table`, `config`, `logger`, ...
+    const REGISTRY: &'static str = "table";
     fn new() -> Self;
-    fn install(self, client: &mut Client) -> Result<Handle<Self>, anyhow::Error> {
-        let info = InternalExtensionInfo::new(
-            Some(Self::NAME.to_string()),
-            env!("CARGO_PKG_VERSION").to_string(),
-            None,
-            None,
-        );
-        let registry = serde_json::from_value(json!({
-            "table": {
-                (Self::NAME) : self.routes(),
-            }
-        }))
-        .map_err(|e| {
-            thrift::Error::Application(ApplicationError::new(
-                thrift::ApplicationErrorKind::InternalError,
-                format!("Failed to generate routes: {}", e),
-            ))
-        })?;
-        let status = client.register_extension(info, registry)?;
+    /// Called for any action the dispatcher doesn't know about itself, with the rest of
+    /// the request osquery sent (minus `action`). Override it to support newer actions,
+    /// or `insert`/`update`/`delete` for a writable table (see `writable`).
+    fn handle_action(&self, action: &str, _request: PluginRequest) -> thrift::Result<Response> {
+        Err(thrift::Error::Protocol(ProtocolError::new(
+            thrift::ProtocolErrorKind::NotImplemented,
+            format!(
+                "action `{}` not supported by plugin `{}`",
+                action,
+                Self::NAME
+            ),
+        )))
+    }
+    /// Work out (and check) the routes osquery gets when the plugin registers, and keep
+    /// them for `install`. Call it right after building a plugin to hear about a bad
+    /// schema then, instead of partway through registering.
+    fn prepare_routes(&self) -> Result<(), anyhow::Error> {
+        cached_routes(self).map(drop)
+    }
+    /// For a plugin whose `install` registers several tables in its place (see
+    /// `dynamic::Host`), each one's name, routes and columns. `None` for one that
+    /// registers itself.
+    fn served_tables(&self) -> Option<Vec<(String, ExtensionPluginResponse, Vec<Column>)>> {
+        None
+    }
+    fn install<C: Connector>(
+        self,
+        client: &mut Client<C>,
+    ) -> Result<Handle<Self, C>, anyhow::Error> {
+        let _span =
+            info_span!("register", plugin = Self::NAME, registry = Self::REGISTRY).entered();
+        let registry = btreemap! {
+            Self::REGISTRY.to_string() => btreemap! {
+                Self::NAME.to_string() => cached_routes(&self)?,
+            },
+        };
+        let uuid = register(client, Self::NAME, registry)?;
         debug!(
-            "registered extension from {}, got back {:?}",
+            "registered extension from {} as {}",
             std::any::type_name::<Self>(),
-            &status
+            uuid
         );
-        let uuid = status.uuid.ok_or_else(|| {
-            thrift::Error::Application(ApplicationError::new(
-                thrift::ApplicationErrorKind::ProtocolError,
-                "Got no UUID from osquery",
-            ))
-        })?;
-        Ok(Handle::new(client.socket_path(uuid)?, self))
+        let socket_path = client.socket_path(uuid)?;
+        builtin::record_registration(Self::NAME, uuid, &socket_path, self.schema());
+        let heartbeat = client.heartbeat();
+        Ok(Handle::on_transport(socket_path, self).with_heartbeat(&client.socket_path, heartbeat))
+    }
+}
+
+/// Register an extension called `name` offering `registry`, returning the uuid osquery
+/// gave it.
+pub(crate) fn register<C: Connector>(
+    client: &mut Client<C>,
+    name: &str,
+    registry: ExtensionRegistry,
+) -> Result<ExtensionRouteUUID, anyhow::Error> {
+    version::check_manager(client)?;
+    // find out now if the transport can't name the extension's socket, rather than
+    // after osquery's expecting it
+    client.socket_path(0)?;
+    let info = InternalExtensionInfo::new(
+        Some(name.to_string()),
+        env!("CARGO_PKG_VERSION").to_string(),
+        None,
+        version::MIN_OSQUERY_VERSION.to_string(),
+    );
+    let status = client.register_extension(info, registry)?;
+    debug!("registered `{}`, got back {:?}", name, &status);
+    if !status.is_success() {
+        let message = status.message.unwrap_or_default();
+        if version::is_version_refusal(&message) {
+            return Err(IncompatibleManager {
+                version: None,
+                reason: message,
+            }
+            .into());
+        }
+        return Err(anyhow!(
+            "osquery refused to register `{}`: {}",
+            name,
+            message
+        ));
     }
+    status.uuid.ok_or_else(|| {
+        thrift::Error::Application(ApplicationError::new(
+            thrift::ApplicationErrorKind::ProtocolError,
+            "Got no UUID from osquery",
+        ))
+        .into()
+    })
+}
+
+/// Withdraw the registration osquery gave `uuid`.
+pub(crate) fn deregister<C: Connector>(
+    client: &mut Client<C>,
+    uuid: ExtensionRouteUUID,
+) -> Result<(), anyhow::Error> {
+    let status = client.deregister_extension(uuid)?;
+    if !status.is_success() {
+        return Err(anyhow!(
+            "osquery wouldn't deregister {}: {}",
+            uuid,
+            status.message.unwrap_or_default()
+        ));
+    }
+    builtin::forget_registration(uuid);
+    Ok(())
 }
 
 impl<T> Handle<T>
@@ -203,211 +536,573 @@ where
     T: Plugin,
 {
     pub fn new<P: AsRef<Path>>(path: P, server: T) -> Self {
+        Self::on_transport(path, server)
+    }
+}
+
+impl<T, C> Handle<T, C>
+where
+    T: Plugin,
+    C: Connector,
+{
+    /// `new`, for a transport other than the default
+    pub fn on_transport<P: AsRef<Path>>(path: P, server: T) -> Self {
         Handle {
             socket_path: path.as_ref().into(),
             server,
+            options: ServerOptions::default(),
+            heartbeat: None,
+            transport: PhantomData,
         }
     }
+
+    pub fn with_options(mut self, options: ServerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Ping the osquery at `manager` while serving, and stop with
+    /// `ShutdownReason::ManagerGone` once it stops answering. Handles from
+    /// `Client::register_table` already have one, going by osquery's own flags.
+    pub fn with_heartbeat<P: AsRef<Path>>(mut self, manager: P, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some((manager.as_ref().into(), heartbeat));
+        self
+    }
+
+    /// Keep serving whether or not osquery's still there.
+    pub fn without_heartbeat(mut self) -> Self {
+        self.heartbeat = None;
+        self
+    }
 }
 
-impl<T: 'static> Handle<T>
+impl<T: 'static, C> Handle<T, C>
 where
-    T: TablePlugin + Debug + Send + Sync,
+    T: Plugin + PluginHandler + Debug + Send + Sync,
+    C: Connector,
 {
     #[tracing::instrument(skip(self), fields(T = "std::any::type_name::<T>()"))]
-    pub fn start(self) -> Result<JoinHandle<Result<(), thrift::Error>>, Error> {
+    /// Serve the plugin on its own thread. The thread finishes with
+    /// `Ok(ShutdownReason::Requested)` once osquery tells the extension to shut down,
+    /// `Ok(ShutdownReason::ManagerGone)` if the heartbeat gives up on osquery, or with the
+    /// error if the listener fails.
+    pub fn start(self) -> Result<JoinHandle<Result<ShutdownReason, thrift::Error>>, Error> {
         let socket_path = self.socket_path;
+        let options = self.options;
 
         // stand up the sync processor (the thing that knows how to go from thrift -> Plugin)
-        let processor = Arc::new(ExtensionSyncProcessor::new(self.server));
-        // listen on the unix socket we got back from osquery
-        let unix_listener = UnixListener::bind(&socket_path)?;
+        let shutdown_requested = Arc::new(AtomicBool::new(false));
+        let workers = Workers::<C::Stream>::new(options.accept_queue);
+        // listen on the socket we got back from osquery
+        let listener = <C::Listener as Listener>::bind(&socket_path, &options)?;
         info!("Listening at {:?}", socket_path);
+        let waker = listener.waker()?;
+        let wake_path = socket_path.clone();
+        let waking = workers.clone();
+        let wake = Arc::new(move || {
+            waking.stop();
+            match &waker {
+                Some(waker) => waker(),
+                None => {
+                    if let Err(error) = C::connect(&wake_path) {
+                        warn!(%error, "couldn't wake the accept loop, it stops on the next connection");
+                    }
+                }
+            }
+        });
+        let on_shutdown = wake.clone();
+        let processor = Arc::new(ExtensionSyncProcessor::new(Served {
+            plugin: Arc::new(self.server),
+            shutdown: broadcast_shutdown,
+            shutdown_requested: shutdown_requested.clone(),
+            wake: Box::new(move || on_shutdown()),
+        }));
+        // connected while we can still reach osquery's socket
+        let heartbeat = self
+            .heartbeat
+            .map(|(manager, heartbeat)| Watch::<C>::new(manager, heartbeat));
+        if let Some(run_as) = &options.run_as {
+            run_as.apply()?;
+            info!(uid = run_as.uid, gid = run_as.gid, "dropped privileges");
+        }
+
+        // connections hand their buffers back here when they close, so the next one starts warm
+        let read_pool = BufferPool::new(options.read_buffer_size, options.pooled_buffers);
+        let write_pool = BufferPool::new(options.write_buffer_size, options.pooled_buffers);
 
         let _span = info_span!("listening").entered();
         let handle = std::thread::spawn(move || {
-            for sock in unix_listener.incoming() {
-                match sock {
+            let _serving = metrics::global().server_started();
+            let manager_gone = Arc::new(AtomicBool::new(false));
+            let gone = manager_gone.clone();
+            // stops with the server
+            let _heartbeat = heartbeat.map(|watch| {
+                watch.spawn(move || {
+                    gone.store(true, Ordering::SeqCst);
+                    wake();
+                })
+            });
+            let mut failures = 0;
+            loop {
+                workers.wait_for_room();
+                let accepted = listener.accept();
+                if shutdown_requested.load(Ordering::SeqCst) {
+                    info!("osquery asked for a shutdown, no longer accepting connections");
+                    return Ok(ShutdownReason::Requested);
+                }
+                if manager_gone.load(Ordering::SeqCst) {
+                    info!("osquery's gone, no longer accepting connections");
+                    return Ok(ShutdownReason::ManagerGone);
+                }
+                match accepted {
                     Ok(stream) => {
+                        failures = 0;
+                        let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+                        let stream = match workers.admit(connection, stream) {
+                            Some(stream) => stream,
+                            // a worker picks it up when one's free
+                            None => continue,
+                        };
                         // every time we get a connection, grab a copy of the processor and get to steppin
                         let processor = processor.clone();
+                        let read_pool = read_pool.clone();
+                        let write_pool = write_pool.clone();
+                        let options = options.clone();
+                        let workers = workers.clone();
                         std::thread::spawn(move || {
-                            let _span = info_span!("new connection", ?stream).entered();
-                            let i_trans = TBufferedReadTransport::new(stream.try_clone()?);
-                            let o_trans = TBufferedWriteTransport::new(stream);
-                            let mut i_prot = TBinaryInputProtocol::new(i_trans, true);
-                            let mut o_prot = TBinaryOutputProtocol::new(o_trans, true);
-                            loop {
-                                match processor.process(&mut i_prot, &mut o_prot) {
-                                    Ok(_) => {}
-                                    Err(thrift::Error::Transport(TransportError {
-                                        kind: TransportErrorKind::EndOfFile,
-                                        ..
-                                    })) => {
-                                        break;
-                                    }
-                                    Err(e) => {
-                                        warn!(error=%e, "processor completed with error");
-                                        break;
-                                    }
+                            let mut next = Some((connection, stream));
+                            while let Some((connection, stream)) = next {
+                                let served = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                                    serve_connection(
+                                        connection,
+                                        stream,
+                                        &*processor,
+                                        &read_pool,
+                                        &write_pool,
+                                        &options,
+                                    )
+                                }));
+                                if served.is_err() {
+                                    error!(connection, "serving the connection panicked");
                                 }
+                                next = workers.next();
                             }
-                            Ok::<_, thrift::Error>(())
                         });
                     }
-                    Err(e) => {
-                        error!("incoming connection had a problem! {}", e);
-                        return Err(e.into());
-                    }
+                    Err(e) => match options.accept_errors.backoff(failures) {
+                        Some(backoff) => {
+                            warn!(error = %e, ?backoff, "couldn't accept a connection, retrying");
+                            failures = failures.saturating_add(1);
+                            std::thread::sleep(backoff);
+                        }
+                        None => {
+                            error!("incoming connection had a problem! {}", e);
+                            return Err(e.into());
+                        }
+                    },
                 }
             }
-            info!("listener shut down");
-            Ok(())
         });
         Ok(handle)
     }
 }
 
+// one connection, from checking who's on the other end until it closes
+fn serve_connection<S: Stream, P: TProcessor>(
+    connection: u64,
+    stream: S,
+    processor: &P,
+    read_pool: &Arc<BufferPool>,
+    write_pool: &Arc<BufferPool>,
+    options: &ServerOptions,
+) -> thrift::Result<()> {
+    if let Some(mut connection) =
+        Connection::open(connection, stream, read_pool, write_pool, options)?
+    {
+        while connection.process(processor) {}
+    }
+    Ok(())
+}
+
+// a connection that got past the peer checks, served a message at a time. logs what it
+// did when it's dropped
+pub(crate) struct Connection<S: Stream> {
+    span: tracing::Span,
+    i_prot: LimitedInputProtocol<PooledReader<Counted<S>>>,
+    o_prot: NegotiatedOutputProtocol<PooledWriter<Counted<S>>>,
+    requests: u64,
+    opened: Instant,
+    bytes_in: Arc<AtomicU64>,
+    bytes_out: Arc<AtomicU64>,
+    _active: metrics::ConnectionGuard<'static>,
+}
+
+impl<S: Stream> Connection<S> {
+    // `None` if it's refused
+    pub(crate) fn open(
+        connection: u64,
+        stream: S,
+        read_pool: &Arc<BufferPool>,
+        write_pool: &Arc<BufferPool>,
+        options: &ServerOptions,
+    ) -> thrift::Result<Option<Self>> {
+        let span = info_span!(
+            "new connection",
+            id = connection,
+            ?stream,
+            peer_pid = field::Empty,
+            peer_uid = field::Empty,
+            peer_gid = field::Empty,
+        );
+        let entered = span.enter();
+        let peer = stream.peer_credentials();
+        if let Ok(Some(peer)) = &peer {
+            if let Some(pid) = peer.pid {
+                span.record("peer_pid", pid);
+            }
+            span.record("peer_uid", peer.uid);
+            span.record("peer_gid", peer.gid);
+        }
+        if options.peer_auth != PeerAuth::Any {
+            match peer {
+                Ok(Some(peer)) if options.peer_auth.allows(&peer) => {
+                    trace!(?peer, "peer allowed");
+                }
+                Ok(Some(peer)) => {
+                    warn!(?peer, "refusing connection from disallowed peer");
+                    return Ok(None);
+                }
+                Ok(None) => {
+                    warn!("transport can't identify peers, refusing connection");
+                    return Ok(None);
+                }
+                Err(error) => {
+                    warn!(%error, "couldn't check peer credentials, refusing connection");
+                    return Ok(None);
+                }
+            }
+        }
+        stream.set_read_timeout(options.read_timeout)?;
+        stream.set_write_timeout(options.write_timeout)?;
+        let active = metrics::global().connection_opened();
+        let (reader, bytes_in) = Counted::new(stream.try_clone()?);
+        let (writer, bytes_out) = Counted::new(stream);
+        let i_trans = PooledReader::new(reader, read_pool.clone());
+        let o_trans = PooledWriter::new(writer, write_pool.clone());
+        #[cfg(all(target_os = "linux", feature = "sandbox"))]
+        if let Some(sandbox) = &options.sandbox {
+            if let Err(error) = sandbox.apply_once() {
+                warn!(%error, "couldn't sandbox the connection, refusing it");
+                return Ok(None);
+            }
+        }
+        let negotiation = Negotiation::new(options.strictness);
+        drop(entered);
+        Ok(Some(Self {
+            span,
+            i_prot: LimitedInputProtocol::new(i_trans, options.limits, negotiation.clone()),
+            o_prot: NegotiatedOutputProtocol::new(o_trans, negotiation),
+            requests: 0,
+            opened: Instant::now(),
+            bytes_in,
+            bytes_out,
+            _active: active,
+        }))
+    }
+
+    // one message and its reply. false once the connection's done with
+    pub(crate) fn process<P: TProcessor>(&mut self, processor: &P) -> bool {
+        let _span = self.span.enter();
+        match processor.process(&mut self.i_prot, &mut self.o_prot) {
+            Ok(_) => {
+                self.requests += 1;
+                true
+            }
+            Err(thrift::Error::Transport(TransportError {
+                kind: TransportErrorKind::EndOfFile,
+                ..
+            })) => false,
+            Err(thrift::Error::Transport(TransportError {
+                kind: TransportErrorKind::TimedOut,
+                ..
+            })) => {
+                debug!("connection timed out, closing it");
+                false
+            }
+            // thrift's Display leaves out the message, which is
+            // where a strictness mismatch gets explained
+            Err(thrift::Error::Protocol(e)) => {
+                warn!(kind = ?e.kind, message = %e.message, "couldn't read a message, closing the connection");
+                false
+            }
+            Err(e) => {
+                warn!(error=%e, "processor completed with error");
+                false
+            }
+        }
+    }
+
+    // whether there's more already read off the socket, which polling it won't show
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn buffered(&self) -> bool {
+        self.i_prot.transport().buffered() > 0
+    }
+}
+
+impl<S: Stream> Drop for Connection<S> {
+    fn drop(&mut self) {
+        let _span = self.span.enter();
+        debug!(
+            requests = self.requests,
+            bytes_in = self.bytes_in.load(Ordering::Relaxed),
+            bytes_out = self.bytes_out.load(Ordering::Relaxed),
+            lifetime = ?self.opened.elapsed(),
+            "connection closed"
+        );
+    }
+}
+
 #[derive(derive_more::Deref, derive_more::DerefMut)]
-pub struct Client {
+pub struct Client<C: Connector = DefaultTransport> {
     socket_path: std::path::PathBuf,
     #[deref]
     #[deref_mut]
-    server: ExtensionManagerSyncClient<BinaryIn, BinaryOut>,
+    server: ExtensionManagerSyncClient<BinaryIn<C>, BinaryOut<C>>,
+    // filled in by the first `capabilities` call
+    capabilities: Option<Capabilities>,
+    hooks: Arc<Hooks>,
+    negotiation: Negotiation,
 }
 
 impl Client {
+    pub fn connect<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Self, thrift::Error> {
+        Self::connect_via(path, timeout)
+    }
+}
+
+impl Client {
+    /// Connect and ping osquery, all before `deadline`, for supervisors that can't have
+    /// a start-up hang on a wedged socket. Calls afterwards time out after however long
+    /// the deadline allowed in total.
+    pub fn connect_deadline<P: AsRef<Path>>(
+        path: P,
+        deadline: Instant,
+    ) -> Result<Self, ConnectError> {
+        Self::connect_deadline_via(path, deadline)
+    }
+}
+
+impl<C: Connector> Client<C> {
+    /// Where the extension osquery registered as `uuid` should listen, see
+    /// `Connector::extension_address`.
     pub fn socket_path(&self, uuid: ExtensionRouteUUID) -> Result<PathBuf, std::io::Error> {
-        let mut socket_path = self.socket_path.clone();
-        let mut name = socket_path
-            .file_name()
-            .ok_or_else(|| {
-                std::io::Error::new(ErrorKind::InvalidData, socket_path.to_string_lossy())
-            })?
-            .to_os_string();
-        name.push(&format!(".{}", uuid));
-        socket_path.set_file_name(name);
-        Ok(socket_path)
+        C::extension_address(&self.socket_path, uuid)
     }
 
-    pub fn connect<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Self, thrift::Error> {
-        let reader = UnixStream::connect(&path)?;
+    /// `connect`, for a transport other than the default
+    pub fn connect_via<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Self, thrift::Error> {
+        let reader = C::connect(path.as_ref())?;
         debug!(?timeout, "set timeout on read and write streams");
-        reader.set_read_timeout(Some(timeout))?;
-        reader.set_write_timeout(Some(timeout))?;
+        reader.set_timeouts(Some(timeout))?;
+        Self::from_stream(path.as_ref(), reader)
+    }
+
+    fn from_stream(path: &Path, reader: C::Stream) -> Result<Self, thrift::Error> {
         let writer = reader.try_clone()?;
-        let input_protocol = TBinaryInputProtocol::new(reader, false);
-        let output_protocol = TBinaryOutputProtocol::new(writer, false);
+        let hooks = Arc::new(Hooks::default());
+        let negotiation = Negotiation::new(Strictness::default());
+        let input_protocol = InterceptedInput::new(
+            LimitedInputProtocol::new(reader, MessageLimits::UNLIMITED, negotiation.clone()),
+            hooks.clone(),
+        );
+        let output_protocol = InterceptedOutput::new(
+            NegotiatedOutputProtocol::new(writer, negotiation.clone()),
+            hooks.clone(),
+        );
         Ok(Self {
-            socket_path: path.as_ref().into(),
+            socket_path: path.into(),
             server: ExtensionManagerSyncClient::new(input_protocol, output_protocol),
+            capabilities: None,
+            hooks,
+            negotiation,
         })
     }
 
+    /// `connect_deadline`, for a transport other than the default
+    pub fn connect_deadline_via<P: AsRef<Path>>(
+        path: P,
+        deadline: Instant,
+    ) -> Result<Self, ConnectError> {
+        let path = path.as_ref();
+        let budget = deadline.saturating_duration_since(Instant::now());
+        let timed_out = || ConnectError::TimedOut {
+            path: path.into(),
+            budget,
+        };
+        let left =
+            || Some(deadline.saturating_duration_since(Instant::now())).filter(|d| !d.is_zero());
+        let stream = match C::connect_timeout(path, left().ok_or_else(timed_out)?) {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == ErrorKind::TimedOut => return Err(timed_out()),
+            Err(e) => return Err(thrift::Error::from(e).into()),
+        };
+        stream.set_timeouts(Some(left().ok_or_else(timed_out)?))?;
+        // timeouts are on the socket, so this handle can change them for the client's
+        let socket = stream.try_clone()?;
+        let mut client = Self::from_stream(path, stream)?;
+        match TExtensionSyncClient::ping(&mut client.server) {
+            Ok(_) => {}
+            Err(thrift::Error::Transport(e))
+                if matches!(e.kind, TransportErrorKind::TimedOut) || Instant::now() >= deadline =>
+            {
+                return Err(timed_out())
+            }
+            Err(e) => return Err(e.into()),
+        }
+        // the handshake's done, later calls get the whole budget
+        socket.set_timeouts(Some(budget.max(Duration::from_millis(1))))?;
+        Ok(client)
+    }
+
+    /// How strictly to speak the binary protocol with osquery from now on. The default,
+    /// `Strictness::Auto`, works with every osquery build; pin it if yours needs to be.
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.negotiation.set(strictness);
+    }
+
+    /// `set_strictness`, builder style.
+    pub fn with_strictness(mut self, strictness: Strictness) -> Self {
+        self.set_strictness(strictness);
+        self
+    }
+
+    /// Run `interceptor`'s hooks around every call this client makes from now on.
+    pub fn intercept<I: Interceptor + 'static>(&mut self, interceptor: I) {
+        self.hooks.add(Arc::new(interceptor));
+    }
+
+    /// `intercept`, builder style.
+    pub fn with_interceptor<I: Interceptor + 'static>(mut self, interceptor: I) -> Self {
+        self.intercept(interceptor);
+        self
+    }
+
     /// Convenience function for registering a table
-    pub fn register_table<T>(&mut self, table: T) -> Result<Handle<T>, anyhow::Error>
+    pub fn register_table<T>(&mut self, table: T) -> Result<Handle<T, C>, anyhow::Error>
     where
         T: Plugin,
         anyhow::Error: From<T::Error>,
     {
         Ok(table.install(self)?)
     }
+
+    /// Push rows into an events-backed table osquery already knows about
+    pub fn send_events(&mut self, name: &str, events: PluginResponse) -> thrift::Result<Status> {
+        self.server.stream_events(name.to_string(), events)
+    }
+
+    /// The node key osquery enrolled with. Empty if it hasn't enrolled anywhere.
+    pub fn node_key(&mut self) -> thrift::Result<String> {
+        self.server.get_node_key()
+    }
+
+    /// Call a plugin osquery knows about, e.g. `call("config", "filesystem", ..)` for
+    /// its config, or `call("table", "processes", ..)` with a `generate` action. A
+    /// non-success status comes back as `CallError::Failed`.
+    pub fn call(
+        &mut self,
+        registry: &str,
+        item: &str,
+        request: PluginRequest,
+    ) -> Result<PluginResponse, CallError> {
+        let response =
+            TExtensionSyncClient::call(&mut self.server, registry.into(), item.into(), request)?;
+        let status = response.status.unwrap_or_default();
+        if !status.is_success() {
+            return Err(CallError::Failed {
+                registry: registry.to_string(),
+                item: item.to_string(),
+                code: status.code(),
+                message: status.message.unwrap_or_default(),
+            });
+        }
+        Ok(response.response.unwrap_or_default())
+    }
+}
+
+/// Why `Client::connect_deadline` gave up.
+#[derive(thiserror::Error, Debug)]
+pub enum ConnectError {
+    #[error("couldn't reach osquery at {path:?} within {budget:?}")]
+    TimedOut { path: PathBuf, budget: Duration },
+    #[error(transparent)]
+    Thrift(#[from] thrift::Error),
+}
+
+impl ConnectError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ConnectError::TimedOut { .. })
+    }
+}
+
+impl From<std::io::Error> for ConnectError {
+    fn from(e: std::io::Error) -> Self {
+        ConnectError::Thrift(e.into())
+    }
+}
+
+/// What can go wrong with `Client::call`
+#[derive(thiserror::Error, Debug)]
+pub enum CallError {
+    #[error(transparent)]
+    Thrift(#[from] thrift::Error),
+    #[error("`{registry}` plugin `{item}` returned {code:?}: {message}")]
+    Failed {
+        registry: String,
+        item: String,
+        /// `None` if the status had no code, or one this crate doesn't know
+        code: Option<Code>,
+        message: String,
+    },
 }
 
-impl<T> ExtensionSyncHandler for T
+impl<T> PluginHandler for T
 where
     T: TablePlugin + Debug,
 {
     #[instrument(target = "osquery::ping", level = "trace")]
     fn handle_ping(&self) -> thrift::Result<ExtensionStatus> {
         trace!(target = "osquery::ping", "pong");
-        Ok(ExtensionStatus {
-            code: Some(Code::ExtSuccess as i32),
-            message: Some("OK".to_string()),
-            uuid: None,
-        })
+        Ok(Status::success().with_message("OK"))
     }
 
-    #[instrument(level = "trace")]
     fn handle_call(
         &self,
         _registry: String,
         _item: String,
-        mut request: ExtensionPluginRequest,
+        request: ExtensionPluginRequest,
     ) -> thrift::Result<Response> {
-        debug!("handling call with request {:?}", &request);
-        let mut get_field = |key| {
-            request.remove(key).ok_or_else(|| {
-                thrift::Error::Application(ApplicationError::new(
-                    thrift::ApplicationErrorKind::ProtocolError,
-                    format!(
-                        "request to `{}` missing required field `{}`",
-                        Self::NAME,
-                        key,
-                    ),
-                ))
-            })
-        };
-        let action = get_field("action")?;
-        let context_data = get_field("context")?;
-        debug!("handling call with context {}", &context_data);
-        let query = serde_json::from_str::<QueryContext>(&context_data).map_err(|e| {
-            thrift::Error::Application(ApplicationError::new(
-                thrift::ApplicationErrorKind::ProtocolError,
-                format!("got error deserializing context: {}\n{}", e, context_data),
-            ))
-        })?;
-
-        let output = match action.as_str() {
-            "generate" => self
-                .generate(&query)
-                .map_err(|e| {
-                    thrift::Error::Application(ApplicationError::new(
-                        thrift::ApplicationErrorKind::InternalError,
-                        e.to_string(),
-                    ))
-                })?
-                .into_iter()
-                .map(|v| {
-                    v.into_iter()
-                        .map(|(k, v)| (k, v.to_string()))
-                        .collect::<BTreeMap<_, _>>()
-                })
-                .collect(),
-            "columns" => self
-                .columns()
-                .map_err(|e| {
-                    thrift::Error::Application(ApplicationError::new(
-                        thrift::ApplicationErrorKind::InternalError,
-                        e.to_string(),
-                    ))
-                })?
-                .iter()
-                .map(|c| {
-                    let (key, val) = c.to_pair();
-                    btreemap! {
-                        "name".to_string() => key,
-                        "type".to_string() => val.to_string(),
-                    }
-                })
-                .collect::<Vec<_>>(),
-            other => {
-                return Err(thrift::Error::Protocol(ProtocolError::new(
-                    thrift::ProtocolErrorKind::NotImplemented,
-                    format!("action `{}` not supported on plugin type `table`", other),
-                )))
+        let span = info_span!(
+            "call",
+            table = Self::NAME,
+            action = field::Empty,
+            rows = field::Empty,
+            success = field::Empty,
+        );
+        let _entered = span.enter();
+        let started = Instant::now();
+        let result = dispatch_table_call(self, request);
+        metrics::global().record_response(Self::NAME, started.elapsed(), &result);
+        match &result {
+            Ok(response) => {
+                span.record("rows", response.response.as_ref().map_or(0, Vec::len));
+                span.record("success", response.is_success());
             }
-        };
-        let response = Response {
-            status: Some(Status {
-                code: Some(Code::ExtSuccess as i32),
-                message: None,
-                uuid: None,
-            }),
-            response: Some(output),
-        };
-
-        Ok(response)
+            Err(_) => {
+                span.record("success", false);
+            }
+        }
+        result
     }
 
     #[instrument(level = "trace")]
@@ -416,3 +1111,234 @@ where
         Ok(())
     }
 }
+
+fn dispatch_table_call<T: TablePlugin>(
+    table: &T,
+    mut request: ExtensionPluginRequest,
+) -> thrift::Result<Response> {
+    debug!("handling call with request {:?}", &request);
+    let action = take_field::<T>(&mut request, "action")?;
+    tracing::Span::current().record("action", action.as_str());
+    let output = match action.as_str() {
+        "generate" => {
+            let context_data = take_field::<T>(&mut request, "context")?;
+            debug!("handling call with context {}", &context_data);
+            let mut query = serde_json::from_str::<QueryContext>(&context_data)
+                .unwrap_or_else(|error| {
+                    // osquery filters the rows itself, so generating without constraints
+                    // is slower but still right
+                    warn!(table = T::NAME, %error, context = %context_data, "couldn't parse query context, using an empty one");
+                    QueryContext::default()
+                });
+            query.request = request;
+            let started = Instant::now();
+            let result = generate(table, &mut query);
+            builtin::record_generate(T::NAME, &query, started.elapsed(), &result);
+            return result;
+        }
+        "columns" => table
+            .columns()
+            .map_err(|e| {
+                thrift::Error::Application(ApplicationError::new(
+                    thrift::ApplicationErrorKind::InternalError,
+                    e.to_string(),
+                ))
+            })?
+            .iter()
+            .map(|c| {
+                let (key, val) = c.to_pair();
+                btreemap! {
+                    "name".to_string() => key,
+                    "type".to_string() => val.to_string(),
+                }
+            })
+            .collect::<Vec<_>>(),
+        other => return table.handle_action(other, request),
+    };
+    Ok(Response::success(output))
+}
+
+// osquery would normally refuse these queries itself, but not every caller is osquery
+fn missing_required<T: TablePlugin>(
+    columns: &Result<Vec<Column>, T::Error>,
+    query: &QueryContext,
+) -> Option<Response> {
+    let columns = columns.as_ref().ok()?;
+    let missing = columns
+        .iter()
+        .find(|c| c.options.contains(ColumnOptions::REQUIRED) && !query.has_equals(&c.name))?;
+    debug!(table = T::NAME, column = %missing.name, "missing required constraint");
+    Some(Response::failure(format!(
+        "table `{}` requires an `=` constraint on `{}`",
+        T::NAME,
+        missing.name
+    )))
+}
+
+fn internal_error<E: ToString>(e: E) -> thrift::Error {
+    thrift::Error::Application(ApplicationError::new(
+        thrift::ApplicationErrorKind::InternalError,
+        e.to_string(),
+    ))
+}
+
+fn generate<T: TablePlugin>(table: &T, query: &mut QueryContext) -> thrift::Result<Response> {
+    let _permit = match table.generate_limiter().map(Limiter::acquire) {
+        Some(None) => {
+            debug!(
+                table = T::NAME,
+                "generate over the table's call limits, turning one away"
+            );
+            return Ok(Response::failure(format!("table `{}` is busy", T::NAME)));
+        }
+        permit => permit,
+    };
+    if let Some(refusal) = missing_required::<T>(&table.columns(), query) {
+        return Ok(refusal);
+    }
+    let allowed = table.generate_deadline();
+    if let Some(allowed) = allowed {
+        query.deadline = Deadline::after(allowed);
+    }
+    let started = Instant::now();
+    let rows = if table.validate_rows() {
+        generate_validated(table, query)
+    } else {
+        table.generate_rows(query).map_err(internal_error)
+    }?;
+    let elapsed = started.elapsed();
+    if matches!(table.slow_query_threshold(), Some(limit) if elapsed > limit) {
+        warn!(
+            table = T::NAME,
+            ?elapsed,
+            constraints = %query.constraint_summary(),
+            rows = rows.len(),
+            "slow generate"
+        );
+    }
+    // a table that kept to its deadline stopped early, one that didn't is late anyway.
+    // cancelling tells anything it left running in the background to stop too
+    if query.deadline.is_expired() {
+        query.deadline.cancel();
+        warn!(
+            table = T::NAME,
+            ?elapsed,
+            ?allowed,
+            "generate ran past its deadline"
+        );
+        return Ok(Response::failure(format!(
+            "generate on `{}` finished past its deadline, after {:?}",
+            T::NAME,
+            elapsed
+        )));
+    }
+    let budget = match table.response_budget() {
+        Some(budget) => budget,
+        None => return Ok(Response::success(rows.into_response())),
+    };
+    let total = rows.len();
+    match rows.into_response_within(budget.max_bytes) {
+        Ok(rows) => Ok(Response::success(rows)),
+        Err(rows) => {
+            warn!(
+                table = T::NAME,
+                max_bytes = budget.max_bytes,
+                kept = rows.len(),
+                rows = total,
+                constraints = %query.constraint_summary(),
+                "generate output over its budget"
+            );
+            let message = format!(
+                "`{}` returned more than {} bytes, {} of {} rows fit",
+                T::NAME,
+                budget.max_bytes,
+                rows.len(),
+                total
+            );
+            match budget.over {
+                OverBudget::Truncate => {
+                    let mut response = Response::success(rows);
+                    response.status = response.status.map(|s| s.with_message(message));
+                    Ok(response)
+                }
+                OverBudget::Fail => Ok(Response::failure(message)),
+            }
+        }
+    }
+}
+
+fn generate_validated<T: TablePlugin>(table: &T, query: &QueryContext) -> thrift::Result<RowSet> {
+    let rows = table.generate(query).map_err(internal_error)?;
+    let columns = table.columns().map_err(internal_error)?;
+    rows::validate_table_rows(T::NAME, &columns, &rows, query).map_err(|e| {
+        error!(table = T::NAME, error = %e, "generated rows don't match the schema");
+        internal_error(e)
+    })?;
+    Ok(RowSet::from_table_rows(&columns, rows))
+}
+
+fn take_field<T: Plugin>(
+    request: &mut ExtensionPluginRequest,
+    key: &str,
+) -> thrift::Result<String> {
+    request.remove(key).ok_or_else(|| {
+        thrift::Error::Application(ApplicationError::new(
+            thrift::ApplicationErrorKind::ProtocolError,
+            format!("request to `{}` missing required field `{}`", T::NAME, key),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // spins until its deadline, the way a table with a lot to do checks it as it goes
+    #[derive(Debug)]
+    struct Patient;
+
+    impl Plugin for Patient {
+        type Error = std::convert::Infallible;
+        const NAME: &'static str = "patient";
+
+        fn new() -> Self {
+            Self
+        }
+    }
+
+    impl TablePlugin for Patient {
+        fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+            Ok(vec![Column::text("waited")])
+        }
+
+        fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+            let started = Instant::now();
+            while !query.deadline().is_expired() && started.elapsed() < Duration::from_secs(10) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Ok(vec![btreemap! {
+                "waited".to_string() => ColumnValue::text(format!("{:?}", started.elapsed())),
+            }])
+        }
+
+        fn generate_deadline(&self) -> Option<Duration> {
+            Some(Duration::from_millis(20))
+        }
+
+        fn shutdown(&self) {}
+    }
+
+    #[test]
+    fn generate_sees_its_deadline_and_stops_early() {
+        let request = btreemap! {
+            "action".to_string() => "generate".to_string(),
+            "context".to_string() => "{}".to_string(),
+        };
+        let started = Instant::now();
+        let response = dispatch_table_call(&Patient, request).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!response.is_success());
+        let message = response.status.and_then(|s| s.message).unwrap_or_default();
+        assert!(message.contains("past its deadline"), "{}", message);
+    }
+}
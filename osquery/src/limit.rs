@@ -0,0 +1,293 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// What to do with a call that shows up when every permit is already taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Wait for a permit, giving up after the timeout if there is one
+    Queue(Option<Duration>),
+    /// Turn the call away immediately with a busy status
+    Reject,
+}
+
+/// Caps how big a single `generate` response can get, counting the bytes of every
+/// column name and value osquery would be sent. Keeps an unbounded table (a `SELECT *`
+/// over a whole filesystem, say) from getting the extension OOM killed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponseBudget {
+    pub max_bytes: usize,
+    pub over: OverBudget,
+}
+
+/// What to do with a response that doesn't fit its `ResponseBudget`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverBudget {
+    /// Send the rows that fit, with a message on the status saying it was cut short
+    Truncate,
+    /// Fail the call and send nothing
+    Fail,
+}
+
+impl ResponseBudget {
+    pub fn truncate(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            over: OverBudget::Truncate,
+        }
+    }
+
+    pub fn fail(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            over: OverBudget::Fail,
+        }
+    }
+}
+
+/// Caps how many `generate` calls can be running at once, and optionally how many can
+/// start per second. The counts belong to the `Limiter` itself, not to the table: keep one
+/// in the table and return it from `TablePlugin::generate_limiter` to limit just that
+/// table, or return the same one from several tables to limit them as a group. A table
+/// that builds a new `Limiter` (when it's reloaded, say) starts again from zero.
+#[derive(Debug)]
+pub struct Limiter {
+    max: usize,
+    overflow: Overflow,
+    rate: Option<Rate>,
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+// a token bucket: `burst` calls up front, refilled at `per_second`
+#[derive(Debug, Clone, Copy)]
+struct Rate {
+    per_second: f64,
+    burst: f64,
+}
+
+#[derive(Debug)]
+struct State {
+    in_use: usize,
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Held for the length of a call, gives its slot back when dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    limiter: &'a Limiter,
+}
+
+impl Limiter {
+    pub fn new(max: usize, overflow: Overflow) -> Self {
+        Self {
+            max: max.max(1),
+            overflow,
+            rate: None,
+            state: Mutex::new(State {
+                in_use: 0,
+                tokens: 0.0,
+                refilled: Instant::now(),
+            }),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Only a rate limit, no cap on concurrent calls. Panics like `with_rate`.
+    pub fn per_second(per_second: f64, burst: u32, overflow: Overflow) -> Self {
+        Self::new(usize::MAX, overflow).with_rate(per_second, burst)
+    }
+
+    /// Also limit calls to `per_second` on average, allowing up to `burst` back to back
+    /// (at least 1). Handy in front of a cloud API that bills or throttles by request.
+    ///
+    /// Panics unless `per_second` is finite and above zero.
+    pub fn with_rate(mut self, per_second: f64, burst: u32) -> Self {
+        assert!(
+            per_second.is_finite() && per_second > 0.0,
+            "a Limiter's rate has to be a positive number of calls per second, not {}",
+            per_second
+        );
+        let burst = f64::from(burst.max(1));
+        self.rate = Some(Rate { per_second, burst });
+        self.state
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .tokens = burst;
+        self
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn in_use(&self) -> usize {
+        self.lock().in_use
+    }
+
+    /// Grab a permit according to the overflow policy. `None` means the caller should
+    /// report the table as busy.
+    pub fn acquire(&self) -> Option<Permit<'_>> {
+        let deadline = match self.overflow {
+            Overflow::Queue(Some(timeout)) => Some(Instant::now() + timeout),
+            _ => None,
+        };
+        let mut state = self.lock();
+        loop {
+            let wait = match self.blocked_for(&mut state) {
+                None => break,
+                Some(wait) => wait,
+            };
+            let wait = match (self.overflow, deadline) {
+                (Overflow::Reject, _) => return None,
+                (_, Some(deadline)) => {
+                    let left = deadline.checked_duration_since(Instant::now())?;
+                    Some(wait.map_or(left, |wait| wait.min(left)))
+                }
+                (_, None) => wait,
+            };
+            state = match wait {
+                Some(wait) => self
+                    .freed
+                    .wait_timeout(state, wait)
+                    .map(|(guard, _)| guard)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner().0),
+                None => self
+                    .freed
+                    .wait(state)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            };
+        }
+        state.in_use += 1;
+        if self.rate.is_some() {
+            state.tokens -= 1.0;
+        }
+        Some(Permit { limiter: self })
+    }
+
+    // `None` if a call can start now, otherwise how long until a token comes back (or
+    // `Some(None)` to wait for a running call to finish)
+    fn blocked_for(&self, state: &mut State) -> Option<Option<Duration>> {
+        if state.in_use >= self.max {
+            return Some(None);
+        }
+        let rate = self.rate?;
+        let now = Instant::now();
+        let earned = now.duration_since(state.refilled).as_secs_f64() * rate.per_second;
+        state.tokens = (state.tokens + earned).min(rate.burst);
+        state.refilled = now;
+        if state.tokens >= 1.0 {
+            return None;
+        }
+        // a rate slow enough that the wait doesn't fit in a Duration may as well be no
+        // rate at all, so wait on a release like a full limiter does
+        let wait = Duration::try_from_secs_f64((1.0 - state.tokens) / rate.per_second).ok();
+        Some(wait)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter.lock().in_use -= 1;
+        // all of them, since a waiter might be after a token rather than this slot
+        self.limiter.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn burst_then_reject() {
+        let limiter = Limiter::per_second(1.0, 3, Overflow::Reject);
+        let permits: Vec<_> = (0..3).map(|_| limiter.acquire().unwrap()).collect();
+        assert!(limiter.acquire().is_none());
+        // handing permits back frees the slot, not the token
+        drop(permits);
+        assert!(limiter.acquire().is_none());
+    }
+
+    #[test]
+    fn tokens_refill_at_the_rate() {
+        let limiter = Limiter::per_second(50.0, 1, Overflow::Reject);
+        drop(limiter.acquire().unwrap());
+        assert!(limiter.acquire().is_none());
+        thread::sleep(Duration::from_millis(40));
+        assert!(limiter.acquire().is_some());
+    }
+
+    #[test]
+    fn queue_waits_for_a_token() {
+        let limiter = Limiter::per_second(20.0, 1, Overflow::Queue(Some(Duration::from_secs(5))));
+        drop(limiter.acquire().unwrap());
+        let started = Instant::now();
+        assert!(limiter.acquire().is_some());
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn queue_gives_up_at_its_timeout() {
+        let timeout = Duration::from_millis(20);
+        let limiter = Limiter::per_second(0.1, 1, Overflow::Queue(Some(timeout)));
+        drop(limiter.acquire().unwrap());
+        let started = Instant::now();
+        assert!(limiter.acquire().is_none());
+        assert!(started.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn queue_waits_for_a_release() {
+        let limiter = Arc::new(Limiter::new(1, Overflow::Queue(None)));
+        let held = limiter.acquire().unwrap();
+        let waiter = {
+            let limiter = limiter.clone();
+            thread::spawn(move || limiter.acquire().map(|_| ()).is_some())
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(limiter.in_use(), 1);
+        drop(held);
+        assert!(waiter.join().unwrap());
+        assert_eq!(limiter.in_use(), 0);
+    }
+
+    #[test]
+    fn reject_past_max() {
+        let limiter = Limiter::new(2, Overflow::Reject);
+        let _a = limiter.acquire().unwrap();
+        let _b = limiter.acquire().unwrap();
+        assert!(limiter.acquire().is_none());
+    }
+
+    #[test]
+    fn tiny_rates_dont_overflow() {
+        let limiter = Limiter::per_second(1e-300, 1, Overflow::Reject);
+        drop(limiter.acquire().unwrap());
+        assert!(limiter.acquire().is_none());
+
+        let timeout = Duration::from_millis(10);
+        let limiter = Limiter::per_second(1e-300, 1, Overflow::Queue(Some(timeout)));
+        drop(limiter.acquire().unwrap());
+        assert!(limiter.acquire().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "positive number")]
+    fn zero_rate_is_refused() {
+        Limiter::per_second(0.0, 1, Overflow::Reject);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive number")]
+    fn nan_rate_is_refused() {
+        Limiter::new(1, Overflow::Reject).with_rate(f64::NAN, 1);
+    }
+}
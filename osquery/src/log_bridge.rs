@@ -0,0 +1,165 @@
+// A tracing layer that ships the extension's own log lines into osqueryd, via the
+// manager's `logger` registry, so they end up next to osquery's status logs.
+use std::cell::Cell;
+use std::fmt::{Debug, Write as _};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use serde_json::json;
+use tracing::field::{Field, Visit};
+use tracing::{warn, Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+use crate::{Client, TExtensionManagerSyncClient};
+
+/// How many lines can wait to be forwarded before new ones are dropped, so a manager
+/// that's slow to take them can't grow the queue without bound.
+pub const QUEUE_LIMIT: usize = 4096;
+
+thread_local! {
+    // set on the forwarding thread, so the thrift calls it makes can't log their way into a loop
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug)]
+struct StatusLine {
+    severity: u8,
+    file: String,
+    line: u32,
+    message: String,
+    time: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct OsqueryLogLayer {
+    sender: Sender<StatusLine>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl OsqueryLogLayer {
+    /// Open a dedicated connection to the manager at `manager_socket` and start forwarding.
+    /// The logger plugin is whatever osqueryd's `--logger_plugin` is set to.
+    pub fn connect<P: AsRef<Path>>(manager_socket: P) -> Result<Self, thrift::Error> {
+        let mut client = Client::connect(manager_socket, Duration::from_secs(3))?;
+        let logger = client
+            .options()?
+            .remove("logger_plugin")
+            .and_then(|o| o.value)
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| "filesystem".to_string());
+        let (sender, receiver) = bounded(QUEUE_LIMIT);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counted = dropped.clone();
+        std::thread::spawn(move || {
+            FORWARDING.with(|f| f.set(true));
+            forward(client, logger, receiver, counted)
+        });
+        Ok(Self { sender, dropped })
+    }
+
+    /// Lines dropped so far because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn forward(
+    mut client: Client,
+    logger: String,
+    receiver: Receiver<StatusLine>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut reported = 0;
+    while let Ok(first) = receiver.recv() {
+        // only the other layers see these, this thread's events aren't forwarded
+        let total = dropped.load(Ordering::Relaxed);
+        if total > reported {
+            warn!(
+                dropped = total - reported,
+                total, "osquery log forwarding fell behind, dropped lines"
+            );
+            reported = total;
+        }
+        // send whatever piled up while we were busy in one go
+        let lines: Vec<_> = std::iter::once(first)
+            .chain(receiver.try_iter())
+            .map(|l| {
+                json!({
+                    "s": l.severity,
+                    "f": l.file,
+                    "i": l.line,
+                    "m": l.message,
+                    "u": l.time,
+                })
+            })
+            .collect();
+        let mut request = crate::ExtensionPluginRequest::new();
+        request.insert("status".to_string(), "true".to_string());
+        request.insert(
+            "log".to_string(),
+            serde_json::Value::from(lines).to_string(),
+        );
+        if let Err(error) = client.call("logger", &logger, request) {
+            warn!(%error, "osquery log forwarding failed");
+        }
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for OsqueryLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if FORWARDING.with(|f| f.get()) {
+            return;
+        }
+        let meta = event.metadata();
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let severity = match *meta.level() {
+            Level::ERROR => 2,
+            Level::WARN => 1,
+            _ => 0,
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let line = StatusLine {
+            severity,
+            file: meta.file().unwrap_or_else(|| meta.target()).to_string(),
+            line: meta.line().unwrap_or_default(),
+            message: visitor.message + &visitor.fields,
+            time,
+        };
+        // never block whatever's logging on the manager
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(line) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
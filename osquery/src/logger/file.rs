@@ -0,0 +1,158 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use tracing::{debug, warn};
+
+use super::{LoggerPlugin, StatusLog};
+use crate::Plugin;
+
+pub const DEFAULT_LOG_DIR: &str = "/var/log/osquery";
+pub const DEFAULT_MAX_BYTES: u64 = 25 * 1024 * 1024;
+pub const DEFAULT_KEEP: usize = 10;
+
+/// Writes results, snapshots and status logs to newline-delimited JSON files in a
+/// directory (`rust.results.log`, `rust.snapshots.log`, `rust.status.log`), rotating each
+/// one to `<file>.1`, `<file>.2`, ... once it gets past `max_bytes` and keeping `keep`
+/// rotated files around.
+#[derive(Debug)]
+pub struct FileLoggerPlugin {
+    results: Mutex<RotatingFile>,
+    snapshots: Mutex<RotatingFile>,
+    status: Mutex<RotatingFile>,
+}
+
+impl FileLoggerPlugin {
+    pub fn in_dir<P: AsRef<Path>>(dir: P) -> Self {
+        Self::with_rotation(dir, DEFAULT_MAX_BYTES, DEFAULT_KEEP)
+    }
+
+    pub fn with_rotation<P: AsRef<Path>>(dir: P, max_bytes: u64, keep: usize) -> Self {
+        let dir = dir.as_ref();
+        let file = |name: &str| Mutex::new(RotatingFile::new(dir.join(name), max_bytes, keep));
+        Self {
+            results: file("rust.results.log"),
+            snapshots: file("rust.snapshots.log"),
+            status: file("rust.status.log"),
+        }
+    }
+}
+
+impl Plugin for FileLoggerPlugin {
+    type Error = std::io::Error;
+    const NAME: &'static str = "file_logger";
+    const REGISTRY: &'static str = "logger";
+
+    fn new() -> Self {
+        Self::in_dir(DEFAULT_LOG_DIR)
+    }
+}
+
+impl LoggerPlugin for FileLoggerPlugin {
+    fn log_string(&self, line: &str) -> Result<(), Self::Error> {
+        lock(&self.results).write_line(line)
+    }
+
+    fn log_snapshot(&self, snapshot: &str) -> Result<(), Self::Error> {
+        lock(&self.snapshots).write_line(snapshot)
+    }
+
+    fn log_status(&self, logs: &[StatusLog]) -> Result<(), Self::Error> {
+        let mut file = lock(&self.status);
+        for log in logs {
+            file.write_line(&serde_json::to_string(log)?)?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        for file in [&self.results, &self.snapshots, &self.status] {
+            if let Err(error) = lock(file).flush() {
+                warn!(%error, "couldn't flush log file on shutdown");
+            }
+        }
+    }
+}
+
+crate::logger_plugin!(FileLoggerPlugin);
+
+fn lock(file: &Mutex<RotatingFile>) -> std::sync::MutexGuard<'_, RotatingFile> {
+    file.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn new(path: PathBuf, max_bytes: u64, keep: usize) -> Self {
+        Self {
+            path,
+            file: None,
+            size: 0,
+            max_bytes,
+            keep,
+        }
+    }
+
+    // opened lazily, so a plugin that's never used doesn't leave empty files around
+    fn file(&mut self) -> std::io::Result<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().expect("just opened"))
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        let file = self.file()?;
+        file.write_all(line.as_bytes())?;
+        file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        debug!(path = ?self.path, "rotating log file");
+        self.file = None;
+        if self.keep == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        // the oldest one falls off the end, everything else shifts down by one
+        let _ = std::fs::remove_file(self.rotated(self.keep));
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated(1))
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use tracing::{debug, info, warn};
+
+use super::{LoggerPlugin, StatusLog};
+use crate::Plugin;
+
+#[derive(Debug, Clone)]
+pub struct HttpLoggerConfig {
+    /// Where batches get POSTed
+    pub endpoint: String,
+    /// Sent with every request, e.g. `("Authorization", "Bearer ...")`
+    pub headers: Vec<(String, String)>,
+    /// Lines per request
+    pub batch_size: usize,
+    /// Send whatever's been collected at least this often
+    pub flush_interval: Duration,
+    pub request_timeout: Duration,
+    /// Batches that couldn't be sent get written here and retried (even across restarts)
+    /// until they go through. Without it, failed batches are only kept in memory.
+    pub spool_dir: Option<PathBuf>,
+    /// How many failed batches to hold in memory before dropping the oldest. With a
+    /// `spool_dir`, the oldest stay on disk instead, and are read back once the rest
+    /// have gone through.
+    pub max_pending: usize,
+    pub max_backoff: Duration,
+}
+
+impl Default for HttpLoggerConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:8080/osquery/log".to_string(),
+            headers: vec![],
+            batch_size: 500,
+            flush_interval: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            spool_dir: None,
+            max_pending: 1000,
+            max_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    log_type: &'static str,
+    line: String,
+}
+
+/// Batches results and status logs and POSTs them as
+/// `{"log_type": "result", "data": [...]}`, the same shape osquery's own TLS logger uses.
+/// Failed batches are retried with exponential backoff, spooling to disk if configured.
+#[derive(Debug)]
+pub struct HttpLoggerPlugin {
+    sender: Mutex<Option<Sender<Entry>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl HttpLoggerPlugin {
+    pub fn start(config: HttpLoggerConfig) -> std::io::Result<Self> {
+        let mut pending = VecDeque::new();
+        if let Some(dir) = &config.spool_dir {
+            std::fs::create_dir_all(dir)?;
+            pending = load_spool(dir)?;
+            if !pending.is_empty() {
+                info!(count = pending.len(), "resuming spooled log batches");
+            }
+        }
+        let (sender, receiver) = unbounded();
+        let worker = std::thread::spawn(move || Worker::new(config, pending).run(receiver));
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(worker)),
+        })
+    }
+
+    fn push(&self, log_type: &'static str, line: String) -> std::io::Result<()> {
+        let sender = self.sender.lock().unwrap_or_else(|e| e.into_inner());
+        sender
+            .as_ref()
+            .and_then(|s| s.send(Entry { log_type, line }).ok())
+            .ok_or_else(|| std::io::Error::other("http logger has shut down"))
+    }
+}
+
+impl Plugin for HttpLoggerPlugin {
+    type Error = std::io::Error;
+    const NAME: &'static str = "http_logger";
+    const REGISTRY: &'static str = "logger";
+
+    fn new() -> Self {
+        Self::start(HttpLoggerConfig::default()).expect("couldn't start http logger")
+    }
+}
+
+impl LoggerPlugin for HttpLoggerPlugin {
+    fn log_string(&self, line: &str) -> Result<(), Self::Error> {
+        self.push("result", line.to_string())
+    }
+
+    fn log_status(&self, logs: &[StatusLog]) -> Result<(), Self::Error> {
+        for log in logs {
+            self.push("status", serde_json::to_string(log)?)?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        // closing the channel tells the worker to send what it has and stop
+        self.sender.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(worker) = self.worker.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+crate::logger_plugin!(HttpLoggerPlugin);
+
+#[derive(Debug)]
+struct Batch {
+    body: String,
+    spooled: Option<PathBuf>,
+}
+
+fn load_spool(dir: &Path) -> std::io::Result<VecDeque<Batch>> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    // names start with a timestamp, so this is oldest first
+    paths.sort();
+    let mut pending = VecDeque::new();
+    for path in paths {
+        match std::fs::read_to_string(&path) {
+            Ok(body) => pending.push_back(Batch {
+                body,
+                spooled: Some(path),
+            }),
+            Err(error) => warn!(?path, %error, "skipping unreadable spool file"),
+        }
+    }
+    Ok(pending)
+}
+
+struct Worker {
+    config: HttpLoggerConfig,
+    agent: ureq::Agent,
+    pending: VecDeque<Batch>,
+    backoff: Duration,
+    retry_at: Instant,
+    spooled: u64,
+    // spooled batches that didn't fit in `pending`, still waiting on disk
+    on_disk: bool,
+}
+
+impl Worker {
+    fn new(config: HttpLoggerConfig, mut pending: VecDeque<Batch>) -> Self {
+        // everything resumed is spooled, so the rest is read back later
+        let on_disk = pending.len() > config.max_pending;
+        pending.truncate(config.max_pending);
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(config.request_timeout))
+            .build()
+            .into();
+        Self {
+            config,
+            agent,
+            pending,
+            backoff: Duration::default(),
+            retry_at: Instant::now(),
+            spooled: 0,
+            on_disk,
+        }
+    }
+
+    fn run(mut self, receiver: Receiver<Entry>) {
+        let mut results = vec![];
+        let mut statuses = vec![];
+        loop {
+            let deadline = Instant::now() + self.config.flush_interval;
+            let closed = loop {
+                match receiver.recv_deadline(deadline) {
+                    Ok(entry) => {
+                        let batch = match entry.log_type {
+                            "status" => &mut statuses,
+                            _ => &mut results,
+                        };
+                        batch.push(entry.line);
+                        if batch.len() >= self.config.batch_size {
+                            break false;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => break false,
+                    Err(RecvTimeoutError::Disconnected) => break true,
+                }
+            };
+            self.enqueue("result", &mut results);
+            self.enqueue("status", &mut statuses);
+            if closed || Instant::now() >= self.retry_at {
+                self.drain();
+            }
+            if closed {
+                if !self.pending.is_empty() {
+                    warn!(
+                        count = self.pending.len(),
+                        "http logger stopping with unsent batches"
+                    );
+                }
+                return;
+            }
+        }
+    }
+
+    fn enqueue(&mut self, log_type: &str, lines: &mut Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+        // the lines are already JSON, so splice them in rather than re-parsing them
+        let body = format!(
+            "{{\"log_type\":\"{}\",\"data\":[{}]}}",
+            log_type,
+            lines.join(",")
+        );
+        lines.clear();
+        let spooled = match &self.config.spool_dir {
+            Some(dir) => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis())
+                    .unwrap_or_default();
+                self.spooled += 1;
+                let path = dir.join(format!("{:020}-{:08}.json", millis, self.spooled));
+                match std::fs::write(&path, &body) {
+                    Ok(()) => Some(path),
+                    Err(error) => {
+                        warn!(?path, %error, "couldn't spool log batch, keeping it in memory");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        self.pending.push_back(Batch { body, spooled });
+        while self.pending.len() > self.config.max_pending {
+            if let Some(dropped) = self.pending.pop_front() {
+                // nothing's deleted before it's been sent
+                if dropped.spooled.is_some() {
+                    debug!("too many unsent log batches, leaving the oldest on disk");
+                    self.on_disk = true;
+                } else {
+                    warn!("too many unsent log batches, dropping the oldest");
+                }
+            }
+        }
+    }
+
+    fn drain(&mut self) {
+        loop {
+            if self.pending.is_empty() && !self.reload() {
+                return;
+            }
+            if !self.send_pending() {
+                return;
+            }
+        }
+    }
+
+    // read back what `enqueue` left on disk, now there's room. false if there's none
+    fn reload(&mut self) -> bool {
+        let dir = match (&self.config.spool_dir, self.on_disk) {
+            (Some(dir), true) => dir,
+            _ => return false,
+        };
+        self.on_disk = false;
+        match load_spool(dir) {
+            Ok(pending) => {
+                info!(count = pending.len(), "resending spooled log batches");
+                self.pending = pending;
+                self.on_disk = self.pending.len() > self.config.max_pending;
+                self.pending.truncate(self.config.max_pending);
+                !self.pending.is_empty()
+            }
+            Err(error) => {
+                warn!(?dir, %error, "couldn't read back spooled log batches");
+                false
+            }
+        }
+    }
+
+    // until one fails. false if it did
+    fn send_pending(&mut self) -> bool {
+        while let Some(batch) = self.pending.front() {
+            match self.send(&batch.body) {
+                Ok(()) => {
+                    debug!(bytes = batch.body.len(), "sent log batch");
+                    if let Some(path) = &batch.spooled {
+                        let _ = std::fs::remove_file(path);
+                    }
+                    self.pending.pop_front();
+                    self.backoff = Duration::default();
+                }
+                Err(error) => {
+                    self.backoff = (self.backoff * 2)
+                        .max(Duration::from_secs(1))
+                        .min(self.config.max_backoff);
+                    self.retry_at = Instant::now() + self.backoff;
+                    warn!(%error, backoff = ?self.backoff, "couldn't send log batch, will retry");
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn send(&self, body: &str) -> Result<(), ureq::Error> {
+        let mut request = self.agent.post(&self.config.endpoint);
+        for (key, value) in &self.config.headers {
+            request = request.header(key.as_str(), value.as_str());
+        }
+        request
+            .content_type("application/json")
+            .send(body.as_bytes())?;
+        Ok(())
+    }
+}
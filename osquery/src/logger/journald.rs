@@ -0,0 +1,113 @@
+use std::os::unix::net::UnixDatagram;
+
+use super::{LoggerPlugin, ResultHeader, StatusLog};
+use crate::Plugin;
+
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Sends results and status logs straight to systemd-journald over its native protocol,
+/// with the interesting bits as their own fields (`OSQUERY_LOG_TYPE`, `OSQUERY_QUERY_NAME`,
+/// `OSQUERY_ACTION`, ...) so `journalctl` can filter on them.
+///
+/// Each entry goes out as a single datagram, so very large results can be turned away by
+/// the kernel with `EMSGSIZE`; those come back as errors to osquery.
+#[derive(Debug)]
+pub struct JournaldLoggerPlugin {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl JournaldLoggerPlugin {
+    pub fn connect() -> std::io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            identifier: "osquery".to_string(),
+        })
+    }
+
+    /// What shows up as `SYSLOG_IDENTIFIER`, "osquery" by default.
+    pub fn with_identifier<S: Into<String>>(mut self, identifier: S) -> Self {
+        self.identifier = identifier.into();
+        self
+    }
+
+    fn send(&self, fields: &[(&str, &str)]) -> std::io::Result<()> {
+        let mut entry = Vec::new();
+        for (key, value) in fields {
+            entry.extend_from_slice(key.as_bytes());
+            if value.contains('\n') {
+                // multi-line values get the length-prefixed binary form
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+                entry.extend_from_slice(value.as_bytes());
+            } else {
+                entry.push(b'=');
+                entry.extend_from_slice(value.as_bytes());
+            }
+            entry.push(b'\n');
+        }
+        self.socket.send_to(&entry, JOURNALD_SOCKET).map(|_| ())
+    }
+
+    fn send_result(&self, log_type: &str, line: &str) -> std::io::Result<()> {
+        let header = ResultHeader::parse(line);
+        let mut fields = vec![
+            ("MESSAGE", line),
+            ("PRIORITY", "6"),
+            ("SYSLOG_IDENTIFIER", self.identifier.as_str()),
+            ("OSQUERY_LOG_TYPE", log_type),
+        ];
+        if let Some(name) = &header.name {
+            fields.push(("OSQUERY_QUERY_NAME", name));
+        }
+        if let Some(action) = &header.action {
+            fields.push(("OSQUERY_ACTION", action));
+        }
+        if let Some(host) = &header.host_identifier {
+            fields.push(("OSQUERY_HOST_IDENTIFIER", host));
+        }
+        self.send(&fields)
+    }
+}
+
+impl Plugin for JournaldLoggerPlugin {
+    type Error = std::io::Error;
+    const NAME: &'static str = "journald_logger";
+    const REGISTRY: &'static str = "logger";
+
+    fn new() -> Self {
+        Self::connect().expect("couldn't create a unix datagram socket")
+    }
+}
+
+impl LoggerPlugin for JournaldLoggerPlugin {
+    fn log_string(&self, line: &str) -> Result<(), Self::Error> {
+        self.send_result("result", line)
+    }
+
+    fn log_snapshot(&self, snapshot: &str) -> Result<(), Self::Error> {
+        self.send_result("snapshot", snapshot)
+    }
+
+    fn log_status(&self, logs: &[StatusLog]) -> Result<(), Self::Error> {
+        for log in logs {
+            let priority = match log.severity {
+                2 => "3",
+                1 => "4",
+                _ => "6",
+            };
+            let line = log.line.to_string();
+            self.send(&[
+                ("MESSAGE", log.message.as_str()),
+                ("PRIORITY", priority),
+                ("SYSLOG_IDENTIFIER", self.identifier.as_str()),
+                ("OSQUERY_LOG_TYPE", "status"),
+                ("CODE_FILE", log.filename.as_str()),
+                ("CODE_LINE", line.as_str()),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+crate::logger_plugin!(JournaldLoggerPlugin);
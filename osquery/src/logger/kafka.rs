@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{BaseRecord, DefaultProducerContext, Producer, ThreadedProducer};
+use tracing::warn;
+
+use super::{LoggerPlugin, ResultHeader, StatusLog};
+use crate::Plugin;
+
+/// What each record's key is taken from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeySelector {
+    /// The scheduled query's name, so every result for a query lands on the same partition
+    QueryName,
+    HostIdentifier,
+    /// No key, records get spread across partitions
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaLoggerConfig {
+    /// `bootstrap.servers`
+    pub brokers: String,
+    pub result_topic: String,
+    /// Defaults to `result_topic`
+    pub snapshot_topic: Option<String>,
+    /// Status logs are only published if this is set
+    pub status_topic: Option<String>,
+    /// Per-query topic overrides, keyed by query name
+    pub query_topics: BTreeMap<String, String>,
+    pub key: KeySelector,
+    /// Anything else to hand librdkafka, e.g. `("compression.type", "lz4")`
+    pub properties: Vec<(String, String)>,
+    pub flush_timeout: Duration,
+}
+
+impl Default for KafkaLoggerConfig {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            result_topic: "osquery_results".to_string(),
+            snapshot_topic: None,
+            status_topic: None,
+            query_topics: BTreeMap::new(),
+            key: KeySelector::QueryName,
+            properties: vec![],
+            flush_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum KafkaLoggerError {
+    #[error(transparent)]
+    Kafka(#[from] KafkaError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Publishes scheduled query results and snapshots (and optionally status logs) to Kafka.
+pub struct KafkaLoggerPlugin {
+    producer: ThreadedProducer<DefaultProducerContext>,
+    config: KafkaLoggerConfig,
+}
+
+impl std::fmt::Debug for KafkaLoggerPlugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaLoggerPlugin")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl KafkaLoggerPlugin {
+    pub fn connect(config: KafkaLoggerConfig) -> Result<Self, KafkaError> {
+        let mut client = ClientConfig::new();
+        client.set("bootstrap.servers", &config.brokers);
+        for (key, value) in &config.properties {
+            client.set(key, value);
+        }
+        Ok(Self {
+            producer: client.create()?,
+            config,
+        })
+    }
+
+    fn publish(&self, default_topic: &str, line: &str) -> Result<(), KafkaLoggerError> {
+        let header = ResultHeader::parse(line);
+        let topic = header
+            .name
+            .as_ref()
+            .and_then(|name| self.config.query_topics.get(name))
+            .map(String::as_str)
+            .unwrap_or(default_topic);
+        let key = match self.config.key {
+            KeySelector::QueryName => header.name.as_deref(),
+            KeySelector::HostIdentifier => header.host_identifier.as_deref(),
+            KeySelector::None => None,
+        };
+        let mut record = BaseRecord::<str, str>::to(topic).payload(line);
+        if let Some(key) = key {
+            record = record.key(key);
+        }
+        self.producer.send(record).map_err(|(e, _)| e)?;
+        Ok(())
+    }
+}
+
+impl Plugin for KafkaLoggerPlugin {
+    type Error = KafkaLoggerError;
+    const NAME: &'static str = "kafka_logger";
+    const REGISTRY: &'static str = "logger";
+
+    fn new() -> Self {
+        Self::connect(KafkaLoggerConfig::default()).expect("couldn't create kafka producer")
+    }
+}
+
+impl LoggerPlugin for KafkaLoggerPlugin {
+    fn log_string(&self, line: &str) -> Result<(), Self::Error> {
+        self.publish(&self.config.result_topic, line)
+    }
+
+    fn log_snapshot(&self, snapshot: &str) -> Result<(), Self::Error> {
+        let topic = self
+            .config
+            .snapshot_topic
+            .as_deref()
+            .unwrap_or(&self.config.result_topic);
+        self.publish(topic, snapshot)
+    }
+
+    fn log_status(&self, logs: &[StatusLog]) -> Result<(), Self::Error> {
+        let topic = match &self.config.status_topic {
+            Some(topic) => topic,
+            None => return Ok(()),
+        };
+        for log in logs {
+            let payload = serde_json::to_string(log)?;
+            self.producer
+                .send(BaseRecord::<str, str>::to(topic).payload(&payload))
+                .map_err(|(e, _)| e)?;
+        }
+        Ok(())
+    }
+
+    fn wants_status(&self) -> bool {
+        self.config.status_topic.is_some()
+    }
+
+    fn shutdown(&self) {
+        if let Err(error) = self.producer.flush(self.config.flush_timeout) {
+            warn!(%error, "couldn't flush kafka producer on shutdown");
+        }
+    }
+}
+
+crate::logger_plugin!(KafkaLoggerPlugin);
@@ -0,0 +1,202 @@
+// Logger plugins receive osquery's results and status logs. Implement `LoggerPlugin`
+// (and `Plugin`, with `REGISTRY = "logger"`), then `logger_plugin!(YourType)` wires
+// up the routes and the thrift handler.
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+use crate::{metrics, ExtensionPluginRequest, ExtensionStatus, Plugin, PluginResponse, Response};
+
+mod file;
+#[cfg(feature = "http-logger")]
+mod http;
+#[cfg(all(unix, feature = "journald"))]
+mod journald;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(all(unix, feature = "syslog"))]
+mod syslog;
+
+pub use file::FileLoggerPlugin;
+#[cfg(feature = "http-logger")]
+pub use http::{HttpLoggerConfig, HttpLoggerPlugin};
+#[cfg(all(unix, feature = "journald"))]
+pub use journald::JournaldLoggerPlugin;
+#[cfg(feature = "kafka")]
+pub use kafka::{KafkaLoggerConfig, KafkaLoggerError, KafkaLoggerPlugin, KeySelector};
+#[cfg(all(unix, feature = "syslog"))]
+pub use syslog::{Facility, SyslogLoggerPlugin};
+
+/// One of osquery's own status log lines (the glog-style ones).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatusLog {
+    /// 0 = info, 1 = warning, 2 = error
+    #[serde(rename = "s", default)]
+    pub severity: i32,
+    #[serde(rename = "f", default)]
+    pub filename: String,
+    #[serde(rename = "i", default)]
+    pub line: u64,
+    #[serde(rename = "m", default)]
+    pub message: String,
+    #[serde(rename = "h", default, skip_serializing_if = "Option::is_none")]
+    pub host_identifier: Option<String>,
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub calendar_time: Option<String>,
+    #[serde(rename = "u", default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+}
+
+/// The handful of fields every result/snapshot line has, for backends that want to
+/// route or label lines without parsing the whole thing.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ResultHeader {
+    pub name: Option<String>,
+    pub action: Option<String>,
+    #[serde(rename = "hostIdentifier")]
+    pub host_identifier: Option<String>,
+}
+
+impl ResultHeader {
+    /// Never fails, a line that doesn't parse just has no header fields.
+    pub fn parse(line: &str) -> Self {
+        serde_json::from_str(line).unwrap_or_default()
+    }
+}
+
+pub trait LoggerPlugin: Plugin {
+    /// A scheduled query result (or anything else osquery logs as a string), already
+    /// serialized as a line of JSON.
+    fn log_string(&self, line: &str) -> Result<(), Self::Error>;
+    fn log_snapshot(&self, snapshot: &str) -> Result<(), Self::Error> {
+        self.log_string(snapshot)
+    }
+    fn log_status(&self, _logs: &[StatusLog]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Sent once when osquery starts using the logger, along with any status logs that
+    /// piled up before it was ready.
+    fn init(&self, _name: &str, logs: &[StatusLog]) -> Result<(), Self::Error> {
+        self.log_status(logs)
+    }
+    /// Whether osquery should send status logs at all.
+    fn wants_status(&self) -> bool {
+        true
+    }
+    fn shutdown(&self) {}
+}
+
+#[doc(hidden)]
+pub fn pong() -> ExtensionStatus {
+    trace!(target = "osquery::ping", "pong");
+    ExtensionStatus::success().with_message("OK")
+}
+
+#[doc(hidden)]
+pub fn routes() -> PluginResponse {
+    vec![]
+}
+
+fn parse_logs(request: &ExtensionPluginRequest) -> thrift::Result<Vec<StatusLog>> {
+    match request.get("log") {
+        Some(log) => serde_json::from_str(log).map_err(|e| {
+            thrift::Error::Application(thrift::ApplicationError::new(
+                thrift::ApplicationErrorKind::ProtocolError,
+                format!("got error deserializing status logs: {}", e),
+            ))
+        }),
+        None => Ok(vec![]),
+    }
+}
+
+// what `logger_plugin!` hooks up as the thrift call handler
+#[doc(hidden)]
+pub fn handle_call<L: LoggerPlugin>(
+    logger: &L,
+    mut request: ExtensionPluginRequest,
+) -> thrift::Result<Response> {
+    let started = Instant::now();
+    debug!(logger = L::NAME, keys = ?request.keys().collect::<Vec<_>>(), "handling logger call");
+    let to_thrift = |e: L::Error| {
+        thrift::Error::Application(thrift::ApplicationError::new(
+            thrift::ApplicationErrorKind::InternalError,
+            e.to_string(),
+        ))
+    };
+    let success = Response::success;
+    let result = if let Some(line) = request.get("string") {
+        logger
+            .log_string(line)
+            .map_err(to_thrift)
+            .map(|_| success(vec![]))
+    } else if let Some(snapshot) = request.get("snapshot") {
+        logger
+            .log_snapshot(snapshot)
+            .map_err(to_thrift)
+            .map(|_| success(vec![]))
+    } else if let Some(name) = request.get("init") {
+        parse_logs(&request).and_then(|logs| {
+            logger
+                .init(name, &logs)
+                .map_err(to_thrift)
+                .map(|_| success(vec![]))
+        })
+    } else if request.contains_key("status") {
+        parse_logs(&request).and_then(|logs| {
+            logger
+                .log_status(&logs)
+                .map_err(to_thrift)
+                .map(|_| success(vec![]))
+        })
+    } else {
+        match request.remove("action").as_deref() {
+            Some("features") => {
+                let mut features = ExtensionPluginRequest::new();
+                let status = if logger.wants_status() { "1" } else { "0" };
+                features.insert("logStatus".to_string(), status.to_string());
+                features.insert("logEvent".to_string(), "0".to_string());
+                Ok(success(vec![features]))
+            }
+            Some(other) => logger.handle_action(other, request),
+            None => Err(thrift::Error::Protocol(thrift::ProtocolError::new(
+                thrift::ProtocolErrorKind::NotImplemented,
+                format!("unsupported call to logger plugin `{}`", L::NAME),
+            ))),
+        }
+    };
+    metrics::global().record_response(L::NAME, started.elapsed(), &result);
+    result
+}
+
+/// Implements `Routes` and the thrift handler for a `LoggerPlugin`.
+#[macro_export]
+macro_rules! logger_plugin {
+    ($logger:ty) => {
+        impl $crate::Routes for $logger {
+            fn routes(&self) -> $crate::PluginResponse {
+                $crate::logger::routes()
+            }
+        }
+
+        impl $crate::PluginHandler for $logger {
+            fn handle_ping(&self) -> $crate::thrift::Result<$crate::ExtensionStatus> {
+                Ok($crate::logger::pong())
+            }
+
+            fn handle_call(
+                &self,
+                _registry: String,
+                _item: String,
+                request: $crate::ExtensionPluginRequest,
+            ) -> $crate::thrift::Result<$crate::Response> {
+                $crate::logger::handle_call(self, request)
+            }
+
+            fn handle_shutdown(&self) -> $crate::thrift::Result<()> {
+                $crate::logger::LoggerPlugin::shutdown(self);
+                Ok(())
+            }
+        }
+    };
+}
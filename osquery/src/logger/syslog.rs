@@ -0,0 +1,145 @@
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use super::{LoggerPlugin, ResultHeader, StatusLog};
+use crate::Plugin;
+
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+// https://datatracker.ietf.org/doc/html/rfc5424#section-6.2.1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Facility {
+    User = 1,
+    Daemon = 3,
+    Auth = 4,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+const SEVERITY_ERROR: u8 = 3;
+const SEVERITY_WARNING: u8 = 4;
+const SEVERITY_INFO: u8 = 6;
+
+/// Sends results and status logs to the local syslog daemon as RFC5424 messages, with
+/// the query name and action in an `osquery@32473` structured data element.
+#[derive(Debug)]
+pub struct SyslogLoggerPlugin {
+    socket: UnixDatagram,
+    path: PathBuf,
+    facility: Facility,
+    app_name: String,
+}
+
+impl SyslogLoggerPlugin {
+    pub fn connect<P: AsRef<Path>>(path: P, facility: Facility) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            path: path.as_ref().into(),
+            facility,
+            app_name: "osquery".to_string(),
+        })
+    }
+
+    pub fn with_app_name<S: Into<String>>(mut self, app_name: S) -> Self {
+        self.app_name = app_name.into();
+        self
+    }
+
+    fn send(
+        &self,
+        severity: u8,
+        msg_id: &str,
+        data: &[(&str, &str)],
+        message: &str,
+    ) -> std::io::Result<()> {
+        let pri = (self.facility as u8) * 8 + severity;
+        let mut structured = String::new();
+        if !data.is_empty() {
+            structured.push_str("[osquery@32473");
+            for (key, value) in data {
+                structured.push_str(&format!(" {}=\"{}\"", key, escape_param(value)));
+            }
+            structured.push(']');
+        } else {
+            structured.push('-');
+        }
+        // timestamp and hostname are left as NILVALUE for the daemon to fill in
+        let line = format!(
+            "<{}>1 - - {} {} {} {} {}",
+            pri,
+            self.app_name,
+            std::process::id(),
+            msg_id,
+            structured,
+            message
+        );
+        self.socket.send_to(line.as_bytes(), &self.path).map(|_| ())
+    }
+
+    fn send_result(&self, msg_id: &str, line: &str) -> std::io::Result<()> {
+        let header = ResultHeader::parse(line);
+        let mut data = vec![];
+        if let Some(name) = &header.name {
+            data.push(("name", name.as_str()));
+        }
+        if let Some(action) = &header.action {
+            data.push(("action", action.as_str()));
+        }
+        self.send(SEVERITY_INFO, msg_id, &data, line)
+    }
+}
+
+// RFC5424 section 6.3.3: '"', '\' and ']' have to be escaped in param values
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Plugin for SyslogLoggerPlugin {
+    type Error = std::io::Error;
+    const NAME: &'static str = "syslog_logger";
+    const REGISTRY: &'static str = "logger";
+
+    fn new() -> Self {
+        Self::connect(DEFAULT_SYSLOG_SOCKET, Facility::Daemon)
+            .expect("couldn't create a unix datagram socket")
+    }
+}
+
+impl LoggerPlugin for SyslogLoggerPlugin {
+    fn log_string(&self, line: &str) -> Result<(), Self::Error> {
+        self.send_result("result", line)
+    }
+
+    fn log_snapshot(&self, snapshot: &str) -> Result<(), Self::Error> {
+        self.send_result("snapshot", snapshot)
+    }
+
+    fn log_status(&self, logs: &[StatusLog]) -> Result<(), Self::Error> {
+        for log in logs {
+            let severity = match log.severity {
+                2 => SEVERITY_ERROR,
+                1 => SEVERITY_WARNING,
+                _ => SEVERITY_INFO,
+            };
+            let line = log.line.to_string();
+            let data = [("file", log.filename.as_str()), ("line", line.as_str())];
+            self.send(severity, "status", &data, &log.message)?;
+        }
+        Ok(())
+    }
+}
+
+crate::logger_plugin!(SyslogLoggerPlugin);
@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::Response;
+
+// how many recent call latencies each table keeps around for computing percentiles
+const LATENCY_SAMPLES: usize = 1024;
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Process-wide counters for everything the extension serves.
+pub fn global() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    active_connections: AtomicU64,
+    servers: AtomicU64,
+    tables: Mutex<BTreeMap<String, TableMetrics>>,
+}
+
+#[derive(Debug, Default)]
+struct TableMetrics {
+    calls: u64,
+    errors: u64,
+    latencies: VecDeque<Duration>,
+    last_call: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    pub uptime: Duration,
+    pub active_connections: u64,
+    /// Servers currently accepting connections from osquery
+    pub servers: u64,
+    pub tables: Vec<TableSnapshot>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableSnapshot {
+    pub name: String,
+    pub calls: u64,
+    pub errors: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub last_call: Option<SystemTime>,
+}
+
+/// Counts a connection as active until dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        ::metrics::gauge!("osquery_extension_active_connections").decrement(1.0);
+    }
+}
+
+/// Counts a server as accepting connections until dropped.
+#[derive(Debug)]
+pub struct ServerGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for ServerGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.servers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            active_connections: AtomicU64::new(0),
+            servers: AtomicU64::new(0),
+            tables: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn connection_opened(&self) -> ConnectionGuard<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        ::metrics::gauge!("osquery_extension_active_connections").increment(1.0);
+        ConnectionGuard { metrics: self }
+    }
+
+    pub fn server_started(&self) -> ServerGuard<'_> {
+        self.servers.fetch_add(1, Ordering::Relaxed);
+        ServerGuard { metrics: self }
+    }
+
+    pub fn record_call(&self, table: &str, elapsed: Duration, ok: bool) {
+        #[cfg(feature = "metrics")]
+        {
+            let table = table.to_string();
+            ::metrics::counter!("osquery_extension_calls_total", "table" => table.clone())
+                .increment(1);
+            if !ok {
+                ::metrics::counter!("osquery_extension_errors_total", "table" => table.clone())
+                    .increment(1);
+            }
+            ::metrics::histogram!("osquery_extension_call_duration_seconds", "table" => table)
+                .record(elapsed.as_secs_f64());
+        }
+        let mut tables = match self.tables.lock() {
+            Ok(tables) => tables,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let entry = tables.entry(table.to_string()).or_default();
+        entry.calls += 1;
+        entry.last_call = Some(SystemTime::now());
+        if !ok {
+            entry.errors += 1;
+        }
+        if entry.latencies.len() == LATENCY_SAMPLES {
+            entry.latencies.pop_front();
+        }
+        entry.latencies.push_back(elapsed);
+    }
+
+    /// `record_call` for a finished call, counting failed statuses (busy, timed out, ...)
+    /// as errors along with outright thrift errors.
+    pub fn record_response(
+        &self,
+        name: &str,
+        elapsed: Duration,
+        result: &thrift::Result<Response>,
+    ) {
+        let ok = match result {
+            Ok(r) => r.is_success(),
+            Err(_) => false,
+        };
+        self.record_call(name, elapsed, ok);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let tables = match self.tables.lock() {
+            Ok(tables) => tables,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        MetricsSnapshot {
+            uptime: self.started.elapsed(),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            servers: self.servers.load(Ordering::Relaxed),
+            tables: tables
+                .iter()
+                .map(|(name, t)| {
+                    let mut sorted: Vec<_> = t.latencies.iter().copied().collect();
+                    sorted.sort_unstable();
+                    TableSnapshot {
+                        name: name.clone(),
+                        calls: t.calls,
+                        errors: t.errors,
+                        p50: percentile(&sorted, 50),
+                        p90: percentile(&sorted, 90),
+                        p99: percentile(&sorted, 99),
+                        last_call: t.last_call,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::default();
+    }
+    let rank = (sorted.len() * pct).div_ceil(100);
+    sorted[rank.saturating_sub(1).min(sorted.len() - 1)]
+}
+
+/// Serve everything recorded through the `metrics` crate, including the counters above,
+/// on `http://<addr>/metrics` for prometheus to scrape. Call it once, early on.
+#[cfg(feature = "metrics")]
+pub fn install_prometheus_exporter(addr: std::net::SocketAddr) -> Result<(), anyhow::Error> {
+    metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()?;
+    Ok(())
+}
@@ -0,0 +1,268 @@
+// Plugins that serve several tables under one registration, for tables only known at
+// runtime: the C API's extensions, `dynamic::Host` and the Python bindings. Each of those
+// says how to find a table and how to call it. Registering, routes, metrics and shutdown
+// are the same for all of them, and `multi_table_plugin!` writes that part.
+use std::sync::Arc;
+use std::time::Instant;
+
+use maplit::btreemap;
+use tracing::{info_span, warn};
+
+use crate::{
+    builtin, metrics, register, Client, Column, Connector, ExtensionPluginRequest,
+    ExtensionPluginResponse, Handle, Plugin, QueryContext, Response,
+};
+
+/// One of the tables a `MultiTable` serves.
+pub(crate) trait ServedTable: Send + Sync + 'static {
+    fn routes(&self) -> &ExtensionPluginResponse;
+    fn columns(&self) -> &[Column];
+    /// Called when osquery shuts the extension down, alongside the other tables
+    fn shutdown(&self) -> Result<(), String>;
+}
+
+/// Several tables, served as one extension.
+pub(crate) trait MultiTable: Send + Sync + 'static {
+    type Table: ServedTable;
+    /// What the extension registers as
+    fn extension_name(&self) -> &str;
+    fn table(&self, name: &str) -> Option<Arc<Self::Table>>;
+    fn tables_by_name(&self) -> Vec<(String, Arc<Self::Table>)>;
+    /// Answer osquery's call to `item`, which is `table`
+    fn call(&self, item: &str, table: &Self::Table, request: ExtensionPluginRequest) -> Response;
+}
+
+/// Each table's name, routes and columns, for `Plugin::served_tables`.
+pub(crate) fn served_tables<M: MultiTable>(
+    plugin: &M,
+) -> Vec<(String, ExtensionPluginResponse, Vec<Column>)> {
+    plugin
+        .tables_by_name()
+        .into_iter()
+        .map(|(name, table)| (name, table.routes().clone(), table.columns().to_vec()))
+        .collect()
+}
+
+/// Register every table in the one extension, for `Plugin::install`.
+pub(crate) fn install<M, C>(
+    plugin: M,
+    client: &mut Client<C>,
+) -> Result<Handle<M, C>, anyhow::Error>
+where
+    M: MultiTable + Plugin,
+    C: Connector,
+{
+    let name = plugin.extension_name().to_string();
+    let _span = info_span!("register", extension = %name).entered();
+    let tables = served_tables(&plugin);
+    let registry = btreemap! {
+        "table".to_string() => tables
+            .iter()
+            .map(|(table, routes, _)| (table.clone(), routes.clone()))
+            .collect(),
+    };
+    let uuid = register(client, &name, registry)?;
+    let socket_path = client.socket_path(uuid)?;
+    for (table, _, columns) in tables {
+        builtin::record_registration(&table, uuid, &socket_path, columns);
+    }
+    let heartbeat = client.heartbeat();
+    Ok(Handle::on_transport(socket_path, plugin).with_heartbeat(&client.socket_path, heartbeat))
+}
+
+/// `PluginHandler::handle_call`: find the table and call it, counting the call.
+pub(crate) fn handle_call<M: MultiTable>(
+    plugin: &M,
+    item: String,
+    request: ExtensionPluginRequest,
+) -> thrift::Result<Response> {
+    let started = Instant::now();
+    let response = match plugin.table(&item) {
+        Some(table) => plugin.call(&item, &table, request),
+        None => Response::failure(format!("no table `{}` registered", item)),
+    };
+    let result = Ok(response);
+    metrics::global().record_response(&item, started.elapsed(), &result);
+    result
+}
+
+/// `PluginHandler::handle_shutdown`: every table's shutdown at once, see `shutdown::broadcast`.
+pub(crate) fn handle_shutdown<M: MultiTable>(plugin: &M) -> thrift::Result<()> {
+    let tables = plugin
+        .tables_by_name()
+        .into_iter()
+        .map(|(name, table)| (name, move || table.shutdown()));
+    crate::shutdown::broadcast(tables, crate::shutdown::DEFAULT_TIMEOUT)?;
+    Ok(())
+}
+
+/// The query a `generate` request is asking for, or the response to anything else, for
+/// tables that aren't `TablePlugin`s.
+#[cfg_attr(not(any(feature = "c-api", feature = "python")), allow(dead_code))]
+pub(crate) fn generate_query(
+    table: &str,
+    columns: &[Column],
+    mut request: ExtensionPluginRequest,
+) -> Result<QueryContext, Response> {
+    match request.remove("action").as_deref() {
+        Some("generate") => {}
+        Some("columns") => {
+            return Err(Response::success(
+                columns
+                    .iter()
+                    .map(|c| {
+                        btreemap! {
+                            "name".to_string() => c.name.clone(),
+                            "type".to_string() => c.kind.to_string(),
+                        }
+                    })
+                    .collect(),
+            ))
+        }
+        Some(other) => {
+            return Err(Response::failure(format!(
+                "action `{}` not supported by `{}`",
+                other, table
+            )))
+        }
+        None => return Err(Response::failure("request has no action")),
+    }
+    let context = request.remove("context").unwrap_or_default();
+    let mut query = serde_json::from_str::<QueryContext>(&context).unwrap_or_else(|error| {
+        // osquery filters the rows itself, so generating without constraints is slower but
+        // still right
+        warn!(table, %error, "couldn't parse query context, using an empty one");
+        QueryContext::default()
+    });
+    query.request = request;
+    Ok(query)
+}
+
+/// `Routes`, `Plugin` and `PluginHandler` for a `MultiTable`. `$name` is its `Plugin::NAME`,
+/// and `$named` makes an empty one registering as the name it's given.
+macro_rules! multi_table_plugin {
+    ($plugin:ty, $name:literal, $named:expr) => {
+        impl $crate::Routes for $plugin {
+            // each table has its own, see `install`
+            fn routes(&self) -> $crate::ExtensionPluginResponse {
+                vec![]
+            }
+        }
+
+        impl $crate::Plugin for $plugin {
+            type Error = std::io::Error;
+            const NAME: &'static str = $name;
+
+            fn new() -> Self {
+                ($named)(Self::NAME)
+            }
+
+            // what `install` registers
+            fn served_tables(
+                &self,
+            ) -> Option<Vec<(String, $crate::ExtensionPluginResponse, Vec<$crate::Column>)>> {
+                Some($crate::multi_table::served_tables(self))
+            }
+
+            // every table goes into the one registration
+            fn install<C: $crate::Connector>(
+                self,
+                client: &mut $crate::Client<C>,
+            ) -> Result<$crate::Handle<Self, C>, anyhow::Error> {
+                $crate::multi_table::install(self, client)
+            }
+        }
+
+        impl $crate::PluginHandler for $plugin {
+            fn handle_ping(&self) -> thrift::Result<$crate::ExtensionStatus> {
+                Ok($crate::Status::success().with_message("OK"))
+            }
+
+            fn handle_call(
+                &self,
+                _registry: String,
+                item: String,
+                request: $crate::ExtensionPluginRequest,
+            ) -> thrift::Result<$crate::Response> {
+                $crate::multi_table::handle_call(self, item, request)
+            }
+
+            fn handle_shutdown(&self) -> thrift::Result<()> {
+                $crate::multi_table::handle_shutdown(self)
+            }
+        }
+    };
+}
+
+pub(crate) use multi_table_plugin;
+
+#[cfg(test)]
+mod tests {
+    use maplit::btreemap;
+
+    use super::*;
+
+    fn columns() -> Vec<Column> {
+        vec![Column::text("name"), Column::integer("count")]
+    }
+
+    fn refused(request: ExtensionPluginRequest) -> Response {
+        match generate_query("fruit", &columns(), request) {
+            Ok(_) => panic!("expected a response, not a query"),
+            Err(response) => response,
+        }
+    }
+
+    fn message(response: &Response) -> Option<String> {
+        response.status.as_ref().and_then(|s| s.message.clone())
+    }
+
+    #[test]
+    fn columns_are_answered_without_generating() {
+        let request = btreemap! { "action".to_string() => "columns".to_string() };
+        let response = refused(request);
+        assert!(response.is_success());
+        assert_eq!(
+            response.response.unwrap(),
+            vec![
+                btreemap! {
+                    "name".to_string() => "name".to_string(),
+                    "type".to_string() => "TEXT".to_string(),
+                },
+                btreemap! {
+                    "name".to_string() => "count".to_string(),
+                    "type".to_string() => "INTEGER".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn other_actions_fail() {
+        let request = btreemap! { "action".to_string() => "delete".to_string() };
+        let response = refused(request);
+        assert!(!response.is_success());
+        assert_eq!(
+            message(&response).as_deref(),
+            Some("action `delete` not supported by `fruit`")
+        );
+
+        let response = refused(btreemap! {});
+        assert_eq!(message(&response).as_deref(), Some("request has no action"));
+    }
+
+    #[test]
+    fn generate_keeps_the_rest_of_the_request() {
+        let request = btreemap! {
+            "action".to_string() => "generate".to_string(),
+            "context".to_string() => "not json".to_string(),
+            "extra".to_string() => "1".to_string(),
+        };
+        let query = generate_query("fruit", &columns(), request).unwrap();
+        assert_eq!(
+            query.request,
+            btreemap! { "extra".to_string() => "1".to_string() }
+        );
+        assert!(!query.has_equals("name"));
+    }
+}
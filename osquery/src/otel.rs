@@ -0,0 +1,73 @@
+//! Exporting the extension's tracing spans to an OpenTelemetry collector over OTLP/HTTP,
+//! so its performance shows up in the same APM tooling as everything else. The spans
+//! worth looking at are `register` (one per plugin installed) and `call` (one per request
+//! from osquery, with the `table`, `action`, `rows` returned and `success`).
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+#[derive(Debug, Clone)]
+pub struct OtelConfig {
+    /// Where to send traces, e.g. `http://collector:4318/v1/traces`. Without one the
+    /// exporter goes by `OTEL_EXPORTER_OTLP_ENDPOINT`, then the OTLP default on localhost.
+    pub endpoint: Option<String>,
+    /// `service.name` on every span
+    pub service_name: String,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            service_name: "osquery-extension".to_string(),
+        }
+    }
+}
+
+/// Flushes spans still waiting to be exported when dropped. Hold on to it for as long
+/// as the extension runs.
+#[derive(Debug)]
+pub struct OtelGuard {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for OtelGuard {
+    fn drop(&mut self) {
+        if let Err(error) = self.provider.shutdown() {
+            tracing::warn!(%error, "couldn't flush traces");
+        }
+    }
+}
+
+/// A tracing layer exporting spans per `config`, to add to the extension's subscriber:
+///
+/// ```ignore
+/// let (otel, _guard) = osquery::otel::layer(&OtelConfig::default())?;
+/// tracing_subscriber::registry().with(otel).with(fmt::layer()).init();
+/// ```
+pub fn layer<S>(config: &OtelConfig) -> Result<(impl Layer<S>, OtelGuard), anyhow::Error>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut exporter = SpanExporter::builder().with_http();
+    if let Some(endpoint) = &config.endpoint {
+        exporter = exporter.with_endpoint(endpoint.as_str());
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter.build()?)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer("osquery");
+    Ok((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        OtelGuard { provider },
+    ))
+}
@@ -0,0 +1,199 @@
+// SQLite's LIKE and GLOB, so tables can filter on them the same way osquery will.
+//
+// LIKE: `%` is any run of characters, `_` is exactly one, and letters match regardless
+// of case (ASCII only, like SQLite without ICU). There's no escape character since
+// osquery doesn't pass one along.
+// GLOB: `*`, `?`, and `[...]` classes (`[^...]` to negate, `a-z` ranges), case-sensitive.
+
+#[derive(Debug)]
+enum Token {
+    Any,
+    One,
+    Char(char),
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        match self {
+            Token::Any | Token::One => true,
+            Token::Char(p) if ignore_case => p.eq_ignore_ascii_case(&c),
+            Token::Char(p) => *p == c,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+pub(crate) fn like(pattern: &str, value: &str) -> bool {
+    let tokens = pattern
+        .chars()
+        .map(|c| match c {
+            '%' => Token::Any,
+            '_' => Token::One,
+            c => Token::Char(c),
+        })
+        .collect::<Vec<_>>();
+    matches(&tokens, value, true)
+}
+
+pub(crate) fn glob(pattern: &str, value: &str) -> bool {
+    match glob_tokens(pattern) {
+        Some(tokens) => matches(&tokens, value, false),
+        // an unclosed `[` never matches anything in SQLite
+        None => false,
+    }
+}
+
+fn glob_tokens(pattern: &str) -> Option<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' => Token::Any,
+            '?' => Token::One,
+            '[' => {
+                let negated = chars.next_if_eq(&'^').is_some();
+                let mut ranges = vec![];
+                // a `]` right after the opening bracket is just a `]`
+                if let Some(c) = chars.next_if_eq(&']') {
+                    ranges.push((c, c));
+                }
+                loop {
+                    match chars.next()? {
+                        ']' => break,
+                        lo => match chars.peek() {
+                            Some('-') => {
+                                chars.next();
+                                match chars.next()? {
+                                    // `-` before the closing bracket is a literal too
+                                    ']' => {
+                                        ranges.push((lo, lo));
+                                        ranges.push(('-', '-'));
+                                        break;
+                                    }
+                                    hi => ranges.push((lo, hi)),
+                                }
+                            }
+                            _ => ranges.push((lo, lo)),
+                        },
+                    }
+                }
+                Token::Class { negated, ranges }
+            }
+            c => Token::Char(c),
+        });
+    }
+    Some(tokens)
+}
+
+// wildcard matching, backtracking to the most recent `Any` on a mismatch
+fn matches(tokens: &[Token], value: &str, ignore_case: bool) -> bool {
+    let value = value.chars().collect::<Vec<_>>();
+    let (mut t, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        match tokens.get(t) {
+            Some(Token::Any) => {
+                backtrack = Some((t, v));
+                t += 1;
+            }
+            Some(token) if token.matches(value[v], ignore_case) => {
+                t += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                // let the last `Any` swallow one more character and try again
+                Some((star, from)) => {
+                    t = star + 1;
+                    v = from + 1;
+                    backtrack = Some((star, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[t..].iter().all(|token| matches!(token, Token::Any))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn like_wildcards() {
+        assert!(like("%.so", "libc.so"));
+        assert!(like("lib%", "lib"));
+        assert!(like("lib%", "libc.so"));
+        assert!(like("%", ""));
+        assert!(like("a%%b", "ab"));
+        assert!(like("a%%b", "axyzb"));
+        assert!(like("%a%b%", "xxaxxbxx"));
+        assert!(!like("%.so", "libc.so.6"));
+        assert!(like("_", "x"));
+        assert!(!like("_", ""));
+        assert!(!like("_", "xy"));
+        assert!(like("a_c%", "abcdef"));
+        assert!(!like("a_c%", "acdef"));
+    }
+
+    #[test]
+    fn like_ignores_ascii_case_only() {
+        assert!(like("/ETC/%", "/etc/passwd"));
+        assert!(like("abc", "ABC"));
+        assert!(!like("é", "É"));
+        assert!(like("É", "É"));
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob("*.so", "libc.so"));
+        assert!(!glob("*.so", "libc.SO"));
+        assert!(glob("lib?.so", "libc.so"));
+        assert!(!glob("lib?.so", "lib.so"));
+        assert!(glob("**", ""));
+        assert!(glob("a*b*c", "aXbYc"));
+        assert!(!glob("a*b*c", "aXbY"));
+        // no special meaning for LIKE's wildcards
+        assert!(glob("100%", "100%"));
+        assert!(!glob("100%", "1000"));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(glob("[abc]", "b"));
+        assert!(!glob("[abc]", "d"));
+        assert!(glob("[a-z]x", "mx"));
+        assert!(!glob("[a-z]x", "Mx"));
+        assert!(glob("[^x]", "y"));
+        assert!(!glob("[^x]", "x"));
+        assert!(glob("[]a]", "]"));
+        assert!(glob("[]a]", "a"));
+        assert!(!glob("[]a]", "b"));
+        assert!(glob("[^]]", "a"));
+        assert!(!glob("[^]]", "]"));
+        assert!(glob("[a-]", "-"));
+        assert!(glob("*[0-9]", "sda1"));
+    }
+
+    #[test]
+    fn unclosed_class_never_matches() {
+        assert!(!glob("[abc", "a"));
+        assert!(!glob("[abc", "[abc"));
+        assert!(!glob("x[a-", "xa"));
+    }
+
+    #[test]
+    fn non_ascii_values() {
+        assert!(like("caf_", "café"));
+        assert!(like("%é", "café"));
+        assert!(glob("caf?", "café"));
+        assert!(glob("[à-ÿ]", "é"));
+        assert!(glob("*日本*", "こんにちは日本語"));
+        assert!(!like("caf_", "cafés"));
+    }
+}
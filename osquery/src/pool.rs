@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::{Client, Connector, DefaultTransport, ExtensionResponse, TExtensionManagerSyncClient};
+
+/// A set of connections to the extension manager for threads that want to make calls at
+/// the same time. A `Client` is one socket and one call at a time; the pool opens up to
+/// `max` of them on demand and hands them out one per caller, so `query` can be called
+/// from any number of threads through a shared `&ClientPool`.
+pub struct ClientPool<C: Connector = DefaultTransport> {
+    path: PathBuf,
+    timeout: Duration,
+    max: usize,
+    state: Mutex<State<C>>,
+    returned: Condvar,
+}
+
+struct State<C: Connector> {
+    idle: Vec<Client<C>>,
+    open: usize,
+}
+
+/// A client checked out of the pool. Derefs to `Client`, and goes back to the pool when
+/// dropped unless it's been `discard`ed.
+pub struct PooledClient<'a, C: Connector = DefaultTransport> {
+    pool: &'a ClientPool<C>,
+    client: Option<Client<C>>,
+}
+
+impl ClientPool {
+    pub fn new<P: AsRef<Path>>(path: P, timeout: Duration, max: usize) -> Self {
+        Self::via(path, timeout, max)
+    }
+}
+
+impl<C: Connector> ClientPool<C> {
+    /// `new`, for a transport other than the default
+    pub fn via<P: AsRef<Path>>(path: P, timeout: Duration, max: usize) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            timeout,
+            max: max.max(1),
+            state: Mutex::new(State {
+                idle: vec![],
+                open: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Connections currently open, idle or checked out
+    pub fn open(&self) -> usize {
+        self.lock().open
+    }
+
+    /// Check out a client, reusing an idle connection, opening a new one if there's room,
+    /// or waiting for one to come back.
+    pub fn get(&self) -> Result<PooledClient<'_, C>, thrift::Error> {
+        let mut state = self.lock();
+        loop {
+            if let Some(client) = state.idle.pop() {
+                return Ok(self.wrap(client));
+            }
+            if state.open < self.max {
+                state.open += 1;
+                // connect without holding the lock, it can take up to `timeout`
+                drop(state);
+                return match Client::connect_via(&self.path, self.timeout) {
+                    Ok(client) => {
+                        debug!(path = ?self.path, "opened pooled connection");
+                        Ok(self.wrap(client))
+                    }
+                    Err(e) => {
+                        self.forget();
+                        Err(e)
+                    }
+                };
+            }
+            state = self.returned.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Run a query on whichever connection is free. A connection that fails at the
+    /// transport level is closed rather than handed to the next caller.
+    pub fn query(&self, sql: &str) -> thrift::Result<ExtensionResponse> {
+        let mut client = self.get()?;
+        let result = client.query(sql.to_string());
+        if let Err(thrift::Error::Transport(_)) = &result {
+            client.discard();
+        }
+        result
+    }
+
+    fn wrap(&self, client: Client<C>) -> PooledClient<'_, C> {
+        PooledClient {
+            pool: self,
+            client: Some(client),
+        }
+    }
+
+    fn give_back(&self, client: Client<C>) {
+        self.lock().idle.push(client);
+        self.returned.notify_one();
+    }
+
+    // a connection went away, make room for a new one
+    fn forget(&self) {
+        self.lock().open -= 1;
+        self.returned.notify_one();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<C>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<C: Connector> std::fmt::Debug for ClientPool<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientPool")
+            .field("path", &self.path)
+            .field("max", &self.max)
+            .field("open", &self.open())
+            .finish()
+    }
+}
+
+impl<C: Connector> PooledClient<'_, C> {
+    /// Close this connection instead of returning it, e.g. after an error left it in an
+    /// unknown state.
+    pub fn discard(mut self) {
+        self.client.take();
+        self.pool.forget();
+    }
+}
+
+impl<C: Connector> std::ops::Deref for PooledClient<'_, C> {
+    type Target = Client<C>;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().expect("only taken on drop or discard")
+    }
+}
+
+impl<C: Connector> std::ops::DerefMut for PooledClient<'_, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().expect("only taken on drop or discard")
+    }
+}
+
+impl<C: Connector> Drop for PooledClient<'_, C> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.give_back(client);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Checking an extension can register before it tries, so a misconfigured deployment
+//! gets told what to fix up front rather than failing partway through the handshake:
+//!
+//! ```ignore
+//! osquery::preflight::check(&socket, &table)?;
+//! let mut client = Client::connect(&socket, Duration::from_secs(3))?;
+//! client.register_table(table)?.start()?;
+//! ```
+//!
+//! or the same through `Runner::preflight`, which `Runner::run` does first.
+//!
+//! `check` looks at everything it can, even after finding a problem, and logs each
+//! problem it finds as well as returning them all.
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tracing::{debug, error, info};
+
+use crate::transport::Stream;
+use crate::version::{self, IncompatibleManager};
+use crate::{
+    table_routes, Client, Connector, DefaultTransport, Plugin, TExtensionManagerSyncClient,
+    TExtensionSyncClient,
+};
+
+// long enough for a busy osqueryd, short enough not to hold up a start-up script
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Something that would stop the extension registering or serving.
+#[derive(Debug)]
+pub enum Problem {
+    /// Nothing at the manager's socket path
+    ManagerMissing { path: PathBuf },
+    /// Something's there, but it can't be connected to (or didn't answer)
+    ManagerUnreachable { path: PathBuf, error: String },
+    /// The osquery there is too old for this crate
+    Incompatible(IncompatibleManager),
+    /// The extension's own socket goes next to the manager's, and can't be created there
+    SocketDirNotWritable { dir: PathBuf, error: String },
+    /// osquery would refuse the plugin's routes, e.g. a table without columns
+    InvalidSchema { plugin: String, error: String },
+    /// osquery already has a plugin (or table alias) by this name in the registry
+    NameTaken { registry: String, name: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::ManagerMissing { path } => write!(
+                f,
+                "no osquery extension socket at {:?}. is osqueryd running, without \
+                 --disable_extensions, and with --extensions_socket set to that path?",
+                path
+            ),
+            Problem::ManagerUnreachable { path, error } => write!(
+                f,
+                "couldn't talk to osquery at {:?}: {}. check this process's user can \
+                 connect to the socket, and that osqueryd isn't wedged",
+                path, error
+            ),
+            Problem::Incompatible(e) => write!(f, "{}. upgrade osquery", e),
+            Problem::SocketDirNotWritable { dir, error } => write!(
+                f,
+                "can't create the extension's socket in {:?}: {}. osquery expects it next \
+                 to its own, so run as a user who can write there",
+                dir, error
+            ),
+            Problem::InvalidSchema { plugin, error } => {
+                write!(f, "osquery would refuse `{}`: {}", plugin, error)
+            }
+            Problem::NameTaken { registry, name } => write!(
+                f,
+                "osquery already has a {} plugin called `{}`, so registering this one \
+                 would fail. rename it (or drop the alias)",
+                registry, name
+            ),
+        }
+    }
+}
+
+/// Everything `check` found wrong.
+#[derive(thiserror::Error, Debug)]
+#[error("preflight found {} problem(s):{}", problems.len(), list(problems))]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+fn list(problems: &[Problem]) -> String {
+    problems.iter().map(|p| format!("\n  - {}", p)).collect()
+}
+
+/// Check `plugin` could register with the osquery at `manager` right now: the socket's
+/// there and answering, osquery's new enough, the extension's socket can be created next
+/// to it, the plugin's schema is valid, and its name (and any aliases) aren't taken. For
+/// a plugin serving several tables (`Plugin::served_tables`), that's every table's.
+pub fn check<P: Plugin>(manager: impl AsRef<Path>, plugin: &P) -> Result<(), Report> {
+    check_via::<DefaultTransport, P>(manager, plugin)
+}
+
+/// `check`, for a transport other than the default
+pub fn check_via<C: Connector, P: Plugin>(
+    manager: impl AsRef<Path>,
+    plugin: &P,
+) -> Result<(), Report> {
+    let manager = manager.as_ref();
+    let mut problems = vec![];
+
+    // what registering would add to osquery's registry, as (registry, name, routes)
+    let mut served = vec![];
+    match plugin.served_tables() {
+        Some(tables) => {
+            for (name, routes, columns) in tables {
+                // the same checks a table registering itself gets
+                if let Err(e) = table_routes(&name, &columns, vec![]) {
+                    problems.push(Problem::InvalidSchema {
+                        plugin: name.clone(),
+                        error: e.to_string(),
+                    });
+                }
+                served.push(("table".to_string(), name, routes));
+            }
+        }
+        None => {
+            let routes = match plugin.prepare_routes() {
+                Ok(()) => plugin.routes(),
+                Err(e) => {
+                    problems.push(Problem::InvalidSchema {
+                        plugin: P::NAME.to_string(),
+                        error: e.to_string(),
+                    });
+                    vec![]
+                }
+            };
+            served.push((P::REGISTRY.to_string(), P::NAME.to_string(), routes));
+        }
+    }
+
+    if let Some(dir) = manager.parent().filter(|d| d.is_dir()) {
+        if let Err(e) = try_create(dir) {
+            problems.push(Problem::SocketDirNotWritable {
+                dir: dir.to_path_buf(),
+                error: e.to_string(),
+            });
+        }
+    }
+
+    match connect::<C>(manager) {
+        Ok(mut client) => {
+            if let Err(e) = version::check_manager(&mut client) {
+                problems.push(Problem::Incompatible(e));
+            }
+            for (registry, name, routes) in served {
+                let aliases = routes
+                    .iter()
+                    .filter(|route| route.get("id").map(String::as_str) == Some("alias"))
+                    .filter_map(|route| route.get("alias").cloned());
+                for name in std::iter::once(name).chain(aliases) {
+                    if registered(&mut client, &registry, &name) {
+                        problems.push(Problem::NameTaken {
+                            registry: registry.clone(),
+                            name,
+                        });
+                    }
+                }
+            }
+        }
+        Err(problem) => problems.push(problem),
+    }
+
+    if problems.is_empty() {
+        info!(plugin = P::NAME, ?manager, "preflight checks passed");
+        return Ok(());
+    }
+    for problem in &problems {
+        error!(plugin = P::NAME, "preflight: {}", problem);
+    }
+    Err(Report { problems })
+}
+
+fn connect<C: Connector>(manager: &Path) -> Result<Client<C>, Problem> {
+    let unreachable = |error: String| Problem::ManagerUnreachable {
+        path: manager.to_path_buf(),
+        error,
+    };
+    let stream = match C::connect_timeout(manager, CONNECT_TIMEOUT) {
+        Ok(stream) => stream,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(Problem::ManagerMissing {
+                path: manager.to_path_buf(),
+            })
+        }
+        Err(e) => return Err(unreachable(e.to_string())),
+    };
+    stream
+        .set_timeouts(Some(CONNECT_TIMEOUT))
+        .map_err(|e| unreachable(e.to_string()))?;
+    let mut client =
+        Client::<C>::from_stream(manager, stream).map_err(|e| unreachable(e.to_string()))?;
+    TExtensionSyncClient::ping(&mut *client).map_err(|e| unreachable(e.to_string()))?;
+    Ok(client)
+}
+
+// the same as binding a socket would need, without leaving one behind
+fn try_create(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".osquery-rs-preflight.{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+// whether osquery's registry already has `name`. a manager that won't say gets the
+// benefit of the doubt
+fn registered<C: Connector>(client: &mut Client<C>, registry: &str, name: &str) -> bool {
+    let sql = format!(
+        "SELECT name FROM osquery_registry WHERE registry = '{}' AND name = '{}'",
+        registry.replace('\'', "''"),
+        name.replace('\'', "''"),
+    );
+    match client.query(sql) {
+        Ok(response) if response.is_success() => !response.response.unwrap_or_default().is_empty(),
+        Ok(response) => {
+            debug!(status = ?response.status, "couldn't read osquery's registry");
+            false
+        }
+        Err(error) => {
+            debug!(%error, "couldn't read osquery's registry");
+            false
+        }
+    }
+}
@@ -0,0 +1,473 @@
+// The binary protocol as both ends here speak it. thrift's own reader trusts the lengths
+// on the wire and allocates whatever they ask for, so a corrupt or hostile frame claiming
+// a 2GB string gets 2GB; this checks every length against `MessageLimits` first.
+//
+// It also deals with the one thing binary protocol peers disagree on: whether a message
+// starts with a version header ("strict") or goes straight into the method name. thrift's
+// reader either insists on the header or takes anything, and its errors for the wrong
+// guess are about buffers and versions, not about the other end being configured
+// differently. Which way to go is `Strictness`, and mismatches say so.
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use thrift::protocol::{
+    TBinaryInputProtocol, TBinaryOutputProtocol, TFieldIdentifier, TInputProtocol, TListIdentifier,
+    TMapIdentifier, TMessageIdentifier, TMessageType, TOutputProtocol, TSetIdentifier,
+    TStructIdentifier,
+};
+use thrift::{ProtocolError, ProtocolErrorKind, TransportError, TransportErrorKind};
+
+use crate::server::MessageLimits;
+
+// osquery's longest method name is a couple dozen bytes, an unversioned header claiming
+// more than this is something else entirely
+pub(crate) const MAX_METHOD_NAME: usize = 256;
+
+const VERSION_1: u32 = 0x8001_0000;
+
+/// Whether messages carry the binary protocol's version header. Different thrift builds
+/// default differently, and osquery has shipped with both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Always write the header, and refuse messages without one
+    Strict,
+    /// Never write the header, take messages with or without one
+    Lenient,
+    /// Take messages with or without the header, and write them however the other end
+    /// last did (with the header until it's said anything)
+    #[default]
+    Auto,
+}
+
+// one connection's strictness and what it's seen, shared by its reader and writer
+#[derive(Debug, Clone)]
+pub(crate) struct Negotiation(Arc<NegotiationState>);
+
+#[derive(Debug)]
+struct NegotiationState {
+    strictness: AtomicU8,
+    peer_strict: AtomicBool,
+    answered: AtomicBool,
+    wrote_lenient: AtomicBool,
+}
+
+impl Negotiation {
+    pub(crate) fn new(strictness: Strictness) -> Self {
+        Self(Arc::new(NegotiationState {
+            strictness: AtomicU8::new(strictness as u8),
+            peer_strict: AtomicBool::new(true),
+            answered: AtomicBool::new(false),
+            wrote_lenient: AtomicBool::new(false),
+        }))
+    }
+
+    pub(crate) fn set(&self, strictness: Strictness) {
+        self.0.strictness.store(strictness as u8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn strictness(&self) -> Strictness {
+        match self.0.strictness.load(Ordering::Relaxed) {
+            s if s == Strictness::Strict as u8 => Strictness::Strict,
+            s if s == Strictness::Lenient as u8 => Strictness::Lenient,
+            _ => Strictness::Auto,
+        }
+    }
+
+    fn peer_wrote(&self, strict: bool) {
+        self.0.peer_strict.store(strict, Ordering::Relaxed);
+        self.0.answered.store(true, Ordering::Relaxed);
+    }
+
+    fn write_strict(&self) -> bool {
+        let strict = match self.strictness() {
+            Strictness::Strict => true,
+            Strictness::Lenient => false,
+            Strictness::Auto => self.0.peer_strict.load(Ordering::Relaxed),
+        };
+        if !strict {
+            self.0.wrote_lenient.store(true, Ordering::Relaxed);
+        }
+        strict
+    }
+
+    // the other end hung up on a header-less message without ever answering, which is
+    // what a strict reader does with one
+    fn rejected_lenient(&self) -> bool {
+        self.0.wrote_lenient.load(Ordering::Relaxed) && !self.0.answered.load(Ordering::Relaxed)
+    }
+}
+
+// counts what's been consumed from the current message
+pub(crate) struct Metered<R> {
+    inner: R,
+    read: usize,
+}
+
+impl<R: Read> Read for Metered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read = self.read.saturating_add(n);
+        Ok(n)
+    }
+}
+
+pub struct LimitedInputProtocol<R: Read> {
+    inner: TBinaryInputProtocol<Metered<R>>,
+    limits: MessageLimits,
+    negotiation: Negotiation,
+}
+
+impl<R: Read> LimitedInputProtocol<R> {
+    pub(crate) fn new(transport: R, limits: MessageLimits, negotiation: Negotiation) -> Self {
+        Self {
+            inner: TBinaryInputProtocol::new(
+                Metered {
+                    inner: transport,
+                    read: 0,
+                },
+                true,
+            ),
+            limits,
+            negotiation,
+        }
+    }
+
+    // what's being read from
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn transport(&self) -> &R {
+        &self.inner.transport.inner
+    }
+
+    fn remaining(&self) -> usize {
+        self.limits
+            .max_message_size
+            .saturating_sub(self.inner.transport.read)
+    }
+
+    // a length off the wire, checked before anything is allocated for it
+    fn length(&self, len: i32, max: usize, what: &str) -> thrift::Result<usize> {
+        let len = usize::try_from(len).map_err(|_| {
+            error(
+                ProtocolErrorKind::NegativeSize,
+                format!("negative {} size {}", what, len),
+            )
+        })?;
+        if len > max {
+            return Err(error(
+                ProtocolErrorKind::SizeLimit,
+                format!("{} of {} exceeds the limit of {}", what, len, max),
+            ));
+        }
+        Ok(len)
+    }
+
+    fn container(&self, size: i32) -> thrift::Result<i32> {
+        self.length(size, self.limits.max_container_size, "container")?;
+        Ok(size)
+    }
+
+    fn metered<T>(&self, result: thrift::Result<T>) -> thrift::Result<T> {
+        if self.inner.transport.read > self.limits.max_message_size {
+            return Err(error(
+                ProtocolErrorKind::SizeLimit,
+                format!(
+                    "message exceeds the limit of {} bytes",
+                    self.limits.max_message_size
+                ),
+            ));
+        }
+        result
+    }
+}
+
+fn error(kind: ProtocolErrorKind, message: String) -> thrift::Error {
+    thrift::Error::Protocol(ProtocolError::new(kind, message))
+}
+
+impl<R: Read> TInputProtocol for LimitedInputProtocol<R> {
+    // thrift's version reads the method name with its own unchecked read_string
+    fn read_message_begin(&mut self) -> thrift::Result<TMessageIdentifier> {
+        self.inner.transport.read = 0;
+        let header = match self.inner.read_i32() {
+            Err(thrift::Error::Transport(e))
+                if e.kind == TransportErrorKind::EndOfFile
+                    && self.negotiation.rejected_lenient() =>
+            {
+                return Err(thrift::Error::Transport(TransportError::new(
+                    TransportErrorKind::EndOfFile,
+                    "the other end closed the connection without answering a message sent \
+                     without a version header; it may only take strict binary protocol, \
+                     try Strictness::Strict or Strictness::Auto",
+                )));
+            }
+            r => r?,
+        };
+        let (message_type, name) = if header < 0 {
+            if header as u32 & 0xffff_0000 != VERSION_1 {
+                return Err(error(
+                    ProtocolErrorKind::BadVersion,
+                    format!(
+                        "unknown protocol version in message header {:#x}, the other end \
+                         may not be speaking the binary protocol",
+                        header
+                    ),
+                ));
+            }
+            let message_type = TMessageType::try_from((header & 0xff) as u8)?;
+            let name = self.read_string()?;
+            self.negotiation.peer_wrote(true);
+            (message_type, name)
+        } else {
+            // no header, so that was the method name's length
+            if self.negotiation.strictness() == Strictness::Strict {
+                return Err(error(
+                    ProtocolErrorKind::BadVersion,
+                    "message has no version header, the other end is writing non-strict \
+                     binary protocol; Strictness::Lenient or Strictness::Auto takes it"
+                        .to_string(),
+                ));
+            }
+            let len = self
+                .length(header, MAX_METHOD_NAME, "method name")
+                .map_err(|_| {
+                    error(
+                        ProtocolErrorKind::InvalidData,
+                        format!(
+                            "message starts with {:#x}, which is neither a version header nor \
+                             a method name; the other end may be framing its messages or \
+                             speaking another protocol",
+                            header
+                        ),
+                    )
+                })?;
+            let mut name = vec![0; len];
+            self.inner.transport.read_exact(&mut name)?;
+            let name = String::from_utf8(name)?;
+            let message_type = TMessageType::try_from(self.inner.read_byte()?)?;
+            self.negotiation.peer_wrote(false);
+            (message_type, name)
+        };
+        let sequence_number = self.read_i32()?;
+        Ok(TMessageIdentifier::new(name, message_type, sequence_number))
+    }
+
+    fn read_message_end(&mut self) -> thrift::Result<()> {
+        self.inner.read_message_end()
+    }
+
+    fn read_struct_begin(&mut self) -> thrift::Result<Option<TStructIdentifier>> {
+        self.inner.read_struct_begin()
+    }
+
+    fn read_struct_end(&mut self) -> thrift::Result<()> {
+        self.inner.read_struct_end()
+    }
+
+    fn read_field_begin(&mut self) -> thrift::Result<TFieldIdentifier> {
+        let r = self.inner.read_field_begin();
+        self.metered(r)
+    }
+
+    fn read_field_end(&mut self) -> thrift::Result<()> {
+        self.inner.read_field_end()
+    }
+
+    fn read_bool(&mut self) -> thrift::Result<bool> {
+        let r = self.inner.read_bool();
+        self.metered(r)
+    }
+
+    fn read_bytes(&mut self) -> thrift::Result<Vec<u8>> {
+        let len = self.inner.read_i32()?;
+        let max = self.limits.max_string_size.min(self.remaining());
+        let len = self.length(len, max, "string")?;
+        let mut buf = vec![0; len];
+        self.inner.transport.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_i8(&mut self) -> thrift::Result<i8> {
+        let r = self.inner.read_i8();
+        self.metered(r)
+    }
+
+    fn read_i16(&mut self) -> thrift::Result<i16> {
+        let r = self.inner.read_i16();
+        self.metered(r)
+    }
+
+    fn read_i32(&mut self) -> thrift::Result<i32> {
+        let r = self.inner.read_i32();
+        self.metered(r)
+    }
+
+    fn read_i64(&mut self) -> thrift::Result<i64> {
+        let r = self.inner.read_i64();
+        self.metered(r)
+    }
+
+    fn read_double(&mut self) -> thrift::Result<f64> {
+        let r = self.inner.read_double();
+        self.metered(r)
+    }
+
+    fn read_string(&mut self) -> thrift::Result<String> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes).map_err(From::from)
+    }
+
+    fn read_list_begin(&mut self) -> thrift::Result<TListIdentifier> {
+        let list = self.inner.read_list_begin()?;
+        let size = self.container(list.size)?;
+        Ok(TListIdentifier::new(list.element_type, size))
+    }
+
+    fn read_list_end(&mut self) -> thrift::Result<()> {
+        self.inner.read_list_end()
+    }
+
+    fn read_set_begin(&mut self) -> thrift::Result<TSetIdentifier> {
+        let set = self.inner.read_set_begin()?;
+        let size = self.container(set.size)?;
+        Ok(TSetIdentifier::new(set.element_type, size))
+    }
+
+    fn read_set_end(&mut self) -> thrift::Result<()> {
+        self.inner.read_set_end()
+    }
+
+    fn read_map_begin(&mut self) -> thrift::Result<TMapIdentifier> {
+        let map = self.inner.read_map_begin()?;
+        let size = self.container(map.size)?;
+        Ok(TMapIdentifier::new(map.key_type, map.value_type, size))
+    }
+
+    fn read_map_end(&mut self) -> thrift::Result<()> {
+        self.inner.read_map_end()
+    }
+
+    fn read_byte(&mut self) -> thrift::Result<u8> {
+        let r = self.inner.read_byte();
+        self.metered(r)
+    }
+}
+
+/// The writing half, putting the version header on messages or not as `Strictness` says.
+pub struct NegotiatedOutputProtocol<W: Write> {
+    inner: TBinaryOutputProtocol<W>,
+    negotiation: Negotiation,
+}
+
+impl<W: Write> NegotiatedOutputProtocol<W> {
+    pub(crate) fn new(transport: W, negotiation: Negotiation) -> Self {
+        Self {
+            inner: TBinaryOutputProtocol::new(transport, true),
+            negotiation,
+        }
+    }
+}
+
+impl<W: Write> TOutputProtocol for NegotiatedOutputProtocol<W> {
+    fn write_message_begin(&mut self, identifier: &TMessageIdentifier) -> thrift::Result<()> {
+        let message_type = u8::from(identifier.message_type);
+        if self.negotiation.write_strict() {
+            self.inner
+                .write_i32((VERSION_1 | u32::from(message_type)) as i32)?;
+            self.inner.write_string(&identifier.name)?;
+        } else {
+            self.inner.write_string(&identifier.name)?;
+            self.inner.write_byte(message_type)?;
+        }
+        self.inner.write_i32(identifier.sequence_number)
+    }
+
+    fn write_message_end(&mut self) -> thrift::Result<()> {
+        self.inner.write_message_end()
+    }
+
+    fn write_struct_begin(&mut self, identifier: &TStructIdentifier) -> thrift::Result<()> {
+        self.inner.write_struct_begin(identifier)
+    }
+
+    fn write_struct_end(&mut self) -> thrift::Result<()> {
+        self.inner.write_struct_end()
+    }
+
+    fn write_field_begin(&mut self, identifier: &TFieldIdentifier) -> thrift::Result<()> {
+        self.inner.write_field_begin(identifier)
+    }
+
+    fn write_field_end(&mut self) -> thrift::Result<()> {
+        self.inner.write_field_end()
+    }
+
+    fn write_field_stop(&mut self) -> thrift::Result<()> {
+        self.inner.write_field_stop()
+    }
+
+    fn write_bool(&mut self, b: bool) -> thrift::Result<()> {
+        self.inner.write_bool(b)
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> thrift::Result<()> {
+        self.inner.write_bytes(b)
+    }
+
+    fn write_i8(&mut self, i: i8) -> thrift::Result<()> {
+        self.inner.write_i8(i)
+    }
+
+    fn write_i16(&mut self, i: i16) -> thrift::Result<()> {
+        self.inner.write_i16(i)
+    }
+
+    fn write_i32(&mut self, i: i32) -> thrift::Result<()> {
+        self.inner.write_i32(i)
+    }
+
+    fn write_i64(&mut self, i: i64) -> thrift::Result<()> {
+        self.inner.write_i64(i)
+    }
+
+    fn write_double(&mut self, d: f64) -> thrift::Result<()> {
+        self.inner.write_double(d)
+    }
+
+    fn write_string(&mut self, s: &str) -> thrift::Result<()> {
+        self.inner.write_string(s)
+    }
+
+    fn write_list_begin(&mut self, identifier: &TListIdentifier) -> thrift::Result<()> {
+        self.inner.write_list_begin(identifier)
+    }
+
+    fn write_list_end(&mut self) -> thrift::Result<()> {
+        self.inner.write_list_end()
+    }
+
+    fn write_set_begin(&mut self, identifier: &TSetIdentifier) -> thrift::Result<()> {
+        self.inner.write_set_begin(identifier)
+    }
+
+    fn write_set_end(&mut self) -> thrift::Result<()> {
+        self.inner.write_set_end()
+    }
+
+    fn write_map_begin(&mut self, identifier: &TMapIdentifier) -> thrift::Result<()> {
+        self.inner.write_map_begin(identifier)
+    }
+
+    fn write_map_end(&mut self) -> thrift::Result<()> {
+        self.inner.write_map_end()
+    }
+
+    fn flush(&mut self) -> thrift::Result<()> {
+        self.inner.flush()
+    }
+
+    fn write_byte(&mut self, b: u8) -> thrift::Result<()> {
+        self.inner.write_byte(b)
+    }
+}
@@ -0,0 +1,274 @@
+// Tables written in Python, served by this crate. Python only says what the columns are
+// and makes rows; registering, the socket, thrift and shutdown are all on the Rust side.
+//
+//     import osquery_rs
+//
+//     class Fruit:
+//         name = "fruit"
+//
+//         def columns(self):
+//             return [("name", "TEXT"), ("count", "INTEGER")]
+//
+//         def generate(self, context):
+//             return [{"name": "apple", "count": 3}]
+//
+//     extension = osquery_rs.Extension("fruit_stand")
+//     extension.add_table(Fruit())
+//     extension.run("/var/osquery/osquery.em")
+//
+// A table can also have a `shutdown()` method, called when osquery shuts the extension
+// down. To get an importable module, build a `cdylib` crate named `osquery_rs` that
+// depends on this one with the `python` feature (and pyo3's `extension-module`) and has
+// `pub use osquery::python::*;`, e.g. with maturin.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt};
+use tracing::warn;
+
+use crate::gen::table::ColumnType;
+use crate::multi_table::{generate_query, multi_table_plugin, MultiTable, ServedTable};
+use crate::{
+    Client, Column, ColumnValue, ExtensionPluginRequest, ExtensionPluginResponse, Plugin,
+    QueryContext, Response, RowSet, TableRows,
+};
+
+/// How often `Extension.run` checks for a KeyboardInterrupt while serving
+const SIGNAL_POLL: Duration = Duration::from_millis(100);
+
+/// The query context osquery sent, as `generate` sees it.
+#[pyclass(name = "QueryContext", module = "osquery_rs", frozen)]
+pub struct PyQueryContext {
+    query: QueryContext,
+}
+
+#[pymethods]
+impl PyQueryContext {
+    /// The values `column` is compared to with `=`.
+    fn equals(&self, column: &str) -> BTreeSet<String> {
+        self.query.equals_set(column).into_iter().collect()
+    }
+
+    fn has_equals(&self, column: &str) -> bool {
+        self.query.has_equals(column)
+    }
+
+    /// Whether the query needs `column` at all, so expensive ones can be skipped.
+    fn is_column_used(&self, column: &str) -> bool {
+        self.query.is_column_used(column)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("QueryContext({})", self.query.constraint_summary())
+    }
+}
+
+pub(crate) struct PyTable {
+    columns: Vec<Column>,
+    routes: ExtensionPluginResponse,
+    object: PyObject,
+}
+
+fn column_type(kind: &str) -> PyResult<ColumnType> {
+    match serde_json::from_value(serde_json::Value::String(kind.to_uppercase())) {
+        Ok(ColumnType::Unknown) | Err(_) => Err(PyValueError::new_err(format!(
+            "unknown column type `{}`, expected TEXT, INTEGER, BIGINT or DOUBLE",
+            kind
+        ))),
+        Ok(kind) => Ok(kind),
+    }
+}
+
+fn column_value(value: &Bound<'_, PyAny>) -> PyResult<Option<ColumnValue>> {
+    if value.is_none() {
+        return Ok(None);
+    }
+    // bool is a subclass of int, so it has to go first
+    let value = if value.is_instance_of::<PyBool>() {
+        ColumnValue::integer(value.extract::<bool>()? as i32)
+    } else if value.is_instance_of::<PyInt>() {
+        ColumnValue::big_int(value.extract::<i64>()?)
+    } else if value.is_instance_of::<PyFloat>() {
+        ColumnValue::double(value.extract::<f64>()?)
+    } else {
+        ColumnValue::text(value.str()?.to_string())
+    };
+    Ok(Some(value))
+}
+
+impl PyTable {
+    fn generate(&self, query: QueryContext) -> PyResult<RowSet> {
+        Python::with_gil(|py| {
+            let context = Py::new(py, PyQueryContext { query })?;
+            let generated = self.object.call_method1(py, "generate", (context,))?;
+            let mut rows = TableRows::new();
+            for row in generated.bind(py).try_iter()? {
+                let row = row?;
+                let row = row.downcast::<PyDict>()?;
+                let mut values = BTreeMap::new();
+                for (column, value) in row.iter() {
+                    if let Some(value) = column_value(&value)? {
+                        values.insert(column.extract::<String>()?, value);
+                    }
+                }
+                rows.push(values);
+            }
+            Ok(RowSet::from_table_rows(&self.columns, rows))
+        })
+    }
+}
+
+impl ServedTable for PyTable {
+    fn routes(&self) -> &ExtensionPluginResponse {
+        &self.routes
+    }
+
+    fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    fn shutdown(&self) -> Result<(), String> {
+        Python::with_gil(|py| {
+            let object = self.object.bind(py);
+            if object.hasattr("shutdown").unwrap_or(false) {
+                object.call_method0("shutdown")?;
+            }
+            Ok(())
+        })
+        .map_err(|e: PyErr| e.to_string())
+    }
+}
+
+/// Tables to serve to osquery as one extension.
+#[pyclass(name = "Extension", module = "osquery_rs")]
+#[derive(Clone)]
+pub struct PyExtension {
+    name: String,
+    tables: BTreeMap<String, Arc<PyTable>>,
+}
+
+impl fmt::Debug for PyExtension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PyExtension")
+            .field("name", &self.name)
+            .field("tables", &self.tables.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[pymethods]
+impl PyExtension {
+    #[new]
+    fn py_new(name: String) -> Self {
+        Self {
+            name,
+            tables: BTreeMap::new(),
+        }
+    }
+
+    /// Add `table`, which needs a `name` attribute, a `columns()` method returning
+    /// `(name, type)` pairs, and a `generate(context)` method returning dicts.
+    fn add_table(&mut self, table: &Bound<'_, PyAny>) -> PyResult<()> {
+        let name = table.getattr("name")?.extract::<String>()?;
+        if self.tables.contains_key(&name) {
+            return Err(PyValueError::new_err(format!(
+                "table `{}` is already added",
+                name
+            )));
+        }
+        let columns = table
+            .call_method0("columns")?
+            .extract::<Vec<(String, String)>>()?
+            .into_iter()
+            .map(|(column, kind)| Ok(Column::new(&column, column_type(&kind)?)))
+            .collect::<PyResult<Vec<_>>>()?;
+        let routes = crate::table_routes(&name, &columns, vec![])
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        self.tables.insert(
+            name,
+            Arc::new(PyTable {
+                columns,
+                routes,
+                object: table.clone().unbind(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Connect to osquery's extension socket (waiting up to `timeout` seconds for it),
+    /// register the tables and serve them until osquery shuts the extension down.
+    #[pyo3(signature = (socket, timeout = 3.0))]
+    fn run(&self, py: Python<'_>, socket: PathBuf, timeout: f64) -> PyResult<()> {
+        let extension = self.clone();
+        let server = py
+            .allow_threads(move || -> Result<_, anyhow::Error> {
+                let mut client = Client::connect(socket, Duration::from_secs_f64(timeout))?;
+                extension.install(&mut client)?.start()
+            })
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        // the GIL is let go while waiting, so the tables can have it, and ^C still works
+        while !server.is_finished() {
+            py.allow_threads(|| std::thread::sleep(SIGNAL_POLL));
+            py.check_signals()?;
+        }
+        match server.join() {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(PyRuntimeError::new_err(e.to_string())),
+            Err(_) => Err(PyRuntimeError::new_err("the server thread panicked")),
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl MultiTable for PyExtension {
+    type Table = PyTable;
+
+    fn extension_name(&self) -> &str {
+        &self.name
+    }
+
+    fn table(&self, name: &str) -> Option<Arc<PyTable>> {
+        self.tables.get(name).cloned()
+    }
+
+    fn tables_by_name(&self) -> Vec<(String, Arc<PyTable>)> {
+        self.tables
+            .iter()
+            .map(|(name, table)| (name.clone(), table.clone()))
+            .collect()
+    }
+
+    fn call(&self, item: &str, table: &PyTable, request: ExtensionPluginRequest) -> Response {
+        let query = match generate_query(item, &table.columns, request) {
+            Ok(query) => query,
+            Err(response) => return response,
+        };
+        match table.generate(query) {
+            Ok(rows) => Response::success(rows.into_response()),
+            Err(error) => {
+                warn!(table = item, %error, "generate raised");
+                Response::failure(format!("`{}` raised {}", item, error))
+            }
+        }
+    }
+}
+
+multi_table_plugin!(PyExtension, "python_extension", |name: &str| {
+    PyExtension::py_new(name.to_string())
+});
+
+/// The `osquery_rs` Python module.
+#[pymodule]
+pub fn osquery_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyExtension>()?;
+    m.add_class::<PyQueryContext>()?;
+    Ok(())
+}
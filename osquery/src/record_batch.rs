@@ -0,0 +1,97 @@
+// Arrow interop, so rows that go to an analytics pipeline as well as to osquery don't
+// get turned into strings and back on the way.
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Builder, Int32Builder, Int64Builder, StringBuilder};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+
+use crate::gen::table::ColumnType;
+use crate::{ColumnValue, RowSet};
+
+impl RowSet {
+    /// The rows as an Arrow batch with a nullable field per column: TEXT as Utf8, INTEGER
+    /// as Int32, BIGINT as Int64 and DOUBLE as Float64. A value of some other type than
+    /// its column's is converted if it can be and null if it can't. Map-style rows can
+    /// come through `RowSet::from_table_rows` first.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let fields: Vec<Field> = self
+            .columns()
+            .iter()
+            .zip(self.column_types())
+            .map(|(name, kind)| Field::new(name.as_str(), data_type(*kind), true))
+            .collect();
+        let arrays = self
+            .column_types()
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| array(*kind, self.rows().map(|row| row[i].as_ref())))
+            .collect();
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+    }
+}
+
+fn data_type(kind: ColumnType) -> DataType {
+    match kind {
+        ColumnType::Integer => DataType::Int32,
+        ColumnType::BigInt => DataType::Int64,
+        ColumnType::Double => DataType::Float64,
+        ColumnType::Text | ColumnType::Unknown => DataType::Utf8,
+    }
+}
+
+fn array<'a, I>(kind: ColumnType, values: I) -> ArrayRef
+where
+    I: Iterator<Item = Option<&'a ColumnValue>>,
+{
+    match kind {
+        ColumnType::Integer => {
+            let mut array = Int32Builder::new();
+            for value in values {
+                array.append_option(value.and_then(as_i64).and_then(|v| i32::try_from(v).ok()));
+            }
+            Arc::new(array.finish())
+        }
+        ColumnType::BigInt => {
+            let mut array = Int64Builder::new();
+            for value in values {
+                array.append_option(value.and_then(as_i64));
+            }
+            Arc::new(array.finish())
+        }
+        ColumnType::Double => {
+            let mut array = Float64Builder::new();
+            for value in values {
+                array.append_option(value.and_then(as_f64));
+            }
+            Arc::new(array.finish())
+        }
+        ColumnType::Text | ColumnType::Unknown => {
+            let mut array = StringBuilder::new();
+            for value in values {
+                array.append_option(value.map(ColumnValue::to_string));
+            }
+            Arc::new(array.finish())
+        }
+    }
+}
+
+fn as_i64(value: &ColumnValue) -> Option<i64> {
+    match value {
+        ColumnValue::Integer(v) => Some(i64::from(*v)),
+        ColumnValue::BigInt(v) => Some(*v),
+        ColumnValue::Double(_) => None,
+        ColumnValue::Text(v) => v.trim().parse().ok(),
+    }
+}
+
+fn as_f64(value: &ColumnValue) -> Option<f64> {
+    match value {
+        ColumnValue::Integer(v) => Some(f64::from(*v)),
+        ColumnValue::BigInt(v) => Some(*v as f64),
+        ColumnValue::Double(v) => Some(*v),
+        ColumnValue::Text(v) => v.trim().parse().ok(),
+    }
+}
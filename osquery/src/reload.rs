@@ -0,0 +1,176 @@
+// Swapping a table's implementation while it's being served, e.g. a CSV table pointed at a
+// new file or a view with new SQL. Calls already running finish on the old table, new ones
+// go to the new table, and the old one is shut down once it's no longer in use. If the
+// columns changed, osquery has to be told with a fresh registration (`reregister`).
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::{
+    Client, Column, Connector, ExtensionRouteUUID, Handle, Plugin, PluginRequest, QueryContext,
+    Response, ResponseBudget, RowSet, TablePlugin, TableRows,
+};
+
+/// How long `swap` waits between checks for calls still on the old table
+const DRAIN_POLL: Duration = Duration::from_millis(10);
+
+/// A table that can be replaced while it's registered. Clones share the table, so keep one
+/// to swap with after the plugin has moved into its `Handle`.
+///
+/// `generate_limiter` isn't passed through, since it borrows from a table that can go away
+/// underneath it. Wrap the `Reloadable` itself if it needs one.
+pub struct Reloadable<T> {
+    current: Arc<RwLock<Arc<T>>>,
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Self {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Reloadable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Reloadable").field(&self.current()).finish()
+    }
+}
+
+/// What a `swap` did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Swapped {
+    /// The new table's routes differ from the old one's, so osquery needs to hear about it
+    pub routes_changed: bool,
+    /// Every call on the old table finished in time, and it's been shut down. Otherwise
+    /// it's dropped (without `shutdown`) whenever the last one does.
+    pub drained: bool,
+}
+
+impl<T> Reloadable<T> {
+    pub fn from_table(table: T) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(table))),
+        }
+    }
+
+    /// The table calls go to right now.
+    pub fn current(&self) -> Arc<T> {
+        self.current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl<T: TablePlugin> Reloadable<T> {
+    /// Put `table` in place of the current one, waiting up to `drain` for calls still
+    /// running on the old one before shutting it down.
+    pub fn swap(&self, table: T, drain: Duration) -> Swapped {
+        let new_routes = table.try_routes().ok();
+        let old = std::mem::replace(
+            &mut *self.current.write().unwrap_or_else(|e| e.into_inner()),
+            Arc::new(table),
+        );
+        let routes_changed = old.try_routes().ok() != new_routes;
+        if routes_changed {
+            crate::forget_routes(T::REGISTRY, T::NAME);
+        }
+        let drained = drain_then_shutdown(T::NAME, old, drain, |table| table.shutdown());
+        info!(table = T::NAME, routes_changed, drained, "swapped table");
+        Swapped {
+            routes_changed,
+            drained,
+        }
+    }
+
+    /// Withdraw the registration osquery gave `uuid`, and register the current table
+    /// again. The returned handle serves it on the new socket osquery hands out; the old
+    /// server stops getting calls.
+    pub fn reregister<C: Connector>(
+        &self,
+        client: &mut Client<C>,
+        uuid: ExtensionRouteUUID,
+    ) -> Result<Handle<Self, C>, anyhow::Error> {
+        crate::deregister(client, uuid)?;
+        self.clone().install(client)
+    }
+}
+
+/// Wait for everyone else to let go of `old`, up to `drain`, then shut it down. Whether it
+/// got that far.
+pub(crate) fn drain_then_shutdown<T>(
+    name: &str,
+    old: Arc<T>,
+    drain: Duration,
+    shutdown: impl FnOnce(&T),
+) -> bool {
+    let deadline = Instant::now() + drain;
+    while Arc::strong_count(&old) > 1 {
+        if Instant::now() >= deadline {
+            warn!(
+                table = name,
+                in_flight = Arc::strong_count(&old) - 1,
+                "calls still running on the replaced table, not waiting for them"
+            );
+            return false;
+        }
+        std::thread::sleep(DRAIN_POLL);
+    }
+    shutdown(&old);
+    true
+}
+
+impl<T: TablePlugin> Plugin for Reloadable<T> {
+    type Error = T::Error;
+    const NAME: &'static str = T::NAME;
+    const REGISTRY: &'static str = T::REGISTRY;
+
+    fn new() -> Self {
+        Self::from_table(T::new())
+    }
+
+    fn handle_action(&self, action: &str, request: PluginRequest) -> thrift::Result<Response> {
+        self.current().handle_action(action, request)
+    }
+}
+
+impl<T: TablePlugin> TablePlugin for Reloadable<T> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        self.current().generate(query)
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        self.current().columns()
+    }
+
+    fn generate_rows(&self, query: &QueryContext) -> Result<RowSet, Self::Error> {
+        self.current().generate_rows(query)
+    }
+
+    fn slow_query_threshold(&self) -> Option<Duration> {
+        self.current().slow_query_threshold()
+    }
+
+    fn response_budget(&self) -> Option<ResponseBudget> {
+        self.current().response_budget()
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        self.current().aliases()
+    }
+
+    fn validate_rows(&self) -> bool {
+        self.current().validate_rows()
+    }
+
+    fn generate_deadline(&self) -> Option<Duration> {
+        self.current().generate_deadline()
+    }
+
+    fn shutdown(&self) {
+        self.current().shutdown()
+    }
+}
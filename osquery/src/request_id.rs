@@ -0,0 +1,61 @@
+// Correlation ids for calls from osquery. Each call gets one on its `request` span, so
+// every log line and span inside the call carries it, and it's added to the message of
+// any failure sent back, so an error in osquery's logs can be matched to the extension's.
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use thrift::{ApplicationError, ProtocolError};
+
+use crate::Response;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct RequestId(u64);
+
+impl RequestId {
+    pub(crate) fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        static PREFIX: OnceLock<u64> = OnceLock::new();
+        // the top half changes from run to run, so a restarted extension doesn't reuse ids
+        let prefix = *PREFIX.get_or_init(|| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            (now.as_secs() ^ u64::from(now.subsec_nanos()) ^ u64::from(std::process::id())) << 32
+        });
+        RequestId(prefix | (NEXT.fetch_add(1, Ordering::Relaxed) & 0xffff_ffff))
+    }
+
+    /// Mention the id in a failed response or error, leaving successes alone.
+    pub(crate) fn tag(self, result: thrift::Result<Response>) -> thrift::Result<Response> {
+        match result {
+            Ok(mut response) if !response.is_success() => {
+                if let Some(status) = &mut response.status {
+                    status.message = Some(self.tagged(status.message.as_deref()));
+                }
+                Ok(response)
+            }
+            Err(thrift::Error::Application(e)) => {
+                Err(ApplicationError::new(e.kind, self.tagged(Some(&e.message))).into())
+            }
+            Err(thrift::Error::Protocol(e)) => {
+                Err(ProtocolError::new(e.kind, self.tagged(Some(&e.message))).into())
+            }
+            other => other,
+        }
+    }
+
+    fn tagged(self, message: Option<&str>) -> String {
+        match message {
+            Some(message) if !message.is_empty() => format!("{} (request {})", message, self),
+            _ => format!("request {}", self),
+        }
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
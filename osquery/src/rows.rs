@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use tracing::debug;
+
+use crate::gen::table::ColumnType;
+use crate::{Column, ColumnValue, ExtensionPluginResponse, QueryContext, TableRows};
+
+// RowSet stores rows positionally against the table schema, so the column
+// names live in one place instead of being duplicated into every row's map.
+// On the wire it looks exactly like TableRows: a list of name -> value maps.
+#[derive(Debug, Clone)]
+pub struct RowSet {
+    columns: Arc<[String]>,
+    types: Arc<[ColumnType]>,
+    index: Arc<HashMap<String, usize>>,
+    values: Vec<Option<ColumnValue>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RowSetError {
+    #[error("row has {got} values but the schema has {expected} columns")]
+    Arity { expected: usize, got: usize },
+    #[error("no column named `{0}` in the schema")]
+    UnknownColumn(String),
+    #[error("row {row} of `{table}` has a value for `{column}`, which isn't one of its columns")]
+    UnexpectedColumn {
+        table: String,
+        row: usize,
+        column: String,
+    },
+    #[error("row {row} of `{table}` is missing column `{column}`")]
+    MissingColumn {
+        table: String,
+        row: usize,
+        column: String,
+    },
+}
+
+impl RowSet {
+    pub fn new(columns: &[Column]) -> Self {
+        Self::with_capacity(columns, 0)
+    }
+
+    pub fn with_capacity(columns: &[Column], rows: usize) -> Self {
+        let types: Arc<[ColumnType]> = columns.iter().map(|c| c.kind).collect();
+        let columns: Arc<[String]> = columns.iter().map(|c| c.name.clone()).collect();
+        let index = columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i))
+            .collect();
+        Self {
+            values: Vec::with_capacity(rows * columns.len()),
+            columns,
+            types,
+            index: Arc::new(index),
+        }
+    }
+
+    /// Build a RowSet out of map-style rows, dropping any keys that aren't in the schema.
+    pub fn from_table_rows(columns: &[Column], rows: TableRows) -> Self {
+        let mut set = Self::with_capacity(columns, rows.len());
+        for row in rows {
+            let mut values: Vec<Option<ColumnValue>> = vec![None; set.width()];
+            for (name, value) in row {
+                match set.index.get(&name) {
+                    Some(&i) => values[i] = Some(value),
+                    None => debug!(column = %name, "dropping value for column not in schema"),
+                }
+            }
+            set.values.extend(values);
+        }
+        set
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Each column's type, in the same order as `columns`
+    pub fn column_types(&self) -> &[ColumnType] {
+        &self.types
+    }
+
+    pub fn width(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn len(&self) -> usize {
+        if self.columns.is_empty() {
+            return 0;
+        }
+        self.values.len() / self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Append a row, with values in schema order. Every column must be present.
+    pub fn push<I>(&mut self, row: I) -> Result<(), RowSetError>
+    where
+        I: IntoIterator,
+        I::Item: Into<ColumnValue>,
+    {
+        self.push_partial(row.into_iter().map(|v| Some(v.into())))
+    }
+
+    /// Append a row, with values in schema order. `None` leaves the column empty.
+    pub fn push_partial<I>(&mut self, row: I) -> Result<(), RowSetError>
+    where
+        I: IntoIterator<Item = Option<ColumnValue>>,
+    {
+        let start = self.values.len();
+        self.values.extend(row);
+        let got = self.values.len() - start;
+        if got != self.width() {
+            self.values.truncate(start);
+            return Err(RowSetError::Arity {
+                expected: self.width(),
+                got,
+            });
+        }
+        Ok(())
+    }
+
+    /// Append a row by column name. Columns left out of `row` stay empty.
+    pub fn push_named<K, V, I>(&mut self, row: I) -> Result<(), RowSetError>
+    where
+        K: AsRef<str>,
+        V: Into<ColumnValue>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut values: Vec<Option<ColumnValue>> = vec![None; self.width()];
+        for (name, value) in row {
+            let name = name.as_ref();
+            let i = *self
+                .index
+                .get(name)
+                .ok_or_else(|| RowSetError::UnknownColumn(name.to_string()))?;
+            values[i] = Some(value.into());
+        }
+        self.values.extend(values);
+        Ok(())
+    }
+
+    pub fn row(&self, i: usize) -> Option<&[Option<ColumnValue>]> {
+        let width = self.width();
+        self.values.get(i * width..(i + 1) * width)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[Option<ColumnValue>]> {
+        // chunks panics on 0, and a schema-less set can't hold anything anyway
+        self.values.chunks(self.width().max(1))
+    }
+
+    pub fn get(&self, row: usize, column: &str) -> Option<&ColumnValue> {
+        let i = *self.index.get(column)?;
+        self.row(row)?.get(i)?.as_ref()
+    }
+
+    /// Turn the set into what osquery expects back from a `generate` call.
+    pub fn into_response(self) -> ExtensionPluginResponse {
+        self.into_maps(|v| v.to_string())
+    }
+
+    /// `into_response`, but stopping at the first row that would take the names and
+    /// values past `max_bytes`. `Err` has the rows that fit.
+    pub fn into_response_within(
+        self,
+        max_bytes: usize,
+    ) -> Result<ExtensionPluginResponse, ExtensionPluginResponse> {
+        let mut total = 0usize;
+        let mut over = false;
+        let maps = self.into_maps_while(
+            |v| v.to_string(),
+            |row| {
+                total = row
+                    .iter()
+                    .map(|(k, v)| k.len() + v.len())
+                    .fold(total, usize::saturating_add);
+                over = total > max_bytes;
+                !over
+            },
+        );
+        if over {
+            return Err(maps);
+        }
+        Ok(maps)
+    }
+
+    fn into_maps<T>(self, f: impl Fn(ColumnValue) -> T) -> Vec<BTreeMap<String, T>> {
+        self.into_maps_while(f, |_| true)
+    }
+
+    // stops before the first row `keep` says no to
+    fn into_maps_while<T>(
+        self,
+        f: impl Fn(ColumnValue) -> T,
+        mut keep: impl FnMut(&BTreeMap<String, T>) -> bool,
+    ) -> Vec<BTreeMap<String, T>> {
+        let width = self.width().max(1);
+        let columns = self.columns;
+        let mut maps = Vec::with_capacity(self.values.len() / width);
+        let mut values = self.values.into_iter();
+        loop {
+            let row: Vec<_> = values.by_ref().take(width).collect();
+            if row.is_empty() {
+                break;
+            }
+            let row = columns
+                .iter()
+                .zip(row)
+                .filter_map(|(name, value)| Some((name.clone(), f(value?))))
+                .collect();
+            if !keep(&row) {
+                break;
+            }
+            maps.push(row);
+        }
+        maps
+    }
+}
+
+impl From<RowSet> for TableRows {
+    fn from(set: RowSet) -> Self {
+        set.into_maps(|v| v)
+    }
+}
+
+/// Check map-style rows against the schema: every key has to be a column, and every
+/// column the query uses has to be present. Catches typos that would otherwise just
+/// show up as empty values.
+pub fn validate_table_rows(
+    table: &str,
+    columns: &[Column],
+    rows: &TableRows,
+    query: &QueryContext,
+) -> Result<(), RowSetError> {
+    for (i, row) in rows.iter().enumerate() {
+        if let Some(column) = row.keys().find(|k| !columns.iter().any(|c| c.name == **k)) {
+            return Err(RowSetError::UnexpectedColumn {
+                table: table.to_string(),
+                row: i,
+                column: column.clone(),
+            });
+        }
+        let missing = columns
+            .iter()
+            .find(|c| query.is_column_used(&c.name) && !row.contains_key(&c.name));
+        if let Some(column) = missing {
+            return Err(RowSetError::MissingColumn {
+                table: table.to_string(),
+                row: i,
+                column: column.name.clone(),
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Running an extension from start to finish: check it could register, register it, and
+//! serve it until osquery's done with it.
+//!
+//! ```ignore
+//! let runner = Runner::new(&socket, table);
+//! runner.preflight()?;
+//! runner.run()?;
+//! ```
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+use crate::preflight::{self, Report};
+use crate::{
+    Client, Connector, DefaultTransport, Handle, Plugin, PluginHandler, ServerOptions,
+    ShutdownReason,
+};
+
+// the same as `Client::connect` callers tend to pick
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// A plugin and the osquery it's for.
+#[derive(Debug)]
+pub struct Runner<P, C = DefaultTransport> {
+    manager: PathBuf,
+    plugin: P,
+    options: ServerOptions,
+    timeout: Duration,
+    transport: PhantomData<C>,
+}
+
+impl<P: Plugin> Runner<P> {
+    pub fn new(manager: impl AsRef<Path>, plugin: P) -> Self {
+        Self::on_transport(manager, plugin)
+    }
+}
+
+impl<P: Plugin, C: Connector> Runner<P, C> {
+    /// `new`, for a transport other than the default
+    pub fn on_transport(manager: impl AsRef<Path>, plugin: P) -> Self {
+        Self {
+            manager: manager.as_ref().into(),
+            plugin,
+            options: ServerOptions::default(),
+            timeout: CONNECT_TIMEOUT,
+            transport: PhantomData,
+        }
+    }
+
+    /// How the extension's server is run once it's registered.
+    pub fn with_options(mut self, options: ServerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// How long each call to osquery gets while registering.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Check the plugin could register right now, without registering it, and say what
+    /// to fix if not. See `preflight::check`.
+    pub fn preflight(&self) -> Result<(), Report> {
+        preflight::check_via::<C, P>(&self.manager, &self.plugin)
+    }
+
+    /// Register the plugin, ready to serve.
+    pub fn register(self) -> Result<Handle<P, C>, anyhow::Error> {
+        let mut client = Client::<C>::connect_via(&self.manager, self.timeout)?;
+        Ok(self.plugin.install(&mut client)?.with_options(self.options))
+    }
+}
+
+impl<P, C> Runner<P, C>
+where
+    P: Plugin + PluginHandler + Debug + Send + Sync + 'static,
+    C: Connector,
+{
+    /// Preflight, register and serve, until osquery asks the extension to shut down or
+    /// goes away.
+    pub fn run(self) -> Result<ShutdownReason, anyhow::Error> {
+        self.preflight()?;
+        let server = self.register()?.start()?;
+        let reason = server
+            .join()
+            .map_err(|_| anyhow!("the extension's server panicked"))??;
+        Ok(reason)
+    }
+}
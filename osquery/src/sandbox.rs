@@ -0,0 +1,431 @@
+// Locking down the threads that run plugin code, for extensions that parse things an
+// attacker controls (file contents, packet captures, ...). Set `ServerOptions::sandbox` and
+// each connection's thread applies it to itself before osquery's first request is read:
+// Landlock to fence off the filesystem, then a seccomp filter to fence off syscalls.
+// Both only affect the calling thread (and threads it starts), the rest of the process
+// carries on as before. Linux only, and nothing here links libseccomp.
+//
+// That makes the per-connection sandbox a guard against accidents, not attackers: a table
+// (or a library it pulls in) reaching for a file or syscall it was never meant to gets
+// refused. Code that's actually been exploited shares memory with the unsandboxed threads
+// (the accept loop, the heartbeat, anything the extension started itself) and can get
+// them to do its bidding. For a real boundary, call `Sandbox::apply` at the top of `main`,
+// before anything starts a thread, so every thread the process ever has inherits it.
+use std::cell::Cell;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SandboxError {
+    #[error("couldn't set no_new_privs: {0}")]
+    NoNewPrivs(io::Error),
+    #[error("couldn't install the seccomp filter: {0}")]
+    Seccomp(io::Error),
+    #[error("the kernel doesn't support Landlock")]
+    LandlockUnsupported,
+    #[error("couldn't set up Landlock: {0}")]
+    Landlock(io::Error),
+    #[error("couldn't allow {path:?} under Landlock: {source}")]
+    LandlockPath { path: PathBuf, source: io::Error },
+}
+
+/// What a thread may do once it's sandboxed. Empty allows everything.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    pub seccomp: Option<SeccompPolicy>,
+    pub landlock: Option<LandlockPolicy>,
+    /// Carry on without Landlock on kernels that don't have it (before 5.13), rather
+    /// than refusing to serve
+    pub best_effort: bool,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn seccomp(mut self, policy: SeccompPolicy) -> Self {
+        self.seccomp = Some(policy);
+        self
+    }
+
+    pub fn landlock(mut self, policy: LandlockPolicy) -> Self {
+        self.landlock = Some(policy);
+        self
+    }
+
+    pub fn best_effort(mut self, best_effort: bool) -> Self {
+        self.best_effort = best_effort;
+        self
+    }
+
+    /// `apply`, unless the calling thread already has. Server threads go on to serve
+    /// other connections, and Landlock only stacks so many layers.
+    pub(crate) fn apply_once(&self) -> Result<(), SandboxError> {
+        thread_local! {
+            static APPLIED: Cell<bool> = const { Cell::new(false) };
+        }
+        if APPLIED.with(Cell::get) {
+            return Ok(());
+        }
+        self.apply()?;
+        APPLIED.with(|applied| applied.set(true));
+        Ok(())
+    }
+
+    /// Sandbox the calling thread, and any it starts from now on. There's no undoing it,
+    /// and threads that are already running aren't touched.
+    pub fn apply(&self) -> Result<(), SandboxError> {
+        // both need it when unprivileged, and it keeps setuid binaries from escaping
+        // safe: prctl on plain integers
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(SandboxError::NoNewPrivs(io::Error::last_os_error()));
+        }
+        // Landlock first, setting it up takes syscalls the filter may not allow
+        if let Some(landlock) = &self.landlock {
+            match landlock.apply() {
+                Err(SandboxError::LandlockUnsupported) if self.best_effort => {
+                    warn!("the kernel doesn't support Landlock, running without it");
+                }
+                result => result?,
+            }
+        }
+        if let Some(seccomp) = &self.seccomp {
+            seccomp.apply()?;
+        }
+        Ok(())
+    }
+}
+
+/// What a syscall outside the allow-list gets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    /// Fails with this errno, the gentlest option
+    Errno(i32),
+    /// The whole process is killed
+    Kill,
+    /// SIGSYS, for catching it in a debugger
+    Trap,
+    /// Allowed, but logged by the kernel. For working out an allow-list.
+    Log,
+}
+
+impl Violation {
+    fn action(self) -> u32 {
+        match self {
+            Violation::Errno(errno) => libc::SECCOMP_RET_ERRNO | (errno as u32 & 0xffff),
+            Violation::Kill => libc::SECCOMP_RET_KILL_PROCESS,
+            Violation::Trap => libc::SECCOMP_RET_TRAP,
+            Violation::Log => libc::SECCOMP_RET_LOG,
+        }
+    }
+}
+
+/// A seccomp allow-list of syscall numbers (`libc::SYS_*`).
+#[derive(Debug, Clone)]
+pub struct SeccompPolicy {
+    pub allowed: Vec<libc::c_long>,
+    pub violation: Violation,
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("the sandbox feature only knows x86_64 and aarch64 syscalls");
+
+impl SeccompPolicy {
+    /// Nothing allowed at all, add to it with `allow`.
+    pub fn empty() -> Self {
+        Self {
+            allowed: vec![],
+            violation: Violation::Errno(libc::EPERM),
+        }
+    }
+
+    /// What serving osquery's calls takes: reading and writing open descriptors,
+    /// memory, threads, time, and opening files (which Landlock can narrow down).
+    /// No exec, no new sockets, no ptrace. Tables needing more should `allow` it.
+    pub fn baseline() -> Self {
+        let mut allowed = vec![
+            libc::SYS_read,
+            libc::SYS_write,
+            libc::SYS_readv,
+            libc::SYS_writev,
+            libc::SYS_pread64,
+            libc::SYS_pwrite64,
+            libc::SYS_recvfrom,
+            libc::SYS_sendto,
+            libc::SYS_recvmsg,
+            libc::SYS_sendmsg,
+            libc::SYS_getsockopt,
+            libc::SYS_setsockopt,
+            libc::SYS_shutdown,
+            libc::SYS_openat,
+            libc::SYS_close,
+            libc::SYS_fstat,
+            libc::SYS_newfstatat,
+            libc::SYS_statx,
+            libc::SYS_lseek,
+            libc::SYS_getdents64,
+            libc::SYS_readlinkat,
+            libc::SYS_fcntl,
+            libc::SYS_ppoll,
+            libc::SYS_mmap,
+            libc::SYS_munmap,
+            libc::SYS_mremap,
+            libc::SYS_mprotect,
+            libc::SYS_madvise,
+            libc::SYS_brk,
+            libc::SYS_futex,
+            libc::SYS_sched_yield,
+            libc::SYS_sched_getaffinity,
+            libc::SYS_clone,
+            libc::SYS_clone3,
+            libc::SYS_set_robust_list,
+            libc::SYS_rseq,
+            libc::SYS_membarrier,
+            libc::SYS_rt_sigprocmask,
+            libc::SYS_rt_sigreturn,
+            libc::SYS_sigaltstack,
+            libc::SYS_clock_gettime,
+            libc::SYS_clock_nanosleep,
+            libc::SYS_nanosleep,
+            libc::SYS_getrandom,
+            libc::SYS_getpid,
+            libc::SYS_gettid,
+            libc::SYS_prlimit64,
+            libc::SYS_restart_syscall,
+            libc::SYS_exit,
+            libc::SYS_exit_group,
+        ];
+        // the older calls that aarch64 never had
+        #[cfg(target_arch = "x86_64")]
+        allowed.extend_from_slice(&[
+            libc::SYS_open,
+            libc::SYS_stat,
+            libc::SYS_lstat,
+            libc::SYS_poll,
+            libc::SYS_readlink,
+        ]);
+        Self {
+            allowed,
+            ..Self::empty()
+        }
+    }
+
+    pub fn allow(mut self, syscalls: &[libc::c_long]) -> Self {
+        self.allowed.extend_from_slice(syscalls);
+        self
+    }
+
+    pub fn on_violation(mut self, violation: Violation) -> Self {
+        self.violation = violation;
+        self
+    }
+
+    // a check per allowed syscall, each jumping to ALLOW or falling through to the next
+    fn program(&self) -> Vec<libc::sock_filter> {
+        const ARCH_OFFSET: u32 = 4;
+        const NR_OFFSET: u32 = 0;
+        let stmt = |code: u32, k: u32| libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let jump_eq = |k: u32, jt: u8, jf: u8| libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+            jt,
+            jf,
+            k,
+        };
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let ret = libc::BPF_RET | libc::BPF_K;
+        // syscall numbers mean something else under another ABI, so those never match
+        let mut program = vec![
+            stmt(load, ARCH_OFFSET),
+            jump_eq(AUDIT_ARCH, 1, 0),
+            stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(load, NR_OFFSET),
+        ];
+        for &nr in &self.allowed {
+            program.push(jump_eq(nr as u32, 0, 1));
+            program.push(stmt(ret, libc::SECCOMP_RET_ALLOW));
+        }
+        program.push(stmt(ret, self.violation.action()));
+        program
+    }
+
+    /// Filter the calling thread's syscalls. Needs no_new_privs (or CAP_SYS_ADMIN).
+    pub fn apply(&self) -> Result<(), SandboxError> {
+        let mut program = self.program();
+        let prog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+        // safe: prog points at program, which outlives the call; no flags means just
+        // this thread
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                0,
+                &prog as *const libc::sock_fprog,
+            )
+        };
+        if ret != 0 {
+            return Err(SandboxError::Seccomp(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+}
+
+// the kernel's own layouts, which libc doesn't carry
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+const ACCESS_EXECUTE: u64 = 1 << 0;
+const ACCESS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_READ_FILE: u64 = 1 << 2;
+const ACCESS_READ_DIR: u64 = 1 << 3;
+// everything ABI 1 knows about
+const ACCESS_ABI_1: u64 = (1 << 13) - 1;
+const ACCESS_REFER: u64 = 1 << 13;
+const ACCESS_TRUNCATE: u64 = 1 << 14;
+
+/// A Landlock allow-list of paths. Anything under them is reachable with the access given,
+/// everything else on the filesystem isn't, including for the thread's root user.
+#[derive(Debug, Clone, Default)]
+pub struct LandlockPolicy {
+    pub read: Vec<PathBuf>,
+    pub read_write: Vec<PathBuf>,
+}
+
+impl LandlockPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.read.push(path.as_ref().into());
+        self
+    }
+
+    /// Reading, writing, creating and removing, under `path`
+    pub fn read_write<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.read_write.push(path.as_ref().into());
+        self
+    }
+
+    /// Restrict the calling thread. Needs no_new_privs.
+    pub fn apply(&self) -> Result<(), SandboxError> {
+        // safe: a version query, no pointers
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Err(SandboxError::LandlockUnsupported);
+        }
+        let mut handled = ACCESS_ABI_1;
+        if abi >= 2 {
+            handled |= ACCESS_REFER;
+        }
+        if abi >= 3 {
+            handled |= ACCESS_TRUNCATE;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // safe: attr is a live ruleset_attr of the size passed
+        let ruleset = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if ruleset < 0 {
+            return Err(SandboxError::Landlock(io::Error::last_os_error()));
+        }
+        let ruleset = Fd(ruleset as libc::c_int);
+        let read = ACCESS_EXECUTE | ACCESS_READ_FILE | ACCESS_READ_DIR;
+        let rules = self.read.iter().map(|path| (path, read));
+        for (path, access) in rules.chain(self.read_write.iter().map(|path| (path, handled))) {
+            add_rule(&ruleset, path, access).map_err(|source| SandboxError::LandlockPath {
+                path: path.clone(),
+                source,
+            })?;
+        }
+        // safe: ruleset is an open ruleset fd
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.0, 0) } != 0 {
+            return Err(SandboxError::Landlock(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+}
+
+struct Fd(libc::c_int);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        // safe: the fd is ours and closed once
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn add_rule(ruleset: &Fd, path: &Path, access: u64) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // safe: c_path is nul-terminated and outlives the call
+    let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = Fd(fd);
+    // directory rights on a file are an error, so keep to the ones files have
+    let access = if path.is_dir() {
+        access
+    } else {
+        access & (ACCESS_EXECUTE | ACCESS_WRITE_FILE | ACCESS_READ_FILE | ACCESS_TRUNCATE)
+    };
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd.0,
+    };
+    // safe: attr is a live path_beneath_attr and both fds are open
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset.0,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{bounded, RecvTimeoutError, Sender};
+use tracing::{debug, warn};
+
+use crate::codegen::column_type;
+use crate::diff::Differential;
+use crate::gen::table::ColumnType;
+use crate::tables::parse_value;
+use crate::{Client, ColumnValue, Connector, DefaultTransport, TExtensionManagerSyncClient};
+
+pub use crate::diff::{Diff, Row};
+
+/// A result row with values parsed to their column's type.
+pub type TypedRow = BTreeMap<String, ColumnValue>;
+
+#[derive(Debug, Clone)]
+struct Scheduled {
+    name: String,
+    sql: String,
+    interval: Duration,
+    next_run: Instant,
+    results: Differential,
+}
+
+/// Runs queries against osquery on intervals and reports what changed, the way osqueryd's
+/// own scheduler does for differential queries. The first run of a query reports every
+/// row as added. Queries that fail are logged and tried again next interval.
+pub struct Scheduler<C: Connector = DefaultTransport> {
+    client: Client<C>,
+    queries: Vec<Scheduled>,
+}
+
+/// Keeps a started `Scheduler` running. Dropping it stops the scheduler too.
+#[derive(Debug)]
+pub struct SchedulerHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<C: Connector> Scheduler<C> {
+    pub fn new(client: Client<C>) -> Self {
+        Self {
+            client,
+            queries: vec![],
+        }
+    }
+
+    /// Run `sql` every `interval`, starting as soon as the scheduler does.
+    /// `name` is what the callback sees.
+    pub fn add(mut self, name: &str, sql: &str, interval: Duration) -> Self {
+        self.queries.push(Scheduled {
+            name: name.to_string(),
+            sql: sql.to_string(),
+            interval,
+            next_run: Instant::now(),
+            results: Differential::new(),
+        });
+        self
+    }
+
+    /// Start running the queries on a thread of their own. `on_diff` gets the query's
+    /// name and what changed since its last run, and isn't called when nothing did.
+    pub fn start<F>(self, on_diff: F) -> SchedulerHandle
+    where
+        F: FnMut(&str, Diff) + Send + 'static,
+    {
+        let (stop, stopped) = bounded(0);
+        let thread = std::thread::spawn(move || {
+            let mut scheduler = self;
+            let mut on_diff = on_diff;
+            loop {
+                let next = scheduler.queries.iter().map(|q| q.next_run).min();
+                let next = match next {
+                    Some(next) => next,
+                    None => {
+                        // nothing to run, just wait to be told to stop
+                        let _ = stopped.recv();
+                        return;
+                    }
+                };
+                match stopped.recv_deadline(next) {
+                    Err(RecvTimeoutError::Timeout) => scheduler.run_due(&mut on_diff),
+                    _ => return,
+                }
+            }
+        });
+        SchedulerHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    fn run_due<F: FnMut(&str, Diff)>(&mut self, on_diff: &mut F) {
+        let now = Instant::now();
+        for query in self.queries.iter_mut().filter(|q| q.next_run <= now) {
+            query.next_run = now + query.interval;
+            let rows = match run_query(&mut self.client, &query.sql) {
+                Ok(rows) => rows,
+                Err(error) => {
+                    warn!(query = %query.name, %error, "scheduled query failed");
+                    continue;
+                }
+            };
+            let diff = query.results.update(rows).diff;
+            debug!(
+                query = %query.name,
+                added = diff.added.len(),
+                removed = diff.removed.len(),
+                "ran scheduled query"
+            );
+            if !diff.is_empty() {
+                on_diff(&query.name, diff);
+            }
+        }
+    }
+}
+
+impl<C: Connector> Client<C> {
+    /// Run `sql` every `interval` on a thread of its own, calling `on_diff` with the rows
+    /// that appeared and disappeared since the last run, e.g. to alert on new listening
+    /// ports. Values are parsed to the types osquery reports for the query's columns;
+    /// ones that don't parse (usually empty, osquery's NULL) are left out of the row.
+    pub fn watch<F>(
+        mut self,
+        sql: &str,
+        interval: Duration,
+        mut on_diff: F,
+    ) -> Result<SchedulerHandle, crate::Error>
+    where
+        F: FnMut(Diff<TypedRow>) + Send + 'static,
+    {
+        let types: BTreeMap<_, _> = query_column_types(&mut self, sql)?.into_iter().collect();
+        let typed = move |row: Row| -> TypedRow {
+            row.into_iter()
+                .filter_map(|(name, raw)| {
+                    let kind = types.get(&name).copied().unwrap_or(ColumnType::Text);
+                    Some((name, parse_value(kind, &raw)?))
+                })
+                .collect()
+        };
+        Ok(Scheduler::new(self)
+            .add(sql, sql, interval)
+            .start(move |_, diff| on_diff(diff.map(&typed))))
+    }
+}
+
+// osquery answers getQueryColumns with one `{name: type}` map per column, in order
+pub(crate) fn query_column_types<C: Connector>(
+    client: &mut Client<C>,
+    sql: &str,
+) -> Result<Vec<(String, ColumnType)>, crate::Error> {
+    let response = client.get_query_columns(sql.to_string())?;
+    let status = response.status.unwrap_or_default();
+    if !status.is_success() {
+        return Err(crate::anyhow!(
+            "osquery couldn't plan the query: {}",
+            status.message.unwrap_or_default()
+        ));
+    }
+    Ok(response
+        .response
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .map(|(name, kind)| (name, column_type(&kind)))
+        .collect())
+}
+
+impl<C: Connector> std::fmt::Debug for Scheduler<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("queries", &self.queries)
+            .finish()
+    }
+}
+
+impl SchedulerHandle {
+    /// Stop running queries and wait for the one in flight, if any, to finish.
+    pub fn stop(mut self) {
+        self.shut_down();
+    }
+
+    fn shut_down(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SchedulerHandle {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+pub(crate) fn run_query<C: Connector>(
+    client: &mut Client<C>,
+    sql: &str,
+) -> Result<Vec<Row>, crate::Error> {
+    let response = client.query(sql.to_string())?;
+    let status = response.status.unwrap_or_default();
+    if !status.is_success() {
+        return Err(crate::anyhow!(
+            "osquery couldn't run the query: {}",
+            status.message.unwrap_or_default()
+        ));
+    }
+    Ok(response.response.unwrap_or_default())
+}
@@ -0,0 +1,319 @@
+// Knobs for the extension-side server that `Handle::start` stands up.
+#[cfg(unix)]
+use std::ffi::CString;
+use std::io::{self, ErrorKind};
+use std::time::Duration;
+
+use crate::protocol::Strictness;
+
+/// Default size of the per-connection read and write buffers, same as thrift's buffered transports.
+pub const DEFAULT_BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Initial size of each connection's read buffer
+    pub read_buffer_size: usize,
+    /// Initial size of each connection's write buffer. Tables that are known to send back
+    /// large `generate` responses should bump this up.
+    pub write_buffer_size: usize,
+    /// How many idle buffers to keep around for reuse by later connections
+    pub pooled_buffers: usize,
+    /// Who's allowed to connect, checked with the socket's peer credentials before
+    /// any request is read
+    pub peer_auth: PeerAuth,
+    /// Permissions for the socket file, e.g. `0o600`. Without it the socket gets
+    /// whatever the umask allows, which is often world-connectable.
+    pub socket_mode: Option<u32>,
+    /// Owner and group for the socket file, e.g. the osquery user's
+    pub socket_uid: Option<u32>,
+    pub socket_gid: Option<u32>,
+    /// If the socket file is already there (left behind by a crash, usually) and nothing
+    /// is listening on it, remove it and bind anyway
+    pub remove_stale_socket: bool,
+    /// What to do when accepting a connection fails, e.g. out of file descriptors
+    pub accept_errors: AcceptErrorPolicy,
+    /// How many connections are served at once, and what happens to the rest
+    pub accept_queue: AcceptQueue,
+    /// Longest to wait on a connection for the next read. osquery keeps connections open
+    /// between calls, so this bounds idle time as well as a stalled request; set it above
+    /// the gap osquery leaves between calls to the extension. A connection that times out
+    /// is closed, and osquery reconnects.
+    pub read_timeout: Option<Duration>,
+    /// Longest to wait for osquery to take a response off the socket
+    pub write_timeout: Option<Duration>,
+    /// Caps on the size of what osquery sends, checked before anything is allocated.
+    /// A request over them closes the connection.
+    pub limits: MessageLimits,
+    /// Whether to insist on the binary protocol's version header from osquery, and
+    /// whether to write one back. See `Strictness`.
+    pub strictness: Strictness,
+    /// Switch the whole process to this user once the socket is bound, so table code
+    /// parsing untrusted data isn't doing it as root. Open the manager connection (and
+    /// start any other servers) first, the new user may not be allowed to.
+    pub run_as: Option<RunAs>,
+    /// Seccomp and Landlock rules each connection's thread applies to itself before it
+    /// reads a request. A connection whose thread can't be sandboxed is refused. The rest
+    /// of the process isn't sandboxed, so this catches tables overstepping by mistake,
+    /// not an attacker who's got code running, see `sandbox`.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    pub sandbox: Option<crate::sandbox::Sandbox>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            read_buffer_size: DEFAULT_BUFFER_SIZE,
+            write_buffer_size: DEFAULT_BUFFER_SIZE,
+            pooled_buffers: 16,
+            peer_auth: PeerAuth::Any,
+            socket_mode: None,
+            socket_uid: None,
+            socket_gid: None,
+            remove_stale_socket: true,
+            accept_errors: AcceptErrorPolicy::default(),
+            accept_queue: AcceptQueue::default(),
+            read_timeout: None,
+            write_timeout: None,
+            limits: MessageLimits::default(),
+            strictness: Strictness::default(),
+            run_as: None,
+            #[cfg(all(target_os = "linux", feature = "sandbox"))]
+            sandbox: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageLimits {
+    /// Most bytes in one request
+    pub max_message_size: usize,
+    /// Longest string in a request
+    pub max_string_size: usize,
+    /// Most entries in any one list, set, or map in a request
+    pub max_container_size: usize,
+}
+
+impl Default for MessageLimits {
+    // roomy enough for big log batches, nowhere near enough to take the process down
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 << 20,
+            max_string_size: 16 << 20,
+            max_container_size: 1 << 20,
+        }
+    }
+}
+
+impl MessageLimits {
+    // for the client, reading osquery's answers, which are as big as what was asked for
+    pub(crate) const UNLIMITED: MessageLimits = MessageLimits {
+        max_message_size: usize::MAX,
+        max_string_size: usize::MAX,
+        max_container_size: usize::MAX,
+    };
+}
+
+/// How the accept loop handles a failed `accept()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AcceptErrorPolicy {
+    /// Log it and try again, waiting longer after each failure in a row (starting at
+    /// 10ms, doubling up to `max_backoff`). Most accept errors, like EMFILE or
+    /// ECONNABORTED, clear up on their own.
+    Retry { max_backoff: Duration },
+    /// Stop the server, finishing its thread with the error
+    FailFast,
+}
+
+impl Default for AcceptErrorPolicy {
+    fn default() -> Self {
+        AcceptErrorPolicy::Retry {
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl AcceptErrorPolicy {
+    /// How long to wait before accepting again after `failures` errors in a row, or
+    /// `None` to give up.
+    pub(crate) fn backoff(&self, failures: u32) -> Option<Duration> {
+        match self {
+            AcceptErrorPolicy::FailFast => None,
+            AcceptErrorPolicy::Retry { max_backoff } => {
+                let backoff = Duration::from_millis(10).saturating_mul(1 << failures.min(16));
+                Some(backoff.min(*max_backoff))
+            }
+        }
+    }
+}
+
+/// Every connection gets a thread of its own, so a query storm with every osquery worker
+/// connecting at once means as many threads. A high-water mark caps that.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AcceptQueue {
+    /// Most connections served at once. `None` serves every one straight away.
+    pub high_water: Option<usize>,
+    /// What happens to connections past `high_water`
+    pub when_full: WhenFull,
+}
+
+impl AcceptQueue {
+    /// At most `high_water` connections at once, the rest waiting in the listen backlog.
+    pub fn hold(high_water: usize) -> Self {
+        Self {
+            high_water: Some(high_water),
+            when_full: WhenFull::Hold,
+        }
+    }
+
+    /// At most `high_water` connections at once, with up to `max_parked` more accepted
+    /// and waiting up to `deadline` for their turn.
+    pub fn park(high_water: usize, max_parked: usize, deadline: Duration) -> Self {
+        Self {
+            high_water: Some(high_water),
+            when_full: WhenFull::Park {
+                max_parked,
+                deadline,
+            },
+        }
+    }
+}
+
+/// What `AcceptQueue` does with a connection when every worker is busy.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WhenFull {
+    /// Stop accepting until a connection closes. New ones queue up in the kernel's listen
+    /// backlog, and fail to connect once that's full.
+    #[default]
+    Hold,
+    /// Accept it and park it until a worker is free, closing it instead if that takes
+    /// longer than `deadline`. Past `max_parked` parked connections, hold.
+    Park {
+        max_parked: usize,
+        deadline: Duration,
+    },
+}
+
+/// Why a server stopped without an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// osquery called `shutdown` on the extension
+    Requested,
+    /// osquery stopped answering the heartbeat (see `Handle::with_heartbeat`)
+    ManagerGone,
+}
+
+/// Which peers the server talks to. Anyone who can reach the socket file can connect,
+/// so this is the way to keep it to osqueryd.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerAuth {
+    Any,
+    /// Only peers running as one of these uids
+    Uids(Vec<u32>),
+}
+
+impl PeerAuth {
+    /// root, plus whoever this process is running as, which covers osqueryd
+    /// autoloading the extension as its own user.
+    #[cfg(unix)]
+    pub fn root_and_current() -> Self {
+        // safe: geteuid can't fail
+        let uid = unsafe { libc::geteuid() };
+        let mut uids = vec![0];
+        if uid != 0 {
+            uids.push(uid);
+        }
+        PeerAuth::Uids(uids)
+    }
+
+    pub fn allows(&self, peer: &PeerCredentials) -> bool {
+        match self {
+            PeerAuth::Any => true,
+            PeerAuth::Uids(uids) => uids.contains(&peer.uid),
+        }
+    }
+}
+
+/// Who's on the other end of a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCredentials {
+    /// Not available everywhere
+    pub pid: Option<u32>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// An unprivileged user to drop to, see `ServerOptions::run_as`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl RunAs {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self { uid, gid }
+    }
+
+    /// `name`'s uid and primary group, from the user database.
+    #[cfg(unix)]
+    pub fn user(name: &str) -> io::Result<Self> {
+        let name = CString::new(name).map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let mut buf = vec![0 as libc::c_char; 1024];
+        loop {
+            // safe: all zeroes is a valid passwd, and it's only read once getpwnam_r fills it
+            let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut found = std::ptr::null_mut();
+            // safe: every pointer is valid for the call, buf's length is what's passed
+            let ret = unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut found,
+                )
+            };
+            match ret {
+                0 if found.is_null() => {
+                    return Err(io::Error::new(
+                        ErrorKind::NotFound,
+                        format!("no user named {:?}", name),
+                    ))
+                }
+                0 => return Ok(Self::new(passwd.pw_uid, passwd.pw_gid)),
+                libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+                e => return Err(io::Error::from_raw_os_error(e)),
+            }
+        }
+    }
+
+    /// Drop every group but `gid`, then switch group and user. There's no way back.
+    #[cfg(unix)]
+    pub fn apply(&self) -> io::Result<()> {
+        // safe: plain syscalls on values; glibc and musl apply them to every thread
+        unsafe {
+            if libc::geteuid() == 0 && libc::setgroups(1, &self.gid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setgid(self.gid) != 0 || libc::setuid(self.uid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // a partial drop that left root reachable is worse than failing loudly
+            if self.uid != 0 && libc::setuid(0) == 0 {
+                return Err(io::Error::new(
+                    ErrorKind::PermissionDenied,
+                    "still able to regain root after dropping privileges",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "can't switch users on this platform",
+        ))
+    }
+}
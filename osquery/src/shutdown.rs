@@ -0,0 +1,128 @@
+// Passing osquery's shutdown on to every plugin an extension serves. `handle_shutdown` is
+// one call per socket, so a handler serving several plugins from it (`dynamic::Host`,
+// the C API's and Python's extensions, or your own) has to fan it out itself. `broadcast`
+// runs each plugin's shutdown on a thread of its own, gives each one `timeout` to finish,
+// and reports every one that failed, panicked or didn't finish in time, instead of
+// stopping at the first. `Handle::start` runs every other plugin's shutdown (tables,
+// loggers, config plugins...) through it too, on its own.
+use std::any::Any;
+use std::fmt::{self, Display};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use thrift::{ApplicationError, ApplicationErrorKind};
+use tracing::{debug, warn};
+
+/// How long each plugin gets by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What went wrong shutting one plugin down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    Failed(String),
+    Panicked(String),
+    /// still running when its time was up. it's left to finish on its own
+    TimedOut(Duration),
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Failed(message) => write!(f, "failed: {}", message),
+            Failure::Panicked(message) => write!(f, "panicked: {}", message),
+            Failure::TimedOut(timeout) => write!(f, "didn't finish within {:?}", timeout),
+        }
+    }
+}
+
+/// The plugins that didn't shut down cleanly, in the order they were given.
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("{} of {total} plugins didn't shut down cleanly: {}", failures.len(), list(failures))]
+pub struct ShutdownErrors {
+    pub failures: Vec<(String, Failure)>,
+    pub total: usize,
+}
+
+fn list(failures: &[(String, Failure)]) -> String {
+    failures
+        .iter()
+        .map(|(name, failure)| format!("`{}` {}", name, failure))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// osquery only logs what comes back, but it may as well say which plugins
+impl From<ShutdownErrors> for thrift::Error {
+    fn from(errors: ShutdownErrors) -> Self {
+        thrift::Error::Application(ApplicationError::new(
+            ApplicationErrorKind::InternalError,
+            errors.to_string(),
+        ))
+    }
+}
+
+/// Shut down every `(name, shutdown)` at once, waiting at most `timeout` for each.
+pub fn broadcast<I, F, E>(plugins: I, timeout: Duration) -> Result<(), ShutdownErrors>
+where
+    I: IntoIterator<Item = (String, F)>,
+    F: FnOnce() -> Result<(), E> + Send + 'static,
+    E: Display,
+{
+    let started = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    let mut names = vec![];
+    for (i, (name, shutdown)) in plugins.into_iter().enumerate() {
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            let result = match catch_unwind(AssertUnwindSafe(shutdown)) {
+                Ok(Ok(())) => None,
+                Ok(Err(error)) => Some(Failure::Failed(error.to_string())),
+                Err(panic) => Some(Failure::Panicked(panic_message(&*panic))),
+            };
+            let _ = sender.send((i, result));
+        });
+        names.push(name);
+    }
+    drop(sender);
+
+    // they all started together, so they share a deadline
+    let deadline = started + timeout;
+    let mut results = vec![Some(Failure::TimedOut(timeout)); names.len()];
+    let mut left = names.len();
+    while left > 0 {
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((i, result)) => {
+                results[i] = result;
+                left -= 1;
+            }
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    let total = names.len();
+    let failures = names
+        .into_iter()
+        .zip(results)
+        .filter_map(|(name, result)| Some((name, result?)))
+        .collect::<Vec<_>>();
+    for (name, failure) in &failures {
+        warn!(plugin = %name, %failure, "plugin didn't shut down cleanly");
+    }
+    debug!(plugins = total, elapsed = ?started.elapsed(), "shutdown broadcast");
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(ShutdownErrors { failures, total })
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(no message)".to_string()
+    }
+}
@@ -0,0 +1,178 @@
+// Running under systemd: socket activation and sd_notify, without linking libsystemd.
+//
+// With the `systemd` feature on, binding the extension socket first looks for a listener
+// systemd passed in (LISTEN_FDS) that's already bound to that path, and uses it as is.
+// `spawn_notifier` tells systemd `READY=1` once the extension is registered and serving,
+// then keeps up `WATCHDOG=1` only while it stays that way, so a wedged extension gets
+// restarted instead of sitting there.
+use std::io::{self, ErrorKind};
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::health;
+
+// systemd's passed descriptors always start here
+const LISTEN_FDS_START: RawFd = 3;
+
+// the passed descriptors nobody has taken yet, read from the environment on first use
+static ACTIVATED: Mutex<Option<Vec<RawFd>>> = Mutex::new(None);
+
+fn for_us(pid_var: &str) -> bool {
+    match std::env::var(pid_var) {
+        Ok(pid) => pid.parse() == Ok(std::process::id()),
+        Err(_) => false,
+    }
+}
+
+/// The descriptors systemd passed this process, taken out of the environment so children
+/// don't think they're theirs. Empty if the process wasn't socket activated.
+fn listen_fds() -> Vec<RawFd> {
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<RawFd>().ok())
+        .filter(|_| for_us("LISTEN_PID"))
+        .unwrap_or(0);
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"].iter() {
+        std::env::remove_var(var);
+    }
+    let fds = (LISTEN_FDS_START..LISTEN_FDS_START + count).collect::<Vec<_>>();
+    for &fd in &fds {
+        // safe: the fd was handed to us open, and this only sets a flag on it
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    fds
+}
+
+/// Whether systemd passed this process any sockets that haven't been taken yet.
+pub fn is_socket_activated() -> bool {
+    let mut activated = ACTIVATED.lock().unwrap_or_else(|e| e.into_inner());
+    !activated.get_or_insert_with(listen_fds).is_empty()
+}
+
+/// The activated listener bound to `path`, if systemd passed one. Each is handed out once.
+pub fn take_listener(path: &Path) -> Option<UnixListener> {
+    let mut activated = ACTIVATED.lock().unwrap_or_else(|e| e.into_inner());
+    let fds = activated.get_or_insert_with(listen_fds);
+    for i in 0..fds.len() {
+        // safe: the fd is one systemd passed and nothing else owns; it's handed back
+        // below unless it's the one we're after
+        let listener = unsafe { UnixListener::from_raw_fd(fds[i]) };
+        let matches = listener
+            .local_addr()
+            .map(|addr| addr.as_pathname() == Some(path))
+            .unwrap_or(false);
+        if matches {
+            fds.remove(i);
+            debug!(?path, "using the socket systemd passed in");
+            return Some(listener);
+        }
+        let _ = listener.into_raw_fd();
+    }
+    None
+}
+
+/// Send `state` (e.g. `"READY=1"`, `"STATUS=..."`) to systemd. Ok(false) if the process
+/// isn't running under a service manager that asked for notifications.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let socket = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => return Ok(false),
+    };
+    let sender = UnixDatagram::unbound()?;
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(socket.as_os_str());
+    match bytes.split_first() {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Some((b'@', name)) => {
+            #[cfg(target_os = "android")]
+            use std::os::android::net::SocketAddrExt;
+            #[cfg(target_os = "linux")]
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sender.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        Some((b'/', _)) => {
+            sender.send_to(state.as_bytes(), &socket)?;
+        }
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "NOTIFY_SOCKET isn't a socket path",
+            ))
+        }
+    }
+    Ok(true)
+}
+
+pub fn ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+pub fn watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+pub fn stopping() -> io::Result<bool> {
+    notify("STOPPING=1")
+}
+
+/// Free-form status text for `systemctl status`
+pub fn status(status: &str) -> io::Result<bool> {
+    notify(&format!("STATUS={}", status))
+}
+
+/// How often systemd expects `WATCHDOG=1`, if the unit has `WatchdogSec=` set.
+pub fn watchdog_interval() -> Option<Duration> {
+    if std::env::var_os("WATCHDOG_PID").is_some() && !for_us("WATCHDOG_PID") {
+        return None;
+    }
+    std::env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|&usec| usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Tell systemd `READY=1` once `health::is_ready()` (registered with osquery and serving),
+/// then send `WATCHDOG=1` at half the watchdog interval whenever it's still ready. Pings
+/// stop while it isn't, so systemd's watchdog gets to act. None if systemd isn't listening.
+pub fn spawn_notifier() -> Option<JoinHandle<()>> {
+    std::env::var_os("NOTIFY_SOCKET")?;
+    let interval = watchdog_interval();
+    Some(std::thread::spawn(move || {
+        while !health::is_ready() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        if let Err(error) = ready() {
+            warn!(%error, "couldn't notify systemd");
+            return;
+        }
+        let interval = match interval {
+            Some(interval) => interval / 2,
+            None => return,
+        };
+        let mut serving = true;
+        loop {
+            std::thread::sleep(interval);
+            // back to pinging if it recovers before systemd gives up on it
+            if health::is_ready() != serving {
+                serving = !serving;
+                if !serving {
+                    warn!("no longer serving, holding off systemd's watchdog pings");
+                }
+            }
+            if !serving {
+                continue;
+            }
+            if let Err(error) = watchdog() {
+                warn!(%error, "couldn't send systemd a watchdog ping");
+            }
+        }
+    }))
+}
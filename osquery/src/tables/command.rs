@@ -0,0 +1,301 @@
+use std::io::Read;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{bounded, RecvTimeoutError};
+use tracing::{debug, warn};
+
+use super::{json_value, parse_value, TableName};
+use crate::{Column, Plugin, QueryContext, RowSet, TablePlugin, TableRows};
+
+/// How the command's stdout turns into rows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// One JSON object per line, keyed by column name
+    JsonLines,
+    /// One row per line, split on `delimiter` (or runs of whitespace if there isn't one).
+    /// The last column gets whatever's left of the line, so things like a command line
+    /// with spaces in it stay whole.
+    Columns {
+        delimiter: Option<char>,
+        skip_header: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandOptions {
+    /// Absolute path to the program, which has to be in `allowed`
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Programs this table is allowed to run. Checked on every run, after resolving symlinks.
+    pub allowed: Vec<PathBuf>,
+    pub columns: Vec<Column>,
+    pub format: OutputFormat,
+    /// Killed if it hasn't finished by then
+    pub timeout: Duration,
+    /// Killed if it writes more than this many bytes to stdout
+    pub max_output: usize,
+    /// The command starts with an empty environment plus these
+    pub env: Vec<(String, String)>,
+    pub working_dir: Option<PathBuf>,
+}
+
+impl CommandOptions {
+    /// Run `program`, allowing only that program.
+    pub fn new<P: Into<PathBuf>>(program: P, columns: Vec<Column>) -> Self {
+        let program = program.into();
+        Self {
+            allowed: vec![program.clone()],
+            program,
+            columns,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for CommandOptions {
+    fn default() -> Self {
+        Self {
+            program: PathBuf::new(),
+            args: vec![],
+            allowed: vec![],
+            columns: vec![],
+            format: OutputFormat::JsonLines,
+            timeout: Duration::from_secs(10),
+            max_output: 16 * 1024 * 1024,
+            env: vec![("PATH".to_string(), "/usr/bin:/bin".to_string())],
+            working_dir: None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CommandTableError {
+    #[error("{0:?} isn't on the allow-list")]
+    NotAllowed(PathBuf),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("command timed out after {0:?}")]
+    TimedOut(Duration),
+    #[error("command wrote more than {0} bytes")]
+    OutputTooLarge(usize),
+    #[error("command failed: {0}")]
+    Failed(ExitStatus),
+    #[error("bad json on line {line}: {source}")]
+    Json {
+        line: usize,
+        source: serde_json::Error,
+    },
+}
+
+/// Runs a command on every `generate` and turns what it prints into rows.
+#[derive(Debug)]
+pub struct CommandTable<N> {
+    options: CommandOptions,
+    _name: PhantomData<fn() -> N>,
+}
+
+impl<N: TableName> CommandTable<N> {
+    /// Fails if the program isn't allowed, so bad configs show up before registering.
+    pub fn open(options: CommandOptions) -> Result<Self, CommandTableError> {
+        let table = Self {
+            options,
+            _name: PhantomData,
+        };
+        table.check_allowed()?;
+        Ok(table)
+    }
+
+    fn check_allowed(&self) -> Result<PathBuf, CommandTableError> {
+        let program = &self.options.program;
+        let not_allowed = || CommandTableError::NotAllowed(program.clone());
+        if !program.is_absolute() {
+            return Err(not_allowed());
+        }
+        let resolved = program.canonicalize().map_err(|_| not_allowed())?;
+        let allowed = self
+            .options
+            .allowed
+            .iter()
+            .filter_map(|p| p.canonicalize().ok())
+            .any(|p| p == resolved);
+        if !allowed {
+            return Err(not_allowed());
+        }
+        Ok(resolved)
+    }
+
+    fn run(&self, query: &QueryContext) -> Result<Vec<u8>, CommandTableError> {
+        let program = self.check_allowed()?;
+        let options = &self.options;
+        let mut command = Command::new(&program);
+        command
+            .args(&options.args)
+            .env_clear()
+            .envs(options.env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        if let Some(dir) = &options.working_dir {
+            command.current_dir(dir);
+        }
+        let timeout = match query.deadline().remaining() {
+            Some(remaining) => remaining.min(options.timeout),
+            None => options.timeout,
+        };
+        let deadline = Instant::now() + timeout;
+        debug!(table = N::NAME, ?program, "running command");
+        let mut child = command.spawn()?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+
+        // read on another thread, so a command that never closes stdout can still time out
+        let cap = options.max_output;
+        let (sender, receiver) = bounded(1);
+        std::thread::spawn(move || {
+            let mut output = vec![];
+            let result = (&mut stdout)
+                .take(cap as u64 + 1)
+                .read_to_end(&mut output)
+                .map(|_| output);
+            let _ = sender.send(result);
+        });
+        let output = match receiver.recv_timeout(timeout) {
+            Ok(output) => output,
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(CommandTableError::TimedOut(timeout));
+            }
+        };
+        let output = match output {
+            Ok(output) if output.len() > cap => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(CommandTableError::OutputTooLarge(cap));
+            }
+            Ok(output) => output,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e.into());
+            }
+        };
+
+        // stdout is closed, it should be on its way out
+        loop {
+            if let Some(status) = child.try_wait()? {
+                if !status.success() {
+                    return Err(CommandTableError::Failed(status));
+                }
+                return Ok(output);
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    table = N::NAME,
+                    "command closed stdout but didn't exit, killing it"
+                );
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(CommandTableError::TimedOut(timeout));
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn parse(&self, output: &str) -> Result<RowSet, CommandTableError> {
+        let columns = &self.options.columns;
+        let mut set = RowSet::new(columns);
+        match &self.options.format {
+            OutputFormat::JsonLines => {
+                for (i, line) in output.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let record: serde_json::Value =
+                        serde_json::from_str(line).map_err(|source| CommandTableError::Json {
+                            line: i + 1,
+                            source,
+                        })?;
+                    let values = columns
+                        .iter()
+                        .map(|c| record.get(&c.name).and_then(|v| json_value(c.kind, v)));
+                    set.push_partial(values)
+                        .expect("one value per column by construction");
+                }
+            }
+            OutputFormat::Columns {
+                delimiter,
+                skip_header,
+            } => {
+                let lines = output.lines().filter(|l| !l.trim().is_empty());
+                for line in lines.skip(*skip_header as usize) {
+                    let fields = split_fields(line, *delimiter, columns.len());
+                    let values = columns
+                        .iter()
+                        .enumerate()
+                        .map(|(i, c)| fields.get(i).and_then(|f| parse_value(c.kind, f)));
+                    set.push_partial(values)
+                        .expect("one value per column by construction");
+                }
+            }
+        }
+        Ok(set)
+    }
+}
+
+/// Split into at most `n` fields, the last one keeping the rest of the line.
+fn split_fields(line: &str, delimiter: Option<char>, n: usize) -> Vec<&str> {
+    match delimiter {
+        Some(d) => line.splitn(n.max(1), d).collect(),
+        None => {
+            let mut fields = vec![];
+            let mut rest = line.trim_start();
+            while !rest.is_empty() {
+                if fields.len() + 1 == n {
+                    fields.push(rest.trim_end());
+                    break;
+                }
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                fields.push(&rest[..end]);
+                rest = rest[end..].trim_start();
+            }
+            fields
+        }
+    }
+}
+
+impl<N: TableName> Plugin for CommandTable<N> {
+    type Error = CommandTableError;
+    const NAME: &'static str = N::NAME;
+
+    /// A table with nothing to run, use `CommandTable::open` instead.
+    fn new() -> Self {
+        Self {
+            options: CommandOptions::default(),
+            _name: PhantomData,
+        }
+    }
+}
+
+impl<N: TableName> TablePlugin for CommandTable<N> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        self.generate_rows(query).map(Into::into)
+    }
+
+    fn generate_rows(&self, query: &QueryContext) -> Result<RowSet, Self::Error> {
+        let output = self.run(query)?;
+        self.parse(&String::from_utf8_lossy(&output))
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(self.options.columns.clone())
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {}
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use tracing::info;
+
+use super::{equals_values, infer_type, parse_value, TableName};
+use crate::{Column, Plugin, QueryContext, RowSet, TablePlugin, TableRows};
+
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub path: PathBuf,
+    /// `b','` for CSV, `b'\t'` for TSV
+    pub delimiter: u8,
+    /// Whether the first record names the columns. Without one they're `c0`, `c1`, ...
+    pub has_headers: bool,
+    /// Declared schema, matched to the header by name (or by position, without headers).
+    /// Left empty, it's inferred the first time the file is read.
+    pub columns: Vec<Column>,
+    /// Column whose `=` constraints are looked up directly instead of scanning every row
+    pub key_column: Option<String>,
+}
+
+impl CsvOptions {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn tsv<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            delimiter: b'\t',
+            ..Self::new(path)
+        }
+    }
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            delimiter: b',',
+            has_headers: true,
+            columns: vec![],
+            key_column: None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CsvTableError {
+    #[error("couldn't read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("column `{0}` isn't in the file's header")]
+    MissingColumn(String),
+}
+
+#[derive(Debug)]
+struct Loaded {
+    modified: SystemTime,
+    records: Vec<csv::StringRecord>,
+    // where each schema column lives in a record
+    positions: Vec<usize>,
+    by_key: HashMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // pinned after the first read, osquery only asks for columns once
+    columns: Vec<Column>,
+    loaded: Option<Loaded>,
+}
+
+/// Exposes a CSV (or TSV) file as a table. The file is re-read whenever its mtime changes.
+#[derive(Debug)]
+pub struct CsvTable<N> {
+    options: CsvOptions,
+    state: Mutex<State>,
+    _name: PhantomData<fn() -> N>,
+}
+
+impl<N: TableName> CsvTable<N> {
+    pub fn open(options: CsvOptions) -> Self {
+        Self {
+            state: Mutex::new(State {
+                columns: options.columns.clone(),
+                loaded: None,
+            }),
+            options,
+            _name: PhantomData,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn refresh<'a>(
+        &self,
+        columns: &mut Vec<Column>,
+        slot: &'a mut Option<Loaded>,
+    ) -> Result<&'a Loaded, CsvTableError> {
+        let path = &self.options.path;
+        let modified = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(|source| CsvTableError::Io {
+                path: path.clone(),
+                source,
+            })?;
+        let loaded = match slot.take() {
+            Some(loaded) if loaded.modified == modified => loaded,
+            _ => {
+                let loaded = self.read(columns, modified)?;
+                info!(
+                    table = N::NAME,
+                    ?path,
+                    rows = loaded.records.len(),
+                    "loaded csv"
+                );
+                loaded
+            }
+        };
+        Ok(slot.insert(loaded))
+    }
+
+    fn read(
+        &self,
+        columns: &mut Vec<Column>,
+        modified: SystemTime,
+    ) -> Result<Loaded, CsvTableError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.options.delimiter)
+            .has_headers(self.options.has_headers)
+            .flexible(true)
+            .from_path(&self.options.path)?;
+        let header: Vec<String> = match self.options.has_headers {
+            true => reader
+                .headers()?
+                .iter()
+                .map(|h| h.trim().to_string())
+                .collect(),
+            false => vec![],
+        };
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        let header = match self.options.has_headers {
+            true => header,
+            false => {
+                let width = records.iter().map(|r| r.len()).max().unwrap_or_default();
+                (0..width).map(|i| format!("c{}", i)).collect()
+            }
+        };
+
+        if columns.is_empty() {
+            *columns = header
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    Column::new(name, infer_type(records.iter().filter_map(|r| r.get(i))))
+                })
+                .collect();
+        }
+        let positions = match self.options.has_headers {
+            true => columns
+                .iter()
+                .map(|c| {
+                    header
+                        .iter()
+                        .position(|h| *h == c.name)
+                        .ok_or_else(|| CsvTableError::MissingColumn(c.name.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            false => (0..columns.len()).collect(),
+        };
+
+        let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+        if let Some(key) = &self.options.key_column {
+            let i = columns
+                .iter()
+                .position(|c| c.name == *key)
+                .ok_or_else(|| CsvTableError::MissingColumn(key.clone()))?;
+            for (row, record) in records.iter().enumerate() {
+                if let Some(value) = record.get(positions[i]) {
+                    by_key
+                        .entry(value.trim().to_string())
+                        .or_default()
+                        .push(row);
+                }
+            }
+        }
+
+        Ok(Loaded {
+            modified,
+            records,
+            positions,
+            by_key,
+        })
+    }
+}
+
+impl<N: TableName> Plugin for CsvTable<N> {
+    type Error = CsvTableError;
+    const NAME: &'static str = N::NAME;
+
+    /// A table with no file behind it, use `CsvTable::open` instead.
+    fn new() -> Self {
+        Self::open(CsvOptions::default())
+    }
+}
+
+impl<N: TableName> TablePlugin for CsvTable<N> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        self.generate_rows(query).map(Into::into)
+    }
+
+    fn generate_rows(&self, query: &QueryContext) -> Result<RowSet, Self::Error> {
+        let mut state = self.state();
+        let state = &mut *state;
+        let loaded = self.refresh(&mut state.columns, &mut state.loaded)?;
+        let columns = &state.columns;
+
+        let keys = match &self.options.key_column {
+            Some(key) => equals_values(query, key),
+            None => vec![],
+        };
+        let matching: Vec<usize> = match keys.is_empty() {
+            true => (0..loaded.records.len()).collect(),
+            false => {
+                let mut rows: Vec<usize> = keys
+                    .iter()
+                    .filter_map(|k| loaded.by_key.get(k.trim()))
+                    .flatten()
+                    .copied()
+                    .collect();
+                rows.sort_unstable();
+                rows.dedup();
+                rows
+            }
+        };
+
+        let mut set = RowSet::with_capacity(columns, matching.len());
+        for row in matching {
+            let record = &loaded.records[row];
+            let values = columns
+                .iter()
+                .zip(&loaded.positions)
+                .map(|(c, &i)| record.get(i).and_then(|v| parse_value(c.kind, v)));
+            set.push_partial(values)
+                .expect("one value per column by construction");
+        }
+        Ok(set)
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        let mut state = self.state();
+        if state.columns.is_empty() {
+            let state = &mut *state;
+            self.refresh(&mut state.columns, &mut state.loaded)?;
+        }
+        Ok(state.columns.clone())
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {}
+}
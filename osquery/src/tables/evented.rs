@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tracing::debug;
+
+use super::TableName;
+use crate::gen::table::Operator;
+use crate::{Column, ColumnValue, Plugin, QueryContext, TablePlugin, TableRows};
+
+type TableRow = BTreeMap<String, ColumnValue>;
+
+/// Every evented table gets this column, the unix time the event was pushed.
+pub const TIME_COLUMN: &str = "time";
+
+#[derive(Debug, Clone)]
+pub struct EventedOptions {
+    /// The event's own columns. `time` is added in front of them.
+    pub columns: Vec<Column>,
+    /// Most events to hold. Past this the oldest are dropped.
+    pub capacity: usize,
+    /// Drop events older than this, if set
+    pub expiry: Option<Duration>,
+}
+
+impl Default for EventedOptions {
+    fn default() -> Self {
+        Self {
+            columns: vec![],
+            capacity: 10_000,
+            expiry: Some(Duration::from_secs(3600)),
+        }
+    }
+}
+
+impl EventedOptions {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self {
+            columns,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buffer {
+    capacity: usize,
+    expiry: Option<Duration>,
+    events: Mutex<VecDeque<(i64, TableRow)>>,
+    closed: AtomicBool,
+}
+
+impl Buffer {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<(i64, TableRow)>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn expire(&self, events: &mut VecDeque<(i64, TableRow)>, now: i64) {
+        if let Some(expiry) = self.expiry {
+            let cutoff = now - expiry.as_secs() as i64;
+            while events.front().is_some_and(|(time, _)| *time < cutoff) {
+                events.pop_front();
+            }
+        }
+    }
+}
+
+/// The producer's end of an `EventedTable`. Cheap to clone, and fine to hand to as many
+/// threads as are collecting events.
+#[derive(Debug, Clone)]
+pub struct EventSink {
+    buffer: Arc<Buffer>,
+}
+
+impl EventSink {
+    /// Record an event that happened just now.
+    pub fn push(&self, row: TableRow) {
+        self.push_at(now(), row)
+    }
+
+    /// Record an event with its own unix timestamp, e.g. one from a kernel message.
+    /// Events are served in the order they're pushed, not sorted by time.
+    pub fn push_at(&self, time: i64, mut row: TableRow) {
+        row.insert(TIME_COLUMN.to_string(), ColumnValue::big_int(time));
+        let mut events = self.buffer.lock();
+        events.push_back((time, row));
+        while events.len() > self.buffer.capacity {
+            events.pop_front();
+        }
+        self.buffer.expire(&mut events, now());
+    }
+
+    /// Set once osquery shuts the table down. Background producers should check this and stop.
+    pub fn is_closed(&self) -> bool {
+        self.buffer.closed.load(Ordering::Relaxed)
+    }
+}
+
+/// A table of events collected in the background: process starts, netlink messages,
+/// anything that happens rather than just is. A producer pushes rows through an
+/// `EventSink`, they're kept in a bounded buffer until they expire, and `generate` serves
+/// what's buffered, narrowed by any `time` constraints in the query.
+#[derive(Debug)]
+pub struct EventedTable<N> {
+    columns: Vec<Column>,
+    buffer: Arc<Buffer>,
+    _name: PhantomData<fn() -> N>,
+}
+
+impl<N: TableName> EventedTable<N> {
+    pub fn with_options(options: EventedOptions) -> Self {
+        let mut columns = vec![Column::big_int(TIME_COLUMN)];
+        columns.extend(
+            options
+                .columns
+                .into_iter()
+                .filter(|c| c.name != TIME_COLUMN),
+        );
+        Self {
+            columns,
+            buffer: Arc::new(Buffer {
+                capacity: options.capacity.max(1),
+                expiry: options.expiry,
+                events: Mutex::new(VecDeque::new()),
+                closed: AtomicBool::new(false),
+            }),
+            _name: PhantomData,
+        }
+    }
+
+    /// `with_options`, plus a thread running `producer` with the table's sink.
+    pub fn spawn<F>(options: EventedOptions, producer: F) -> Self
+    where
+        F: FnOnce(EventSink) + Send + 'static,
+    {
+        let table = Self::with_options(options);
+        let sink = table.sink();
+        std::thread::spawn(move || producer(sink));
+        table
+    }
+
+    pub fn sink(&self) -> EventSink {
+        EventSink {
+            buffer: self.buffer.clone(),
+        }
+    }
+
+    /// Events currently buffered
+    pub fn len(&self) -> usize {
+        self.buffer.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<N: TableName> Plugin for EventedTable<N> {
+    type Error = Infallible;
+    const NAME: &'static str = N::NAME;
+
+    /// A table with only a `time` column, use `EventedTable::with_options` instead.
+    fn new() -> Self {
+        Self::with_options(EventedOptions::default())
+    }
+}
+
+impl<N: TableName> TablePlugin for EventedTable<N> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let range = TimeRange::of(query);
+        let mut events = self.buffer.lock();
+        self.buffer.expire(&mut events, now());
+        let rows: TableRows = events
+            .iter()
+            .filter(|(time, _)| range.contains(*time))
+            .map(|(_, row)| row.clone())
+            .collect();
+        debug!(
+            table = N::NAME,
+            buffered = events.len(),
+            served = rows.len(),
+            "served events"
+        );
+        Ok(rows)
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(self.columns.clone())
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {
+        self.buffer.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+// The times the query's `time` constraints allow. osquery still filters the rows
+// itself, this just avoids copying ones it would throw away.
+struct TimeRange {
+    start: i64,
+    end: i64,
+    // `time IN (...)` shows up as several `=`, any of which can match
+    exactly: Vec<i64>,
+}
+
+impl TimeRange {
+    fn of(query: &QueryContext) -> Self {
+        let mut range = TimeRange {
+            start: i64::MIN,
+            end: i64::MAX,
+            exactly: vec![],
+        };
+        let constraints = query
+            .constraints
+            .iter()
+            .filter(|c| c.name == TIME_COLUMN)
+            .flat_map(|c| c.list.iter());
+        for constraint in constraints {
+            let value = match constraint.expr.trim().parse::<i64>() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            match constraint.op {
+                Operator::Equals => range.exactly.push(value),
+                Operator::GreaterThan => range.start = range.start.max(value.saturating_add(1)),
+                Operator::GreaterThanOrEquals => range.start = range.start.max(value),
+                Operator::LessThan => range.end = range.end.min(value.saturating_sub(1)),
+                Operator::LessThanOrEquals => range.end = range.end.min(value),
+                _ => {}
+            }
+        }
+        range
+    }
+
+    fn contains(&self, time: i64) -> bool {
+        time >= self.start
+            && time <= self.end
+            && (self.exactly.is_empty() || self.exactly.contains(&time))
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
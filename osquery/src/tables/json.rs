@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use serde_json::Value;
+use tracing::info;
+
+use super::{equals_values, infer_type, json_value, TableName};
+use crate::{Column, Plugin, QueryContext, RowSet, TablePlugin, TableRows};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonFormat {
+    /// Go by the first non-whitespace character: `[` is an array, anything else NDJSON
+    Auto,
+    Array,
+    /// One object per line
+    Lines,
+}
+
+/// A column and where to find its value in each record, e.g. `$.os.version` or
+/// `$.addresses[0]`. Objects and arrays come through as JSON text.
+#[derive(Debug, Clone)]
+pub struct JsonColumn {
+    pub column: Column,
+    pub path: String,
+}
+
+impl JsonColumn {
+    pub fn new(column: Column, path: &str) -> Self {
+        Self {
+            column,
+            path: path.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JsonOptions {
+    pub path: PathBuf,
+    pub format: JsonFormat,
+    /// Where the records live inside an array-format document, e.g. `$.data.hosts`.
+    /// Defaults to the document itself.
+    pub records: Option<String>,
+    /// Left empty, one column per top-level key is inferred the first time the file is read.
+    pub columns: Vec<JsonColumn>,
+    /// Column whose `=` constraints are looked up directly instead of scanning every row
+    pub key_column: Option<String>,
+}
+
+impl JsonOptions {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            format: JsonFormat::Auto,
+            records: None,
+            columns: vec![],
+            key_column: None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum JsonTableError {
+    #[error("couldn't read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("bad json on line {line}: {source}")]
+    Json {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("bad path `{0}`")]
+    BadPath(String),
+    #[error("`{0}` isn't an array of records")]
+    NotAnArray(String),
+    #[error("no column named `{0}`")]
+    MissingColumn(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// The bit of JSONPath that's useful for picking fields: `$`, `.key`, `['key']`, `[0]`.
+/// Inside `['...']`, a backslash escapes the next character, so `['it\'s']` is `it's`.
+#[derive(Debug, Clone, PartialEq)]
+struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+    fn parse(path: &str) -> Result<Self, JsonTableError> {
+        let bad = || JsonTableError::BadPath(path.to_string());
+        let mut rest = path.trim();
+        rest = rest.strip_prefix('$').unwrap_or(rest);
+        let mut segments = vec![];
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix("['") {
+                let mut key = String::new();
+                let mut chars = after.char_indices();
+                let end = loop {
+                    match chars.next().ok_or_else(bad)? {
+                        (_, '\\') => key.push(chars.next().ok_or_else(bad)?.1),
+                        (i, '\'') if after[i..].starts_with("']") => break i,
+                        (_, c) => key.push(c),
+                    }
+                };
+                segments.push(Segment::Key(key));
+                rest = &after[end + 2..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(bad)?;
+                let index = after[..end].trim().parse().map_err(|_| bad())?;
+                segments.push(Segment::Index(index));
+                rest = &after[end + 1..];
+            } else {
+                let after = rest.strip_prefix('.').unwrap_or(rest);
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(bad());
+                }
+                segments.push(Segment::Key(after[..end].to_string()));
+                rest = &after[end..];
+            }
+        }
+        Ok(Self(segments))
+    }
+
+    /// `$['key']`, escaped so it parses back to `key`.
+    fn quoted_key(key: &str) -> String {
+        format!("$['{}']", key.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+
+    fn select<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0.iter().try_fold(value, |v, segment| match segment {
+            Segment::Key(key) => v.get(key),
+            Segment::Index(i) => v.get(i),
+        })
+    }
+}
+
+// what infer_type gets to look at
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(b) => Some(if *b { "1" } else { "0" }.to_string()),
+        // never a number, so it comes out as text
+        Value::Array(_) | Value::Object(_) => Some("{}".to_string()),
+        other => Some(other.to_string()),
+    }
+}
+
+#[derive(Debug)]
+struct Loaded {
+    modified: SystemTime,
+    rows: RowSet,
+    by_key: HashMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // pinned after the first read, osquery only asks for columns once
+    columns: Vec<(JsonColumn, JsonPath)>,
+    loaded: Option<Loaded>,
+}
+
+/// Exposes a JSON array or NDJSON file as a table, one row per record. The file is
+/// re-read whenever its mtime changes.
+#[derive(Debug)]
+pub struct JsonTable<N> {
+    options: JsonOptions,
+    state: Mutex<State>,
+    _name: PhantomData<fn() -> N>,
+}
+
+impl<N: TableName> JsonTable<N> {
+    /// Fails if any of the column or record paths don't parse.
+    pub fn open(options: JsonOptions) -> Result<Self, JsonTableError> {
+        if let Some(records) = &options.records {
+            JsonPath::parse(records)?;
+        }
+        let columns = options
+            .columns
+            .iter()
+            .map(|c| Ok((c.clone(), JsonPath::parse(&c.path)?)))
+            .collect::<Result<_, JsonTableError>>()?;
+        Ok(Self {
+            state: Mutex::new(State {
+                columns,
+                loaded: None,
+            }),
+            options,
+            _name: PhantomData,
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn io_error(&self, source: std::io::Error) -> JsonTableError {
+        JsonTableError::Io {
+            path: self.options.path.clone(),
+            source,
+        }
+    }
+
+    fn refresh<'a>(
+        &self,
+        columns: &mut Vec<(JsonColumn, JsonPath)>,
+        slot: &'a mut Option<Loaded>,
+    ) -> Result<&'a Loaded, JsonTableError> {
+        let modified = std::fs::metadata(&self.options.path)
+            .and_then(|m| m.modified())
+            .map_err(|e| self.io_error(e))?;
+        let loaded = match slot.take() {
+            Some(loaded) if loaded.modified == modified => loaded,
+            _ => {
+                let loaded = self.read(columns, modified)?;
+                info!(table = N::NAME, path = ?self.options.path, rows = loaded.rows.len(), "loaded json");
+                loaded
+            }
+        };
+        Ok(slot.insert(loaded))
+    }
+
+    fn records(&self) -> Result<Vec<Value>, JsonTableError> {
+        let content = std::fs::read_to_string(&self.options.path).map_err(|e| self.io_error(e))?;
+        let array = match self.options.format {
+            JsonFormat::Array => true,
+            JsonFormat::Lines => false,
+            JsonFormat::Auto => content.trim_start().starts_with('['),
+        };
+        if !array {
+            return content
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| {
+                    serde_json::from_str(line).map_err(|source| JsonTableError::Json {
+                        line: i + 1,
+                        source,
+                    })
+                })
+                .collect();
+        }
+        let document: Value =
+            serde_json::from_str(&content).map_err(|source| JsonTableError::Json {
+                line: source.line(),
+                source,
+            })?;
+        let records = match &self.options.records {
+            Some(path) => JsonPath::parse(path)?.select(&document).cloned(),
+            None => Some(document),
+        };
+        match records {
+            Some(Value::Array(records)) => Ok(records),
+            _ => Err(JsonTableError::NotAnArray(
+                self.options
+                    .records
+                    .clone()
+                    .unwrap_or_else(|| "$".to_string()),
+            )),
+        }
+    }
+
+    fn read(
+        &self,
+        columns: &mut Vec<(JsonColumn, JsonPath)>,
+        modified: SystemTime,
+    ) -> Result<Loaded, JsonTableError> {
+        let records = self.records()?;
+        if columns.is_empty() {
+            let mut keys: Vec<&String> = vec![];
+            for key in records
+                .iter()
+                .filter_map(Value::as_object)
+                .flat_map(|o| o.keys())
+            {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+            *columns = keys
+                .into_iter()
+                .map(|key| {
+                    let path = JsonPath(vec![Segment::Key(key.clone())]);
+                    let samples: Vec<String> = records
+                        .iter()
+                        .filter_map(|r| path.select(r).and_then(scalar_text))
+                        .collect();
+                    let column = Column::new(key, infer_type(samples.iter().map(String::as_str)));
+                    (JsonColumn::new(column, &JsonPath::quoted_key(key)), path)
+                })
+                .collect();
+        }
+
+        let schema: Vec<Column> = columns.iter().map(|(c, _)| c.column.clone()).collect();
+        let mut rows = RowSet::with_capacity(&schema, records.len());
+        for record in &records {
+            let values = columns.iter().map(|(c, path)| {
+                path.select(record)
+                    .and_then(|v| json_value(c.column.kind, v))
+            });
+            rows.push_partial(values)
+                .expect("one value per column by construction");
+        }
+
+        let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+        if let Some(key) = &self.options.key_column {
+            if !schema.iter().any(|c| c.name == *key) {
+                return Err(JsonTableError::MissingColumn(key.clone()));
+            }
+            for row in 0..rows.len() {
+                if let Some(value) = rows.get(row, key) {
+                    by_key.entry(value.to_string()).or_default().push(row);
+                }
+            }
+        }
+
+        Ok(Loaded {
+            modified,
+            rows,
+            by_key,
+        })
+    }
+}
+
+impl<N: TableName> Plugin for JsonTable<N> {
+    type Error = JsonTableError;
+    const NAME: &'static str = N::NAME;
+
+    /// A table with no file behind it, use `JsonTable::open` instead.
+    fn new() -> Self {
+        Self::open(JsonOptions::default()).expect("default options have no paths to parse")
+    }
+}
+
+impl<N: TableName> TablePlugin for JsonTable<N> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        self.generate_rows(query).map(Into::into)
+    }
+
+    fn generate_rows(&self, query: &QueryContext) -> Result<RowSet, Self::Error> {
+        let mut state = self.state();
+        let state = &mut *state;
+        let loaded = self.refresh(&mut state.columns, &mut state.loaded)?;
+
+        let keys = match &self.options.key_column {
+            Some(key) => equals_values(query, key),
+            None => vec![],
+        };
+        if keys.is_empty() {
+            return Ok(loaded.rows.clone());
+        }
+        let mut matching: Vec<usize> = keys
+            .iter()
+            .filter_map(|k| loaded.by_key.get(k.trim()))
+            .flatten()
+            .copied()
+            .collect();
+        matching.sort_unstable();
+        matching.dedup();
+
+        let schema: Vec<Column> = state
+            .columns
+            .iter()
+            .map(|(c, _)| c.column.clone())
+            .collect();
+        let mut set = RowSet::with_capacity(&schema, matching.len());
+        for row in matching.into_iter().filter_map(|i| loaded.rows.row(i)) {
+            set.push_partial(row.to_vec())
+                .expect("same schema as the loaded rows");
+        }
+        Ok(set)
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        let mut state = self.state();
+        let state = &mut *state;
+        if state.columns.is_empty() {
+            self.refresh(&mut state.columns, &mut state.loaded)?;
+        }
+        Ok(state
+            .columns
+            .iter()
+            .map(|(c, _)| c.column.clone())
+            .collect())
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    crate::table_name!(Hosts = "hosts");
+
+    fn path(path: &str) -> Vec<Segment> {
+        JsonPath::parse(path).expect("path should parse").0
+    }
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.to_string())
+    }
+
+    // a file of its own per test, removed when dropped
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "osquery-rs-json-{}-{}",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+
+        fn table(&self) -> JsonTable<Hosts> {
+            JsonTable::open(JsonOptions::new(&self.0)).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(path("$"), []);
+        assert_eq!(path("$.os.version"), [key("os"), key("version")]);
+        assert_eq!(path("os.version"), [key("os"), key("version")]);
+        assert_eq!(
+            path("$.addresses[0].ip"),
+            [key("addresses"), Segment::Index(0), key("ip")]
+        );
+        assert_eq!(path("$[2][ 1 ]"), [Segment::Index(2), Segment::Index(1)]);
+        assert_eq!(path("$['a.b']['c[0]']"), [key("a.b"), key("c[0]")]);
+        assert_eq!(path(r"$['it\'s']"), [key("it's")]);
+        assert_eq!(path(r"$['back\\slash']"), [key(r"back\slash")]);
+    }
+
+    #[test]
+    fn bad_paths() {
+        for bad in &[
+            "$.", "$..a", "$.a.", "$[x]", "$[-1]", "$[0", "$['open", r"$['a\",
+        ] {
+            match JsonPath::parse(bad) {
+                Err(JsonTableError::BadPath(p)) => assert_eq!(p, *bad),
+                other => panic!("{} should be a bad path, got {:?}", bad, other),
+            }
+        }
+    }
+
+    #[test]
+    fn select() {
+        let record = json!({"os": {"version": "14.1"}, "addresses": ["10.0.0.1", "10.0.0.2"]});
+        let select = |p: &str| JsonPath::parse(p).unwrap().select(&record).cloned();
+        assert_eq!(select("$.os.version"), Some(json!("14.1")));
+        assert_eq!(select("$.addresses[1]"), Some(json!("10.0.0.2")));
+        assert_eq!(select("$.addresses[2]"), None);
+        assert_eq!(select("$.os[0]"), None);
+        assert_eq!(select("$.missing.version"), None);
+    }
+
+    #[test]
+    fn quoted_keys_round_trip() {
+        for k in &["plain", "it's", r"back\slash", r"\'", "a.b[0]", "']"] {
+            let quoted = JsonPath::quoted_key(k);
+            assert_eq!(path(&quoted), [key(k)], "{}", quoted);
+        }
+    }
+
+    #[test]
+    fn inferred_columns_use_quoted_paths() {
+        let file = Scratch::new("inferred", r#"[{"host's name": "a", "port": 22}]"#);
+        let table = file.table();
+        let names: Vec<_> = table
+            .columns()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["host's name", "port"]);
+        let state = table.state();
+        let (column, parsed) = &state.columns[0];
+        assert_eq!(column.path, r"$['host\'s name']");
+        assert_eq!(JsonPath::parse(&column.path).unwrap(), *parsed);
+    }
+
+    #[test]
+    fn format_is_detected() {
+        let array = Scratch::new("array", "  \n[{\"a\": 1}, {\"a\": 2}]");
+        assert_eq!(array.table().records().unwrap().len(), 2);
+        let lines = Scratch::new("lines", "{\"a\": 1}\n\n{\"a\": 2}\n{\"a\": 3}\n");
+        assert_eq!(lines.table().records().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn ndjson_errors_name_the_line() {
+        let file = Scratch::new("bad-line", "{\"a\": 1}\n\n{\"a\": }\n{\"a\": 3}\n");
+        match file.table().records() {
+            Err(JsonTableError::Json { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a json error, got {:?}", other),
+        }
+        let file = Scratch::new("bad-array", "[\n{\"a\": 1},\n{\"a\" 2}\n]");
+        match file.table().records() {
+            Err(JsonTableError::Json { line, .. }) => assert_eq!(line, 3),
+            other => panic!("expected a json error, got {:?}", other),
+        }
+    }
+}
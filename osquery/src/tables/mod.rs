@@ -0,0 +1,107 @@
+// Ready-made tables for the common cases: a file or tool as a table, data collected in
+// the background, rows merged from several sources, or a query over osquery's own
+// tables, so they don't each need a hand-written plugin. Plugin names are consts, so
+// each table is generic over a `TableName`, which `table_name!` will make.
+mod command;
+#[cfg(feature = "csv-table")]
+mod csv;
+mod evented;
+mod json;
+mod multi;
+mod refreshed;
+#[cfg(feature = "sqlite-table")]
+mod sqlite;
+mod view;
+
+pub use self::command::{CommandOptions, CommandTable, CommandTableError, OutputFormat};
+#[cfg(feature = "csv-table")]
+pub use self::csv::{CsvOptions, CsvTable, CsvTableError};
+pub use self::evented::{EventSink, EventedOptions, EventedTable, TIME_COLUMN};
+pub use self::json::{JsonColumn, JsonFormat, JsonOptions, JsonTable, JsonTableError};
+pub use self::multi::{MultiSourceTable, SOURCE_COLUMN};
+pub use self::refreshed::RefreshedTable;
+#[cfg(feature = "sqlite-table")]
+pub use self::sqlite::{SqliteOptions, SqliteTable, SqliteTableError};
+pub use self::view::{SqlViewError, SqlViewTable};
+
+use crate::gen::table::{ColumnType, Operator};
+use crate::{ColumnValue, QueryContext};
+
+/// Supplies the name a generic table registers under.
+pub trait TableName {
+    const NAME: &'static str;
+    /// Other names osquery should answer to, see `TablePlugin::aliases`
+    const ALIASES: &'static [&'static str] = &[];
+}
+
+/// Declare a marker type to name a generic table with, e.g.
+/// `table_name!(pub Inventory = "inventory");` then `CsvTable::<Inventory>::open(...)`.
+/// Aliases go in brackets after the name: `table_name!(Assets = "corp_assets" ["assets"]);`
+#[macro_export]
+macro_rules! table_name {
+    ($vis:vis $ty:ident = $name:literal $([$($alias:literal),* $(,)?])?) => {
+        #[derive(Debug, Default, Clone, Copy)]
+        $vis struct $ty;
+
+        impl $crate::tables::TableName for $ty {
+            const NAME: &'static str = $name;
+            const ALIASES: &'static [&'static str] = &[$($($alias),*)?];
+        }
+    };
+}
+
+pub(crate) fn aliases<N: TableName>() -> Vec<String> {
+    N::ALIASES.iter().map(|a| a.to_string()).collect()
+}
+
+/// The right-hand sides of every `column = ...` constraint in the query.
+pub(crate) fn equals_values<'a>(query: &'a QueryContext, column: &'a str) -> Vec<&'a str> {
+    query
+        .constraints_on(column)
+        .filter(|c| c.op == Operator::Equals)
+        .map(|c| c.expr.as_str())
+        .collect()
+}
+
+/// Parse a raw field as `kind`. Anything that doesn't parse comes back empty, same as
+/// osquery does with a bad value.
+pub(crate) fn parse_value(kind: ColumnType, raw: &str) -> Option<ColumnValue> {
+    let raw = raw.trim();
+    match kind {
+        ColumnType::Text | ColumnType::Unknown => Some(ColumnValue::text(raw)),
+        ColumnType::Integer => raw.parse::<i32>().ok().map(ColumnValue::integer),
+        ColumnType::BigInt => raw.parse::<i64>().ok().map(ColumnValue::big_int),
+        ColumnType::Double => raw.parse::<f64>().ok().map(ColumnValue::double),
+    }
+}
+
+/// `parse_value` for a JSON value. Bools are 1/0, objects and arrays come out as JSON text.
+pub(crate) fn json_value(kind: ColumnType, value: &serde_json::Value) -> Option<ColumnValue> {
+    use serde_json::Value;
+    match value {
+        Value::Null => None,
+        Value::String(s) => parse_value(kind, s),
+        Value::Bool(b) => parse_value(kind, if *b { "1" } else { "0" }),
+        other => parse_value(kind, &other.to_string()),
+    }
+}
+
+/// The narrowest type every non-empty value fits in.
+pub(crate) fn infer_type<'a, I: IntoIterator<Item = &'a str>>(values: I) -> ColumnType {
+    let mut kind = ColumnType::BigInt;
+    let mut seen = false;
+    for value in values.into_iter().map(str::trim).filter(|v| !v.is_empty()) {
+        seen = true;
+        if matches!(kind, ColumnType::BigInt) && value.parse::<i64>().is_err() {
+            kind = ColumnType::Double;
+        }
+        if matches!(kind, ColumnType::Double) && value.parse::<f64>().is_err() {
+            return ColumnType::Text;
+        }
+    }
+    if seen {
+        kind
+    } else {
+        ColumnType::Text
+    }
+}
@@ -0,0 +1,148 @@
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+use tracing::{debug, warn};
+
+use super::TableName;
+use crate::gen::table::ColumnType;
+use crate::{Column, ColumnValue, Plugin, QueryContext, TablePlugin, TableRows};
+
+/// The column `MultiSourceTable` adds, naming which source each row came from.
+pub const SOURCE_COLUMN: &str = "source";
+
+type Source = Box<dyn Fn(&QueryContext) -> Result<TableRows, String> + Send + Sync>;
+
+/// A table made of several producers of the same rows, one per container, user, cloud
+/// account and so on. `generate` runs them all at once on scoped threads and merges what
+/// they return, adding a `source` column with the name each row came from. A query with
+/// `source = '...'` only runs the sources it names. A source that fails is logged and
+/// left out, so one bad container doesn't empty the whole table.
+pub struct MultiSourceTable<N> {
+    columns: Vec<Column>,
+    sources: Vec<(String, Source)>,
+    _name: PhantomData<fn() -> N>,
+}
+
+impl<N> std::fmt::Debug for MultiSourceTable<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MultiSourceTable")
+            .field("columns", &self.columns)
+            .field(
+                "sources",
+                &self
+                    .sources
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl<N: TableName> MultiSourceTable<N> {
+    /// A table with these columns (plus `source`) and no sources yet.
+    pub fn with_columns(columns: Vec<Column>) -> Self {
+        Self {
+            columns,
+            sources: vec![],
+            _name: PhantomData,
+        }
+    }
+
+    /// Add a source that produces rows with a closure.
+    pub fn source<F, E>(mut self, name: impl Into<String>, generate: F) -> Self
+    where
+        F: Fn(&QueryContext) -> Result<TableRows, E> + Send + Sync + 'static,
+        E: Display,
+    {
+        self.sources.push((
+            name.into(),
+            Box::new(move |query| generate(query).map_err(|e| e.to_string())),
+        ));
+        self
+    }
+
+    /// Add another table as a source. Its columns should match this table's.
+    pub fn table<T>(self, name: impl Into<String>, table: T) -> Self
+    where
+        T: TablePlugin + Send + Sync + 'static,
+    {
+        self.source(name, move |query| table.generate(query))
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl<N: TableName> Plugin for MultiSourceTable<N> {
+    type Error = Infallible;
+    const NAME: &'static str = N::NAME;
+
+    /// A table with no columns and no sources, use `MultiSourceTable::with_columns` instead.
+    fn new() -> Self {
+        Self::with_columns(vec![])
+    }
+}
+
+impl<N: TableName> TablePlugin for MultiSourceTable<N> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let wanted = super::equals_values(query, SOURCE_COLUMN);
+        let sources = self
+            .sources
+            .iter()
+            .filter(|(name, _)| wanted.is_empty() || wanted.contains(&name.as_str()));
+        let results = std::thread::scope(|scope| {
+            let running = sources
+                .map(|(name, generate)| {
+                    let handle = scope.spawn(move || {
+                        let started = Instant::now();
+                        let rows = generate(query);
+                        (rows, started.elapsed())
+                    });
+                    (name, handle)
+                })
+                .collect::<Vec<_>>();
+            running
+                .into_iter()
+                .map(|(name, handle)| (name, handle.join()))
+                .collect::<Vec<_>>()
+        });
+
+        let mut merged = vec![];
+        for (name, result) in results {
+            match result {
+                Ok((Ok(rows), took)) => {
+                    debug!(table = N::NAME, source = %name, rows = rows.len(), ?took, "source generated");
+                    merged.extend(rows.into_iter().map(|mut row| {
+                        row.insert(SOURCE_COLUMN.to_string(), ColumnValue::text(name.as_str()));
+                        row
+                    }));
+                }
+                Ok((Err(error), _)) => {
+                    warn!(table = N::NAME, source = %name, %error, "source failed, leaving it out")
+                }
+                Err(_) => {
+                    warn!(table = N::NAME, source = %name, "source panicked, leaving it out")
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        let mut columns = self.columns.clone();
+        if !columns.iter().any(|c| c.name == SOURCE_COLUMN) {
+            columns.push(Column::new(SOURCE_COLUMN, ColumnType::Text));
+        }
+        Ok(columns)
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {}
+}
@@ -0,0 +1,119 @@
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use crossbeam::channel::{bounded, RecvTimeoutError, Sender};
+use tracing::{debug, warn};
+
+use super::TableName;
+use crate::{Column, Plugin, QueryContext, TablePlugin, TableRows};
+
+#[derive(Debug)]
+struct Snapshot {
+    rows: Arc<TableRows>,
+    taken: SystemTime,
+}
+
+/// A table whose rows are collected on a thread of their own every so often, with
+/// `generate` serving the last collection that worked. For data that's slow to gather
+/// (walking the filesystem, calling out to an API) and fine to be a little stale, so
+/// queries never wait on it and never trip osquery's watchdog.
+#[derive(Debug)]
+pub struct RefreshedTable<N> {
+    columns: Vec<Column>,
+    snapshot: Arc<RwLock<Option<Snapshot>>>,
+    stop: Mutex<Option<Sender<()>>>,
+    _name: PhantomData<fn() -> N>,
+}
+
+impl<N: TableName> RefreshedTable<N> {
+    /// Start calling `collect` every `interval`, beginning right away. A failed collection
+    /// is logged and the previous rows kept. Until the first one succeeds the table is empty.
+    pub fn spawn<F, E>(columns: Vec<Column>, interval: Duration, mut collect: F) -> Self
+    where
+        F: FnMut() -> Result<TableRows, E> + Send + 'static,
+        E: Display,
+    {
+        let snapshot: Arc<RwLock<Option<Snapshot>>> = Arc::default();
+        let (stop, stopped) = bounded::<()>(0);
+        let latest = snapshot.clone();
+        std::thread::spawn(move || loop {
+            let started = Instant::now();
+            match collect() {
+                Ok(rows) => {
+                    debug!(table = N::NAME, rows = rows.len(), took = ?started.elapsed(), "refreshed table");
+                    *latest.write().unwrap_or_else(|e| e.into_inner()) = Some(Snapshot {
+                        rows: Arc::new(rows),
+                        taken: SystemTime::now(),
+                    });
+                }
+                Err(error) => {
+                    warn!(table = N::NAME, %error, "couldn't refresh table, keeping the last rows")
+                }
+            }
+            match stopped.recv_timeout(interval.saturating_sub(started.elapsed())) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        });
+        Self {
+            columns,
+            snapshot,
+            stop: Mutex::new(Some(stop)),
+            _name: PhantomData,
+        }
+    }
+
+    /// When the rows being served were collected, if they have been yet.
+    pub fn last_refresh(&self) -> Option<SystemTime> {
+        self.read().as_ref().map(|s| s.taken)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Option<Snapshot>> {
+        self.snapshot.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<N: TableName> Plugin for RefreshedTable<N> {
+    type Error = Infallible;
+    const NAME: &'static str = N::NAME;
+
+    /// A table with no columns and nothing collecting, use `RefreshedTable::spawn` instead.
+    fn new() -> Self {
+        Self {
+            columns: vec![],
+            snapshot: Arc::default(),
+            stop: Mutex::new(None),
+            _name: PhantomData,
+        }
+    }
+}
+
+impl<N: TableName> TablePlugin for RefreshedTable<N> {
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
+        // clone the Arc and let go of the lock, so a refresh isn't held up by a big copy
+        let rows = self.read().as_ref().map(|s| s.rows.clone());
+        match rows {
+            Some(rows) => Ok(rows.as_ref().clone()),
+            None => {
+                debug!(table = N::NAME, "no rows collected yet");
+                Ok(vec![])
+            }
+        }
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(self.columns.clone())
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {
+        // the collector notices on its next wait, an in-flight collection still finishes
+        self.stop.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+}
@@ -0,0 +1,292 @@
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{Connection, OpenFlags};
+use tracing::debug;
+
+use super::{parse_value, TableName};
+use crate::gen::table::{ColumnType, Operator};
+use crate::{Column, ColumnValue, Plugin, QueryContext, RowSet, TablePlugin, TableRows};
+
+#[derive(Debug, Clone)]
+pub struct SqliteOptions {
+    /// The database file
+    pub path: PathBuf,
+    /// Table (or view) in that database to expose
+    pub table: String,
+    /// Declared schema. Left empty, it comes from the table's own declaration the first
+    /// time it's needed.
+    pub columns: Vec<Column>,
+    /// Open with `immutable=1`, for databases another process holds locked (browser
+    /// profiles, mostly). Reads can be stale or torn if the file is being written.
+    pub immutable: bool,
+}
+
+impl SqliteOptions {
+    pub fn new<P: Into<PathBuf>>(path: P, table: &str) -> Self {
+        Self {
+            path: path.into(),
+            table: table.to_string(),
+            columns: vec![],
+            immutable: false,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SqliteTableError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("`{0}` has no columns, does it exist?")]
+    NoColumns(String),
+}
+
+/// Exposes a table from some other SQLite database. `=`, `<`, `>`, `LIKE` and `GLOB`
+/// constraints are passed down as a `WHERE` clause, so the database's own indexes get used.
+/// The file is opened read-only for each `generate`, so no lock is held between queries.
+#[derive(Debug)]
+pub struct SqliteTable<N> {
+    options: SqliteOptions,
+    columns: Mutex<Vec<Column>>,
+    _name: PhantomData<fn() -> N>,
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+// SQLite's own affinity rules, more or less
+fn column_type(declared: &str) -> ColumnType {
+    let declared = declared.to_uppercase();
+    if declared.contains("INT") {
+        ColumnType::BigInt
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|t| declared.contains(t))
+    {
+        ColumnType::Double
+    } else {
+        ColumnType::Text
+    }
+}
+
+fn to_column_value(kind: ColumnType, value: ValueRef<'_>) -> Option<ColumnValue> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => match kind {
+            ColumnType::Integer => i32::try_from(i).ok().map(ColumnValue::integer),
+            ColumnType::Double => Some(ColumnValue::double(i as f64)),
+            ColumnType::Text | ColumnType::Unknown => Some(ColumnValue::text(i.to_string())),
+            ColumnType::BigInt => Some(ColumnValue::big_int(i)),
+        },
+        ValueRef::Real(f) => parse_value(kind, &f.to_string()),
+        ValueRef::Text(text) => parse_value(kind, &String::from_utf8_lossy(text)),
+        ValueRef::Blob(blob) => {
+            let hex: String = blob.iter().map(|b| format!("{:02x}", b)).collect();
+            Some(ColumnValue::text(hex))
+        }
+    }
+}
+
+// bound with the column's type so comparisons work the same as they would in osquery
+fn to_sql_value(kind: ColumnType, expr: &str) -> SqlValue {
+    match parse_value(kind, expr) {
+        Some(ColumnValue::Integer(i)) => SqlValue::Integer(i.into()),
+        Some(ColumnValue::BigInt(i)) => SqlValue::Integer(i),
+        Some(ColumnValue::Double(f)) => SqlValue::Real(f),
+        _ => SqlValue::Text(expr.to_string()),
+    }
+}
+
+// `path` for the middle of a `file:` URI. SQLite decodes `%XX` in the path and stops it
+// at `?` or `#`, so those three have to be escaped to mean themselves
+fn uri_path(path: &Path) -> String {
+    let mut uri = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '%' => uri.push_str("%25"),
+            '?' => uri.push_str("%3f"),
+            '#' => uri.push_str("%23"),
+            c => uri.push(c),
+        }
+    }
+    uri
+}
+
+impl<N: TableName> SqliteTable<N> {
+    pub fn open(options: SqliteOptions) -> Self {
+        Self {
+            columns: Mutex::new(options.columns.clone()),
+            options,
+            _name: PhantomData,
+        }
+    }
+
+    fn connect(&self) -> Result<Connection, rusqlite::Error> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        if self.options.immutable {
+            let uri = format!("file:{}?immutable=1", uri_path(&self.options.path));
+            Connection::open_with_flags(uri, flags | OpenFlags::SQLITE_OPEN_URI)
+        } else {
+            Connection::open_with_flags(&self.options.path, flags)
+        }
+    }
+
+    fn columns_locked(&self) -> MutexGuard<'_, Vec<Column>> {
+        self.columns.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn schema(&self, conn: &Connection) -> Result<Vec<Column>, SqliteTableError> {
+        let mut columns = self.columns_locked();
+        if columns.is_empty() {
+            let mut statement = conn.prepare("SELECT name, type FROM pragma_table_info(?1)")?;
+            *columns = statement
+                .query_map([&self.options.table], |row| {
+                    let name: String = row.get(0)?;
+                    let declared: String = row.get(1)?;
+                    Ok(Column::new(&name, column_type(&declared)))
+                })?
+                .collect::<Result<_, _>>()?;
+            if columns.is_empty() {
+                return Err(SqliteTableError::NoColumns(self.options.table.clone()));
+            }
+        }
+        Ok(columns.clone())
+    }
+}
+
+/// Build the `SELECT` for a query, with a `WHERE` for whatever constraints SQLite can
+/// evaluate itself. Columns the query doesn't use are selected as `NULL`.
+fn build_query(table: &str, columns: &[Column], query: &QueryContext) -> (String, Vec<SqlValue>) {
+    let selected: Vec<String> = columns
+        .iter()
+        .map(|c| {
+            if query.is_column_used(&c.name) {
+                quote(&c.name)
+            } else {
+                "NULL".to_string()
+            }
+        })
+        .collect();
+    let mut clauses = vec![];
+    let mut params = vec![];
+    for list in &query.constraints {
+        let column = match columns.iter().find(|c| c.name == list.name) {
+            Some(column) => column,
+            None => continue,
+        };
+        for constraint in &list.list {
+            let op = match constraint.op {
+                Operator::Equals
+                | Operator::GreaterThan
+                | Operator::GreaterThanOrEquals
+                | Operator::LessThan
+                | Operator::LessThanOrEquals
+                | Operator::Like
+                | Operator::Glob => constraint.op.as_sql(),
+                // osquery will still filter on these itself
+                _ => continue,
+            };
+            params.push(to_sql_value(column.kind, &constraint.expr));
+            clauses.push(format!("{} {} ?{}", quote(&column.name), op, params.len()));
+        }
+    }
+    let mut sql = format!("SELECT {} FROM {}", selected.join(", "), quote(table));
+    if !clauses.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&clauses.join(" AND "));
+    }
+    (sql, params)
+}
+
+impl<N: TableName> Plugin for SqliteTable<N> {
+    type Error = SqliteTableError;
+    const NAME: &'static str = N::NAME;
+
+    /// A table with no database behind it, use `SqliteTable::open` instead.
+    fn new() -> Self {
+        Self::open(SqliteOptions::new("", N::NAME))
+    }
+}
+
+impl<N: TableName> TablePlugin for SqliteTable<N> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        self.generate_rows(query).map(Into::into)
+    }
+
+    fn generate_rows(&self, query: &QueryContext) -> Result<RowSet, Self::Error> {
+        let conn = self.connect()?;
+        let columns = self.schema(&conn)?;
+        let (sql, params) = build_query(&self.options.table, &columns, query);
+        debug!(table = N::NAME, %sql, "querying sqlite");
+        let mut statement = conn.prepare(&sql)?;
+        let mut rows = statement.query(rusqlite::params_from_iter(params))?;
+        let mut set = RowSet::new(&columns);
+        while let Some(row) = rows.next()? {
+            let values = columns
+                .iter()
+                .enumerate()
+                .map(|(i, c)| row.get_ref(i).ok().and_then(|v| to_column_value(c.kind, v)))
+                .collect::<Vec<_>>();
+            set.push_partial(values)
+                .expect("one value per column by construction");
+            if query.deadline().is_expired() {
+                break;
+            }
+        }
+        Ok(set)
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        let columns = self.columns_locked().clone();
+        if !columns.is_empty() {
+            return Ok(columns);
+        }
+        self.schema(&self.connect()?)
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::table_name!(Hosts = "hosts");
+
+    #[test]
+    fn uri_path_escapes_what_sqlite_would_read_into() {
+        assert_eq!(uri_path(Path::new("/var/db/x.db")), "/var/db/x.db");
+        assert_eq!(
+            uri_path(Path::new("/tmp/100% #1?.db")),
+            "/tmp/100%25 %231%3f.db"
+        );
+    }
+
+    #[test]
+    fn immutable_opens_the_file_named() {
+        let dir = std::env::temp_dir().join(format!("osquery-rs-sqlite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("odd 50%25 #1?mode=memory.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch("CREATE TABLE hosts(name TEXT); INSERT INTO hosts VALUES ('a');")
+                .unwrap();
+        }
+        let mut options = SqliteOptions::new(&path, "hosts");
+        options.immutable = true;
+        let rows = SqliteTable::<Hosts>::open(options).generate(&QueryContext::default());
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let rows = rows.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["name"], ColumnValue::text("a"));
+    }
+}
@@ -0,0 +1,305 @@
+use std::marker::PhantomData;
+use std::path::Path;
+use std::time::Duration;
+
+use tracing::debug;
+
+use super::TableName;
+use crate::gen::table::{ColumnType, Operator};
+use crate::{
+    scheduler, ClientPool, Column, Connector, DefaultTransport, Plugin, QueryContext, TablePlugin,
+    TableRows,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SqlViewError {
+    #[error(transparent)]
+    Thrift(#[from] thrift::Error),
+    #[error("{0}")]
+    Osquery(String),
+    #[error("view isn't connected to osquery, use `SqlViewTable::connect`")]
+    NotConnected,
+}
+
+/// A table whose rows come from running a SQL statement against osquery itself, to
+/// publish a join or aggregate over built-in tables under a name of its own.
+///
+/// Constraints on the view's columns are passed along by wrapping the statement,
+/// `SELECT * FROM (<sql>) WHERE ...`, so osquery can still push them down into the
+/// tables underneath. Values only ever go in as escaped literals (numbers only when they
+/// parse as numbers), so whatever's in a constraint can't change the statement.
+pub struct SqlViewTable<N, C: Connector = DefaultTransport> {
+    sql: String,
+    columns: Vec<Column>,
+    pool: Option<ClientPool<C>>,
+    _name: PhantomData<fn() -> N>,
+}
+
+impl<N, C: Connector> std::fmt::Debug for SqlViewTable<N, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqlViewTable")
+            .field("sql", &self.sql)
+            .field("columns", &self.columns)
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+impl<N: TableName> SqlViewTable<N> {
+    /// Connect to osquery's extension socket and ask it what columns `sql` returns.
+    pub fn connect<P: AsRef<Path>, S: Into<String>>(
+        path: P,
+        timeout: Duration,
+        sql: S,
+    ) -> Result<Self, SqlViewError> {
+        Self::on_pool(ClientPool::new(path, timeout, 4), sql)
+    }
+}
+
+impl<N: TableName, C: Connector> SqlViewTable<N, C> {
+    /// `connect`, on a pool of your own (or another transport).
+    pub fn on_pool<S: Into<String>>(pool: ClientPool<C>, sql: S) -> Result<Self, SqlViewError> {
+        let sql = trim_statement(sql.into());
+        let columns = {
+            let mut client = pool.get()?;
+            scheduler::query_column_types(&mut client, &sql)
+                .map_err(|e| SqlViewError::Osquery(e.to_string()))?
+        };
+        let columns = columns
+            .into_iter()
+            .map(|(name, kind)| Column::new(&name, kind))
+            .collect();
+        Ok(Self::with_columns(pool, sql, columns))
+    }
+
+    /// Skip asking osquery and use these columns, e.g. to describe them.
+    pub fn with_columns<S: Into<String>>(
+        pool: ClientPool<C>,
+        sql: S,
+        columns: Vec<Column>,
+    ) -> Self {
+        Self {
+            sql: trim_statement(sql.into()),
+            columns,
+            pool: Some(pool),
+            _name: PhantomData,
+        }
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// The statement `generate` runs for `query`.
+    pub fn statement(&self, query: &QueryContext) -> String {
+        let mut filters = vec![];
+        for column in &self.columns {
+            let mut equals = vec![];
+            for c in query.constraints_on(&column.name) {
+                match &c.op {
+                    // an IN list shows up as one `=` per value
+                    Operator::Equals => equals.push(literal(column.kind, &c.expr)),
+                    Operator::Match | Operator::Regexp | Operator::Unique => {}
+                    op => filters.push(format!(
+                        "{} {} {}",
+                        identifier(&column.name),
+                        op.as_sql(),
+                        literal(column.kind, &c.expr)
+                    )),
+                }
+            }
+            match equals.len() {
+                0 => {}
+                1 => filters.push(format!("{} = {}", identifier(&column.name), equals[0])),
+                _ => filters.push(format!(
+                    "{} IN ({})",
+                    identifier(&column.name),
+                    equals.join(", ")
+                )),
+            }
+        }
+        if filters.is_empty() {
+            return self.sql.clone();
+        }
+        format!(
+            "SELECT * FROM ({}) WHERE {}",
+            self.sql,
+            filters.join(" AND ")
+        )
+    }
+}
+
+// the statement without the trailing `;` it'd usually be written with, which would be a
+// syntax error once it's wrapped in `SELECT * FROM (...)`
+fn trim_statement(sql: String) -> String {
+    let trimmed = sql.trim_end_matches(|c: char| c == ';' || c.is_whitespace());
+    if trimmed.len() == sql.len() {
+        sql
+    } else {
+        trimmed.to_string()
+    }
+}
+
+// a constraint value as a SQL literal that can't be anything but a value
+fn literal(kind: ColumnType, value: &str) -> String {
+    let numeric = match kind {
+        ColumnType::Integer | ColumnType::BigInt => value.trim().parse::<i64>().is_ok(),
+        ColumnType::Double => value.trim().parse::<f64>().is_ok_and(f64::is_finite),
+        ColumnType::Text | ColumnType::Unknown => false,
+    };
+    if numeric {
+        value.trim().to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
+
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl<N: TableName, C: Connector> Plugin for SqlViewTable<N, C> {
+    type Error = SqlViewError;
+    const NAME: &'static str = N::NAME;
+
+    /// A view of nothing, use `SqlViewTable::connect` instead.
+    fn new() -> Self {
+        Self {
+            sql: String::new(),
+            columns: vec![],
+            pool: None,
+            _name: PhantomData,
+        }
+    }
+}
+
+impl<N: TableName, C: Connector> TablePlugin for SqlViewTable<N, C> {
+    fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+        let pool = self.pool.as_ref().ok_or(SqlViewError::NotConnected)?;
+        let statement = self.statement(query);
+        debug!(table = N::NAME, %statement, "running view");
+        let response = pool.query(&statement)?;
+        let status = response.status.unwrap_or_default();
+        if !status.is_success() {
+            return Err(SqlViewError::Osquery(format!(
+                "osquery couldn't run the view: {}",
+                status.message.unwrap_or_default()
+            )));
+        }
+        Ok(response
+            .response
+            .unwrap_or_default()
+            .into_iter()
+            .map(|row| {
+                self.columns
+                    .iter()
+                    .filter_map(|c| {
+                        let value = super::parse_value(c.kind, row.get(&c.name)?)?;
+                        Some((c.name.clone(), value))
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(self.columns.clone())
+    }
+
+    fn aliases(&self) -> Vec<String> {
+        super::aliases::<N>()
+    }
+
+    fn shutdown(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::Loopback;
+
+    struct Listening;
+
+    impl TableName for Listening {
+        const NAME: &'static str = "listening";
+    }
+
+    fn view(sql: &str) -> SqlViewTable<Listening, Loopback> {
+        let columns = vec![
+            Column::new("path", ColumnType::Text),
+            Column::new("port", ColumnType::Integer),
+            Column::new("odd \"name\"", ColumnType::Text),
+        ];
+        SqlViewTable::with_columns(
+            ClientPool::via("sql-view-test", Duration::from_secs(1), 1),
+            sql,
+            columns,
+        )
+    }
+
+    #[test]
+    fn trailing_semicolons_are_dropped() {
+        let view = view("SELECT path, port FROM listening_ports ; \n");
+        assert_eq!(view.sql(), "SELECT path, port FROM listening_ports");
+        let query = QueryContext::builder().equals("port", 22).build();
+        assert_eq!(
+            view.statement(&query),
+            "SELECT * FROM (SELECT path, port FROM listening_ports) WHERE \"port\" = 22"
+        );
+    }
+
+    #[test]
+    fn no_constraints_runs_the_statement_as_is() {
+        let view = view("SELECT 1;");
+        assert_eq!(view.statement(&QueryContext::default()), "SELECT 1");
+    }
+
+    #[test]
+    fn quotes_in_text_are_escaped() {
+        let query = QueryContext::builder()
+            .equals("path", "/tmp/x' OR '1'='1")
+            .build();
+        assert_eq!(
+            view("SELECT 1").statement(&query),
+            "SELECT * FROM (SELECT 1) WHERE \"path\" = '/tmp/x'' OR ''1''=''1'"
+        );
+    }
+
+    #[test]
+    fn non_numbers_on_integer_columns_are_text() {
+        let query = QueryContext::builder()
+            .greater_than("port", "1 OR 1=1")
+            .build();
+        assert_eq!(
+            view("SELECT 1").statement(&query),
+            "SELECT * FROM (SELECT 1) WHERE \"port\" > '1 OR 1=1'"
+        );
+        assert_eq!(literal(ColumnType::Integer, " 80 "), "80");
+        assert_eq!(literal(ColumnType::Double, "NaN"), "'NaN'");
+        assert_eq!(literal(ColumnType::Double, "1.5"), "1.5");
+    }
+
+    #[test]
+    fn identifiers_are_quoted() {
+        assert_eq!(identifier("port"), "\"port\"");
+        let query = QueryContext::builder().equals("odd \"name\"", "x").build();
+        assert_eq!(
+            view("SELECT 1").statement(&query),
+            "SELECT * FROM (SELECT 1) WHERE \"odd \"\"name\"\"\" = 'x'"
+        );
+    }
+
+    #[test]
+    fn several_equals_are_an_in_list() {
+        let query = QueryContext::builder()
+            .equals("port", 22)
+            .equals("port", 443)
+            .equals("port", "http")
+            .less_than("path", "/z")
+            .build();
+        assert_eq!(
+            view("SELECT 1").statement(&query),
+            "SELECT * FROM (SELECT 1) WHERE \"path\" < '/z' AND \"port\" IN (22, 443, 'http')"
+        );
+    }
+}
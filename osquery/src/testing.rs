@@ -0,0 +1,749 @@
+// Helpers for testing extensions.
+//
+// `qc!` writes a `QueryContext` as the WHERE clause it stands for.
+//
+// `assert_rows_eq!` and `assert_rows_contain!` compare rows the way osquery would see
+// them, so `ColumnValue::integer(1)`, `"1"` and `1.0` are all the same value, and say which
+// columns of which rows are off instead of dumping both sides.
+//
+// `Snapshots` runs a table against named query contexts and compares what osquery would
+// get back with snapshots stored next to the tests, to catch schema and behavior changes.
+//
+// `spawn_osqueryi` starts `osqueryi` with extensions turned on and its manager socket in a
+// fresh temporary directory, and hands back a connected `Client`. Dropping the `Osqueryi`
+// kills it and cleans up. The binary is `$OSQUERYI` if that's set, otherwise `osqueryi`
+// from the PATH.
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::diff::{Diff, Row};
+use crate::{Client, ColumnValue, ConnectError, PluginHandler, QueryContext, RowSet, TablePlugin};
+
+/// A `QueryContext` for a `WHERE` clause, for tests:
+///
+/// ```ignore
+/// let query = qc!(path = "/etc/passwd" AND size > 10 AND name LIKE "%.so" AND uid IN (0, 1));
+/// assert!(query.has_equals("path"));
+/// ```
+///
+/// Operators are `=`, `>`, `>=`, `<`, `<=`, `LIKE`, `GLOB`, `REGEXP` and `IN (...)`.
+/// Values are single tokens, so a negative number or any other expression needs
+/// parentheses: `offset > (-1)`.
+#[macro_export]
+macro_rules! qc {
+    () => { $crate::QueryContext::default() };
+    (@ $b:expr;) => { $b.build() };
+    (@ $b:expr; AND $($rest:tt)*) => { $crate::qc!(@ $b; $($rest)*) };
+    (@ $b:expr; $col:ident = $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.equals(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident > $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.greater_than(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident >= $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.greater_than_or_equals(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident < $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.less_than(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident <= $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.less_than_or_equals(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident LIKE $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.like(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident GLOB $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.glob(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident REGEXP $v:tt $($rest:tt)*) => {
+        $crate::qc!(@ $b.regexp(stringify!($col), $v); $($rest)*)
+    };
+    (@ $b:expr; $col:ident IN ($($v:tt),+ $(,)?) $($rest:tt)*) => {
+        $crate::qc!(@ $b$(.equals(stringify!($col), $v))+; $($rest)*)
+    };
+    ($($clause:tt)+) => { $crate::qc!(@ $crate::QueryContext::builder(); $($clause)+) };
+}
+
+/// Fail unless `actual` and `expected` have the same rows in the same order, ignoring
+/// column order and comparing numbers by value. Either side can be `TableRows`, rows of
+/// strings (like a query response), or a `RowSet`.
+#[macro_export]
+macro_rules! assert_rows_eq {
+    ($actual:expr, $expected:expr $(,)?) => {
+        if let Err(diff) = $crate::testing::rows_eq(&$actual, &$expected) {
+            panic!("rows differ:\n{}", diff);
+        }
+    };
+}
+
+/// Fail unless every row in `expected` matches some row of `actual`, on the columns the
+/// expected row has.
+#[macro_export]
+macro_rules! assert_rows_contain {
+    ($actual:expr, $expected:expr $(,)?) => {
+        if let Err(diff) = $crate::testing::rows_contain(&$actual, &$expected) {
+            panic!("rows missing:\n{}", diff);
+        }
+    };
+}
+
+/// Rows that `assert_rows_eq!` and friends can compare.
+pub trait ToRows {
+    fn to_rows(&self) -> Vec<Row>;
+}
+
+/// A value in a row, as osquery would get it.
+pub trait ToCell {
+    fn to_cell(&self) -> String;
+}
+
+impl ToCell for ColumnValue {
+    fn to_cell(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ToCell for String {
+    fn to_cell(&self) -> String {
+        self.clone()
+    }
+}
+
+impl ToCell for &str {
+    fn to_cell(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<K: AsRef<str>, V: ToCell> ToRows for [BTreeMap<K, V>] {
+    fn to_rows(&self) -> Vec<Row> {
+        self.iter()
+            .map(|row| {
+                row.iter()
+                    .map(|(k, v)| (k.as_ref().to_string(), v.to_cell()))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl<K: AsRef<str>, V: ToCell> ToRows for Vec<BTreeMap<K, V>> {
+    fn to_rows(&self) -> Vec<Row> {
+        self.as_slice().to_rows()
+    }
+}
+
+impl<K: AsRef<str>, V: ToCell, const N: usize> ToRows for [BTreeMap<K, V>; N] {
+    fn to_rows(&self) -> Vec<Row> {
+        self[..].to_rows()
+    }
+}
+
+impl ToRows for RowSet {
+    fn to_rows(&self) -> Vec<Row> {
+        self.clone().into_response()
+    }
+}
+
+// "1", "1.0" and "1e0" are the same to SQLite, so the same here
+fn cells_match(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// how `actual` differs from `expected`, one line per column. With `partial`, columns
+// `expected` doesn't mention don't count.
+fn row_diff(actual: &Row, expected: &Row, partial: bool) -> Vec<String> {
+    let mut diffs = vec![];
+    for (column, want) in expected {
+        match actual.get(column) {
+            Some(got) if cells_match(got, want) => {}
+            Some(got) => diffs.push(format!("  {}: {:?} != {:?} (expected)", column, got, want)),
+            None => diffs.push(format!("  {}: missing, expected {:?}", column, want)),
+        }
+    }
+    if !partial {
+        for (column, got) in actual {
+            if !expected.contains_key(column) {
+                diffs.push(format!("  {}: {:?} wasn't expected", column, got));
+            }
+        }
+    }
+    diffs
+}
+
+/// `assert_rows_eq!` without the panic: what's different, if anything.
+pub fn rows_eq<A, E>(actual: &A, expected: &E) -> Result<(), String>
+where
+    A: ToRows + ?Sized,
+    E: ToRows + ?Sized,
+{
+    let (actual, expected) = (actual.to_rows(), expected.to_rows());
+    let mut lines = vec![];
+    if actual.len() != expected.len() {
+        lines.push(format!(
+            "{} rows, expected {}",
+            actual.len(),
+            expected.len()
+        ));
+    }
+    for (i, (got, want)) in actual.iter().zip(&expected).enumerate() {
+        let diffs = row_diff(got, want, false);
+        if !diffs.is_empty() {
+            lines.push(format!("row {}:", i));
+            lines.extend(diffs);
+        }
+    }
+    for (i, row) in actual.iter().enumerate().skip(expected.len()) {
+        lines.push(format!("row {}: extra {:?}", i, row));
+    }
+    for (i, row) in expected.iter().enumerate().skip(actual.len()) {
+        lines.push(format!("row {}: missing {:?}", i, row));
+    }
+    if lines.is_empty() {
+        Ok(())
+    } else {
+        Err(lines.join("\n"))
+    }
+}
+
+/// `assert_rows_contain!` without the panic: the expected rows nothing matched, each with
+/// how the closest actual row differs.
+pub fn rows_contain<A, E>(actual: &A, expected: &E) -> Result<(), String>
+where
+    A: ToRows + ?Sized,
+    E: ToRows + ?Sized,
+{
+    let actual = actual.to_rows();
+    let mut lines = vec![];
+    for want in expected.to_rows() {
+        let closest = actual
+            .iter()
+            .enumerate()
+            .map(|(i, got)| (i, row_diff(got, &want, true)))
+            .min_by_key(|(_, diffs)| diffs.len());
+        match closest {
+            Some((_, diffs)) if diffs.is_empty() => {}
+            Some((i, diffs)) => {
+                lines.push(format!("{:?}, closest is row {}:", want, i));
+                lines.extend(diffs);
+            }
+            None => lines.push(format!("{:?}, there are no rows", want)),
+        }
+    }
+    if lines.is_empty() {
+        Ok(())
+    } else {
+        Err(lines.join("\n"))
+    }
+}
+
+/// How long to give osqueryi to come up, unless told otherwise
+pub const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+// how often to look for the manager socket while osqueryi starts
+const SOCKET_POLL: Duration = Duration::from_millis(50);
+
+// tells apart the sockets of several osqueryis spawned by the same test binary
+static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(thiserror::Error, Debug)]
+pub enum SpawnError {
+    #[error("couldn't run {binary:?}: {source}")]
+    Spawn { binary: OsString, source: io::Error },
+    #[error("osqueryi exited ({status}) before its socket came up: {stderr}")]
+    Exited { status: ExitStatus, stderr: String },
+    #[error("osqueryi's socket {0:?} didn't come up in time")]
+    TimedOut(PathBuf),
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// How to start osqueryi, for when `spawn_osqueryi`'s defaults don't do.
+#[derive(Debug, Clone)]
+pub struct Spawn {
+    binary: OsString,
+    timeout: Duration,
+    args: Vec<OsString>,
+}
+
+impl Default for Spawn {
+    fn default() -> Self {
+        Self {
+            binary: std::env::var_os("OSQUERYI").unwrap_or_else(|| "osqueryi".into()),
+            timeout: DEFAULT_STARTUP_TIMEOUT,
+            args: vec![],
+        }
+    }
+}
+
+impl Spawn {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn binary<S: Into<OsString>>(mut self, binary: S) -> Self {
+        self.binary = binary.into();
+        self
+    }
+
+    /// How long osqueryi gets to start listening, and the client's call timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// An extra flag for osqueryi, e.g. `--verbose`
+    pub fn arg<S: Into<OsString>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn spawn(self) -> Result<Osqueryi, SpawnError> {
+        let dir = std::env::temp_dir().join(format!(
+            "osquery-rs-{}-{}",
+            std::process::id(),
+            SPAWNED.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let socket = dir.join("osquery.em");
+        let mut extensions_socket = OsString::from("--extensions_socket=");
+        extensions_socket.push(&socket);
+        let child = Command::new(&self.binary)
+            .arg("--nodisable_extensions")
+            .arg(extensions_socket)
+            .arg("--extensions_interval=1")
+            .arg(format!(
+                "--extensions_timeout={}",
+                self.timeout.as_secs().max(1)
+            ))
+            .args(&self.args)
+            // osqueryi's a shell, and quits when its input does
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(source) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(SpawnError::Spawn {
+                    binary: self.binary,
+                    source,
+                });
+            }
+        };
+        debug!(pid = child.id(), ?socket, "spawned osqueryi");
+        let mut osqueryi = Osqueryi {
+            stdin: None,
+            child,
+            dir,
+            socket,
+            client: None,
+        };
+        osqueryi.stdin = osqueryi.child.stdin.take();
+        let client = osqueryi.connect(Instant::now() + self.timeout)?;
+        osqueryi.client = Some(client);
+        Ok(osqueryi)
+    }
+}
+
+/// A running osqueryi, killed when this is dropped.
+pub struct Osqueryi {
+    // held open so osqueryi doesn't see end of input and quit
+    stdin: Option<ChildStdin>,
+    child: Child,
+    dir: PathBuf,
+    socket: PathBuf,
+    client: Option<Client>,
+}
+
+impl fmt::Debug for Osqueryi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Osqueryi")
+            .field("pid", &self.child.id())
+            .field("socket", &self.socket)
+            .finish()
+    }
+}
+
+impl Osqueryi {
+    fn connect(&mut self, deadline: Instant) -> Result<Client, SpawnError> {
+        while !self.socket.exists() {
+            if let Some(status) = self.child.try_wait()? {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = self.child.stderr.take() {
+                    let _ = pipe.read_to_string(&mut stderr);
+                }
+                return Err(SpawnError::Exited {
+                    status,
+                    stderr: stderr.trim().to_string(),
+                });
+            }
+            if Instant::now() >= deadline {
+                return Err(SpawnError::TimedOut(self.socket.clone()));
+            }
+            std::thread::sleep(SOCKET_POLL);
+        }
+        Ok(Client::connect_deadline(&self.socket, deadline)?)
+    }
+
+    /// The extension manager socket, for registering plugins against.
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// A client already connected to osqueryi.
+    pub fn client(&mut self) -> &mut Client {
+        self.client
+            .as_mut()
+            .expect("spawn always connects a client")
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+}
+
+impl Drop for Osqueryi {
+    fn drop(&mut self) {
+        self.client = None;
+        self.stdin = None;
+        if let Err(error) = self.child.kill() {
+            // already gone is fine, anything else is worth hearing about
+            if error.kind() != io::ErrorKind::InvalidInput {
+                warn!(%error, pid = self.child.id(), "couldn't kill osqueryi");
+            }
+        }
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Start osqueryi with extensions enabled on a temporary socket and connect to it. It's
+/// killed, and the socket cleaned up, when the `Osqueryi` is dropped.
+pub fn spawn_osqueryi() -> Result<Osqueryi, SpawnError> {
+    Spawn::new().spawn()
+}
+
+/// Set (to anything) to rewrite snapshots with whatever the tables return now
+pub const UPDATE_SNAPSHOTS: &str = "OSQUERY_UPDATE_SNAPSHOTS";
+// what a redacted value is replaced with
+const REDACTED: &str = "[redacted]";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error("{}", .0.join("\n\n"))]
+    Mismatched(Vec<String>),
+    #[error("fixture `{fixture}`: generate failed: {message}")]
+    Generate { fixture: String, message: String },
+    #[error("{path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{path:?}: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+// what's stored for each fixture: the schema and the rows as osquery would see them
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Snapshot {
+    columns: Vec<SnapshotColumn>,
+    rows: Vec<Row>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SnapshotColumn {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+type Redaction = Box<dyn Fn(&str) -> String>;
+
+/// Golden-output tests for a table. Each fixture is a named `QueryContext`; its rows go
+/// through the same dispatch osquery's calls do, volatile columns are redacted, and the
+/// result is compared with `<dir>/<table>.<fixture>.json`. Row order doesn't matter.
+///
+/// A missing snapshot is written and passes, unless `CI` is set. With
+/// `OSQUERY_UPDATE_SNAPSHOTS` set every snapshot is rewritten instead.
+pub struct Snapshots<'a, T> {
+    table: &'a T,
+    dir: PathBuf,
+    fixtures: Vec<(String, QueryContext)>,
+    redactions: BTreeMap<String, Redaction>,
+}
+
+impl<'a, T: TablePlugin + PluginHandler> Snapshots<'a, T> {
+    pub fn new<P: AsRef<Path>>(table: &'a T, dir: P) -> Self {
+        Self {
+            table,
+            dir: dir.as_ref().into(),
+            fixtures: vec![],
+            redactions: BTreeMap::new(),
+        }
+    }
+
+    pub fn fixture<S: Into<String>>(mut self, name: S, query: QueryContext) -> Self {
+        self.fixtures.push((name.into(), query));
+        self
+    }
+
+    /// Store `column` as `[redacted]`, for values that change from run to run.
+    pub fn redact<S: Into<String>>(self, column: S) -> Self {
+        self.redact_with(column, |_| REDACTED.to_string())
+    }
+
+    /// Store `column` as `redact` of its value, e.g. to keep a timestamp's shape but not
+    /// its value.
+    pub fn redact_with<S, F>(mut self, column: S, redact: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn(&str) -> String + 'static,
+    {
+        self.redactions.insert(column.into(), Box::new(redact));
+        self
+    }
+
+    fn take(&self, fixture: &str, query: &QueryContext) -> Result<Snapshot, SnapshotError> {
+        let columns = self
+            .table
+            .columns()
+            .map_err(|e| SnapshotError::Generate {
+                fixture: fixture.into(),
+                message: e.to_string(),
+            })?
+            .iter()
+            .map(|c| SnapshotColumn {
+                name: c.name.clone(),
+                kind: c.kind.to_string(),
+            })
+            .collect();
+        let request = maplit::btreemap! {
+            "action".to_string() => "generate".to_string(),
+            "context".to_string() => serde_json::to_string(query).unwrap_or_default(),
+        };
+        let failed = |message: String| SnapshotError::Generate {
+            fixture: fixture.into(),
+            message,
+        };
+        let response = self
+            .table
+            .handle_call(T::REGISTRY.into(), T::NAME.into(), request)
+            .map_err(|e| failed(e.to_string()))?;
+        if !response.is_success() {
+            let status = response.status.unwrap_or_default();
+            return Err(failed(status.message.unwrap_or_default()));
+        }
+        let mut rows = response.response.unwrap_or_default();
+        for row in &mut rows {
+            for (column, redact) in &self.redactions {
+                if let Some(value) = row.get_mut(column) {
+                    *value = redact(value);
+                }
+            }
+        }
+        rows.sort();
+        Ok(Snapshot { columns, rows })
+    }
+
+    /// Run every fixture and compare, reporting all the ones that don't match.
+    pub fn check(&self) -> Result<(), SnapshotError> {
+        let update = std::env::var_os(UPDATE_SNAPSHOTS).is_some();
+        let mut mismatches = vec![];
+        for (fixture, query) in &self.fixtures {
+            let path = self.dir.join(format!("{}.{}.json", T::NAME, fixture));
+            let actual = self.take(fixture, query)?;
+            let stored = match fs::read_to_string(&path) {
+                Ok(_) if update => None,
+                Ok(stored) => {
+                    Some(serde_json::from_str::<Snapshot>(&stored).map_err(|source| {
+                        SnapshotError::Json {
+                            path: path.clone(),
+                            source,
+                        }
+                    })?)
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    if std::env::var_os("CI").is_some() && !update {
+                        mismatches.push(format!("{:?} is missing", path));
+                        continue;
+                    }
+                    None
+                }
+                Err(source) => return Err(SnapshotError::Io { path, source }),
+            };
+            match stored {
+                Some(stored) => {
+                    if let Some(mismatch) = compare(&stored, &actual) {
+                        mismatches.push(format!("{:?} doesn't match:\n{}", path, mismatch));
+                    }
+                }
+                None => {
+                    let io = |source| SnapshotError::Io {
+                        path: path.clone(),
+                        source,
+                    };
+                    fs::create_dir_all(&self.dir).map_err(io)?;
+                    let json = serde_json::to_string_pretty(&actual).unwrap_or_default();
+                    fs::write(&path, json + "\n").map_err(io)?;
+                    info!(?path, "wrote snapshot");
+                }
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(SnapshotError::Mismatched(mismatches))
+        }
+    }
+
+    /// `check`, panicking with the differences, for use in `#[test]`s.
+    pub fn assert(&self) {
+        if let Err(e) = self.check() {
+            panic!("{}", e);
+        }
+    }
+}
+
+// what changed between the stored snapshot and this run, if anything
+fn compare(stored: &Snapshot, actual: &Snapshot) -> Option<String> {
+    let mut lines = vec![];
+    if stored.columns != actual.columns {
+        lines.push(format!("  columns were {:?}", stored.columns));
+        lines.push(format!("  columns are  {:?}", actual.columns));
+    }
+    let diff = Diff::between(&stored.rows, &actual.rows);
+    for row in &diff.removed {
+        lines.push(format!("- {:?}", row));
+    }
+    for row in &diff.added {
+        lines.push(format!("+ {:?}", row));
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Column, Plugin, TableRows};
+    use maplit::btreemap;
+
+    #[derive(Debug)]
+    struct Files;
+
+    impl Plugin for Files {
+        type Error = std::convert::Infallible;
+        const NAME: &'static str = "files";
+
+        fn new() -> Self {
+            Self
+        }
+    }
+
+    impl TablePlugin for Files {
+        fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+            Ok(vec![Column::text("path"), Column::integer("size")])
+        }
+
+        fn generate(&self, query: &QueryContext) -> Result<TableRows, Self::Error> {
+            Ok(query
+                .equals_set("path")
+                .into_iter()
+                .map(|path| {
+                    btreemap! {
+                        "size".to_string() => ColumnValue::integer(path.len() as i32),
+                        "path".to_string() => ColumnValue::text(path),
+                    }
+                })
+                .collect())
+        }
+
+        fn shutdown(&self) {}
+    }
+
+    #[test]
+    fn qc_reads_like_the_where_clause() {
+        let query = qc!(path = "/etc/passwd" AND size > 10 AND uid IN (0, 1));
+        assert!(query.has_equals("path"));
+        assert!(!query.has_equals("size"));
+        let uids = query.equals_set("uid");
+        assert_eq!(uids.len(), 2);
+        assert!(uids.contains("0") && uids.contains("1"));
+    }
+
+    #[test]
+    fn rows_compare_numbers_by_value() {
+        let rows = Files.generate(&qc!(path = "/etc")).unwrap();
+        assert_rows_eq!(rows, [btreemap! { "path" => "/etc", "size" => "4.0" }]);
+        assert_rows_contain!(rows, [btreemap! { "size" => "4" }]);
+
+        let diff = rows_eq(&rows, &[btreemap! { "path" => "/etc", "size" => "5" }]).unwrap_err();
+        assert!(diff.contains("size"), "{}", diff);
+        assert!(!diff.contains("path"), "{}", diff);
+        assert!(rows_contain(&rows, &[btreemap! { "path" => "/usr" }]).is_err());
+    }
+
+    #[test]
+    fn snapshots_catch_changed_rows() {
+        let dir = std::env::temp_dir().join(format!("osquery-rs-snapshots-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stored = |size: &str| {
+            let snapshot = Snapshot {
+                columns: vec![
+                    SnapshotColumn {
+                        name: "path".into(),
+                        kind: "TEXT".into(),
+                    },
+                    SnapshotColumn {
+                        name: "size".into(),
+                        kind: "INTEGER".into(),
+                    },
+                ],
+                rows: vec![btreemap! {
+                    "path".to_string() => "/etc".to_string(),
+                    "size".to_string() => size.to_string(),
+                }],
+            };
+            let json = serde_json::to_string_pretty(&snapshot).unwrap();
+            fs::write(dir.join("files.etc.json"), json).unwrap();
+        };
+        let snapshots = Snapshots::new(&Files, &dir).fixture("etc", qc!(path = "/etc"));
+
+        stored("4");
+        let matched = snapshots.check();
+        stored("5");
+        let changed = snapshots.check();
+        let _ = fs::remove_dir_all(&dir);
+
+        matched.unwrap();
+        match changed {
+            Err(SnapshotError::Mismatched(mismatches)) => {
+                assert_eq!(mismatches.len(), 1);
+                assert!(mismatches[0].contains("+ "), "{}", mismatches[0]);
+            }
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+    }
+}
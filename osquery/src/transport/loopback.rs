@@ -0,0 +1,238 @@
+// An in-process transport: `bind` and `connect` meet in a table of paths kept in memory
+// instead of on the filesystem, and bytes go through buffers instead of the kernel. It's
+// for measuring and testing everything above the socket (generating, stringifying, thrift)
+// without the socket's noise, e.g. `Handle::<_, Loopback>::on_transport("bench", table)`
+// called by an `ExtensionSyncClient` on `Loopback::connect("bench")` (see benches/rows.rs).
+// osquery can't see it.
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{Connector, Listener, Stream};
+use crate::server::ServerOptions;
+
+// bound paths, with which listener bound them so a stale one doesn't unbind a newer one
+static LISTENERS: Mutex<BTreeMap<PathBuf, (u64, Sender<LoopbackStream>)>> =
+    Mutex::new(BTreeMap::new());
+static NEXT_LISTENER: AtomicU64 = AtomicU64::new(0);
+
+/// Connections within the process, addressed by any path. Benchmarks and tests only.
+#[derive(Debug)]
+pub struct Loopback;
+
+impl Connector for Loopback {
+    type Stream = LoopbackStream;
+    type Listener = LoopbackListener;
+
+    fn connect(path: &Path) -> io::Result<LoopbackStream> {
+        let listeners = LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+        let (_, incoming) = listeners.get(path).ok_or_else(|| refused(path))?;
+        let (ours, theirs) = LoopbackStream::pair();
+        incoming.send(theirs).map_err(|_| refused(path))?;
+        Ok(ours)
+    }
+
+    // nothing to wait on, it connects or it doesn't
+    fn connect_timeout(path: &Path, _timeout: Duration) -> io::Result<LoopbackStream> {
+        Self::connect(path)
+    }
+}
+
+fn refused(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::ConnectionRefused,
+        format!("nothing listening at {:?}", path),
+    )
+}
+
+/// The server half of `Loopback`. The path is free again once it's dropped.
+pub struct LoopbackListener {
+    path: PathBuf,
+    id: u64,
+    incoming: Receiver<LoopbackStream>,
+}
+
+impl fmt::Debug for LoopbackListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoopbackListener")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl Listener for LoopbackListener {
+    type Stream = LoopbackStream;
+
+    fn bind(path: &Path, _options: &ServerOptions) -> io::Result<Self> {
+        let mut listeners = LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+        if listeners.contains_key(path) {
+            return Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("{:?} is already bound", path),
+            ));
+        }
+        let id = NEXT_LISTENER.fetch_add(1, Ordering::Relaxed);
+        let (sender, incoming) = mpsc::channel();
+        listeners.insert(path.to_path_buf(), (id, sender));
+        Ok(Self {
+            path: path.to_path_buf(),
+            id,
+            incoming,
+        })
+    }
+
+    fn accept(&self) -> io::Result<LoopbackStream> {
+        // the sender lives in LISTENERS until this is dropped
+        self.incoming
+            .recv()
+            .map_err(|_| io::Error::new(ErrorKind::NotConnected, "listener was unbound"))
+    }
+}
+
+impl Drop for LoopbackListener {
+    fn drop(&mut self) {
+        let mut listeners = LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+        if listeners.get(&self.path).map(|(id, _)| *id) == Some(self.id) {
+            listeners.remove(&self.path);
+        }
+    }
+}
+
+// bytes going one way. closed once either end of the connection is dropped
+#[derive(Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        self.readable.notify_all();
+    }
+}
+
+// one end, shared by every handle `try_clone` makes, like a socket's fd
+struct End {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        // the other end reads what's left then EOF, and can't write any more
+        self.outgoing.close();
+        self.incoming.close();
+    }
+}
+
+/// One end of a `Loopback` connection. Writes never block, they're buffered until the
+/// other end reads them.
+#[derive(Clone)]
+pub struct LoopbackStream {
+    end: Arc<End>,
+}
+
+impl LoopbackStream {
+    /// Two ends of a new connection, not going through any listener.
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+        (Self::new(a.clone(), b.clone()), Self::new(b, a))
+    }
+
+    fn new(incoming: Arc<Pipe>, outgoing: Arc<Pipe>) -> Self {
+        let end = End {
+            incoming,
+            outgoing,
+            read_timeout: Mutex::new(None),
+        };
+        Self { end: Arc::new(end) }
+    }
+}
+
+impl fmt::Debug for LoopbackStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LoopbackStream({:p})", Arc::as_ptr(&self.end))
+    }
+}
+
+impl Read for LoopbackStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let timeout = *self
+            .end
+            .read_timeout
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let pipe = &self.end.incoming;
+        let mut state = pipe.state.lock().unwrap_or_else(|e| e.into_inner());
+        while state.bytes.is_empty() && !state.closed {
+            state = match deadline {
+                None => pipe.readable.wait(state).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(io::Error::new(ErrorKind::TimedOut, "read timed out"));
+                    }
+                    pipe.readable
+                        .wait_timeout(state, left)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+        state.bytes.read(buf)
+    }
+}
+
+impl Write for LoopbackStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pipe = &self.end.outgoing;
+        let mut state = pipe.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.closed {
+            return Err(io::Error::new(ErrorKind::BrokenPipe, "other end is closed"));
+        }
+        state.bytes.extend(buf);
+        pipe.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for LoopbackStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self
+            .end
+            .read_timeout
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = timeout;
+        Ok(())
+    }
+
+    // writes don't wait for anything
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
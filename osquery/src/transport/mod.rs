@@ -0,0 +1,207 @@
+// How the client reaches osquery and how osquery reaches the extension's server.
+// osquery speaks thrift over unix sockets (named pipes on windows), so that's the
+// default, but nothing above this module cares what the bytes travel over.
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::server::{PeerCredentials, ServerOptions};
+use crate::ExtensionRouteUUID;
+
+mod loopback;
+#[cfg(unix)]
+mod unix;
+
+pub use self::loopback::{Loopback, LoopbackListener, LoopbackStream};
+
+#[cfg(unix)]
+pub use self::unix::Unix;
+
+#[cfg(feature = "tcp-transport")]
+mod tcp;
+#[cfg(feature = "tls-transport")]
+pub mod tls;
+
+#[cfg(feature = "tcp-transport")]
+pub use self::tcp::Tcp;
+#[cfg(feature = "tls-transport")]
+pub use self::tls::{Tls, TlsListener, TlsStream};
+
+/// One connection, either end.
+pub trait Stream: Read + Write + Debug + Send + Sized + 'static {
+    /// Another handle to the same connection, so reads and writes can be split
+    fn try_clone(&self) -> io::Result<Self>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_timeouts(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+    /// Who's on the other end, if the transport can tell
+    fn peer_credentials(&self) -> io::Result<Option<PeerCredentials>> {
+        Ok(None)
+    }
+}
+
+/// Wakes a thread blocked in `Listener::accept`.
+pub type Waker = Box<dyn Fn() + Send + Sync>;
+
+/// The server half of a transport.
+pub trait Listener: Send + Sized + 'static {
+    type Stream: Stream;
+    /// Start listening at `path`, applying whichever socket options make sense here.
+    fn bind(path: &Path, options: &ServerOptions) -> io::Result<Self>;
+    fn accept(&self) -> io::Result<Self::Stream>;
+    /// A way to wake `accept` for stopping the server, after which the listener is done
+    /// with. It mustn't need to connect: after `RunAs::apply` the server may not be
+    /// allowed on its own socket. `None` if the transport has no other way, and the
+    /// server connects to itself instead.
+    fn waker(&self) -> io::Result<Option<Waker>> {
+        Ok(None)
+    }
+}
+
+// shutting a listening socket down fails the `accept` it's blocked in, on linux. done on a
+// dup, so it can't hit some other socket that's reused the fd once the listener's gone
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn shutdown_waker(fd: std::os::unix::io::RawFd) -> io::Result<Option<Waker>> {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    // safe: dup of an fd the caller's holding open
+    let dup = unsafe { libc::dup(fd) };
+    if dup < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // safe: dup is ours alone
+    let dup = unsafe { OwnedFd::from_raw_fd(dup) };
+    Ok(Some(Box::new(move || {
+        // safe: dup stays open as long as the closure does
+        unsafe { libc::shutdown(dup.as_raw_fd(), libc::SHUT_RD) };
+    })))
+}
+
+/// A transport, named for its client half. `Client` and `Handle` are generic over it,
+/// `Listener` is the matching server half.
+pub trait Connector: Debug + Send + Sync + 'static {
+    type Stream: Stream;
+    type Listener: Listener<Stream = Self::Stream>;
+    fn connect(path: &Path) -> io::Result<Self::Stream>;
+    /// `connect`, failing with `ErrorKind::TimedOut` if it takes longer than `timeout`.
+    /// The default runs `connect` on a thread of its own and stops waiting for it, so
+    /// transports that can bound the connect themselves should.
+    fn connect_timeout(path: &Path, timeout: Duration) -> io::Result<Self::Stream> {
+        let (done, result) = std::sync::mpsc::sync_channel(1);
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            let _ = done.send(Self::connect(&path));
+        });
+        result
+            .recv_timeout(timeout)
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out")))
+    }
+    /// Where the extension osquery registered as `uuid` should listen, given the manager's
+    /// address. The default is osquery's own scheme, the manager's socket with `.<uuid>`
+    /// on the end. An error if the transport has no way to work one out.
+    fn extension_address(manager: &Path, uuid: ExtensionRouteUUID) -> io::Result<PathBuf> {
+        let mut name = manager
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, manager.to_string_lossy()))?
+            .to_os_string();
+        name.push(format!(".{}", uuid));
+        Ok(manager.with_file_name(name))
+    }
+}
+
+#[cfg(unix)]
+pub type DefaultTransport = Unix;
+#[cfg(not(unix))]
+pub type DefaultTransport = Unsupported;
+
+/// Placeholder default on platforms without a built-in transport yet. Everything fails
+/// with `ErrorKind::Unsupported`, bring your own `Connector` instead.
+#[cfg(not(unix))]
+#[derive(Debug)]
+pub enum Unsupported {}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "no built-in osquery transport on this platform",
+    )
+}
+
+#[cfg(not(unix))]
+impl Read for Unsupported {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        match *self {}
+    }
+}
+
+#[cfg(not(unix))]
+impl Write for Unsupported {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        match *self {}
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(not(unix))]
+impl Stream for Unsupported {
+    fn try_clone(&self) -> io::Result<Self> {
+        match *self {}
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        match *self {}
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        match *self {}
+    }
+}
+
+#[cfg(not(unix))]
+impl Listener for Unsupported {
+    type Stream = Unsupported;
+
+    fn bind(_path: &Path, _options: &ServerOptions) -> io::Result<Self> {
+        Err(unsupported())
+    }
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        match *self {}
+    }
+}
+
+#[cfg(not(unix))]
+impl Connector for Unsupported {
+    type Stream = Unsupported;
+    type Listener = Unsupported;
+
+    fn connect(_path: &Path) -> io::Result<Self::Stream> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_address_follows_osquerys_scheme() {
+        let address = Loopback::extension_address(Path::new("/var/osquery/osquery.em"), 42);
+        assert_eq!(address.unwrap(), Path::new("/var/osquery/osquery.em.42"));
+    }
+
+    #[cfg(feature = "tcp-transport")]
+    #[test]
+    fn tcp_has_no_extension_address() {
+        let error = Tcp::extension_address(Path::new("127.0.0.1:9000"), 42).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,124 @@
+// Plain TCP, for poking at the protocol from another machine or container. osquery itself
+// only ever talks over its local socket, so nothing here is used unless you name it:
+// `Client::<Tcp>::connect_via("10.0.0.5:9000", timeout)` and
+// `Handle::<_, Tcp>::on_transport("0.0.0.0:9000", ..).start()`. There's no `.<uuid>` to put
+// on the end of a port, so registering through a `Client<Tcp>` is refused: serve the
+// plugin at an address of your choosing with `Handle::on_transport` instead.
+// There's no authentication and nothing is encrypted, keep it off networks you don't own.
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tracing::warn;
+
+use super::{Connector, Listener, Stream};
+use crate::server::ServerOptions;
+use crate::ExtensionRouteUUID;
+
+/// TCP, addressed by `host:port` in place of a socket path. Development only.
+#[derive(Debug)]
+pub struct Tcp;
+
+impl Connector for Tcp {
+    type Stream = TcpStream;
+    type Listener = TcpListener;
+
+    fn connect(path: &Path) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect(addresses(path)?.as_slice())?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    fn connect_timeout(path: &Path, timeout: Duration) -> io::Result<TcpStream> {
+        let mut last = None;
+        for addr in addresses(path)? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+        Err(last.unwrap_or_else(|| no_address(path)))
+    }
+
+    fn extension_address(manager: &Path, _uuid: ExtensionRouteUUID) -> io::Result<PathBuf> {
+        Err(no_extension_address(manager))
+    }
+}
+
+// why a plugin can't be registered through a manager reached over TCP
+fn no_extension_address(manager: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "can't work out an extension address from {:?} over TCP, serve the plugin with \
+             Handle::on_transport at an address of your own instead of registering it",
+            manager
+        ),
+    )
+}
+
+/// The socket addresses `path` names, as `host:port`.
+pub(crate) fn addresses(path: &Path) -> io::Result<Vec<SocketAddr>> {
+    let addr = path.to_str().ok_or_else(|| no_address(path))?;
+    let addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(no_address(path));
+    }
+    Ok(addrs)
+}
+
+fn no_address(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("{:?} isn't a host:port address", path),
+    )
+}
+
+/// Bind `path` as `host:port`, complaining if that's reachable from off the box.
+pub(crate) fn bind(path: &Path) -> io::Result<TcpListener> {
+    let listener = TcpListener::bind(addresses(path)?.as_slice())?;
+    let local = listener.local_addr()?;
+    if !local.ip().is_loopback() {
+        warn!(%local, "extension server listening on TCP, this transport is for development only");
+    }
+    Ok(listener)
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    // the socket file options don't mean anything for TCP
+    fn bind(path: &Path, _options: &ServerOptions) -> io::Result<Self> {
+        bind(path)
+    }
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        let (stream, _) = TcpListener::accept(self)?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn waker(&self) -> io::Result<Option<super::Waker>> {
+        use std::os::unix::io::AsRawFd;
+        super::shutdown_waker(self.as_raw_fd())
+    }
+}
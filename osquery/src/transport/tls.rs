@@ -0,0 +1,192 @@
+// `Tcp` wrapped in rustls, for when the development traffic crosses a network you'd rather
+// it were encrypted on. Still never the default, and still never what osquery speaks.
+// Connectors and listeners are built from nothing but an address, so the rustls configs
+// are set once per process with `set_client_config` and `set_server_config`.
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, ServerConfig, ServerConnection, StreamOwned};
+
+use super::{tcp, Connector, Listener, Stream, Tcp};
+use crate::server::ServerOptions;
+use crate::ExtensionRouteUUID;
+
+static CLIENT_CONFIG: Mutex<Option<Arc<ClientConfig>>> = Mutex::new(None);
+static SERVER_CONFIG: Mutex<Option<Arc<ServerConfig>>> = Mutex::new(None);
+
+/// What `Tls` connections verify the server with (and present, for client auth).
+pub fn set_client_config(config: Arc<ClientConfig>) {
+    *CLIENT_CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+/// The certificate `TlsListener` serves (and how it checks clients, if it does).
+pub fn set_server_config(config: Arc<ServerConfig>) {
+    *SERVER_CONFIG.lock().unwrap_or_else(|e| e.into_inner()) = Some(config);
+}
+
+fn config<T>(config: &Mutex<Option<Arc<T>>>, which: &str) -> io::Result<Arc<T>> {
+    config
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::NotFound,
+                format!("no TLS {} config, set one first", which),
+            )
+        })
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e)
+}
+
+/// TLS over TCP, addressed by `host:port`. The host is also the name the server's
+/// certificate is checked against. Development only.
+#[derive(Debug)]
+pub struct Tls;
+
+impl Tls {
+    fn wrap(path: &Path, tcp: TcpStream) -> io::Result<TlsStream> {
+        let host = path
+            .to_str()
+            .and_then(|addr| addr.rsplit_once(':'))
+            .map(|(host, _)| host.trim_start_matches('[').trim_end_matches(']'))
+            .unwrap_or_default();
+        let name = ServerName::try_from(host.to_owned())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let conn =
+            ClientConnection::new(config(&CLIENT_CONFIG, "client")?, name).map_err(tls_error)?;
+        Ok(TlsStream::new(
+            Session::Client(StreamOwned::new(conn, tcp.try_clone()?)),
+            tcp,
+        ))
+    }
+}
+
+impl Connector for Tls {
+    type Stream = TlsStream;
+    type Listener = TlsListener;
+
+    fn connect(path: &Path) -> io::Result<TlsStream> {
+        Self::wrap(path, Tcp::connect(path)?)
+    }
+
+    fn connect_timeout(path: &Path, timeout: Duration) -> io::Result<TlsStream> {
+        Self::wrap(path, Tcp::connect_timeout(path, timeout)?)
+    }
+
+    fn extension_address(manager: &Path, uuid: ExtensionRouteUUID) -> io::Result<PathBuf> {
+        Tcp::extension_address(manager, uuid)
+    }
+}
+
+enum Session {
+    Client(StreamOwned<ClientConnection, TcpStream>),
+    Server(StreamOwned<ServerConnection, TcpStream>),
+}
+
+/// A TLS connection. rustls can't split one session into independent halves, so clones
+/// share it behind a lock. Fine for thrift, which never reads and writes at once.
+#[derive(Clone)]
+pub struct TlsStream {
+    session: Arc<Mutex<Session>>,
+    // the same socket, for timeouts without taking the lock
+    tcp: Arc<TcpStream>,
+}
+
+impl TlsStream {
+    fn new(session: Session, tcp: TcpStream) -> Self {
+        Self {
+            session: Arc::new(Mutex::new(session)),
+            tcp: Arc::new(tcp),
+        }
+    }
+
+    fn session(&self) -> MutexGuard<'_, Session> {
+        self.session.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for TlsStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsStream").field("tcp", &self.tcp).finish()
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut *self.session() {
+            Session::Client(s) => s.read(buf),
+            Session::Server(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *self.session() {
+            Session::Client(s) => s.write(buf),
+            Session::Server(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.session() {
+            Session::Client(s) => s.flush(),
+            Session::Server(s) => s.flush(),
+        }
+    }
+}
+
+impl Stream for TlsStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_write_timeout(timeout)
+    }
+}
+
+/// The server half of `Tls`. The handshake happens on the connection's first read, so a
+/// slow or broken client never holds up `accept`.
+#[derive(Debug)]
+pub struct TlsListener {
+    tcp: TcpListener,
+}
+
+impl Listener for TlsListener {
+    type Stream = TlsStream;
+
+    fn bind(path: &Path, _options: &ServerOptions) -> io::Result<Self> {
+        // fail now rather than on every connection
+        config(&SERVER_CONFIG, "server")?;
+        Ok(Self {
+            tcp: tcp::bind(path)?,
+        })
+    }
+
+    fn accept(&self) -> io::Result<TlsStream> {
+        let tcp = Listener::accept(&self.tcp)?;
+        let conn = ServerConnection::new(config(&SERVER_CONFIG, "server")?).map_err(tls_error)?;
+        Ok(TlsStream::new(
+            Session::Server(StreamOwned::new(conn, tcp.try_clone()?)),
+            tcp,
+        ))
+    }
+
+    fn waker(&self) -> io::Result<Option<super::Waker>> {
+        self.tcp.waker()
+    }
+}
@@ -0,0 +1,286 @@
+use std::convert::TryFrom;
+use std::fs::Permissions;
+use std::io::{self, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use super::{Connector, Listener, Stream};
+use crate::server::{PeerCredentials, ServerOptions};
+
+/// Unix domain sockets, what osquery uses everywhere but windows.
+#[derive(Debug)]
+pub struct Unix;
+
+impl Connector for Unix {
+    type Stream = UnixStream;
+    type Listener = UnixListener;
+
+    fn connect(path: &Path) -> io::Result<UnixStream> {
+        UnixStream::connect(path)
+    }
+
+    // a non-blocking connect, waited on with poll
+    fn connect_timeout(path: &Path, timeout: Duration) -> io::Result<UnixStream> {
+        let deadline = Instant::now() + timeout;
+        let (addr, len) = sockaddr(path)?;
+        loop {
+            // safe: plain socket(2), the fd is owned by the stream from here on
+            let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let stream = unsafe { UnixStream::from_raw_fd(fd) };
+            // safe: fd is open
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            stream.set_nonblocking(true)?;
+            // safe: addr is a sockaddr_un and len covers the path in it
+            let ret = unsafe {
+                libc::connect(
+                    fd,
+                    &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                    len,
+                )
+            };
+            let error = io::Error::last_os_error();
+            if ret == 0 || error.raw_os_error() == Some(libc::EINPROGRESS) {
+                if ret != 0 {
+                    wait_writable(fd, deadline)?;
+                    if let Some(e) = stream.take_error()? {
+                        return Err(e);
+                    }
+                }
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            // the listener's backlog is full, try again until time's up
+            if error.raw_os_error() != Some(libc::EAGAIN) {
+                return Err(error);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(timed_out());
+            }
+            std::thread::sleep(left.min(Duration::from_millis(10)));
+        }
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(ErrorKind::TimedOut, "connect timed out")
+}
+
+fn sockaddr(path: &Path) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    // safe: all zeroes is a valid sockaddr_un
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let bytes = path.as_os_str().as_bytes();
+    // leave room for the nul
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "socket path is too long",
+        ));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+    let offset = addr.sun_path.as_ptr() as usize - &addr as *const _ as usize;
+    Ok((addr, (offset + bytes.len() + 1) as libc::socklen_t))
+}
+
+fn wait_writable(fd: RawFd, deadline: Instant) -> io::Result<()> {
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        // round up, so a sliver of time left doesn't become a 0ms poll that spins
+        let millis = left.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as libc::c_int;
+        // safe: one pollfd, alive for the call
+        match unsafe { libc::poll(&mut poll, 1, millis) } {
+            0 => return Err(timed_out()),
+            n if n > 0 => return Ok(()),
+            _ => {
+                let error = io::Error::last_os_error();
+                if error.kind() != ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn peer_credentials(&self) -> io::Result<Option<PeerCredentials>> {
+        peer_credentials(self).map(Some)
+    }
+}
+
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn bind(path: &Path, options: &ServerOptions) -> io::Result<Self> {
+        // systemd already bound it and set its permissions
+        #[cfg(feature = "systemd")]
+        if let Some(listener) = crate::systemd::take_listener(path) {
+            return Ok(listener);
+        }
+        // created no more open than asked for, so there's no window between the bind and
+        // `secure_socket` where someone else can connect
+        let chowning = options.socket_uid.is_some() || options.socket_gid.is_some();
+        let restrict = match options.socket_mode {
+            Some(mode) => Some((!mode & 0o777) as libc::mode_t),
+            // nobody but us until it's the right owner's
+            None if chowning => Some(0o077),
+            None => None,
+        };
+        let (listener, umask) = match restrict {
+            Some(mask) => {
+                let umask = Umask::set(mask);
+                (bind(path, options)?, Some(umask.restore()))
+            }
+            None => (bind(path, options)?, None),
+        };
+        secure_socket(path, options, umask)?;
+        Ok(listener)
+    }
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn waker(&self) -> io::Result<Option<super::Waker>> {
+        super::shutdown_waker(self.as_raw_fd())
+    }
+}
+
+/// Bind `path`, clearing out a stale socket file first if the options allow it.
+fn bind(path: &Path, options: &ServerOptions) -> io::Result<UnixListener> {
+    let error = match UnixListener::bind(path) {
+        Ok(listener) => return Ok(listener),
+        Err(e) if e.kind() == ErrorKind::AddrInUse && options.remove_stale_socket => e,
+        Err(e) => return Err(e),
+    };
+    let is_socket = std::fs::symlink_metadata(path)
+        .map(|m| m.file_type().is_socket())
+        .unwrap_or(false);
+    if !is_socket {
+        return Err(error);
+    }
+    match UnixStream::connect(path) {
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            warn!(?path, "removing stale socket");
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        // someone's home, leave it be
+        _ => Err(error),
+    }
+}
+
+// the process's umask, put back when dropped. it's process-wide, so files another thread
+// creates meanwhile get it too, but only ever more restrictive than they'd otherwise be
+struct Umask(libc::mode_t);
+
+impl Umask {
+    fn set(mask: libc::mode_t) -> Self {
+        // safe: umask can't fail
+        Umask(unsafe { libc::umask(mask) })
+    }
+
+    // putting back the umask it replaced, which it returns
+    #[allow(clippy::useless_conversion)] // mode_t is u16 on macos
+    fn restore(self) -> u32 {
+        u32::from(self.0)
+    }
+}
+
+impl Drop for Umask {
+    fn drop(&mut self) {
+        // safe: as above
+        unsafe { libc::umask(self.0) };
+    }
+}
+
+/// Apply the socket file options, right after binding and before accepting anything.
+/// `umask` is the process's own, if the socket was bound under a stricter one.
+fn secure_socket(path: &Path, options: &ServerOptions, umask: Option<u32>) -> io::Result<()> {
+    if options.socket_uid.is_some() || options.socket_gid.is_some() {
+        std::os::unix::fs::chown(path, options.socket_uid, options.socket_gid)?;
+    }
+    // the mode asked for, or what it'd have had without owner-only binding
+    let mode = options
+        .socket_mode
+        .or_else(|| umask.map(|umask| 0o777 & !umask));
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // safe: cred and len are sized for SO_PEERCRED and outlive the call
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        pid: u32::try_from(cred.pid).ok().filter(|&pid| pid != 0),
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    let mut uid = 0;
+    let mut gid = 0;
+    // safe: uid and gid outlive the call
+    let ret = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials {
+        pid: None,
+        uid,
+        gid,
+    })
+}
@@ -0,0 +1,195 @@
+//! Typed rows from a few of osquery's own tables, for extension code that reads osquery
+//! data and would rather have the compiler check field names than index maps by string.
+//! Only columns every platform has are included; `Client::select` works for any type
+//! made with `typed_table!`.
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::scheduler::run_query;
+use crate::{Client, Connector};
+
+#[derive(thiserror::Error, Debug)]
+#[error("`{table}.{column}` has `{value}`, which doesn't fit the field")]
+pub struct TypedRowError {
+    pub table: &'static str,
+    pub column: &'static str,
+    pub value: String,
+}
+
+/// A struct that one row of an osquery table reads into.
+pub trait TypedTable: Sized {
+    const TABLE: &'static str;
+    const COLUMNS: &'static [&'static str];
+    fn from_row(row: &BTreeMap<String, String>) -> Result<Self, TypedRowError>;
+}
+
+/// How a field reads from its column's text. Numbers have to parse, `Option`s are
+/// `None` when the column is empty or missing.
+pub trait FromColumn: Sized {
+    fn from_column(raw: Option<&str>) -> Option<Self>;
+}
+
+impl FromColumn for String {
+    fn from_column(raw: Option<&str>) -> Option<Self> {
+        Some(raw.unwrap_or_default().to_string())
+    }
+}
+
+macro_rules! parsed {
+    ($($ty:ty),+) => {
+        $(
+        impl FromColumn for $ty {
+            fn from_column(raw: Option<&str>) -> Option<Self> {
+                <$ty>::from_str(raw?.trim()).ok()
+            }
+        }
+        )+
+    };
+}
+
+parsed!(i32, i64, u16, u32, u64, f64);
+
+impl<T: FromColumn> FromColumn for Option<T> {
+    fn from_column(raw: Option<&str>) -> Option<Self> {
+        match raw.map(str::trim) {
+            None | Some("") => Some(None),
+            raw => T::from_column(raw).map(Some),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn field<T: FromColumn>(
+    row: &BTreeMap<String, String>,
+    table: &'static str,
+    column: &'static str,
+) -> Result<T, TypedRowError> {
+    let raw = row.get(column).map(String::as_str);
+    T::from_column(raw).ok_or_else(|| TypedRowError {
+        table,
+        column,
+        value: raw.unwrap_or_default().to_string(),
+    })
+}
+
+/// Declare a struct for the rows of an osquery table, one field per column:
+/// `typed_table!(pub Mount = "mounts" { path: String, blocks: Option<i64> });`
+#[macro_export]
+macro_rules! typed_table {
+    ($(#[$meta:meta])* $vis:vis $ty:ident = $table:literal {
+        $($(#[$field_meta:meta])* $field:ident: $kind:ty),+ $(,)?
+    }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq)]
+        $vis struct $ty {
+            $($(#[$field_meta])* pub $field: $kind,)+
+        }
+
+        impl $crate::typed::TypedTable for $ty {
+            const TABLE: &'static str = $table;
+            const COLUMNS: &'static [&'static str] = &[$(stringify!($field)),+];
+
+            fn from_row(
+                row: &::std::collections::BTreeMap<String, String>,
+            ) -> Result<Self, $crate::typed::TypedRowError> {
+                Ok(Self {
+                    $($field: $crate::typed::field(row, $table, stringify!($field))?,)+
+                })
+            }
+        }
+    };
+}
+
+typed_table!(
+    /// A row of `processes`
+    pub Process = "processes" {
+        pid: i64,
+        name: String,
+        path: String,
+        cmdline: String,
+        cwd: String,
+        uid: i64,
+        gid: i64,
+        parent: i64,
+        start_time: Option<i64>,
+        resident_size: Option<i64>,
+        total_size: Option<i64>,
+    }
+);
+
+typed_table!(
+    /// A row of `users`
+    pub User = "users" {
+        uid: i64,
+        gid: i64,
+        username: String,
+        description: String,
+        directory: String,
+        shell: String,
+        uuid: String,
+    }
+);
+
+typed_table!(
+    /// A row of `listening_ports`
+    pub ListeningPort = "listening_ports" {
+        pid: i64,
+        port: i64,
+        /// IPPROTO_TCP, IPPROTO_UDP, ...
+        protocol: i64,
+        /// AF_INET, AF_INET6, ...
+        family: i64,
+        address: String,
+        fd: i64,
+        socket: i64,
+        /// For unix domain sockets
+        path: String,
+    }
+);
+
+typed_table!(
+    /// The one row of `osquery_info`
+    pub OsqueryInfo = "osquery_info" {
+        pid: i64,
+        uuid: String,
+        instance_id: String,
+        version: String,
+        config_hash: String,
+        config_valid: i32,
+        extensions: String,
+        build_platform: String,
+        build_distro: String,
+        start_time: i64,
+        watcher: i64,
+        platform_mask: i64,
+    }
+);
+
+impl<C: Connector> Client<C> {
+    /// Every row of `T`'s table. A row that doesn't fit `T` fails the whole call.
+    pub fn select<T: TypedTable>(&mut self) -> Result<Vec<T>, crate::Error> {
+        let sql = format!("SELECT {} FROM {}", T::COLUMNS.join(", "), T::TABLE);
+        run_query(self, &sql)?
+            .iter()
+            .map(|row| T::from_row(row).map_err(crate::Error::from))
+            .collect()
+    }
+
+    pub fn processes(&mut self) -> Result<Vec<Process>, crate::Error> {
+        self.select()
+    }
+
+    pub fn users(&mut self) -> Result<Vec<User>, crate::Error> {
+        self.select()
+    }
+
+    pub fn listening_ports(&mut self) -> Result<Vec<ListeningPort>, crate::Error> {
+        self.select()
+    }
+
+    pub fn osquery_info(&mut self) -> Result<OsqueryInfo, crate::Error> {
+        self.select()?
+            .pop()
+            .ok_or_else(|| crate::anyhow!("osquery_info came back empty"))
+    }
+}
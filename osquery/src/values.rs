@@ -0,0 +1,91 @@
+// More ways into a `ColumnValue`, so tables format common types the way osquery's own
+// tables do instead of each picking their own.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Column, ColumnValue};
+
+impl ColumnValue {
+    /// A MAC address as lowercase, colon separated hex (`00:1a:2b:3c:4d:5e`), the way
+    /// `interface_details` and `arp_cache` show them.
+    pub fn mac_address(mac: [u8; 6]) -> Self {
+        let hex = mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>();
+        ColumnValue::Text(hex.join(":"))
+    }
+
+    /// Bytes as lowercase hex, the format of osquery's `hash` table. Takes arrays or the
+    /// output of any `digest` hasher (`Sha256::digest(data)`) as they are.
+    pub fn hex<B: AsRef<[u8]>>(bytes: B) -> Self {
+        let bytes = bytes.as_ref();
+        let mut hex = String::with_capacity(bytes.len() * 2);
+        for b in bytes {
+            hex.push_str(&format!("{:02x}", b));
+        }
+        ColumnValue::Text(hex)
+    }
+
+    /// Nested data as compact JSON text, for queries to pick apart with `json_extract`
+    /// and friends. Only fails for things JSON can't hold, like maps with non-string keys.
+    pub fn json<T: serde::Serialize + ?Sized>(value: &T) -> serde_json::Result<Self> {
+        serde_json::to_string(value).map(ColumnValue::Text)
+    }
+}
+
+impl Column {
+    /// A BIGINT of seconds since the unix epoch, how osquery stores every time
+    pub fn unix_time(name: &str) -> Column {
+        Column::big_int(name)
+    }
+
+    /// A TEXT column holding `ColumnValue::json` values
+    pub fn json(name: &str) -> Column {
+        Column::text(name)
+    }
+}
+
+/// Whole seconds since the unix epoch, rounding down, so times before 1970 come out negative.
+impl From<SystemTime> for ColumnValue {
+    fn from(time: SystemTime) -> Self {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => {
+                let before = e.duration();
+                -(before.as_secs() as i64) - i64::from(before.subsec_nanos() > 0)
+            }
+        };
+        ColumnValue::BigInt(secs)
+    }
+}
+
+/// Whole seconds since the unix epoch, whatever the time zone.
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> From<chrono::DateTime<Tz>> for ColumnValue {
+    fn from(time: chrono::DateTime<Tz>) -> Self {
+        ColumnValue::BigInt(time.timestamp())
+    }
+}
+
+/// Hyphenated and lowercase. `system_info.uuid` is uppercase, so compare with
+/// `upper()` when joining against it.
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for ColumnValue {
+    fn from(id: uuid::Uuid) -> Self {
+        ColumnValue::Text(id.to_string())
+    }
+}
+
+// addresses print the way inet_ntop does, which is what osquery's tables use: dotted quads,
+// and compressed lowercase IPv6 (`fe80::1`, `::ffff:10.0.0.1`)
+macro_rules! as_text {
+    ($($kind:ty),+) => {
+        $(
+        impl From<$kind> for ColumnValue {
+            fn from(value: $kind) -> Self {
+                ColumnValue::Text(value.to_string())
+            }
+        }
+        )+
+    };
+}
+
+as_text!(IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr);
@@ -0,0 +1,191 @@
+// Checking the manager we're talking to is new enough, before registering with it,
+// rather than finding out later from protocol errors.
+use std::fmt;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::{Client, Connector, TExtensionManagerSyncClient};
+
+/// Oldest osquery whose extension API matches the IDL this crate is generated from.
+/// Also sent as the extension's `min_sdk_version`, so osquery checks it from its side too.
+pub const MIN_OSQUERY_VERSION: Version = Version::new(4, 0, 0);
+
+/// First osquery that passes `INSERT`/`UPDATE`/`DELETE` through to extension tables.
+pub const WRITABLE_TABLES_SINCE: Version = Version::new(5, 0, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse the leading `major.minor.patch` of a version string, ignoring any
+    /// suffix like `-12-gabcdef`. Missing minor/patch count as 0.
+    pub fn parse(version: &str) -> Option<Self> {
+        let numbers = version
+            .trim()
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()?;
+        let mut parts = numbers.split('.').map(|p| p.parse::<u32>().ok());
+        Some(Self {
+            major: parts.next()??,
+            minor: parts.next().flatten().unwrap_or_default(),
+            patch: parts.next().flatten().unwrap_or_default(),
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The manager is too old for this crate, or turned the extension away over its SDK version.
+#[derive(thiserror::Error, Debug)]
+#[error("incompatible osquery manager{}: {reason}", version.as_ref().map(|v| format!(" ({})", v)).unwrap_or_default())]
+pub struct IncompatibleManager {
+    /// What the manager reported itself as, if it said
+    pub version: Option<String>,
+    pub reason: String,
+}
+
+/// Registration failures osquery reports in the status message when versions don't line up.
+pub(crate) fn is_version_refusal(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("sdk") || message.contains("version")
+}
+
+/// Ask the manager for its version and make sure it's at least `MIN_OSQUERY_VERSION`.
+/// Managers that won't say (or aren't osqueryd) get the benefit of the doubt.
+pub fn check_manager<C: Connector>(
+    client: &mut Client<C>,
+) -> Result<Option<Version>, IncompatibleManager> {
+    let capabilities = match client.capabilities() {
+        Ok(capabilities) => capabilities,
+        Err(error) => {
+            warn!(%error, "couldn't ask the manager for its version, skipping the check");
+            return Ok(None);
+        }
+    };
+    let reported = match &capabilities.reported_version {
+        Some(reported) => reported,
+        None => {
+            debug!("manager didn't report a version");
+            return Ok(None);
+        }
+    };
+    match capabilities.version {
+        Some(version) if version < MIN_OSQUERY_VERSION => Err(IncompatibleManager {
+            reason: format!(
+                "this extension needs osquery {} or newer",
+                MIN_OSQUERY_VERSION
+            ),
+            version: Some(reported.clone()),
+        }),
+        Some(version) => Ok(Some(version)),
+        None => {
+            debug!(%reported, "couldn't parse the manager's version");
+            Ok(None)
+        }
+    }
+}
+
+/// What the osquery on the other end of a `Client` is and how it's set up, probed once
+/// and kept on the client. Anything osquery wouldn't say is `None`.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    /// As osquery reported it, e.g. `5.10.2`
+    pub reported_version: Option<String>,
+    pub version: Option<Version>,
+    /// How often osquery checks on its extensions (`--extensions_interval`)
+    pub extensions_interval: Option<Duration>,
+    /// How long osquery waits on an extension (`--extensions_timeout`)
+    pub extensions_timeout: Option<Duration>,
+}
+
+impl Capabilities {
+    pub fn supports_writable_tables(&self) -> bool {
+        matches!(self.version, Some(v) if v >= WRITABLE_TABLES_SINCE)
+    }
+}
+
+// one value out of a single-row query, `None` if the query failed or came back empty
+fn scalar<C: Connector>(
+    client: &mut Client<C>,
+    sql: &str,
+    column: &str,
+) -> thrift::Result<Option<String>> {
+    let response = client.query(sql.to_string())?;
+    if !response.is_success() {
+        debug!(%sql, status = ?response.status, "probe query failed");
+        return Ok(None);
+    }
+    Ok(response
+        .response
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .and_then(|mut row| row.remove(column)))
+}
+
+impl<C: Connector> Client<C> {
+    /// Ask osquery about itself, the first time, and keep the answer. Only a transport
+    /// or protocol failure is an error, and isn't cached, so the next call tries again.
+    pub fn capabilities(&mut self) -> thrift::Result<&Capabilities> {
+        if self.capabilities.is_none() {
+            let reported_version = scalar(self, "SELECT version FROM osquery_info", "version")?;
+            let flags = match self.options() {
+                Ok(flags) => flags,
+                Err(thrift::Error::Transport(e)) => return Err(thrift::Error::Transport(e)),
+                Err(error) => {
+                    debug!(%error, "manager wouldn't list its flags");
+                    Default::default()
+                }
+            };
+            let seconds = |name: &str| {
+                let flag = flags.get(name)?;
+                let value = flag.value.as_ref().or(flag.default_value.as_ref())?;
+                value.trim().parse().ok().map(Duration::from_secs)
+            };
+            let capabilities = Capabilities {
+                version: reported_version.as_deref().and_then(Version::parse),
+                reported_version,
+                extensions_interval: seconds("extensions_interval"),
+                extensions_timeout: seconds("extensions_timeout"),
+            };
+            debug!(?capabilities, "probed osquery");
+            self.capabilities = Some(capabilities);
+        }
+        Ok(self.capabilities.get_or_insert_with(Default::default))
+    }
+
+    /// The osquery version, if it said.
+    pub fn osquery_version(&mut self) -> thrift::Result<Option<Version>> {
+        Ok(self.capabilities()?.version)
+    }
+
+    /// Whether osquery can write to extension tables. `false` when the version's unknown.
+    pub fn supports_writable_tables(&mut self) -> thrift::Result<bool> {
+        Ok(self.capabilities()?.supports_writable_tables())
+    }
+
+    pub fn extensions_interval(&mut self) -> thrift::Result<Option<Duration>> {
+        Ok(self.capabilities()?.extensions_interval)
+    }
+
+    pub fn extensions_timeout(&mut self) -> thrift::Result<Option<Duration>> {
+        Ok(self.capabilities()?.extensions_timeout)
+    }
+}
@@ -0,0 +1,128 @@
+// Keeps count of the threads serving connections for `AcceptQueue`. The accept loop waits
+// here for room before accepting, and hands each connection over to be either served on a
+// new thread or parked; a thread done with its connection picks up the next parked one
+// instead of exiting.
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Instant;
+
+use tracing::{debug, warn};
+
+use crate::server::{AcceptQueue, WhenFull};
+
+pub(crate) struct Workers<S> {
+    queue: AcceptQueue,
+    state: Mutex<State<S>>,
+    freed: Condvar,
+}
+
+struct State<S> {
+    active: usize,
+    // connection id, the connection, and when it was parked
+    parked: VecDeque<(u64, S, Instant)>,
+    stopping: bool,
+}
+
+impl<S> Workers<S> {
+    pub(crate) fn new(queue: AcceptQueue) -> Arc<Self> {
+        Arc::new(Self {
+            queue,
+            state: Mutex::new(State {
+                active: 0,
+                parked: VecDeque::new(),
+                stopping: false,
+            }),
+            freed: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<S>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn busy(&self, state: &State<S>) -> bool {
+        self.queue
+            .high_water
+            .is_some_and(|high_water| state.active >= high_water)
+    }
+
+    fn full(&self, state: &State<S>) -> bool {
+        self.busy(state)
+            && match self.queue.when_full {
+                WhenFull::Hold => true,
+                WhenFull::Park { max_parked, .. } => state.parked.len() >= max_parked,
+            }
+    }
+
+    /// Block until accepting another connection wouldn't go over the queue's limits, or
+    /// the server is stopping.
+    pub(crate) fn wait_for_room(&self) {
+        let mut state = self.lock();
+        if self.full(&state) {
+            debug!(
+                active = state.active,
+                "every worker is busy, holding off on accepting"
+            );
+        }
+        while self.full(&state) && !state.stopping {
+            state = self.freed.wait(state).unwrap_or_else(|e| e.into_inner());
+            self.expire(&mut state);
+        }
+    }
+
+    /// An accepted connection, back to be served on a new thread straight away, or `None`
+    /// if it's been parked.
+    pub(crate) fn admit(&self, connection: u64, stream: S) -> Option<S> {
+        let mut state = self.lock();
+        self.expire(&mut state);
+        if self.busy(&state) {
+            debug!(connection, "every worker is busy, parking the connection");
+            state.parked.push_back((connection, stream, Instant::now()));
+            return None;
+        }
+        state.active += 1;
+        Some(stream)
+    }
+
+    /// A worker's connection closed. The next parked connection for it to serve, or
+    /// `None` if it's done.
+    pub(crate) fn next(&self) -> Option<(u64, S)> {
+        let mut state = self.lock();
+        self.expire(&mut state);
+        match state.parked.pop_front() {
+            Some((connection, stream, _)) => Some((connection, stream)),
+            None => {
+                state.active = state.active.saturating_sub(1);
+                self.freed.notify_all();
+                None
+            }
+        }
+    }
+
+    /// Let the accept loop through, so it can notice the shutdown.
+    pub(crate) fn stop(&self) {
+        self.lock().stopping = true;
+        self.freed.notify_all();
+    }
+
+    // closes parked connections that have waited past the deadline
+    fn expire(&self, state: &mut State<S>) {
+        let deadline = match self.queue.when_full {
+            WhenFull::Park { deadline, .. } => deadline,
+            WhenFull::Hold => return,
+        };
+        let before = state.parked.len();
+        state
+            .parked
+            .retain(|(_, _, parked)| parked.elapsed() < deadline);
+        let expired = before - state.parked.len();
+        if expired > 0 {
+            warn!(
+                expired,
+                ?deadline,
+                "closing connections parked past the deadline"
+            );
+            self.freed.notify_all();
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Reading what osquery sends a writable table. `INSERT` and `UPDATE` arrive as
+//! `insert`/`update` actions (see `Plugin::handle_action`) carrying the new row as a JSON
+//! array of values in column order, under `json_value_array`. `values` turns that into
+//! `ColumnValue`s typed by the table's schema, and a `DecodeError` knows which status to
+//! answer osquery with when it doesn't fit.
+//!
+//! Rows are told apart by SQLite rowid. `generate` has to include each row's under
+//! `rowid`, and `Write::decode` works out which rowid an `INSERT`, `UPDATE` or `DELETE`
+//! is about, handing out new ones from a `RowIdAllocator` when osquery wants one picked.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use maplit::btreemap;
+
+use crate::{Column, ColumnValue, PluginRequest, Response};
+
+/// The request field holding the new row.
+pub const VALUES_FIELD: &str = "json_value_array";
+
+/// The column `generate` reports each row's id in.
+pub const ROWID_COLUMN: &str = "rowid";
+
+#[derive(thiserror::Error, Debug)]
+pub enum DecodeError {
+    #[error("request has no `{0}`")]
+    MissingField(&'static str),
+    #[error("`{VALUES_FIELD}` isn't a JSON array: {0}")]
+    Malformed(String),
+    #[error("got {got} values for a table with {expected} columns")]
+    Arity { expected: usize, got: usize },
+    #[error("`{key}` isn't a row id: {value}")]
+    BadRowId { key: &'static str, value: String },
+    #[error("unknown write action `{0}`")]
+    UnknownAction(String),
+    #[error("`{value}` isn't a valid {kind} for column `{column}`")]
+    Type {
+        column: String,
+        kind: String,
+        value: String,
+    },
+}
+
+impl DecodeError {
+    /// What to send back to osquery. A row that doesn't fit the schema is a constraint
+    /// violation as far as SQLite is concerned, anything else is a plain failure.
+    pub fn response(&self) -> Response {
+        let status = match self {
+            DecodeError::Arity { .. } | DecodeError::Type { .. } => "constraint",
+            DecodeError::MissingField(_)
+            | DecodeError::Malformed(_)
+            | DecodeError::BadRowId { .. }
+            | DecodeError::UnknownAction(_) => "failure",
+        };
+        Response::success(vec![btreemap! {
+            "status".to_string() => status.to_string(),
+            "message".to_string() => self.to_string(),
+        }])
+    }
+}
+
+/// The new row's values, one per column in schema order. `None` is SQL `NULL`.
+/// Numeric strings are accepted for numeric columns, bools as 1/0.
+pub fn values(
+    columns: &[Column],
+    request: &PluginRequest,
+) -> Result<Vec<Option<ColumnValue>>, DecodeError> {
+    let raw = request
+        .get(VALUES_FIELD)
+        .ok_or(DecodeError::MissingField(VALUES_FIELD))?;
+    let array: Vec<serde_json::Value> =
+        serde_json::from_str(raw).map_err(|e| DecodeError::Malformed(e.to_string()))?;
+    if array.len() != columns.len() {
+        return Err(DecodeError::Arity {
+            expected: columns.len(),
+            got: array.len(),
+        });
+    }
+    columns
+        .iter()
+        .zip(array)
+        .map(|(column, value)| {
+            if value.is_null() {
+                return Ok(None);
+            }
+            match crate::tables::json_value(column.kind, &value) {
+                Some(typed) => Ok(Some(typed)),
+                None => Err(DecodeError::Type {
+                    column: column.name.clone(),
+                    kind: column.kind.to_string(),
+                    value: value.to_string(),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// `values` as a row keyed by column name, leaving out the `NULL`s.
+pub fn row(
+    columns: &[Column],
+    request: &PluginRequest,
+) -> Result<BTreeMap<String, ColumnValue>, DecodeError> {
+    Ok(columns
+        .iter()
+        .zip(values(columns, request)?)
+        .filter_map(|(column, value)| Some((column.name.clone(), value?)))
+        .collect())
+}
+
+/// The row id osquery sent under `key` (`id` for the row being changed or deleted,
+/// `new_id` when an `UPDATE` moves it). `None` if it's missing or not a number.
+pub fn row_id(request: &PluginRequest, key: &str) -> Option<i64> {
+    request.get(key)?.trim().parse().ok()
+}
+
+// a row id osquery has to have sent
+fn required_id(request: &PluginRequest, key: &'static str) -> Result<i64, DecodeError> {
+    let raw = request.get(key).ok_or(DecodeError::MissingField(key))?;
+    raw.trim().parse().map_err(|_| DecodeError::BadRowId {
+        key,
+        value: raw.clone(),
+    })
+}
+
+/// Hands out rowids for rows inserted without one. Ids only go up, and any id osquery
+/// picks itself (an `INSERT` naming its rowid, an `UPDATE` moving one) is skipped past,
+/// so an allocated id never lands on a row that already exists.
+#[derive(Debug)]
+pub struct RowIdAllocator {
+    next: AtomicI64,
+}
+
+impl Default for RowIdAllocator {
+    fn default() -> Self {
+        Self::starting_at(1)
+    }
+}
+
+impl RowIdAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start above the rows a table already had, e.g. ones loaded from disk.
+    pub fn starting_at(first: i64) -> Self {
+        Self {
+            next: AtomicI64::new(first),
+        }
+    }
+
+    pub fn allocate(&self) -> i64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Note an id that's in use, so `allocate` never hands it out.
+    pub fn observe(&self, id: i64) {
+        self.next.fetch_max(id.saturating_add(1), Ordering::Relaxed);
+    }
+}
+
+/// A write osquery wants made, with the rowids sorted out.
+#[derive(Debug, Clone)]
+pub enum Write {
+    /// Add a row with this id. The id still has to be sent back, see `Write::success`.
+    Insert {
+        id: i64,
+        values: Vec<Option<ColumnValue>>,
+    },
+    /// Replace row `id`, moving it to `new_id` if that's different.
+    Update {
+        id: i64,
+        new_id: i64,
+        values: Vec<Option<ColumnValue>>,
+    },
+    Delete {
+        id: i64,
+    },
+}
+
+impl Write {
+    /// Make sense of an `insert`, `update` or `delete` action. An insert gets a new id
+    /// from `ids` when osquery leaves it to the table (`auto_rowid`), and any id osquery
+    /// chooses itself is passed on to `ids` so it won't be handed out again.
+    pub fn decode(
+        action: &str,
+        columns: &[Column],
+        request: &PluginRequest,
+        ids: &RowIdAllocator,
+    ) -> Result<Self, DecodeError> {
+        match action {
+            "insert" => {
+                let values = values(columns, request)?;
+                let auto = request.get("auto_rowid").map(String::as_str) == Some("true");
+                let id = match row_id(request, "id") {
+                    Some(id) if !auto => id,
+                    _ => ids.allocate(),
+                };
+                ids.observe(id);
+                Ok(Write::Insert { id, values })
+            }
+            "update" => {
+                let id = required_id(request, "id")?;
+                let new_id = match request.get("new_id") {
+                    Some(_) => required_id(request, "new_id")?,
+                    None => id,
+                };
+                ids.observe(new_id);
+                Ok(Write::Update {
+                    id,
+                    new_id,
+                    values: values(columns, request)?,
+                })
+            }
+            "delete" => Ok(Write::Delete {
+                id: required_id(request, "id")?,
+            }),
+            other => Err(DecodeError::UnknownAction(other.to_string())),
+        }
+    }
+
+    /// The row the write is about (the old id, for an update).
+    pub fn id(&self) -> i64 {
+        match self {
+            Write::Insert { id, .. } | Write::Update { id, .. } | Write::Delete { id } => *id,
+        }
+    }
+
+    /// What to answer osquery with once the write has been made. Inserts have to report
+    /// the id the row went in under, or osquery loses track of it.
+    pub fn success(&self) -> Response {
+        match self {
+            Write::Insert { id, .. } => success(Some(*id)),
+            _ => success(None),
+        }
+    }
+}
+
+/// Tell osquery a write went through, with the row id for an `INSERT`.
+pub fn success(id: Option<i64>) -> Response {
+    let mut row = btreemap! { "status".to_string() => "success".to_string() };
+    if let Some(id) = id {
+        row.insert("id".to_string(), id.to_string());
+    }
+    Response::success(vec![row])
+}
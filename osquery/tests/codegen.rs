@@ -0,0 +1,19 @@
+// The skeleton `osquery-table-gen` writes for tests/codegen/processes.table, built against
+// the crate as it is now, so an API change that breaks generated code fails here.
+use osquery::{Column, ColumnOptions, Plugin, TablePlugin};
+
+mod processes {
+    include!("codegen/processes.rs");
+}
+
+#[test]
+fn generated_skeleton_builds_and_serves_its_columns() {
+    let table = processes::Processes::new();
+    let columns = table.columns().unwrap();
+    assert_eq!(columns.len(), processes::COLUMNS.len());
+    let pid = &columns[0];
+    assert_eq!(pid.name, "pid");
+    let expected = Column::new("pid", osquery::gen::table::ColumnType::BigInt)
+        .with_options(ColumnOptions::INDEX | ColumnOptions::OPTIMIZED);
+    assert_eq!(pid.options.bits(), expected.options.bits());
+}
@@ -0,0 +1,57 @@
+// generated from the `processes` table spec
+use osquery::gen::table::ColumnType;
+use osquery::{Column, ColumnOptions, Plugin, QueryContext, TablePlugin, TableRows};
+
+pub const COLUMNS: &[(&str, ColumnType, ColumnOptions)] = &[
+    // Process (or thread) ID (index, optimized)
+    ("pid", ColumnType::BigInt, ColumnOptions::INDEX.union(ColumnOptions::OPTIMIZED)),
+    // The process path or shorthand argv[0]
+    ("name", ColumnType::Text, ColumnOptions::DEFAULT),
+    // Path to executed binary
+    ("path", ColumnType::Text, ColumnOptions::DEFAULT),
+    // Complete argv
+    ("cmdline", ColumnType::Text, ColumnOptions::DEFAULT),
+    // The process path exists yes=1, no=0, unknown=-1
+    ("on_disk", ColumnType::Integer, ColumnOptions::DEFAULT),
+    // Bytes of private memory used by process
+    ("resident_size", ColumnType::BigInt, ColumnOptions::DEFAULT),
+    // CPU time in milliseconds spent in user space
+    ("user_time", ColumnType::BigInt, ColumnOptions::DEFAULT),
+    // Process parent's PID (additional)
+    ("parent", ColumnType::BigInt, ColumnOptions::ADDITIONAL),
+    // Process uses elevated token yes=1, no=0 [WINDOWS]
+    ("elevated_token", ColumnType::Integer, ColumnOptions::DEFAULT),
+    // Process is secure (IUM) yes=1, no=0 (hidden) [WINDOWS]
+    ("secure_process", ColumnType::Integer, ColumnOptions::HIDDEN),
+    // A 64bit pid that is never reused. Returns -1 if we couldn't gather them from the system. [DARWIN]
+    ("upid", ColumnType::BigInt, ColumnOptions::DEFAULT),
+];
+osquery::assert_columns!(COLUMNS);
+
+/// All running processes on the host system.
+#[derive(Debug, Default)]
+pub struct Processes;
+
+impl Plugin for Processes {
+    type Error = std::convert::Infallible;
+    const NAME: &'static str = "processes";
+
+    fn new() -> Self {
+        Processes
+    }
+}
+
+impl TablePlugin for Processes {
+    fn generate(&self, _query: &QueryContext) -> Result<TableRows, Self::Error> {
+        todo!()
+    }
+
+    fn columns(&self) -> Result<Vec<Column>, Self::Error> {
+        Ok(COLUMNS
+            .iter()
+            .map(|&(name, kind, options)| Column::new(name, kind).with_options(options))
+            .collect())
+    }
+
+    fn shutdown(&self) {}
+}